@@ -0,0 +1,108 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use darling::FromField;
+use quote::Tokens;
+use syn::{Data, DeriveInput, Field, Fields, Ident};
+
+#[derive(Default, FromField)]
+#[darling(attributes(animate), default)]
+pub struct AnimateFieldAttrs {
+    /// Whether the field flips from one value to the other at the midpoint,
+    /// instead of being interpolated.
+    pub discrete: bool,
+}
+
+fn field_attrs(field: &Field) -> AnimateFieldAttrs {
+    match AnimateFieldAttrs::from_field(field) {
+        Ok(attrs) => attrs,
+        Err(e) => panic!("failed to parse field attributes: {}", e),
+    }
+}
+
+/// Structs interpolate each of their fields independently, so a field that
+/// can't be interpolated flips discretely without affecting the others.
+///
+/// Enums only interpolate values of the same variant, whose fields must all be
+/// interpolable. Unit variants are only equal to themselves, so keywords are
+/// discrete.
+pub fn derive(input: DeriveInput) -> Tokens {
+    let name = &input.ident;
+    let is_enum = matches!(input.data, Data::Enum(..));
+    let s = synstructure::Structure::new(&input);
+
+    let match_body = s.variants().iter().fold(quote!(), |match_body, variant| {
+        let ident = variant.ast().ident;
+        let path = if is_enum {
+            quote! { #name::#ident }
+        } else {
+            quote! { #name }
+        };
+
+        let fields: Vec<&Field> = match *variant.ast().fields {
+            Fields::Named(ref fields) => fields.named.iter().collect(),
+            Fields::Unnamed(ref fields) => fields.unnamed.iter().collect(),
+            Fields::Unit => vec![],
+        };
+        let this = (0..fields.len()).map(|i| Ident::from(format!("this_{}", i))).collect::<Vec<_>>();
+        let other = (0..fields.len()).map(|i| Ident::from(format!("other_{}", i))).collect::<Vec<_>>();
+        let values = fields
+            .iter()
+            .zip(this.iter().zip(&other))
+            .map(|(field, (this, other))| {
+                if field_attrs(field).discrete {
+                    assert!(!is_enum, "Enum fields can't be discrete");
+                    quote! { crate::animate::discrete(#this, #other, progress) }
+                } else if is_enum {
+                    quote! { crate::animate::Animate::animate(#this, #other, progress)? }
+                } else {
+                    quote! { crate::animate::interpolate(#this, #other, progress) }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let (this_pattern, other_pattern, result) = match *variant.ast().fields {
+            Fields::Named(..) => {
+                let names = fields.iter().map(|field| field.ident.unwrap()).collect::<Vec<_>>();
+                let names2 = names.clone();
+                let names3 = names.clone();
+                (
+                    quote! { #path { #( #names: ref #this, )* } },
+                    quote! { #path { #( #names2: ref #other, )* } },
+                    quote! { #path { #( #names3: #values, )* } },
+                )
+            },
+            Fields::Unnamed(..) => (
+                quote! { #path( #( ref #this, )* ) },
+                quote! { #path( #( ref #other, )* ) },
+                quote! { #path( #( #values, )* ) },
+            ),
+            Fields::Unit => (quote! { #path }, quote! { #path }, quote! { #path }),
+        };
+
+        quote! {
+            #match_body
+            (&#this_pattern, &#other_pattern) => Ok(#result),
+        }
+    });
+
+    let fallback = if is_enum && s.variants().len() > 1 {
+        quote! { _ => Err(()), }
+    } else {
+        quote!()
+    };
+
+    quote! {
+        impl crate::animate::Animate for #name {
+            // Keywords don't use `progress`.
+            #[allow(unused_variables)]
+            fn animate(&self, other: &Self, progress: f32) -> Result<Self, ()> {
+                match (self, other) {
+                    #match_body
+                    #fallback
+                }
+            }
+        }
+    }
+}
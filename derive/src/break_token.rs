@@ -5,7 +5,6 @@
 use crate::cg;
 use quote::Tokens;
 use syn::{DeriveInput, Ident};
-use synstructure;
 
 pub fn derive(input: DeriveInput) -> Tokens {
     let name = &input.ident;
@@ -32,6 +31,7 @@ pub fn derive(input: DeriveInput) -> Tokens {
                 }
             }
 
+            #[allow(dead_code)]
             impl #name {
                 #[inline]
                 fn #as_name(&self) -> Option<&#ty> {
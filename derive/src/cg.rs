@@ -45,7 +45,7 @@ pub fn to_css_identifier(mut camel_case: &str) -> String {
             }
         }
         if !first {
-            result.push_str("-");
+            result.push('-');
         }
         first = false;
         result.push_str(&segment.to_lowercase());
@@ -53,6 +53,20 @@ pub fn to_css_identifier(mut camel_case: &str) -> String {
     result
 }
 
+/// Transforms "foo-bar" to "FooBar".
+pub fn to_camel_case(css_identifier: &str) -> String {
+    css_identifier
+        .split('-')
+        .map(|segment| {
+            let mut chars = segment.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
 /// Given "FooBar", returns "Foo" and sets `camel_case` to "Bar".
 fn split_camel_segment<'input>(camel_case: &mut &'input str) -> Option<&'input str> {
     let index = match camel_case.chars().next() {
@@ -5,14 +5,24 @@
 use crate::cg;
 use quote::Tokens;
 use syn::DeriveInput;
-use synstructure;
+use synstructure::{self, VariantAst};
 
-#[darling(attributes(parse), default)]
 #[derive(Default, FromVariant)]
+#[darling(attributes(parse), default)]
 pub struct ParseVariantAttrs {
     pub aliases: Option<String>,
 }
 
+/// Returns the keyword a variant serializes as, which is its first alias if it
+/// has any, or its name in kebab case otherwise.
+pub fn canonical_keyword(variant: &VariantAst) -> String {
+    let parse_attrs = cg::parse_variant_attrs_from_ast::<ParseVariantAttrs>(variant);
+    match parse_attrs.aliases {
+        Some(aliases) => aliases.split(",").next().unwrap().to_owned(),
+        None => cg::to_css_identifier(variant.ident.as_ref()),
+    }
+}
+
 pub fn derive(input: DeriveInput) -> Tokens {
     let name = &input.ident;
     let s = synstructure::Structure::new(&input);
@@ -48,8 +58,39 @@ pub fn derive(input: DeriveInput) -> Tokens {
         body
     });
 
+    let variants = s
+        .variants()
+        .iter()
+        .map(|variant| {
+            let ident = variant.ast().ident;
+            quote!(#name::#ident)
+        })
+        .collect::<Vec<_>>();
+    let to_css_str_body = s.variants().iter().fold(quote!(), |body, variant| {
+        let ident = &variant.ast().ident;
+        let keyword = canonical_keyword(&variant.ast());
+        quote! {
+            #body
+            #name::#ident => #keyword,
+        }
+    });
+
     quote! {
         impl #name {
+            /// Returns all the values of this keyword, in declaration order.
+            #[inline]
+            pub fn all() -> &'static [Self] {
+                &[ #( #variants, )* ]
+            }
+
+            /// Returns the keyword this value serializes as.
+            #[inline]
+            pub fn to_css_str(&self) -> &'static str {
+                match *self {
+                    #to_css_str_body
+                }
+            }
+
             /// Parse this keyword.
             #[inline]
             pub fn parse<'i, 't>(
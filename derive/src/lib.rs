@@ -14,10 +14,13 @@ extern crate synstructure;
 
 use proc_macro::TokenStream;
 
+mod animate;
 mod break_token;
 mod cg;
 mod keyword;
 mod property_declaration;
+mod shorthand;
+mod to_css;
 
 #[proc_macro_derive(Keyword, attributes(css, parse))]
 pub fn derive_parse(stream: TokenStream) -> TokenStream {
@@ -25,12 +28,30 @@ pub fn derive_parse(stream: TokenStream) -> TokenStream {
     keyword::derive(input).into()
 }
 
+#[proc_macro_derive(ToCss)]
+pub fn derive_to_css(stream: TokenStream) -> TokenStream {
+    let input = syn::parse(stream).unwrap();
+    to_css::derive(input).into()
+}
+
 #[proc_macro_derive(PropertyDeclaration, attributes(declaration))]
 pub fn derive_property_declaration(stream: TokenStream) -> TokenStream {
     let input = syn::parse(stream).unwrap();
     property_declaration::derive(input).into()
 }
 
+#[proc_macro_derive(Shorthand, attributes(shorthand))]
+pub fn derive_shorthand(stream: TokenStream) -> TokenStream {
+    let input = syn::parse(stream).unwrap();
+    shorthand::derive(input).into()
+}
+
+#[proc_macro_derive(Animate, attributes(animate))]
+pub fn derive_animate(stream: TokenStream) -> TokenStream {
+    let input = syn::parse(stream).unwrap();
+    animate::derive(input).into()
+}
+
 #[proc_macro_derive(BreakToken)]
 pub fn derive_break_token(stream: TokenStream) -> TokenStream {
     let input = syn::parse(stream).unwrap();
@@ -1,14 +1,96 @@
 use crate::cg;
 use quote::Tokens;
 use syn::{DeriveInput, Ident, Path, Type};
-use synstructure;
+use synstructure::{self, VariantAst};
 
-#[darling(attributes(declaration), default)]
 #[derive(Default, FromVariant)]
+#[darling(attributes(declaration), default)]
 pub struct DeclarationVariantAttrs {
     pub field: Option<Ident>,
     pub logical: bool,
     pub early: bool,
+    /// Whether the specified value needs to be computed against the parent
+    /// style, via a `to_computed_value` method.
+    pub computed: bool,
+    /// Whether the property is inherited by default, in which case children
+    /// start with the value of their parent instead of the initial value.
+    pub inherited: bool,
+}
+
+/// Returns an expression that borrows the field of `style` that a
+/// declaration of `variant` sets, which for logical properties depends on the
+/// writing mode.
+fn style_field(
+    variant: &VariantAst,
+    variant_attrs: &DeclarationVariantAttrs,
+    mutability: Tokens,
+) -> Tokens {
+    let property_name = cg::to_css_identifier(variant.ident.as_ref());
+    let field_name = property_name.replace("-", "_");
+    if !variant_attrs.logical {
+        let field_name = variant_attrs.field.unwrap_or(Ident::from(field_name));
+        return quote! { &#mutability style.#field_name };
+    }
+
+    if property_name.contains("block-size") || property_name.contains("inline-size") {
+        let is_block = property_name.contains("block-size");
+        let pattern_to_replace = if is_block {
+            "block_size"
+        } else {
+            "inline_size"
+        };
+
+        let width = Ident::from(field_name.replace(pattern_to_replace, "width"));
+        let height = Ident::from(field_name.replace(pattern_to_replace, "height"));
+
+        let maybe_neg = if is_block {
+            quote! {}
+        } else {
+            quote! { ! }
+        };
+        return quote! {
+            (if #maybe_neg style.writing_mode.is_vertical() {
+                &#mutability style.#height
+            } else {
+                &#mutability style.#width
+            })
+        };
+    }
+
+    assert!(
+        property_name.contains("inline-start") ||
+            property_name.contains("inline-end") ||
+            property_name.contains("block-start") ||
+            property_name.contains("block-end")
+    );
+
+    let pattern_to_replace = if property_name.contains("inline-start") {
+        "inline_start"
+    } else if property_name.contains("inline-end") {
+        "inline_end"
+    } else if property_name.contains("block-start") {
+        "block_start"
+    } else {
+        "block_end"
+    };
+
+    let field_name = field_name.replace("inset_", "");
+
+    let function_name = Ident::from(pattern_to_replace.to_owned() + "_physical_side");
+
+    let top = Ident::from(field_name.replace(pattern_to_replace, "top"));
+    let bottom = Ident::from(field_name.replace(pattern_to_replace, "bottom"));
+    let left = Ident::from(field_name.replace(pattern_to_replace, "left"));
+    let right = Ident::from(field_name.replace(pattern_to_replace, "right"));
+
+    quote! {
+        (match style.writing_mode.#function_name() {
+            crate::logical_geometry::PhysicalSide::Left => &#mutability style.#left,
+            crate::logical_geometry::PhysicalSide::Right => &#mutability style.#right,
+            crate::logical_geometry::PhysicalSide::Top => &#mutability style.#top,
+            crate::logical_geometry::PhysicalSide::Bottom => &#mutability style.#bottom,
+        })
+    }
 }
 
 pub fn derive(input: DeriveInput) -> Tokens {
@@ -22,6 +104,30 @@ pub fn derive(input: DeriveInput) -> Tokens {
         quote! { #early }
     });
 
+    let is_inherited_body = s.each_variant(|variant| {
+        let variant_attrs =
+            cg::parse_variant_attrs_from_ast::<DeclarationVariantAttrs>(&variant.ast());
+        let inherited = variant_attrs.inherited;
+        quote! { #inherited }
+    });
+
+    let inherit_body = s.variants().iter().fold(quote!(), |inherit_body, variant| {
+        let variant_attrs =
+            cg::parse_variant_attrs_from_ast::<DeclarationVariantAttrs>(&variant.ast());
+        if !variant_attrs.inherited {
+            return inherit_body;
+        }
+        assert!(!variant_attrs.logical, "Logical properties are not stored, so can't be inherited");
+        let property_name = cg::to_css_identifier(variant.ast().ident.as_ref());
+        let field_name = variant_attrs
+            .field
+            .unwrap_or(Ident::from(property_name.replace("-", "_")));
+        quote! {
+            #inherit_body
+            style.#field_name = parent.#field_name.clone();
+        }
+    });
+
     let compute_body = s.each_variant(|variant| {
         let variant_attrs =
             cg::parse_variant_attrs_from_ast::<DeclarationVariantAttrs>(&variant.ast());
@@ -29,78 +135,17 @@ pub fn derive(input: DeriveInput) -> Tokens {
         let bindings = variant.bindings();
         assert_eq!(bindings.len(), 1);
         assert!(!variant_attrs.logical || variant_attrs.field.is_none());
+        assert!(!variant_attrs.logical || !variant_attrs.computed);
 
-        let property_name = cg::to_css_identifier(variant.ast().ident.as_ref());
-        let field_name = property_name.replace("-", "_");
         let value = &bindings[0];
-        let value = quote! { #value.clone() };
-        if !variant_attrs.logical {
-            let field_name = variant_attrs.field.unwrap_or(Ident::from(field_name));
-            return quote! { style.#field_name = #value };
-        }
-
-        if property_name.contains("block-size") || property_name.contains("inline-size") {
-            let is_block = property_name.contains("block-size");
-            let pattern_to_replace = if is_block {
-                "block_size"
-            } else {
-                "inline_size"
-            };
-
-            let width = Ident::from(field_name.replace(pattern_to_replace, "width"));
-            let height = Ident::from(field_name.replace(pattern_to_replace, "height"));
-
-            let maybe_neg = if is_block {
-                quote! {}
-            } else {
-                quote! { ! }
-            };
-            quote! {
-                if #maybe_neg style.writing_mode.is_vertical() {
-                    style.#height= #value;
-                } else {
-                    style.#width = #value;
-                }
-            }
+        let value = if variant_attrs.computed {
+            quote! { #value.to_computed_value(parent) }
         } else {
-            assert!(
-                property_name.contains("inline-start") ||
-                    property_name.contains("inline-end") ||
-                    property_name.contains("block-start") ||
-                    property_name.contains("block-end")
-            );
-
-            let pattern_to_replace = if property_name.contains("inline-start") {
-                "inline_start"
-            } else if property_name.contains("inline-end") {
-                "inline_end"
-            } else if property_name.contains("block-start") {
-                "block_start"
-            } else {
-                "block_end"
-            };
-
-            let field_name = field_name.replace("inset_", "");
-
-            let function_name = Ident::from(pattern_to_replace.to_owned() + "_physical_side");
-
-            let top = Ident::from(field_name.replace(pattern_to_replace, "top"));
-            let bottom = Ident::from(field_name.replace(pattern_to_replace, "bottom"));
-            let left = Ident::from(field_name.replace(pattern_to_replace, "left"));
-            let right = Ident::from(field_name.replace(pattern_to_replace, "right"));
-
-            quote! {
-                let mut field = match style.writing_mode.#function_name() {
-                    crate::logical_geometry::PhysicalSide::Left => &mut style.#left,
-                    crate::logical_geometry::PhysicalSide::Right => &mut style.#right,
-                    crate::logical_geometry::PhysicalSide::Top => &mut style.#top,
-                    crate::logical_geometry::PhysicalSide::Bottom => &mut style.#bottom,
-                };
-                *field = #value;
-            }
-        }
+            quote! { #value.clone() }
+        };
+        let field = style_field(&variant.ast(), &variant_attrs, quote!(mut));
+        quote! { *#field = #value }
     });
-
     fn known_parse_function(path: &Path) -> Option<Ident> {
         Some(Ident::from(
             match path.segments.last().unwrap().value().ident.as_ref() {
@@ -116,7 +161,52 @@ pub fn derive(input: DeriveInput) -> Tokens {
         ))
     }
 
-    let parse_body = s.variants().iter().fold(quote!(), |parse_body, variant| {
+    // The logical property group of each property, which physical properties
+    // only have if a logical property shares it.
+    //
+    // https://drafts.csswg.org/css-logical/#logical-property-group
+    let groups = s
+        .variants()
+        .iter()
+        .map(|variant| {
+            let property_name = cg::to_css_identifier(variant.ast().ident.as_ref());
+            let segments = property_name.split('-').collect::<Vec<_>>();
+            let is_side = |segment: &&str| {
+                matches!(*segment, "start" | "end" | "top" | "right" | "bottom" | "left")
+            };
+            let segments = if segments.iter().any(is_side) {
+                // Like `margin-top` or `border-inline-start-width`.
+                segments
+                    .into_iter()
+                    .filter(|segment| !is_side(segment) && *segment != "inline" && *segment != "block")
+                    .collect::<Vec<_>>()
+            } else if segments.iter().any(|s| ["inline", "block", "width", "height"].contains(s)) {
+                // Like `width` or `min-block-size`.
+                segments
+                    .into_iter()
+                    .filter(|segment| *segment != "inline" && *segment != "block")
+                    .map(|segment| if segment == "width" || segment == "height" { "size" } else { segment })
+                    .collect::<Vec<_>>()
+            } else {
+                return None;
+            };
+            Some(if segments.is_empty() { "inset".to_owned() } else { segments.join("-") })
+        })
+        .collect::<Vec<_>>();
+    let logical_groups = s
+        .variants()
+        .iter()
+        .zip(&groups)
+        .filter(|&(variant, _)| {
+            cg::parse_variant_attrs_from_ast::<DeclarationVariantAttrs>(&variant.ast()).logical
+        })
+        .filter_map(|(_, group)| group.clone())
+        .collect::<Vec<_>>();
+
+    let mut parse_functions = quote!();
+    let mut serialize_functions = quote!();
+    let mut properties = quote!();
+    for (variant, group) in s.variants().iter().zip(&groups) {
         let field = &variant.bindings()[0].ast();
         let ty_path = match field.ty {
             Type::Path(ref ty_path) => ty_path,
@@ -128,33 +218,78 @@ pub fn derive(input: DeriveInput) -> Tokens {
             None => quote! { #ty_path::parse(input) },
         };
 
+        let variant_attrs =
+            cg::parse_variant_attrs_from_ast::<DeclarationVariantAttrs>(&variant.ast());
         let ident = &variant.ast().ident;
         let property_name = cg::to_css_identifier(ident.as_ref());
-        quote! {
-            #parse_body
-            #property_name => Ok(#name::#ident(#parse?)),
-        }
-    });
+        let parse_function = Ident::from(format!("parse_{}", property_name.replace("-", "_")));
+        parse_functions = quote! {
+            #parse_functions
+
+            fn #parse_function<'i, 't>(
+                input: &mut cssparser::Parser<'i, 't>,
+            ) -> Result<Self, ParseError<'i>> {
+                Ok(#name::#ident(#parse?))
+            }
+        };
+
+        let serialize_function = Ident::from(format!("serialize_{}", property_name.replace("-", "_")));
+        let field = style_field(&variant.ast(), &variant_attrs, quote!());
+        serialize_functions = quote! {
+            #serialize_functions
+
+            fn #serialize_function(style: &crate::style::ComputedStyle) -> String {
+                cssparser::ToCss::to_css_string(#field)
+            }
+        };
+
+        let logical_group = match *group {
+            Some(ref group) if logical_groups.contains(group) => quote! { Some(#group) },
+            _ => quote! { None },
+        };
+        let logical = variant_attrs.logical;
+        let early = variant_attrs.early;
+        let inherited = variant_attrs.inherited;
+        properties = quote! {
+            #properties
+            crate::css::PropertyMetadata {
+                name: #property_name,
+                logical_group: #logical_group,
+                logical: #logical,
+                early: #early,
+                inherited: #inherited,
+                parse: #name::#parse_function,
+                serialize: #name::#serialize_function,
+            },
+        };
+    }
 
     quote! {
         impl #name {
+            #parse_functions
+
+            #serialize_functions
+
             #[inline]
             fn parse_longhand<'i, 't>(
                 name: &cssparser::CowRcStr<'i>,
                 input: &mut cssparser::Parser<'i, 't>,
             ) -> Result<Self, ParseError<'i>> {
-                let location = input.current_source_location();
-                match &**name {
-                    #parse_body
-                    _ => {
-                        Err(location.new_custom_error(
+                match crate::css::PropertyMetadata::find(name) {
+                    Some(property) => (property.parse)(input),
+                    None => {
+                        Err(input.new_custom_error(
                             crate::css::Error::UnknownPropertyName(name.clone())
                         ))
                     }
                 }
             }
 
-            fn compute(&self, style: &mut crate::style::MutableComputedStyle) {
+            fn compute(
+                &self,
+                style: &mut crate::style::MutableComputedStyle,
+                parent: &crate::style::MutableComputedStyle,
+            ) {
                 match *self {
                     #compute_body
                 }
@@ -165,6 +300,27 @@ pub fn derive(input: DeriveInput) -> Tokens {
                     #is_early_body
                 }
             }
+
+            /// Whether this is a declaration of an inherited property.
+            pub fn is_inherited(&self) -> bool {
+                match *self {
+                    #is_inherited_body
+                }
+            }
+
+            /// Sets the inherited properties of `style` to their value in
+            /// `parent`.
+            pub(crate) fn inherit(
+                style: &mut crate::style::MutableComputedStyle,
+                parent: &crate::style::MutableComputedStyle,
+            ) {
+                #inherit_body
+            }
         }
+
+        /// The longhands we support, in declaration order.
+        pub static PROPERTIES: &[crate::css::PropertyMetadata] = &[
+            #properties
+        ];
     }
 }
@@ -0,0 +1,176 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::cg;
+use quote::Tokens;
+use syn::{DeriveInput, Ident};
+
+/// How a shorthand expands into its longhands. Exactly one of these must be
+/// specified, and longhands are comma-separated property names.
+#[derive(Default, FromVariant)]
+#[darling(attributes(shorthand), default)]
+pub struct ShorthandVariantAttrs {
+    /// One to four values, for the top, right, bottom and left longhands.
+    pub four_sides: Option<String>,
+    /// One or two values, where the second longhand defaults to the first.
+    pub pair: Option<String>,
+    /// Other single-value shorthands, in any order, but each at most once.
+    /// The omitted ones are reset to the comma-separated `initial` values.
+    pub any_order: Option<String>,
+    pub initial: Option<String>,
+    /// A legacy name of a longhand.
+    pub alias: Option<String>,
+    /// A hand-written parse function.
+    pub function: Option<Ident>,
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',').map(|item| item.trim().to_owned()).collect()
+}
+
+/// Returns the `PropertyDeclaration` variant of a longhand.
+fn longhand_variant(name: &str) -> Tokens {
+    let variant = Ident::from(cg::to_camel_case(name));
+    quote! { crate::css::PropertyDeclaration::#variant }
+}
+
+/// Returns an expression that parses a single value of `longhand` from
+/// `input`.
+fn parse_longhand_value(longhand: &str) -> Tokens {
+    let variant = longhand_variant(longhand);
+    quote! {
+        match crate::css::PropertyDeclaration::parse_longhand(&cssparser::CowRcStr::from(#longhand), input)? {
+            #variant(value) => value,
+            _ => unreachable!(),
+        }
+    }
+}
+
+pub fn derive(input: DeriveInput) -> Tokens {
+    let name = &input.ident;
+    let s = synstructure::Structure::new(&input);
+
+    let mut from_name_body = quote!();
+    let mut name_body = quote!();
+    let mut parse_body = quote!();
+    let mut parse_single_value_body = quote!();
+    for variant in s.variants() {
+        assert!(variant.bindings().is_empty(), "Shorthands can't have fields");
+        let ident = &variant.ast().ident;
+        let property_name = cg::to_css_identifier(ident.as_ref());
+        from_name_body = quote! {
+            #from_name_body
+            #property_name => Some(#name::#ident),
+        };
+        name_body = quote! {
+            #name_body
+            #name::#ident => #property_name,
+        };
+
+        let attrs = cg::parse_variant_attrs_from_ast::<ShorthandVariantAttrs>(&variant.ast());
+        let longhands = attrs
+            .four_sides
+            .as_ref()
+            .or(attrs.pair.as_ref())
+            .map(|longhands| split_list(longhands));
+        let parse = if let Some(ref longhands) = longhands {
+            let parse_one = parse_longhand_value(&longhands[0]);
+            let constructors = longhands.iter().map(|longhand| longhand_variant(longhand)).collect::<Vec<_>>();
+            let helper = if attrs.four_sides.is_some() {
+                assert_eq!(longhands.len(), 4, "{} needs four longhands", property_name);
+                quote! { crate::css::parse_four_sides }
+            } else {
+                assert_eq!(longhands.len(), 2, "{} needs two longhands", property_name);
+                quote! { crate::css::parse_pair }
+            };
+            let single_constructors = constructors.clone();
+            parse_single_value_body = quote! {
+                #parse_single_value_body
+                #name::#ident => {
+                    let value = #parse_one;
+                    let mut declarations = smallvec::SmallVec::new();
+                    #( declarations.push(#single_constructors(value.clone())); )*
+                    Ok(declarations)
+                },
+            };
+            quote! {
+                #helper(input, #( #constructors, )* |input| Ok(#parse_one))
+            }
+        } else if let Some(ref components) = attrs.any_order {
+            let components = split_list(components);
+            let initial = split_list(attrs.initial.as_ref().expect("any_order shorthands need initial values"));
+            assert_eq!(components.len(), initial.len(), "{} needs an initial value per component", property_name);
+            let components = components
+                .iter()
+                .map(|component| {
+                    let component = Ident::from(cg::to_camel_case(component));
+                    quote!(#name::#component)
+                })
+                .collect::<Vec<_>>();
+            quote! {
+                crate::css::parse_any_order(input, &[ #( (#components, #initial), )* ])
+            }
+        } else if let Some(ref longhand) = attrs.alias {
+            let parse_one = parse_longhand_value(longhand);
+            let constructor = longhand_variant(longhand);
+            quote! {{
+                let mut declarations = smallvec::SmallVec::new();
+                declarations.push(#constructor(#parse_one));
+                Ok(declarations)
+            }}
+        } else if let Some(ref function) = attrs.function {
+            quote! { #function(input) }
+        } else {
+            panic!("{} doesn't say how it expands", property_name);
+        };
+
+        parse_body = quote! {
+            #parse_body
+            #name::#ident => #parse,
+        };
+    }
+
+    quote! {
+        impl #name {
+            /// Returns the shorthand with the given name, if any.
+            pub fn from_name(name: &str) -> Option<Self> {
+                match_ignore_ascii_case! { name,
+                    #from_name_body
+                    _ => None,
+                }
+            }
+
+            /// Returns the name of this shorthand.
+            pub fn name(&self) -> &'static str {
+                match *self {
+                    #name_body
+                }
+            }
+
+            /// Parses the value of this shorthand, and expands it into its
+            /// longhands.
+            pub fn parse<'i, 't>(
+                &self,
+                input: &mut cssparser::Parser<'i, 't>,
+            ) -> Result<smallvec::SmallVec<[crate::css::PropertyDeclaration; 1]>, crate::css::ParseError<'i>> {
+                match *self {
+                    #parse_body
+                }
+            }
+
+            /// Parses a single value, and sets all the longhands of this
+            /// shorthand to it, for shorthands that are components of other
+            /// shorthands.
+            fn parse_single_value<'i, 't>(
+                &self,
+                input: &mut cssparser::Parser<'i, 't>,
+            ) -> Result<smallvec::SmallVec<[crate::css::PropertyDeclaration; 1]>, crate::css::ParseError<'i>> {
+                match *self {
+                    #parse_single_value_body
+                    _ => panic!("{} can't be a component of another shorthand", self.name()),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,47 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::keyword;
+use quote::Tokens;
+use syn::DeriveInput;
+
+/// Serializes unit variants as their canonical keyword, like `Keyword` does,
+/// and delegates to the field of single-field variants and structs.
+pub fn derive(input: DeriveInput) -> Tokens {
+    let name = &input.ident;
+    let s = synstructure::Structure::new(&input);
+
+    let match_body = s.variants().iter().fold(quote!(), |match_body, variant| {
+        let pat = variant.pat();
+        let bindings = variant.bindings();
+        let expr = match bindings.len() {
+            0 => {
+                let identifier = keyword::canonical_keyword(&variant.ast());
+                quote! { dest.write_str(#identifier) }
+            },
+            1 => {
+                let binding = &bindings[0];
+                quote! { cssparser::ToCss::to_css(#binding, dest) }
+            },
+            _ => panic!("ToCss is only supported for unit and single-field variants for now"),
+        };
+        quote! {
+            #match_body
+            #pat => #expr,
+        }
+    });
+
+    quote! {
+        impl cssparser::ToCss for #name {
+            fn to_css<W>(&self, dest: &mut W) -> std::fmt::Result
+            where
+                W: std::fmt::Write,
+            {
+                match *self {
+                    #match_body
+                }
+            }
+        }
+    }
+}
@@ -2,14 +2,300 @@
 extern crate clap;
 
 use app_units::Au;
+use nglayoutng::css;
+use nglayoutng::display_list::DisplayList;
 use nglayoutng::dom::print_dom;
-use nglayoutng::layout_tree::builder::LayoutTreeBuilder;
+use nglayoutng::fragment_tree::FragmentTree;
+use nglayoutng::layout_tree::builder::{LayoutTreeBuilder, LayoutTreeBuilderResult};
+use nglayoutng::query::LayoutResultQuery;
 use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Where to read the document from.
+enum Input<'a> {
+    File(&'a str),
+    Stdin,
+    Html(&'a str),
+}
+
+impl<'a> Input<'a> {
+    fn from_args(args: &'a clap::ArgMatches) -> Self {
+        if let Some(html) = args.value_of("html") {
+            return Input::Html(html);
+        }
+        Self::from_path(args.value_of("input").unwrap())
+    }
+
+    fn from_path(path: &'a str) -> Self {
+        match path {
+            "-" => Input::Stdin,
+            path => Input::File(path),
+        }
+    }
+
+    /// The file the document is read from, if any.
+    fn path(&self) -> Option<&Path> {
+        match *self {
+            Input::File(path) => Some(Path::new(path)),
+            Input::Stdin | Input::Html(..) => None,
+        }
+    }
+
+    /// Reads the document, and returns it along with the directory relative
+    /// urls in it are resolved against.
+    ///
+    /// Documents that don't come from a file resolve urls against the current
+    /// directory.
+    fn read(&self) -> io::Result<(Vec<u8>, Option<PathBuf>)> {
+        let mut bytes = vec![];
+        let base_directory = match *self {
+            Input::File(path) => {
+                File::open(path)?.read_to_end(&mut bytes)?;
+                Path::new(path).parent().map(|p| p.to_owned())
+            },
+            Input::Stdin => {
+                io::stdin().read_to_end(&mut bytes)?;
+                std::env::current_dir().ok()
+            },
+            Input::Html(html) => {
+                bytes.extend_from_slice(html.as_bytes());
+                std::env::current_dir().ok()
+            },
+        };
+        Ok((bytes, base_directory))
+    }
+}
+
+fn input_args<'a, 'b>() -> Vec<clap::Arg<'a, 'b>> {
+    use clap::Arg;
+
+    vec![
+        Arg::from_usage("[input]  'The document to build the tree for, or - to read it from stdin'")
+            .required_unless("html"),
+        Arg::from_usage("--html [html]  'An HTML string to use as the document'").conflicts_with("input"),
+        watch_arg(),
+    ]
+}
+
+fn watch_arg<'a, 'b>() -> clap::Arg<'a, 'b> {
+    clap::Arg::from_usage("-w, --watch  'Runs again whenever the input or the UA style sheet change'")
+}
+
+/// The writing modes accepted by `--root-writing-mode`.
+const WRITING_MODES: &[&str] = &[
+    "horizontal-tb",
+    "vertical-rl",
+    "vertical-lr",
+    "sideways-rl",
+    "sideways-lr",
+];
+
+/// The viewport a document is laid out in.
+struct Viewport<'a> {
+    /// The size of the viewport, in CSS pixels.
+    size: euclid::default::Size2D<Au>,
+    /// How many device pixels a CSS pixel takes when painting.
+    device_pixel_ratio: f32,
+    /// The writing mode to force on the root element, if any.
+    root_writing_mode: Option<&'a str>,
+}
+
+impl<'a> Viewport<'a> {
+    fn from_args(args: &'a clap::ArgMatches) -> Self {
+        let px = |name| {
+            let value = value_t!(args, name, f32).unwrap_or_else(|e| e.exit());
+            Au::from_f32_px(value)
+        };
+        Self {
+            size: euclid::Size2D::new(px("width"), px("height")),
+            device_pixel_ratio: value_t!(args, "dpr", f32).unwrap_or_else(|e| e.exit()),
+            root_writing_mode: args.value_of("root-writing-mode"),
+        }
+    }
+}
+
+/// Appends `writing-mode` to the inline style of the root element, which
+/// restyles it and propagates the new writing mode to the viewport.
+fn force_root_writing_mode(builder: &mut LayoutTreeBuilder, writing_mode: &str) {
+    let root = match builder.dom().children().find(|child| child.as_element().is_some()) {
+        Some(root) => root,
+        None => return,
+    };
+    let style = {
+        let attributes = root.as_element().unwrap().attributes.borrow();
+        match attributes.get("style") {
+            Some(existing) => format!("{}; writing-mode: {}", existing, writing_mode),
+            None => format!("writing-mode: {}", writing_mode),
+        }
+    };
+    builder
+        .set_attribute(&root, "style", &style)
+        .expect("Couldn't set the root writing mode");
+}
+
+fn viewport_args<'a, 'b>() -> Vec<clap::Arg<'a, 'b>> {
+    use clap::Arg;
+
+    vec![
+        Arg::from_usage("--width [width]  'The width of the viewport, in CSS pixels'").default_value("800"),
+        Arg::from_usage("--height [height]  'The height of the viewport, in CSS pixels'").default_value("600"),
+        Arg::from_usage("--dpr [dpr]  'The device pixel ratio used when painting'").default_value("1"),
+        Arg::from_usage("--root-writing-mode [writing-mode]  'Overrides the writing-mode of the root element'")
+            .possible_values(WRITING_MODES),
+    ]
+}
+
+type Element = kuchiki::NodeDataRef<kuchiki::ElementData>;
+
+fn select(dom: &kuchiki::NodeRef, selector: &str) -> impl Iterator<Item = Element> {
+    match dom.select(selector) {
+        Ok(elements) => elements,
+        Err(()) => {
+            eprintln!("Invalid selector: {}", selector);
+            std::process::exit(1);
+        },
+    }
+}
+
+fn print_start_tag(element: &Element) {
+    print!("<{}", element.name.local);
+    for (name, attr) in element.attributes.borrow().map.iter() {
+        print!(" {}=\"{}\"", name.local, attr.value);
+    }
+    println!(">");
+}
+
+/// Prints the computed style of the elements that match `selector`, or only
+/// the given properties of it, if any.
+fn print_styles(builder: &LayoutTreeBuilder, selector: &str, properties: &[String]) {
+    for element in select(builder.dom(), selector) {
+        print_start_tag(&element);
+        let node = element.as_node();
+        if properties.is_empty() {
+            let style = match builder.computed_style(node) {
+                Some(style) => style,
+                None => continue,
+            };
+            for declaration in style.to_css_block().lines() {
+                println!("  {}", declaration);
+            }
+            continue;
+        }
+        for property in properties {
+            if let Some(value) = builder.computed_style_property(node, property) {
+                println!("  {}: {};", property, value);
+            }
+        }
+    }
+}
+
+fn print_rect(label: &str, rect: Option<euclid::default::Rect<f32>>) {
+    match rect {
+        Some(rect) => println!(
+            "  {}: {}x{} at ({}, {})",
+            label, rect.size.width, rect.size.height, rect.origin.x, rect.origin.y,
+        ),
+        None => println!("  {}: none", label),
+    }
+}
+
+/// Prints the geometry and a summary of the computed style of the elements
+/// that match `selector`.
+fn print_query(result: &LayoutTreeBuilderResult, query: &LayoutResultQuery, selector: &str) {
+    for element in select(&result.dom, selector) {
+        let node = element.as_node();
+        print_start_tag(&element);
+        print_rect("border-box", query.border_box_rect(node));
+        print_rect("content-box", query.content_box_rect(node));
+        let layout_node = result
+            .dom_node_ids
+            .get(node)
+            .and_then(|id| result.principal_boxes.get(&id));
+        match layout_node {
+            Some(id) => {
+                let style = &result.layout_tree[*id].style;
+                println!(
+                    "  style: display: {:?}, position: {:?}, float: {:?}, writing-mode: {:?}",
+                    style.display, style.position, style.float, style.computed_writing_mode,
+                );
+            },
+            None => println!("  style: no box"),
+        }
+    }
+}
+
+/// Parses a `WIDTHxHEIGHT` viewport size, in CSS pixels.
+fn parse_viewport_size(size: &str) -> Option<euclid::default::Size2D<Au>> {
+    let mut parts = size.splitn(2, 'x');
+    let width = parts.next()?.parse::<f32>().ok()?;
+    let height = parts.next()?.parse::<f32>().ok()?;
+    Some(euclid::Size2D::new(Au::from_f32_px(width), Au::from_f32_px(height)))
+}
+
+/// Reads and constructs the layout tree of a document.
+fn build(input: &Input, viewport: Option<&Viewport>) -> LayoutTreeBuilder {
+    let (document, base_directory) = input.read().expect("Couldn't read input");
+
+    let mut builder = LayoutTreeBuilder::new_with_base_directory(&mut &*document, base_directory.as_deref())
+        .expect("Failed to parse input file?");
+    for error in builder.stylesheet_errors() {
+        eprintln!("{}", error);
+    }
+
+    builder.construct().expect("Failed to build the layout tree");
+    if let Some(writing_mode) = viewport.and_then(|v| v.root_writing_mode) {
+        force_root_writing_mode(&mut builder, writing_mode);
+    }
+    builder
+}
+
+#[derive(Copy, Clone)]
+enum OutputFormat {
+    Text,
+    Dot,
+}
+
+impl OutputFormat {
+    fn from_args(args: &clap::ArgMatches) -> Self {
+        match args.value_of("format").unwrap() {
+            "dot" => OutputFormat::Dot,
+            _ => OutputFormat::Text,
+        }
+    }
+}
+
+fn format_arg<'a, 'b>() -> clap::Arg<'a, 'b> {
+    clap::Arg::from_usage("--format [format]  'The output format: an ASCII tree or a Graphviz graph'")
+        .possible_values(&["text", "dot"])
+        .default_value("text")
+}
 
 enum DumpKind {
-    Layout,
-    LayoutTree,
+    Layout(OutputFormat),
+    LayoutTree(OutputFormat),
+    InlineItems,
     Dom,
+    Memory,
+    Render { output: String },
+    Query { selector: String },
+    Styles { selector: String, properties: Vec<String> },
+    Diff { other: Option<String>, other_viewport_size: Option<euclid::default::Size2D<Au>> },
+    Bench { iterations: usize },
+    Repl,
+    Trace { output: Option<String> },
+    Wpt { root: Option<String>, filter: Option<String>, verbose: bool },
+}
+
+impl DumpKind {
+    /// The second document of a diff, if any.
+    fn other_input(&self) -> Option<Input<'_>> {
+        match *self {
+            DumpKind::Diff { other: Some(ref other), .. } => Some(Input::from_path(other)),
+            _ => None,
+        }
+    }
 }
 
 fn main() {
@@ -21,47 +307,612 @@ fn main() {
         .subcommand(
             SubCommand::with_name("layout")
                 .about("Dumps a fragment tree from an HTML document")
-                .arg_from_usage("<input>  'The document to build the tree for'"),
+                .args(&input_args())
+                .arg(format_arg())
+                .args(&viewport_args()),
         )
         .subcommand(
             SubCommand::with_name("layout-tree")
                 .about("Dumps a layout tree from an HTML document")
-                .arg_from_usage("<input>  'The document to build the tree for'"),
+                .args(&input_args())
+                .arg(format_arg())
+                .args(&viewport_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("inline-items")
+                .about("Dumps the inline items of each inline formatting context of an HTML document")
+                .args(&input_args())
+                .args(&viewport_args()),
         )
         .subcommand(
             SubCommand::with_name("dom")
                 .about("Dumps a DOM tree from an HTML document")
-                .arg_from_usage("<input>  'The document to build the tree for'"),
+                .args(&input_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("memory")
+                .about("Reports the memory used by the layout and fragment trees of an HTML document")
+                .args(&input_args())
+                .args(&viewport_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("render")
+                .about("Lays out and paints an HTML document to a PNG image")
+                .args(&input_args())
+                .arg_from_usage("-o, --output <output>  'The PNG file to write'")
+                .args(&viewport_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("query")
+                .about("Prints the geometry of the elements of an HTML document that match a selector")
+                .setting(AppSettings::AllowMissingPositional)
+                .args(&input_args())
+                .arg_from_usage("<selector>  'The selector to match elements against'")
+                .args(&viewport_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("styles")
+                .about("Dumps the computed style of the elements of an HTML document")
+                .args(&input_args())
+                .arg(
+                    clap::Arg::from_usage("-s, --selector [selector]  'Only dump the elements that match this selector'")
+                        .default_value("*"),
+                )
+                .arg(
+                    clap::Arg::from_usage("-p, --property [property]...  'Only dump this property, can be repeated'")
+                        .number_of_values(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Prints the differences between the fragment trees of two documents, or two viewports")
+                .arg_from_usage("<input>  'The first document, or - to read it from stdin'")
+                .arg_from_usage("[other]  'The second document, which defaults to the first one'")
+                .arg_from_usage("--viewport2 [size]  'The viewport size to lay out the second document with, as WIDTHxHEIGHT'")
+                .arg(watch_arg())
+                .args(&viewport_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("Times building and laying out an HTML document")
+                .args(&input_args())
+                .arg(
+                    clap::Arg::from_usage("-n, --iterations [iterations]  'How many times to build and lay out the document'")
+                        .default_value("10"),
+                )
+                .args(&viewport_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("repl")
+                .about("Mutates the DOM of an HTML document interactively, updating its layout tree")
+                .args(&input_args())
+                .args(&viewport_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("trace")
+                .about("Records the layout decisions made for an HTML document as JSON lines")
+                .args(&input_args())
+                .arg_from_usage("-o, --output [output]  'The file to write the trace to, instead of stdout'")
+                .args(&viewport_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("wpt")
+                .about("Runs the reftests and check-layout tests of a web-platform-tests directory")
+                .arg_from_usage("<input>  'The directory to look for tests in'")
+                .arg_from_usage("--root [root]  'The root of the checkout, which defaults to the directory'")
+                .arg_from_usage("-f, --filter [filter]  'Only run the tests whose path contains this'")
+                .arg_from_usage("-v, --verbose  'Reports passing and skipped tests too'"),
         )
         .setting(AppSettings::ArgRequiredElseHelp)
         .get_matches();
-    let (input, kind) = {
+    let watch = args.subcommand().1.is_some_and(|args| args.is_present("watch"));
+    let (input, kind, viewport) = {
         if let Some(args) = args.subcommand_matches("layout") {
-            let input = args.value_of("input").unwrap();
-            (input, DumpKind::Layout)
+            let input = Input::from_args(args);
+            let format = OutputFormat::from_args(args);
+            (input, DumpKind::Layout(format), Some(Viewport::from_args(args)))
         } else if let Some(args) = args.subcommand_matches("layout-tree") {
-            let input = args.value_of("input").unwrap();
-            (input, DumpKind::LayoutTree)
+            let input = Input::from_args(args);
+            let format = OutputFormat::from_args(args);
+            (input, DumpKind::LayoutTree(format), Some(Viewport::from_args(args)))
+        } else if let Some(args) = args.subcommand_matches("inline-items") {
+            let input = Input::from_args(args);
+            (input, DumpKind::InlineItems, Some(Viewport::from_args(args)))
         } else if let Some(args) = args.subcommand_matches("dom") {
-            let input = args.value_of("input").unwrap();
-            (input, DumpKind::Dom)
+            let input = Input::from_args(args);
+            (input, DumpKind::Dom, None)
+        } else if let Some(args) = args.subcommand_matches("memory") {
+            let input = Input::from_args(args);
+            (input, DumpKind::Memory, Some(Viewport::from_args(args)))
+        } else if let Some(args) = args.subcommand_matches("render") {
+            let input = Input::from_args(args);
+            let output = args.value_of("output").unwrap().to_owned();
+            (input, DumpKind::Render { output }, Some(Viewport::from_args(args)))
+        } else if let Some(args) = args.subcommand_matches("query") {
+            let input = Input::from_args(args);
+            let selector = args.value_of("selector").unwrap().to_owned();
+            (input, DumpKind::Query { selector }, Some(Viewport::from_args(args)))
+        } else if let Some(args) = args.subcommand_matches("styles") {
+            let input = Input::from_args(args);
+            let selector = args.value_of("selector").unwrap().to_owned();
+            let properties = args
+                .values_of("property")
+                .map_or(vec![], |properties| properties.map(|p| p.to_owned()).collect());
+            for property in &properties {
+                if css::PropertyMetadata::find(property).is_none() {
+                    eprintln!("Unknown property: {}", property);
+                    std::process::exit(1);
+                }
+            }
+            (input, DumpKind::Styles { selector, properties }, None)
+        } else if let Some(args) = args.subcommand_matches("diff") {
+            let input = Input::from_path(args.value_of("input").unwrap());
+            let other = args.value_of("other").map(|o| o.to_owned());
+            let other_viewport_size = args.value_of("viewport2").map(|size| {
+                parse_viewport_size(size).unwrap_or_else(|| {
+                    eprintln!("Invalid viewport size: {}", size);
+                    std::process::exit(1);
+                })
+            });
+            let kind = DumpKind::Diff { other, other_viewport_size };
+            (input, kind, Some(Viewport::from_args(args)))
+        } else if let Some(args) = args.subcommand_matches("bench") {
+            let input = Input::from_args(args);
+            let iterations = value_t!(args, "iterations", usize).unwrap_or_else(|e| e.exit());
+            if iterations == 0 {
+                eprintln!("Need at least one iteration");
+                std::process::exit(1);
+            }
+            (input, DumpKind::Bench { iterations }, Some(Viewport::from_args(args)))
+        } else if let Some(args) = args.subcommand_matches("trace") {
+            let input = Input::from_args(args);
+            let output = args.value_of("output").map(|o| o.to_owned());
+            (input, DumpKind::Trace { output }, Some(Viewport::from_args(args)))
+        } else if let Some(args) = args.subcommand_matches("wpt") {
+            let input = Input::File(args.value_of("input").unwrap());
+            let kind = DumpKind::Wpt {
+                root: args.value_of("root").map(|r| r.to_owned()),
+                filter: args.value_of("filter").map(|f| f.to_owned()),
+                verbose: args.is_present("verbose"),
+            };
+            (input, kind, None)
+        } else if let Some(args) = args.subcommand_matches("repl") {
+            let input = Input::from_args(args);
+            if let Input::Stdin = input {
+                eprintln!("The REPL reads commands from stdin, so the document can't come from it");
+                std::process::exit(1);
+            }
+            (input, DumpKind::Repl, Some(Viewport::from_args(args)))
         } else {
             panic!("Unknown subcommand, {:?}", args);
         }
     };
 
-    let mut file = File::open(input).expect("Couldn't open input file");
+    if !watch {
+        return run(&input, &kind, viewport.as_ref());
+    }
+
+    let mut paths = vec![css::ua_stylesheet_path()];
+    for input in std::iter::once(&input).chain(kind.other_input().as_ref()) {
+        match input.path() {
+            Some(path) => paths.push(path.to_owned()),
+            None => {
+                eprintln!("--watch needs the input to be a file");
+                std::process::exit(1);
+            },
+        }
+    }
+    watch_files(&paths, || run(&input, &kind, viewport.as_ref()));
+}
+
+/// Calls `run` once, and then again every time one of the files in `paths`
+/// changes.
+fn watch_files(paths: &[PathBuf], run: impl Fn()) {
+    use notify::{DebouncedEvent, RecursiveMode, Watcher};
+    use std::sync::mpsc;
+
+    // Editors often replace files instead of writing to them, so watch the
+    // directories that contain them.
+    let paths = paths
+        .iter()
+        .map(|path| path.canonicalize().expect("Couldn't find file to watch"))
+        .collect::<Vec<_>>();
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::watcher(sender, Duration::from_millis(100)).expect("Couldn't create watcher");
+    for path in &paths {
+        watcher
+            .watch(path.parent().unwrap(), RecursiveMode::NonRecursive)
+            .expect("Couldn't watch file");
+    }
+
+    // Layout still panics in a bunch of cases, which shouldn't stop watching.
+    let run_catching_panics = || {
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(&run));
+    };
+    run_catching_panics();
+    for event in receiver {
+        let changed = match event {
+            DebouncedEvent::Create(path) | DebouncedEvent::Write(path) | DebouncedEvent::Rename(_, path) => path,
+            _ => continue,
+        };
+        if !paths.contains(&changed) {
+            continue;
+        }
+        eprintln!("--- {} changed", changed.display());
+        run_catching_panics();
+    }
+}
+
+/// Returns the sample at percentile `p`, in the range [0, 1], of a sorted
+/// list of samples.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+/// Builds and lays out a document `iterations` times, and prints statistics
+/// about how long each phase took.
+fn bench(input: &Input, iterations: usize, viewport: &Viewport) {
+    let (document, base_directory) = input.read().expect("Couldn't read input");
+
+    let mut phases = [
+        ("DOM parsing", vec![]),
+        ("CSS parsing", vec![]),
+        ("Style computation", vec![]),
+        ("Box construction", vec![]),
+        ("Layout", vec![]),
+        ("Total", vec![]),
+    ];
+    for _ in 0..iterations {
+        let mut builder = LayoutTreeBuilder::new_with_base_directory(&mut &*document, base_directory.as_deref())
+            .expect("Failed to parse input file?");
+        builder.construct().expect("Failed to build the layout tree");
+        // Forcing the writing mode restyles the root, which is not timed.
+        if let Some(writing_mode) = viewport.root_writing_mode {
+            force_root_writing_mode(&mut builder, writing_mode);
+        }
+        let timings = *builder.timings();
+        let result = builder.finish();
+        let quirks_mode = result.dom.as_document().unwrap().quirks_mode();
+
+        let start = Instant::now();
+        result.layout_tree.layout(quirks_mode, viewport.size);
+        let layout = start.elapsed();
+
+        let samples = [
+            timings.dom_parsing,
+            timings.css_parsing,
+            timings.style_computation,
+            timings.box_construction,
+            layout,
+        ];
+        let total: Duration = samples.iter().sum();
+        for (phase, sample) in phases.iter_mut().zip(samples.iter().chain(std::iter::once(&total))) {
+            phase.1.push(*sample);
+        }
+    }
+
+    let ms = |duration: Duration| format!("{:.3}ms", duration.as_secs_f64() * 1000.);
+    println!("{} iterations", iterations);
+    println!("{:<20}{:>12}{:>12}{:>12}{:>12}{:>12}", "", "min", "p50", "p90", "p99", "max");
+    for (name, samples) in phases.iter_mut() {
+        samples.sort();
+        println!(
+            "{:<20}{:>12}{:>12}{:>12}{:>12}{:>12}",
+            name,
+            ms(samples[0]),
+            ms(percentile(samples, 0.5)),
+            ms(percentile(samples, 0.9)),
+            ms(percentile(samples, 0.99)),
+            ms(samples[samples.len() - 1]),
+        );
+    }
+}
+
+const REPL_HELP: &str = "\
+Commands, where <selector> can't contain spaces and applies to its first match:
+  remove <selector>         Removes the element from the DOM
+  append <selector> <html>  Appends the parsed HTML to the children of the element
+  set-style <selector> <css>  Sets the style attribute of the element
+  relayout                  Lays out the document and prints the fragment tree
+  print                     Prints the layout tree
+  dom                       Prints the DOM tree
+  help                      Prints this message
+  quit                      Exits";
 
-    let builder = LayoutTreeBuilder::new(&mut file).expect("Failed to parse input file?");
+/// Runs a single REPL command.
+fn repl_command(builder: &mut LayoutTreeBuilder, viewport: &Viewport, line: &str) -> Result<(), String> {
+    let mut parts = line.trim().splitn(3, char::is_whitespace);
+    let command = parts.next().unwrap_or("");
+    let mut element = || -> Result<kuchiki::NodeRef, String> {
+        let selector = parts.next().ok_or("Missing selector")?;
+        let element = builder
+            .dom()
+            .select_first(selector)
+            .map_err(|()| format!("No element matches {}", selector))?;
+        Ok(element.as_node().clone())
+    };
+    match command {
+        "" => {},
+        "remove" => {
+            let element = element()?;
+            builder.remove_child(&element).map_err(|e| e.to_string())?;
+        },
+        "append" => {
+            let parent = element()?;
+            let html = parts.next().ok_or("Missing HTML")?;
+            let source = nglayoutng::dom::build_dom(&mut html.as_bytes()).map_err(|e| e.to_string())?;
+            let body = source.select_first("body").unwrap();
+            let children = body.as_node().children().collect::<Vec<_>>();
+            for child in children {
+                builder.append_child(&parent, child).map_err(|e| e.to_string())?;
+            }
+        },
+        "set-style" => {
+            let element = element()?;
+            let css = parts.next().unwrap_or("");
+            builder.set_attribute(&element, "style", css).map_err(|e| e.to_string())?;
+        },
+        "relayout" => {
+            let quirks_mode = builder.dom().as_document().unwrap().quirks_mode();
+            builder.layout_tree().layout(quirks_mode, viewport.size).print();
+        },
+        "print" => builder.layout_tree().print(),
+        "dom" => print_dom(builder.dom()),
+        "help" => println!("{}", REPL_HELP),
+        _ => return Err(format!("Unknown command {:?}, try help", command)),
+    }
+    Ok(())
+}
+
+/// Reads commands that mutate the DOM from stdin, updating the layout tree
+/// incrementally after each one.
+fn repl(mut builder: LayoutTreeBuilder, viewport: &Viewport) {
+    use std::io::{BufRead, Write};
 
-    let result = builder.build();
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("> ");
+        io::stdout().flush().unwrap();
+        let line = match lines.next() {
+            Some(line) => line.expect("Couldn't read command"),
+            None => break,
+        };
+        if line.trim() == "quit" {
+            break;
+        }
+        match repl_command(&mut builder, viewport, &line) {
+            Ok(()) => builder.layout_tree().assert_consistent(),
+            Err(error) => eprintln!("{}", error),
+        }
+    }
+}
+
+/// The viewport size web-platform-tests assume.
+const WPT_VIEWPORT_SIZE: (f32, f32) = (800.0, 600.0);
+
+/// The outcome of a web-platform-test.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WptOutcome {
+    Pass,
+    Fail,
+    Crash,
+    /// Not a reftest nor a check-layout test, like testharness.js tests,
+    /// which need script.
+    Skip,
+}
+
+/// Returns the test files under `directory`, sorted, skipping references and
+/// support files.
+fn wpt_test_files(directory: &Path, tests: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        if path.is_dir() {
+            if !["reference", "support", "resources", "tools"].contains(&name) {
+                wpt_test_files(&path, tests)?;
+            }
+            continue;
+        }
+        let is_html = matches!(path.extension().and_then(|e| e.to_str()), Some("html" | "htm" | "xht"));
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        if is_html && !stem.ends_with("-ref") && !stem.ends_with("-notref") {
+            tests.push(path);
+        }
+    }
+    tests.sort();
+    Ok(())
+}
+
+/// Builds and lays out a test or reference file.
+fn wpt_layout(path: &Path) -> Result<(LayoutTreeBuilderResult, FragmentTree), String> {
+    let mut file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let result = LayoutTreeBuilder::new_with_base_directory(&mut file, path.parent())
+        .and_then(|builder| builder.build())
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let quirks_mode = result.dom.as_document().unwrap().quirks_mode();
+    let (width, height) = WPT_VIEWPORT_SIZE;
+    let viewport_size = euclid::Size2D::new(Au::from_f32_px(width), Au::from_f32_px(height));
+    let fragment_tree = result.layout_tree.layout(quirks_mode, viewport_size);
+    Ok((result, fragment_tree))
+}
+
+/// Resolves the url of a reference, relative to the test, or to the root of
+/// the checkout if it starts with a slash.
+fn wpt_reference_path(root: &Path, test: &Path, url: &str) -> PathBuf {
+    let url = url.split(['?', '#']).next().unwrap();
+    if let Some(url) = url.strip_prefix('/') {
+        root.join(url)
+    } else {
+        test.parent().unwrap().join(url)
+    }
+}
+
+/// Runs a single test, returning its outcome along with why it failed.
+fn run_wpt_test(root: &Path, path: &Path) -> (WptOutcome, Option<String>) {
+    use nglayoutng::wpt::{self, ReferenceKind};
+
+    let (result, fragment_tree) = match wpt_layout(path) {
+        Ok(laid_out) => laid_out,
+        Err(error) => return (WptOutcome::Fail, Some(error)),
+    };
+
+    let references = wpt::references(&result.dom);
+    if !references.is_empty() {
+        let fuzz = match wpt::Fuzz::from_document(&result.dom) {
+            Ok(fuzz) => fuzz,
+            Err(error) => return (WptOutcome::Fail, Some(error)),
+        };
+        let test = nglayoutng::paint::paint(&DisplayList::build(&fragment_tree), 1.0);
+        for (kind, url) in references {
+            let reference_path = wpt_reference_path(root, path, &url);
+            let reference = match wpt_layout(&reference_path) {
+                Ok((_, tree)) => nglayoutng::paint::paint(&DisplayList::build(&tree), 1.0),
+                Err(error) => return (WptOutcome::Fail, Some(error)),
+            };
+            let matches = wpt::compare_images(&test, &reference).is_some_and(|d| fuzz.allows(&d));
+            if matches != (kind == ReferenceKind::Match) {
+                let reason = format!("{:?} of {} failed", kind, reference_path.display());
+                return (WptOutcome::Fail, Some(reason));
+            }
+        }
+        return (WptOutcome::Pass, None);
+    }
+
+    if wpt::has_check_layout_annotations(&result.dom) {
+        let failures = wpt::check_layout(&result, Some(&fragment_tree));
+        if failures.is_empty() {
+            return (WptOutcome::Pass, None);
+        }
+        return (WptOutcome::Fail, Some(failures.join("\n  ")));
+    }
+
+    (WptOutcome::Skip, None)
+}
+
+/// Runs the reftests and check-layout tests of a web-platform-tests checkout,
+/// or a subdirectory of it, and reports how many pass.
+///
+/// Panics are reported as crashes, and don't stop the run.
+fn run_wpt(directory: &Path, root: Option<&str>, filter: Option<&str>, verbose: bool) {
+    let mut tests = vec![];
+    wpt_test_files(directory, &mut tests).expect("Couldn't list tests");
+    let root = root.map_or(directory, Path::new);
+
+    // Crashes are reported with the rest of the results instead.
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+
+    let (mut passed, mut failed, mut crashed, mut skipped) = (0, 0, 0, 0);
+    for path in &tests {
+        let name = path.strip_prefix(root).unwrap_or(path).display().to_string();
+        if filter.is_some_and(|filter| !name.contains(filter)) {
+            continue;
+        }
+        let (outcome, reason) = std::panic::catch_unwind(|| run_wpt_test(root, path)).unwrap_or_else(|payload| {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned());
+            (WptOutcome::Crash, message)
+        });
+        match outcome {
+            WptOutcome::Pass => passed += 1,
+            WptOutcome::Fail => failed += 1,
+            WptOutcome::Crash => crashed += 1,
+            WptOutcome::Skip => skipped += 1,
+        }
+        if verbose || outcome == WptOutcome::Fail || outcome == WptOutcome::Crash {
+            println!("{:?}: {}", outcome, name);
+            if let Some(reason) = reason {
+                println!("  {}", reason);
+            }
+        }
+    }
+
+    std::panic::set_hook(default_hook);
+    let total = passed + failed + crashed;
+    println!(
+        "Passed {} of {} tests ({} failed, {} crashed), skipped {}",
+        passed, total, failed, crashed, skipped,
+    );
+}
+
+fn run(input: &Input, kind: &DumpKind, viewport: Option<&Viewport>) {
+    if let DumpKind::Bench { iterations } = *kind {
+        return bench(input, iterations, viewport.unwrap());
+    }
+    if let DumpKind::Wpt { ref root, ref filter, verbose } = *kind {
+        let directory = input.path().unwrap();
+        return run_wpt(directory, root.as_ref().map(|r| &**r), filter.as_ref().map(|f| &**f), verbose);
+    }
+    let builder = build(input, viewport);
+    match *kind {
+        DumpKind::Styles { ref selector, ref properties } => return print_styles(&builder, selector, properties),
+        DumpKind::Repl => return repl(builder, viewport.unwrap()),
+        _ => {},
+    }
+    let result = builder.finish();
     result.layout_tree.assert_consistent();
-    match kind {
-        DumpKind::Layout => {
-            let result = result.layout_tree.layout(result.dom.as_document().unwrap().quirks_mode(), euclid::Size2D::new(Au::from_f32_px(800.0), Au::from_f32_px(600.0)));
-            println!("{:#?}", result.fragment);
+    let quirks_mode = result.dom.as_document().unwrap().quirks_mode();
+    let layout = || result.layout_tree.layout(quirks_mode, viewport.unwrap().size);
+    match *kind {
+        DumpKind::Layout(OutputFormat::Text) => layout().print(),
+        DumpKind::Layout(OutputFormat::Dot) => layout().print_dot_to(&mut io::stdout()),
+        DumpKind::Render { ref output } => {
+            let fragment_tree = layout();
+            let display_list = DisplayList::build(&fragment_tree);
+            let device_pixel_ratio = viewport.unwrap().device_pixel_ratio;
+            nglayoutng::paint::paint_to_png(&display_list, device_pixel_ratio, Path::new(output))
+                .expect("Couldn't write output image");
+        },
+        DumpKind::Memory => {
+            let fragment_tree = layout();
+            nglayoutng::memory::memory_report(&result.layout_tree, Some(&fragment_tree)).print();
+        },
+        DumpKind::Query { ref selector } => {
+            let fragment_tree = layout();
+            let query = LayoutResultQuery::new(&result.dom_node_ids, &result.principal_boxes, &fragment_tree);
+            print_query(&result, &query, selector);
+        },
+        DumpKind::Diff { ref other, other_viewport_size } => {
+            let viewport = viewport.unwrap();
+            let fragment_tree = layout();
+            let other_result;
+            let (other_layout_tree, other_quirks_mode) = match *other {
+                Some(ref other) => {
+                    other_result = build(&Input::from_path(other), Some(viewport)).finish();
+                    let quirks_mode = other_result.dom.as_document().unwrap().quirks_mode();
+                    (&other_result.layout_tree, quirks_mode)
+                },
+                None => (&result.layout_tree, quirks_mode),
+            };
+            let other_viewport_size = other_viewport_size.unwrap_or(viewport.size);
+            let other_fragment_tree = other_layout_tree.layout(other_quirks_mode, other_viewport_size);
+            let diffs = nglayoutng::fragment_tree::diff::diff(&fragment_tree, &other_fragment_tree);
+            if diffs.is_empty() {
+                println!("No differences");
+            }
+            for diff in diffs {
+                println!("{}", diff);
+            }
+        },
+        DumpKind::LayoutTree(OutputFormat::Text) => result.layout_tree.print(),
+        DumpKind::LayoutTree(OutputFormat::Dot) => result.layout_tree.print_dot_to(&mut io::stdout()),
+        DumpKind::Trace { ref output } => {
+            let (_, events) = nglayoutng::trace::collect(layout);
+            let written = match *output {
+                Some(ref output) => std::fs::File::create(output)
+                    .and_then(|mut file| nglayoutng::trace::write_json_lines(&events, &mut file)),
+                None => nglayoutng::trace::write_json_lines(&events, &mut io::stdout()),
+            };
+            written.expect("Couldn't write trace");
+        },
+        DumpKind::InlineItems => {
+            let viewport_size = viewport.unwrap().size;
+            result.layout_tree.print_inline_items_to(quirks_mode, viewport_size, &mut io::stdout());
         },
-        DumpKind::LayoutTree => result.layout_tree.print(),
         DumpKind::Dom => print_dom(&result.dom),
+        DumpKind::Styles { .. } | DumpKind::Bench { .. } | DumpKind::Repl | DumpKind::Wpt { .. } => unreachable!(),
     }
 }
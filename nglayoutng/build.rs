@@ -1,19 +1,47 @@
+use std::collections::HashMap;
 use std::env;
 use std::fs::{self, File};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 
+/// Reads the expected outcome of each test from `tests/expectations.txt`,
+/// returning the `Expectation` variant for each file.
+fn read_expectations(path: &Path) -> HashMap<String, &'static str> {
+    let mut expectations = HashMap::new();
+    for (i, line) in fs::read_to_string(path).unwrap().lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.split_whitespace();
+        let file = parts.next().unwrap();
+        let expectation = match parts.next() {
+            Some("pass") => "Pass",
+            Some("fail") => "Fail",
+            Some("panic") => "Panic",
+            other => panic!("{:?}:{}: Invalid expectation {:?}", path, i + 1, other),
+        };
+        assert!(parts.next().is_none(), "{:?}:{}: Trailing garbage", path, i + 1);
+        let previous = expectations.insert(file.to_owned(), expectation);
+        assert!(previous.is_none(), "{:?}:{}: Duplicate expectation for {}", path, i + 1, file);
+    }
+    expectations
+}
+
 fn generate_tests() {
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
     let mut dst = File::create(Path::new(&out_dir).join("tests.rs")).unwrap();
 
     let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
     let html_dir = manifest_dir.join("tests").join("html");
-    let html = fs::read_dir(html_dir).unwrap();
+    let html = fs::read_dir(&html_dir).unwrap();
 
     let expectations_path = manifest_dir.join("tests").join("expectations");
+    let manifest_path = manifest_dir.join("tests").join("expectations.txt");
+    let mut expectations = read_expectations(&manifest_path);
 
     println!("cargo:rerun-if-changed=tests/html");
+    println!("cargo:rerun-if-changed=tests/expectations.txt");
 
     for entry in html {
         let entry = entry.unwrap();
@@ -25,17 +53,56 @@ fn generate_tests() {
             .unwrap()
             .replace(|c: char| !c.is_alphanumeric(), "_")
             .to_lowercase();
+        let expectation = expectations
+            .remove(entry.file_name().to_str().unwrap())
+            .unwrap_or("Pass");
         writeln!(
             dst,
-            "test_doc!(header_{}, {:?}, {:?});",
+            "test_doc!(header_{}, {:?}, {:?}, Expectation::{});",
             func,
             entry.path(),
             expectations_path,
+            expectation,
         )
         .unwrap();
     }
+
+    assert!(
+        expectations.is_empty(),
+        "Expectations for tests that don't exist: {:?}",
+        expectations.keys().collect::<Vec<_>>(),
+    );
+}
+
+/// Generates a reftest for each `foo.html` in `tests/reftests`, which must
+/// render like `foo-ref.html`.
+fn generate_reftests() {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let mut dst = File::create(Path::new(&out_dir).join("reftests.rs")).unwrap();
+
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let reftests_dir = manifest_dir.join("tests").join("reftests");
+
+    println!("cargo:rerun-if-changed=tests/reftests");
+
+    for entry in fs::read_dir(&reftests_dir).unwrap() {
+        let path = entry.unwrap().path();
+        assert_eq!(path.extension().unwrap().to_str().unwrap(), "html");
+        let stem = path.file_stem().unwrap().to_str().unwrap();
+        if stem.ends_with("-ref") {
+            continue;
+        }
+        let reference = reftests_dir.join(format!("{}-ref.html", stem));
+        assert!(reference.exists(), "Reftest without a reference: {:?}", path);
+
+        let func = stem
+            .replace(|c: char| !c.is_alphanumeric(), "_")
+            .to_lowercase();
+        writeln!(dst, "reftest!(reftest_{}, {:?}, {:?});", func, path, reference).unwrap();
+    }
 }
 
 fn main() {
     generate_tests();
+    generate_reftests();
 }
@@ -1,6 +1,45 @@
+use std::fmt;
+
+/// An index into an `Allocator`.
+///
+/// Ids remember the generation of the slot they were allocated in, and slots
+/// bump their generation when freed, so an id that outlives its item doesn't
+/// silently alias whatever gets allocated in the same slot later.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Id {
+    index: usize,
+    generation: u32,
+}
+
+impl Id {
+    pub fn index(self) -> usize {
+        self.index
+    }
+
+    pub fn generation(self) -> u32 {
+        self.generation
+    }
+}
+
+impl fmt::Debug for Id {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.generation == 0 {
+            write!(f, "{}", self.index)
+        } else {
+            write!(f, "{}@{}", self.index, self.generation)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Slot<T> {
+    generation: u32,
+    item: Option<T>,
+}
+
 #[derive(Debug)]
 pub struct Allocator<T> {
-    nodes: Vec<Option<T>>,
+    nodes: Vec<Slot<T>>,
     free_list: Vec<usize>,
     len: usize,
 }
@@ -16,32 +55,84 @@ impl<T> Default for Allocator<T> {
 }
 
 impl<T> Allocator<T> {
-    pub fn allocate(&mut self, item: T) -> usize {
+    pub fn allocate(&mut self, item: T) -> Id {
         self.len += 1;
 
         if let Some(free) = self.free_list.pop() {
-            assert!(self.nodes[free].is_none());
-            self.nodes[free] = Some(item);
-            return free;
+            let slot = &mut self.nodes[free];
+            assert!(slot.item.is_none());
+            slot.item = Some(item);
+            return Id {
+                index: free,
+                generation: slot.generation,
+            };
         }
 
-        self.nodes.push(Some(item));
-        self.nodes.len() - 1
+        self.nodes.push(Slot {
+            generation: 0,
+            item: Some(item),
+        });
+        Id {
+            index: self.nodes.len() - 1,
+            generation: 0,
+        }
     }
 
-    pub fn deallocate(&mut self, id: usize) -> T {
-        let node = self.nodes[id].take().expect("Deallocating free item");
-        self.free_list.push(id);
+    pub fn deallocate(&mut self, id: Id) -> T {
+        assert!(self.contains(id), "Deallocating free item {:?}", id);
+        let slot = &mut self.nodes[id.index];
+        slot.generation = slot.generation.wrapping_add(1);
+        let node = slot.item.take().unwrap();
+        self.free_list.push(id.index);
         self.len -= 1;
         node
     }
 
-    pub fn get(&self, id: usize) -> Option<&T> {
-        self.nodes[id].as_ref()
+    /// Whether `id` points to a live item, that is, whether it hasn't been
+    /// deallocated.
+    pub fn contains(&self, id: Id) -> bool {
+        self.nodes
+            .get(id.index)
+            .is_some_and(|slot| slot.generation == id.generation && slot.item.is_some())
     }
 
-    pub fn get_mut(&mut self, id: usize) -> Option<&mut T> {
-        self.nodes[id].as_mut()
+    pub fn get(&self, id: Id) -> Option<&T> {
+        let slot = self.nodes.get(id.index)?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        slot.item.as_ref()
+    }
+
+    pub fn get_mut(&mut self, id: Id) -> Option<&mut T> {
+        let slot = self.nodes.get_mut(id.index)?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        slot.item.as_mut()
+    }
+
+    /// Iterates over the live items, in allocation slot order.
+    pub fn iter(&self) -> impl Iterator<Item = (Id, &T)> {
+        self.nodes.iter().enumerate().filter_map(|(index, slot)| {
+            let item = slot.item.as_ref()?;
+            Some((
+                Id {
+                    index,
+                    generation: slot.generation,
+                },
+                item,
+            ))
+        })
+    }
+
+    /// Like `iter`, but with mutable access to the items.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Id, &mut T)> {
+        self.nodes.iter_mut().enumerate().filter_map(|(index, slot)| {
+            let generation = slot.generation;
+            let item = slot.item.as_mut()?;
+            Some((Id { index, generation }, item))
+        })
     }
 
     pub fn len(&self) -> usize {
@@ -53,16 +144,22 @@ impl<T> Allocator<T> {
     }
 }
 
-impl<T> ::std::ops::Index<usize> for Allocator<T> {
+impl<T> ::std::ops::Index<Id> for Allocator<T> {
     type Output = T;
 
-    fn index(&self, index: usize) -> &T {
-        self.get(index).expect("Use of node after free")
+    fn index(&self, id: Id) -> &T {
+        match self.get(id) {
+            Some(item) => item,
+            None => panic!("Use of node after free: {:?}", id),
+        }
     }
 }
 
-impl<T> ::std::ops::IndexMut<usize> for Allocator<T> {
-    fn index_mut(&mut self, index: usize) -> &mut T {
-        self.get_mut(index).expect("Use of node after free")
+impl<T> ::std::ops::IndexMut<Id> for Allocator<T> {
+    fn index_mut(&mut self, id: Id) -> &mut T {
+        match self.get_mut(id) {
+            Some(item) => item,
+            None => panic!("Use of node after free: {:?}", id),
+        }
     }
 }
@@ -0,0 +1,116 @@
+//! Interpolation of computed values, for transitions and animations.
+//!
+//! https://drafts.csswg.org/css-values-4/#interpolation
+//!
+//! Most types derive `Animate`. The ones here are the leaves, which actually
+//! interpolate numbers.
+
+use crate::style::{LengthPercentage, Percentage};
+use app_units::Au;
+use cssparser::{Color, RGBA};
+
+/// A value that can be interpolated with another value of the same type.
+pub trait Animate: Sized {
+    /// Returns the value at `progress` between `self`, at 0, and `other`, at
+    /// 1. `progress` can be outside of that range, to extrapolate.
+    ///
+    /// Returns `Err` if both values can't be interpolated, like `auto` and a
+    /// length, in which case the value flips discretely instead.
+    #[allow(clippy::result_unit_err)]
+    fn animate(&self, other: &Self, progress: f32) -> Result<Self, ()>;
+}
+
+/// Returns the value at `progress` between `from` and `to`, flipping from one
+/// to the other at the midpoint if they can't be interpolated.
+pub fn interpolate<T: Animate + Clone>(from: &T, to: &T, progress: f32) -> T {
+    from.animate(to, progress)
+        .unwrap_or_else(|()| discrete(from, to, progress))
+}
+
+/// Returns `from` before the midpoint, and `to` after it.
+///
+/// https://drafts.csswg.org/css-values-4/#discrete
+pub fn discrete<T: Clone>(from: &T, to: &T, progress: f32) -> T {
+    if progress < 0.5 {
+        from.clone()
+    } else {
+        to.clone()
+    }
+}
+
+impl Animate for f32 {
+    fn animate(&self, other: &Self, progress: f32) -> Result<Self, ()> {
+        Ok(self + (other - self) * progress)
+    }
+}
+
+impl Animate for Au {
+    fn animate(&self, other: &Self, progress: f32) -> Result<Self, ()> {
+        Ok(Au((self.0 as f32).animate(&(other.0 as f32), progress)?.round() as i32))
+    }
+}
+
+impl Animate for Percentage {
+    fn animate(&self, other: &Self, progress: f32) -> Result<Self, ()> {
+        Ok(Percentage(self.0.animate(&other.0, progress)?))
+    }
+}
+
+impl Animate for LengthPercentage {
+    /// A missing percentage interpolates as `0%`, so that lengths and
+    /// percentages can be interpolated through `calc()`. If that ends up
+    /// being `0%`, we go back to a plain length, like at the endpoints.
+    fn animate(&self, other: &Self, progress: f32) -> Result<Self, ()> {
+        let percentage = match (self.percentage, other.percentage) {
+            (None, None) => None,
+            (from, to) => {
+                let zero = Percentage(0.);
+                let percentage = from.unwrap_or(zero).animate(&to.unwrap_or(zero), progress)?;
+                if percentage.0 == 0. && (from.is_none() || to.is_none()) {
+                    None
+                } else {
+                    Some(percentage)
+                }
+            },
+        };
+        Ok(LengthPercentage {
+            fixed: self.fixed.animate(&other.fixed, progress)?,
+            percentage,
+        })
+    }
+}
+
+impl Animate for RGBA {
+    /// Colors are interpolated with premultiplied alpha, so that transparent
+    /// colors don't tint the result.
+    ///
+    /// https://drafts.csswg.org/css-color-4/#interpolation-alpha
+    fn animate(&self, other: &Self, progress: f32) -> Result<Self, ()> {
+        let alpha = self.alpha_f32().animate(&other.alpha_f32(), progress)?.clamp(0., 1.);
+        if alpha == 0. {
+            return Ok(RGBA::transparent());
+        }
+        let channel = |from: f32, to: f32| -> Result<f32, ()> {
+            let premultiplied = (from * self.alpha_f32()).animate(&(to * other.alpha_f32()), progress)?;
+            Ok((premultiplied / alpha).clamp(0., 1.))
+        };
+        Ok(RGBA::from_floats(
+            channel(self.red_f32(), other.red_f32())?,
+            channel(self.green_f32(), other.green_f32())?,
+            channel(self.blue_f32(), other.blue_f32())?,
+            alpha,
+        ))
+    }
+}
+
+impl Animate for Color {
+    /// `currentcolor` can't be resolved here, so it only interpolates with
+    /// itself.
+    fn animate(&self, other: &Self, progress: f32) -> Result<Self, ()> {
+        match (*self, *other) {
+            (Color::RGBA(ref from), Color::RGBA(ref to)) => Ok(Color::RGBA(from.animate(to, progress)?)),
+            (Color::CurrentColor, Color::CurrentColor) => Ok(Color::CurrentColor),
+            _ => Err(()),
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Decoding of style sheets loaded from bytes, honoring byte order marks and
+//! `@charset` rules.
+//!
+//! This only knows about UTF-8, UTF-16 and windows-1252, which is what the
+//! documents we care about use. Sheets in any other encoding are decoded as
+//! UTF-8, which is the fallback encoding in the absence of a referring
+//! document with a different one.
+//!
+//! https://drafts.csswg.org/css-syntax/#input-byte-stream
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Encoding {
+    Utf8,
+    Utf16Be,
+    Utf16Le,
+    Windows1252,
+}
+
+impl Encoding {
+    /// https://encoding.spec.whatwg.org/#concept-encoding-get
+    fn for_label(label: &[u8]) -> Option<Self> {
+        let label = std::str::from_utf8(label).ok()?.trim().to_ascii_lowercase();
+        Some(match &*label {
+            "unicode-1-1-utf-8" | "unicode11utf8" | "unicode20utf8" | "utf-8" | "utf8" | "x-unicode20utf8" => {
+                Encoding::Utf8
+            },
+            "unicodefffe" | "utf-16be" => Encoding::Utf16Be,
+            "csunicode" | "iso-10646-ucs-2" | "ucs-2" | "unicode" | "unicodefeff" | "utf-16" | "utf-16le" => {
+                Encoding::Utf16Le
+            },
+            "ansi_x3.4-1968" | "ascii" | "cp1252" | "cp819" | "csisolatin1" | "ibm819" | "iso-8859-1" |
+            "iso-ir-100" | "iso8859-1" | "iso88591" | "iso_8859-1" | "iso_8859-1:1987" | "l1" | "latin1" |
+            "us-ascii" | "windows-1252" | "x-cp1252" => Encoding::Windows1252,
+            _ => return None,
+        })
+    }
+
+    fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Utf16Be => decode_utf16(bytes, u16::from_be_bytes),
+            Encoding::Utf16Le => decode_utf16(bytes, u16::from_le_bytes),
+            Encoding::Windows1252 => bytes.iter().map(|&b| windows_1252_to_char(b)).collect(),
+        }
+    }
+}
+
+fn decode_utf16(bytes: &[u8], to_unit: fn([u8; 2]) -> u16) -> String {
+    let units = bytes
+        .chunks_exact(2)
+        .map(|pair| to_unit([pair[0], pair[1]]))
+        .collect::<Vec<_>>();
+    let mut result = String::from_utf16_lossy(&units);
+    if !bytes.len().is_multiple_of(2) {
+        result.push(std::char::REPLACEMENT_CHARACTER);
+    }
+    result
+}
+
+/// https://encoding.spec.whatwg.org/index-windows-1252.txt
+fn windows_1252_to_char(byte: u8) -> char {
+    const HIGH: [char; 32] = [
+        '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+        '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+        '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+        '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+    ];
+    match byte {
+        0x80..=0x9F => HIGH[(byte - 0x80) as usize],
+        _ => byte as char,
+    }
+}
+
+/// Returns the label of the `@charset` rule at the start of `bytes`, if any.
+///
+/// https://drafts.csswg.org/css-syntax/#determine-the-fallback-encoding
+fn charset_rule_label(bytes: &[u8]) -> Option<&[u8]> {
+    const PREFIX: &[u8] = b"@charset \"";
+    let bytes = &bytes[..bytes.len().min(1024)];
+    if !bytes.starts_with(PREFIX) {
+        return None;
+    }
+    let rest = &bytes[PREFIX.len()..];
+    let end = rest.iter().position(|&b| b == b'"' || b == b';')?;
+    if rest[end] != b'"' || rest.get(end + 1) != Some(&b';') {
+        return None;
+    }
+    Some(&rest[..end])
+}
+
+/// Decodes the bytes of a style sheet: A byte order mark wins over an
+/// `@charset` rule, which wins over the UTF-8 default.
+///
+/// https://drafts.csswg.org/css-syntax/#decode-bytes
+pub fn decode_stylesheet(bytes: &[u8]) -> String {
+    if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        return Encoding::Utf8.decode(&bytes[3..]);
+    }
+    if bytes.starts_with(&[0xFE, 0xFF]) {
+        return Encoding::Utf16Be.decode(&bytes[2..]);
+    }
+    if bytes.starts_with(&[0xFF, 0xFE]) {
+        return Encoding::Utf16Le.decode(&bytes[2..]);
+    }
+    let encoding = match charset_rule_label(bytes).and_then(Encoding::for_label) {
+        // An `@charset` rule can't be encoded in UTF-16, so a sheet that
+        // claims so is really UTF-8.
+        Some(Encoding::Utf16Be) | Some(Encoding::Utf16Le) | None => Encoding::Utf8,
+        Some(encoding) => encoding,
+    };
+    encoding.decode(bytes)
+}
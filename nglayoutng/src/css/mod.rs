@@ -1,27 +1,38 @@
 //! This implements a very basic style engine without support for `!important`
-//! other CSS rules that aren't style rules, or parsing specified values other
-//! than the ones we need, which includes `calc(<length> + <percentage>)`.
+//! other CSS rules that aren't style rules or `@font-face`, or parsing
+//! specified values other than the ones we need, which includes
+//! `calc(<length> + <percentage>)`.
 //!
 //! Also, it doesn't have any css-like error handling. Any syntax error reports
 //! an error and stops parsing entirely.
 
+mod encoding;
+mod presentational_hints;
+mod selector_map;
+
+pub use self::encoding::decode_stylesheet;
+
+use crate::dom::{DomNodeId, DomNodeIds};
+use self::selector_map::{AncestorFilter, SelectorHashes, SelectorMap};
 use crate::logical_geometry::WritingMode;
 use crate::style::{self, ComputedStyle, MutableComputedStyle};
 use app_units::Au;
 use cssparser::{self, CowRcStr, Parser, ParserInput, Token};
 use smallvec::SmallVec;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
 #[derive(PropertyDeclaration)]
 pub enum PropertyDeclaration {
-    #[declaration(early, field = "computed_writing_mode")]
+    #[declaration(early, field = "computed_writing_mode", inherited)]
     WritingMode(style::WritingMode),
-    #[declaration(early)]
+    #[declaration(early, inherited)]
     Direction(style::Direction),
-    #[declaration(early)]
+    #[declaration(early, inherited)]
     TextOrientation(style::TextOrientation),
 
+    #[declaration(inherited)]
     Color(cssparser::RGBA),
     BackgroundColor(cssparser::Color),
 
@@ -116,25 +127,178 @@ pub enum PropertyDeclaration {
     #[declaration(logical)]
     BorderInlineEndStyle(style::BorderStyle),
 
+    Top(style::LengthPercentage),
+    Right(style::LengthPercentage),
+    Bottom(style::LengthPercentage),
+    Left(style::LengthPercentage),
+
     Display(style::Display),
     Position(style::Position),
     BoxSizing(style::BoxSizing),
+    AspectRatio(style::AspectRatio),
 
     OverflowX(style::Overflow),
     OverflowY(style::Overflow),
+    TextOverflow(style::TextOverflow),
 
     Float(style::Float),
     Clear(style::Clear),
+
+    #[declaration(inherited)]
+    WhiteSpace(style::WhiteSpace),
+    #[declaration(inherited)]
+    WordBreak(style::WordBreak),
+    #[declaration(inherited)]
+    OverflowWrap(style::OverflowWrap),
+    #[declaration(inherited)]
+    Hyphens(style::Hyphens),
+    #[declaration(inherited)]
+    TextIndent(style::LengthPercentage),
+    #[declaration(inherited)]
+    TextTransform(style::TextTransform),
+    #[declaration(inherited)]
+    TabSize(style::TabSize),
+
+    #[declaration(inherited)]
+    FontStyle(style::FontStyle),
+    #[declaration(computed, inherited)]
+    FontWeight(style::SpecifiedFontWeight),
+    #[declaration(inherited)]
+    FontStretch(style::FontStretch),
+    #[declaration(inherited)]
+    FontVariantCaps(style::FontVariantCaps),
+    #[declaration(inherited)]
+    FontFeatureSettings(style::FontFeatureSettings),
+    // font-size is early so that other properties can eventually be
+    // computed against it.
+    #[declaration(early, computed, inherited)]
+    FontSize(style::SpecifiedFontSize),
+    #[declaration(inherited)]
+    LineHeight(style::LineHeight),
+    #[declaration(inherited)]
+    FontFamily(style::FontFamilyList),
+}
+
+/// Static information about a longhand, generated from `PropertyDeclaration`.
+pub struct PropertyMetadata {
+    /// The CSS name of the property.
+    pub name: &'static str,
+    /// The logical property group of the property, like `margin` for both
+    /// `margin-top` and `margin-inline-start`, if it's part of one.
+    ///
+    /// https://drafts.csswg.org/css-logical/#logical-property-group
+    pub logical_group: Option<&'static str>,
+    /// Whether this is a flow-relative property, which maps to a physical one.
+    pub logical: bool,
+    /// Whether the property is applied before the rest, since they depend on
+    /// it.
+    pub early: bool,
+    pub inherited: bool,
+    /// Parses a value of this property.
+    pub parse: for<'i, 't> fn(&mut Parser<'i, 't>) -> Result<PropertyDeclaration, ParseError<'i>>,
+    /// Serializes the value of this property in a computed style. Logical
+    /// properties serialize the physical property they map to.
+    pub serialize: fn(&ComputedStyle) -> String,
+}
+
+impl PropertyMetadata {
+    /// Returns the longhand with the given name, if any.
+    pub fn find(name: &str) -> Option<&'static Self> {
+        PROPERTIES.iter().find(|property| property.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// The shorthand properties we support, and how they expand into
+/// `PropertyDeclaration` longhands.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Shorthand)]
+pub enum Shorthand {
+    #[shorthand(four_sides = "margin-top, margin-right, margin-bottom, margin-left")]
+    Margin,
+    #[shorthand(four_sides = "padding-top, padding-right, padding-bottom, padding-left")]
+    Padding,
+    #[shorthand(four_sides = "border-top-width, border-right-width, border-bottom-width, border-left-width")]
+    BorderWidth,
+    #[shorthand(four_sides = "border-top-style, border-right-style, border-bottom-style, border-left-style")]
+    BorderStyle,
+    #[shorthand(four_sides = "border-top-color, border-right-color, border-bottom-color, border-left-color")]
+    BorderColor,
+    /// https://drafts.csswg.org/css-backgrounds/#propdef-border
+    #[shorthand(any_order = "border-color, border-style, border-width", initial = "currentcolor, none, 3px")]
+    Border,
+    /// https://drafts.csswg.org/css-logical/#propdef-inset
+    #[shorthand(four_sides = "top, right, bottom, left")]
+    Inset,
+    #[shorthand(pair = "overflow-x, overflow-y")]
+    Overflow,
+    #[shorthand(function = "parse_font")]
+    Font,
+    /// https://drafts.csswg.org/css-text-3/#propdef-word-wrap
+    #[shorthand(alias = "overflow-wrap")]
+    WordWrap,
 }
 
 pub struct CssStyleRule {
     selectors: kuchiki::Selectors,
+    /// The hashes of each of the selectors, in the same order.
+    hashes: Vec<SelectorHashes>,
     declarations: Vec<PropertyDeclaration>,
 }
 
+/// https://drafts.csswg.org/css-fonts/#font-face-rule
+#[derive(Debug, Clone)]
+pub struct FontFaceRule {
+    pub family: Option<String>,
+    pub sources: Vec<FontFaceSource>,
+    pub weight: style::FontWeight,
+    pub style: style::FontStyle,
+}
+
+/// https://drafts.csswg.org/css-fonts/#descdef-font-face-src
+#[derive(Debug, Clone)]
+pub enum FontFaceSource {
+    Url(String),
+    Local(String),
+}
+
+pub enum CssRule {
+    Style(CssStyleRule),
+    FontFace(FontFaceRule),
+    /// An `@charset` rule, which has no effect other than on the decoding of
+    /// the sheet.
+    Charset,
+}
+
+/// The result of parsing a stylesheet.
+#[derive(Default)]
+pub struct Stylesheet {
+    /// The style rules, sorted by specificity and source order.
+    pub rules: Vec<Rule>,
+    pub font_faces: Vec<FontFaceRule>,
+    /// The errors found while parsing, for the rules that have been dropped.
+    pub errors: Vec<crate::Error>,
+}
+
+/// The text of a stylesheet, and the url it's been loaded from, if any, which
+/// is used to report parse errors.
+#[derive(Debug, Clone)]
+pub struct StylesheetSource {
+    pub css: String,
+    pub url: Option<String>,
+}
+
+/// Where a stylesheet comes from, which determines its cascade level.
+///
+/// https://drafts.csswg.org/css-cascade/#cascading-origins
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Origin {
+    UserAgent,
+    Author,
+}
+
 /// A rule with a single selector, used for sorting by specificity and source
 /// order.
 pub struct Rule {
+    origin: Origin,
     /// The index of the original selector in the rule.
     selector_index: usize,
     original_rule: Rc<CssStyleRule>,
@@ -142,13 +306,56 @@ pub struct Rule {
     source_order: usize,
 }
 
+impl Rule {
+    fn hashes(&self) -> &SelectorHashes {
+        &self.original_rule.hashes[self.selector_index]
+    }
+}
+
+/// The rules of all the style sheets that apply to a document, in cascade
+/// order, and indexed so that matching them against an element is fast.
+///
+/// The user-agent rules must come before the author rules.
+pub struct RuleSet {
+    rules: Vec<Rule>,
+    map: SelectorMap,
+    /// The index of the first author rule.
+    author_start: usize,
+}
+
+impl RuleSet {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        let mut map = SelectorMap::default();
+        for (index, rule) in rules.iter().enumerate() {
+            map.insert(&rule.hashes().bucket, index);
+        }
+        let author_start = rules
+            .iter()
+            .position(|rule| rule.origin == Origin::Author)
+            .unwrap_or(rules.len());
+        debug_assert!(rules[author_start..].iter().all(|rule| rule.origin == Origin::Author));
+        Self {
+            rules,
+            map,
+            author_start,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Error<'i> {
     InvalidSelector,
     CurrentColorInColor,
-    EmptyBorder,
+    EmptyShorthand,
+    InvalidFont,
     UnknownPropertyName(CowRcStr<'i>),
+    UnknownDescriptorName(CowRcStr<'i>),
     UnknownLengthUnit(CowRcStr<'i>),
+    NegativeValue,
+    OutOfRangeValue,
+    InvalidFontFaceFamily,
+    InvalidFeatureTag,
+    InvalidAspectRatio,
 }
 
 pub type ParseError<'i> = cssparser::ParseError<'i, Error<'i>>;
@@ -157,15 +364,41 @@ struct CssParser;
 impl<'i> cssparser::AtRuleParser<'i> for CssParser {
     type PreludeBlock = ();
     type PreludeNoBlock = ();
-    type AtRule = CssStyleRule;
+    type AtRule = CssRule;
     type Error = Error<'i>;
 
-    // Default methods reject everything.
+    fn parse_prelude<'t>(
+        &mut self,
+        name: CowRcStr<'i>,
+        input: &mut Parser<'i, 't>,
+    ) -> Result<cssparser::AtRuleType<(), ()>, ParseError<'i>> {
+        match_ignore_ascii_case! { &name,
+            "font-face" => Ok(cssparser::AtRuleType::WithBlock(())),
+            "charset" => {
+                input.expect_string()?;
+                Ok(cssparser::AtRuleType::WithoutBlock(()))
+            },
+            _ => Err(input.new_error(cssparser::BasicParseErrorKind::AtRuleInvalid(name.clone()))),
+        }
+    }
+
+    fn rule_without_block(&mut self, _: (), _location: cssparser::SourceLocation) -> CssRule {
+        CssRule::Charset
+    }
+
+    fn parse_block<'t>(
+        &mut self,
+        _: (),
+        _location: cssparser::SourceLocation,
+        input: &mut Parser<'i, 't>,
+    ) -> Result<CssRule, ParseError<'i>> {
+        Ok(CssRule::FontFace(parse_font_face_block(input)))
+    }
 }
 
 impl<'i> cssparser::QualifiedRuleParser<'i> for CssParser {
-    type Prelude = kuchiki::Selectors;
-    type QualifiedRule = CssStyleRule;
+    type Prelude = (kuchiki::Selectors, Vec<SelectorHashes>);
+    type QualifiedRule = CssRule;
     type Error = Error<'i>;
 
     #[inline]
@@ -176,24 +409,118 @@ impl<'i> cssparser::QualifiedRuleParser<'i> for CssParser {
         let location = input.current_source_location();
         let position = input.position();
         while input.next().is_ok() {}
-        kuchiki::Selectors::compile(input.slice_from(position))
-            .map_err(|()| location.new_custom_error(Error::InvalidSelector))
+        let text = input.slice_from(position);
+        let selectors = kuchiki::Selectors::compile(text)
+            .map_err(|()| location.new_custom_error(Error::InvalidSelector))?;
+        let hashes = SelectorHashes::for_selector_list(text, selectors.0.len());
+        Ok((selectors, hashes))
     }
 
     #[inline]
     fn parse_block<'t>(
         &mut self,
-        selectors: Self::Prelude,
+        (selectors, hashes): Self::Prelude,
         _location: cssparser::SourceLocation,
         input: &mut Parser<'i, 't>,
-    ) -> Result<CssStyleRule, ParseError<'i>> {
-        Ok(CssStyleRule {
+    ) -> Result<CssRule, ParseError<'i>> {
+        Ok(CssRule::Style(CssStyleRule {
             selectors,
+            hashes,
             declarations: parse_declarations(input).map_err(|e| e.0)?,
+        }))
+    }
+}
+
+enum FontFaceDescriptor {
+    Family(String),
+    Sources(Vec<FontFaceSource>),
+    Weight(style::FontWeight),
+    Style(style::FontStyle),
+}
+
+struct FontFaceDescriptorParser;
+impl<'i> cssparser::DeclarationParser<'i> for FontFaceDescriptorParser {
+    type Declaration = FontFaceDescriptor;
+    type Error = Error<'i>;
+
+    fn parse_value<'t>(
+        &mut self,
+        name: CowRcStr<'i>,
+        input: &mut Parser<'i, 't>,
+    ) -> Result<Self::Declaration, ParseError<'i>> {
+        Ok(match_ignore_ascii_case! { &name,
+            "font-family" => {
+                let family = match style::SingleFontFamily::parse(input)? {
+                    style::SingleFontFamily::Named(named) => named.name,
+                    style::SingleFontFamily::Generic(..) => {
+                        return Err(input.new_custom_error(Error::InvalidFontFaceFamily));
+                    },
+                };
+                FontFaceDescriptor::Family(family)
+            },
+            "src" => FontFaceDescriptor::Sources(input.parse_comma_separated(parse_font_face_source)?),
+            "font-weight" => FontFaceDescriptor::Weight(style::FontWeight::parse(input)?),
+            "font-style" => FontFaceDescriptor::Style(style::FontStyle::parse(input)?),
+            _ => return Err(input.new_custom_error(Error::UnknownDescriptorName(name.clone()))),
         })
     }
 }
 
+impl<'i> cssparser::AtRuleParser<'i> for FontFaceDescriptorParser {
+    type PreludeBlock = ();
+    type PreludeNoBlock = ();
+    type AtRule = FontFaceDescriptor;
+    type Error = Error<'i>;
+}
+
+/// https://drafts.csswg.org/css-fonts/#descdef-font-face-src
+fn parse_font_face_source<'i>(input: &mut Parser<'i, '_>) -> Result<FontFaceSource, ParseError<'i>> {
+    if let Ok(url) = input.try_parse(|input| input.expect_url()) {
+        let source = FontFaceSource::Url(url.as_ref().to_owned());
+        // TODO(emilio): Honor format() hints instead of ignoring them.
+        let _ = input.try_parse(|input| -> Result<_, ParseError<'i>> {
+            input.expect_function_matching("format")?;
+            input.parse_nested_block(|input| {
+                input.parse_comma_separated(|input| -> Result<_, ParseError<'i>> {
+                    Ok(input.expect_string()?.clone())
+                })
+            })
+        });
+        return Ok(source);
+    }
+
+    input.expect_function_matching("local")?;
+    input.parse_nested_block(|input| {
+        let name = match style::SingleFontFamily::parse(input)? {
+            style::SingleFontFamily::Named(named) => named.name,
+            style::SingleFontFamily::Generic(..) => {
+                return Err(input.new_custom_error(Error::InvalidFontFaceFamily));
+            },
+        };
+        Ok(FontFaceSource::Local(name))
+    })
+}
+
+fn parse_font_face_block<'i>(input: &mut Parser<'i, '_>) -> FontFaceRule {
+    let mut rule = FontFaceRule {
+        family: None,
+        sources: Vec::new(),
+        weight: style::FontWeight::NORMAL,
+        style: style::FontStyle::Normal,
+    };
+    let iter = cssparser::DeclarationListParser::new(input, FontFaceDescriptorParser);
+    for descriptor in iter {
+        match descriptor {
+            Ok(FontFaceDescriptor::Family(family)) => rule.family = Some(family),
+            Ok(FontFaceDescriptor::Sources(sources)) => rule.sources = sources,
+            Ok(FontFaceDescriptor::Weight(weight)) => rule.weight = weight,
+            Ok(FontFaceDescriptor::Style(style)) => rule.style = style,
+            Err(e) => eprintln!("@font-face descriptor dropped: {:?}", e),
+        }
+    }
+    rule
+}
+
 fn length_from_dimension(unit: &str, value: f32) -> Result<style::Length, ()> {
     if !unit.eq_ignore_ascii_case("px") {
         return Err(());
@@ -213,7 +540,7 @@ fn parse_rgba<'i>(input: &mut Parser<'i, '_>) -> Result<cssparser::RGBA, ParseEr
     }
 }
 
-fn parse_length<'i>(input: &mut Parser<'i, '_>) -> Result<style::Length, ParseError<'i>> {
+pub(crate) fn parse_length<'i>(input: &mut Parser<'i, '_>) -> Result<style::Length, ParseError<'i>> {
     let location = input.current_source_location();
     match *input.next()? {
         Token::Dimension {
@@ -290,71 +617,140 @@ fn parse_length_or_percentage_or_auto<'i>(
     ))
 }
 
-fn parse_overflow_shorthand<'i>(
+/// https://drafts.csswg.org/css-fonts/#font-prop
+///
+/// TODO(emilio): System fonts.
+fn parse_font<'i>(
     input: &mut Parser<'i, '_>,
 ) -> Result<SmallVec<[PropertyDeclaration; 1]>, ParseError<'i>> {
-    let mut ret = SmallVec::new();
-    let x = style::Overflow::parse(input)?;
-    let y = input.try_parse(|i| style::Overflow::parse(i)).unwrap_or(x);
-    ret.push(PropertyDeclaration::OverflowX(x));
-    ret.push(PropertyDeclaration::OverflowY(y));
-    Ok(ret)
-}
-
-fn parse_border<'i>(
-    input: &mut Parser<'i, '_>,
-) -> Result<SmallVec<[PropertyDeclaration; 1]>, ParseError<'i>> {
-    let mut color = None;
-    let mut style = None;
-    let mut width = None;
-    let mut any = false;
+    let mut font_style = None;
+    let mut weight = None;
+    let mut stretch = None;
+    let mut small_caps = false;
+    let mut normals = 0;
     loop {
-        if color.is_none() {
-            if let Ok(value) = input.try_parse(parse_color) {
-                color = Some(value);
-                any = true;
+        // `normal` is valid for all of them, so we just count it and don't
+        // assign it to any in particular.
+        if input.try_parse(|i| i.expect_ident_matching("normal")).is_ok() {
+            normals += 1;
+            continue;
+        }
+        if font_style.is_none() {
+            if let Ok(value) = input.try_parse(style::FontStyle::parse) {
+                font_style = Some(value);
                 continue;
             }
         }
-        if style.is_none() {
-            if let Ok(value) = input.try_parse(style::BorderStyle::parse) {
-                style = Some(value);
-                any = true;
+        if weight.is_none() {
+            if let Ok(value) = input.try_parse(style::SpecifiedFontWeight::parse) {
+                weight = Some(value);
                 continue;
             }
         }
-        if width.is_none() {
-            if let Ok(value) = input.try_parse(parse_length) {
-                width = Some(value);
-                any = true;
+        if stretch.is_none() {
+            if let Ok(value) = input.try_parse(style::FontStretch::parse_keyword) {
+                stretch = Some(value);
                 continue;
             }
         }
+        // <font-variant-css2> only allows small-caps.
+        if !small_caps && input.try_parse(|i| i.expect_ident_matching("small-caps")).is_ok() {
+            small_caps = true;
+            continue;
+        }
         break;
     }
-    if !any {
-        return Err(input.new_custom_error(Error::EmptyBorder));
+
+    // <font-style>, <font-variant-css2>, <font-weight> and
+    // <font-stretch-css3>, each at most once.
+    let count = normals +
+        font_style.is_some() as usize +
+        weight.is_some() as usize +
+        stretch.is_some() as usize +
+        small_caps as usize;
+    if count > 4 {
+        return Err(input.new_custom_error(Error::InvalidFont));
     }
-    let mut ret = SmallVec::new();
-    let color = color.unwrap_or(cssparser::Color::CurrentColor);
-    let style = style.unwrap_or(style::BorderStyle::None);
-    let width = width.unwrap_or(style::Length(Au::from_f32_px(3.)));
 
-    ret.push(PropertyDeclaration::BorderTopColor(color));
-    ret.push(PropertyDeclaration::BorderRightColor(color));
-    ret.push(PropertyDeclaration::BorderBottomColor(color));
-    ret.push(PropertyDeclaration::BorderLeftColor(color));
+    // The size and family are mandatory.
+    let size = style::SpecifiedFontSize::parse(input)?;
+    let line_height = if input.try_parse(|i| i.expect_delim('/')).is_ok() {
+        style::LineHeight::parse(input)?
+    } else {
+        style::LineHeight::Normal
+    };
+    let family = style::FontFamilyList::parse(input)?;
 
-    ret.push(PropertyDeclaration::BorderTopStyle(style));
-    ret.push(PropertyDeclaration::BorderRightStyle(style));
-    ret.push(PropertyDeclaration::BorderBottomStyle(style));
-    ret.push(PropertyDeclaration::BorderLeftStyle(style));
+    // All the longhands that aren't specified are reset to their initial
+    // values.
+    let mut ret = SmallVec::new();
+    ret.push(PropertyDeclaration::FontStyle(font_style.unwrap_or(style::FontStyle::Normal)));
+    ret.push(PropertyDeclaration::FontWeight(
+        weight.unwrap_or(style::SpecifiedFontWeight::Absolute(style::FontWeight::NORMAL)),
+    ));
+    ret.push(PropertyDeclaration::FontStretch(stretch.unwrap_or(style::FontStretch::NORMAL)));
+    ret.push(PropertyDeclaration::FontVariantCaps(if small_caps {
+        style::FontVariantCaps::SmallCaps
+    } else {
+        style::FontVariantCaps::Normal
+    }));
+    ret.push(PropertyDeclaration::FontSize(size));
+    ret.push(PropertyDeclaration::LineHeight(line_height));
+    ret.push(PropertyDeclaration::FontFamily(family));
+    Ok(ret)
+}
+
+/// Parses the components of a shorthand like `border`, which can be in any
+/// order, but at most once each. At least one is needed, and the omitted ones
+/// are reset to the given initial values.
+fn parse_any_order<'i>(
+    input: &mut Parser<'i, '_>,
+    components: &[(Shorthand, &'static str)],
+) -> Result<SmallVec<[PropertyDeclaration; 1]>, ParseError<'i>> {
+    let mut values = components.iter().map(|_| None).collect::<Vec<_>>();
+    'components: loop {
+        for (value, &(component, _)) in values.iter_mut().zip(components) {
+            if value.is_none() {
+                if let Ok(declarations) = input.try_parse(|i| component.parse_single_value(i)) {
+                    *value = Some(declarations);
+                    continue 'components;
+                }
+            }
+        }
+        break;
+    }
+    if values.iter().all(Option::is_none) {
+        return Err(input.new_custom_error(Error::EmptyShorthand));
+    }
 
-    ret.push(PropertyDeclaration::BorderTopWidth(width));
-    ret.push(PropertyDeclaration::BorderRightWidth(width));
-    ret.push(PropertyDeclaration::BorderBottomWidth(width));
-    ret.push(PropertyDeclaration::BorderLeftWidth(width));
+    let mut ret = SmallVec::new();
+    for (value, &(component, initial)) in values.into_iter().zip(components) {
+        ret.extend(value.unwrap_or_else(|| {
+            let mut input = ParserInput::new(initial);
+            component
+                .parse_single_value(&mut Parser::new(&mut input))
+                .expect("Invalid initial value")
+        }));
+    }
+    Ok(ret)
+}
 
+/// Parses a shorthand like `overflow`, where the second value defaults to the
+/// first.
+fn parse_pair<'i, L>(
+    input: &mut Parser<'i, '_>,
+    get_first: fn(L) -> PropertyDeclaration,
+    get_second: fn(L) -> PropertyDeclaration,
+    parse_one: impl Fn(&mut Parser<'i, '_>) -> Result<L, ParseError<'i>>,
+) -> Result<SmallVec<[PropertyDeclaration; 1]>, ParseError<'i>>
+where
+    L: Clone,
+{
+    let mut ret = SmallVec::new();
+    let first = parse_one(input)?;
+    let second = input.try_parse(&parse_one).unwrap_or_else(|_| first.clone());
+    ret.push(get_first(first));
+    ret.push(get_second(second));
     Ok(ret)
 }
 
@@ -364,17 +760,16 @@ fn parse_four_sides<'i, L>(
     get_right: fn(L) -> PropertyDeclaration,
     get_bottom: fn(L) -> PropertyDeclaration,
     get_left: fn(L) -> PropertyDeclaration,
-    parse_one: fn(&mut Parser<'i, '_>) -> Result<L, ParseError<'i>>,
+    parse_one: impl Fn(&mut Parser<'i, '_>) -> Result<L, ParseError<'i>>,
 ) -> Result<SmallVec<[PropertyDeclaration; 1]>, ParseError<'i>>
 where
     L: Clone,
 {
     let mut ret = SmallVec::new();
     let top = parse_one(input)?;
-    let right = input.try_parse(parse_one).ok();
-    let bottom = input.try_parse(parse_one).ok();
-    let left = input.try_parse(parse_one).ok();
-
+    let right = input.try_parse(&parse_one).ok();
+    let bottom = input.try_parse(&parse_one).ok();
+    let left = input.try_parse(&parse_one).ok();
     match (right, bottom, left) {
         (Some(right), Some(bottom), Some(left)) => {
             ret.push(get_top(top));
@@ -423,50 +818,9 @@ impl<'i> cssparser::DeclarationParser<'i> for PropertyDeclarationParser {
             return Ok(declarations);
         }
 
-        match_ignore_ascii_case! { &name,
-            "margin" => parse_four_sides(
-                input,
-                PropertyDeclaration::MarginTop,
-                PropertyDeclaration::MarginRight,
-                PropertyDeclaration::MarginBottom,
-                PropertyDeclaration::MarginLeft,
-                parse_length_or_percentage_or_auto,
-            ),
-            "padding" => parse_four_sides(
-                input,
-                PropertyDeclaration::PaddingTop,
-                PropertyDeclaration::PaddingRight,
-                PropertyDeclaration::PaddingBottom,
-                PropertyDeclaration::PaddingLeft,
-                parse_length_or_percentage,
-            ),
-            "border-width" => parse_four_sides(
-                input,
-                PropertyDeclaration::BorderTopWidth,
-                PropertyDeclaration::BorderRightWidth,
-                PropertyDeclaration::BorderBottomWidth,
-                PropertyDeclaration::BorderLeftWidth,
-                parse_length,
-            ),
-            "border-style" => parse_four_sides(
-                input,
-                PropertyDeclaration::BorderTopStyle,
-                PropertyDeclaration::BorderRightStyle,
-                PropertyDeclaration::BorderBottomStyle,
-                PropertyDeclaration::BorderLeftStyle,
-                style::BorderStyle::parse,
-            ),
-            "border-color" => parse_four_sides(
-                input,
-                PropertyDeclaration::BorderTopColor,
-                PropertyDeclaration::BorderRightColor,
-                PropertyDeclaration::BorderBottomColor,
-                PropertyDeclaration::BorderLeftColor,
-                parse_color,
-            ),
-            "border" => parse_border(input),
-            "overflow" => parse_overflow_shorthand(input),
-            _ => Err(input.new_custom_error(Error::UnknownPropertyName(name.clone()))),
+        match Shorthand::from_name(&name) {
+            Some(shorthand) => shorthand.parse(input),
+            None => Err(input.new_custom_error(Error::UnknownPropertyName(name.clone()))),
         }
     }
 }
@@ -498,30 +852,92 @@ pub fn parse_declarations<'i>(
     Ok(declarations)
 }
 
-pub fn parse_css<'i>(css: &'i str) -> Vec<Rule> {
+pub fn parse_css(css: &str, origin: Origin) -> Stylesheet {
+    let mut stylesheet = Stylesheet::default();
+    let mut css_rules = Vec::new();
+    parse_rules(css, None, &mut css_rules, &mut stylesheet);
+    stylesheet.rules = sort_rules(css_rules, origin);
+    stylesheet
+}
+
+/// The path of the user-agent style sheet, which is read every time a
+/// document is loaded.
+pub fn ua_stylesheet_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("src")
+        .join("css")
+        .join("res")
+        .join("ua.css")
+}
+
+/// Parses a list of stylesheets of the same origin, which cascade in order.
+pub fn parse_stylesheets(sources: &[StylesheetSource], origin: Origin) -> Stylesheet {
+    let mut stylesheet = Stylesheet::default();
+    let mut css_rules = Vec::new();
+    for source in sources {
+        parse_rules(&source.css, source.url.as_deref(), &mut css_rules, &mut stylesheet);
+    }
+    stylesheet.rules = sort_rules(css_rules, origin);
+    stylesheet
+}
+
+/// Converts the error that caused a rule to be dropped to a crate error.
+fn rule_error(error: ParseError, rule: &str, url: Option<&str>) -> crate::Error {
+    let location = error.location;
+    if let cssparser::ParseErrorKind::Basic(cssparser::BasicParseErrorKind::AtRuleInvalid(ref name)) = error.kind {
+        return crate::Error::Unsupported(format!(
+            "@{} rule at {}:{}:{}",
+            name,
+            url.unwrap_or("<inline>"),
+            location.line + 1,
+            location.column
+        ));
+    }
+    crate::Error::Parse {
+        url: url.map(|url| url.to_owned()),
+        line: location.line + 1,
+        column: location.column,
+        message: format!("Rule dropped: {:?}, {:?}", error.kind, rule),
+    }
+}
+
+fn parse_rules(
+    css: &str,
+    url: Option<&str>,
+    css_rules: &mut Vec<Rc<CssStyleRule>>,
+    stylesheet: &mut Stylesheet,
+) {
+    // A byte order mark may have survived decoding, if the sheet comes from
+    // the text of a `<style>` element.
+    let css = css.trim_start_matches('\u{feff}');
     let mut input = ParserInput::new(css);
     let mut input = Parser::new(&mut input);
 
     let iter = cssparser::RuleListParser::new_for_stylesheet(&mut input, CssParser);
-    let mut css_rules = Vec::new();
-
     for result in iter {
         let rule = match result {
             Ok(r) => r,
             Err((error, string)) => {
-                eprintln!("Rule dropped: {:?}, {:?}", error, string);
+                stylesheet.errors.push(rule_error(error, string, url));
                 continue;
             },
         };
-        css_rules.push(Rc::new(rule));
+        match rule {
+            CssRule::Style(rule) => css_rules.push(Rc::new(rule)),
+            CssRule::FontFace(rule) => stylesheet.font_faces.push(rule),
+            CssRule::Charset => {},
+        }
     }
+}
 
+fn sort_rules(css_rules: Vec<Rc<CssStyleRule>>, origin: Origin) -> Vec<Rule> {
     // Now sort each selector by (specificity, source_order).
     let mut rules = Vec::new();
 
     for (source_order, rule) in css_rules.into_iter().enumerate() {
         for (selector_index, selector) in rule.selectors.0.iter().enumerate() {
             rules.push(Rule {
+                origin,
                 selector_index,
                 original_rule: rule.clone(),
                 specificity: selector.specificity(),
@@ -531,21 +947,50 @@ pub fn parse_css<'i>(css: &'i str) -> Vec<Rule> {
     }
 
     rules.sort_by_key(|rule| (rule.specificity, rule.source_order));
-
     rules
 }
 
 /// A map with styles from each element to its style.
-pub type StyleMap = HashMap<*const kuchiki::Node, ComputedStyle>;
+pub type StyleMap = HashMap<DomNodeId, Rc<ComputedStyle>>;
 
-pub fn compute_styles(root: &kuchiki::NodeRef, rules: &[Rule]) -> StyleMap {
+/// Computes the styles of all the elements in the tree rooted at `root`, which
+/// must have been assigned ids already.
+pub fn compute_styles(root: &kuchiki::NodeRef, rules: &RuleSet, ids: &DomNodeIds) -> Result<StyleMap, crate::Error> {
     let mut map = Default::default();
-    compute_styles_for_tree(root, rules, None, &mut map);
-    map
+    let mut filter = AncestorFilter::new();
+    compute_styles_for_tree(root, rules, ids, None, &mut filter, &mut map)?;
+    Ok(map)
+}
+
+/// Recomputes the styles of `root` and its descendants, inheriting from the
+/// style of the closest styled ancestor of `root`.
+pub fn compute_styles_for_subtree(
+    root: &kuchiki::NodeRef,
+    rules: &RuleSet,
+    ids: &DomNodeIds,
+    map: &mut StyleMap,
+) -> Result<(), crate::Error> {
+    let inherited_style = root
+        .ancestors()
+        .filter_map(|ancestor| map.get(&ids.get(&ancestor)?).cloned())
+        .next();
+    let mut filter = AncestorFilter::for_ancestors_of(root);
+    compute_styles_for_tree(
+        root,
+        rules,
+        ids,
+        inherited_style.as_deref(),
+        &mut filter,
+        map,
+    )
 }
 
-fn apply_declaration(style: &mut MutableComputedStyle, declaration: &PropertyDeclaration) {
-    declaration.compute(style);
+fn apply_declaration(
+    style: &mut MutableComputedStyle,
+    parent: &MutableComputedStyle,
+    declaration: &PropertyDeclaration,
+) {
+    declaration.compute(style, parent);
 }
 
 fn compute_element_style(
@@ -557,11 +1002,21 @@ fn compute_element_style(
         None => ComputedStyle::initial(),
     };
 
+    // The root element computes relative values against the initial style.
+    let initial_style;
+    let parent: &MutableComputedStyle = match inherited_style {
+        Some(s) => s,
+        None => {
+            initial_style = ComputedStyle::initial();
+            &initial_style
+        },
+    };
+
     // Apply early properties first.
     for block in matching_declaration_blocks {
         for declaration in &**block {
             if declaration.is_early() {
-                apply_declaration(&mut style, declaration);
+                apply_declaration(&mut style, parent, declaration);
             }
         }
     }
@@ -577,7 +1032,7 @@ fn compute_element_style(
     for block in matching_declaration_blocks {
         for declaration in &**block {
             if !declaration.is_early() {
-                apply_declaration(&mut style, declaration);
+                apply_declaration(&mut style, parent, declaration);
             }
         }
     }
@@ -588,27 +1043,44 @@ fn compute_element_style(
 
 fn compute_styles_for_tree(
     node: &kuchiki::NodeRef,
-    rules: &[Rule],
+    rules: &RuleSet,
+    ids: &DomNodeIds,
     inherited_style: Option<&ComputedStyle>,
+    filter: &mut AncestorFilter,
     map: &mut StyleMap,
-) {
+) -> Result<(), crate::Error> {
     let element = match node.clone().into_element_ref() {
         Some(e) => e,
         None => {
             for child in node.children() {
-                compute_styles_for_tree(&child, rules, inherited_style, map);
+                compute_styles_for_tree(&child, rules, ids, inherited_style, filter, map)?;
             }
-            return;
+            return Ok(());
         },
     };
 
     let mut matching_declaration_blocks = Vec::new();
 
-    for rule in rules {
+    // Presentational hints go after the user-agent rules, and before any
+    // author rule.
+    let hints = presentational_hints::presentational_hints(node, &element);
+    let mut pushed_hints = false;
+    for index in rules.map.candidates(&element) {
+        if index >= rules.author_start && !pushed_hints {
+            matching_declaration_blocks.push(&hints);
+            pushed_hints = true;
+        }
+        let rule = &rules.rules[index];
+        if !rule.hashes().might_match(filter) {
+            continue;
+        }
         if rule.original_rule.selectors.0[rule.selector_index].matches(&element) {
             matching_declaration_blocks.push(&rule.original_rule.declarations);
         }
     }
+    if !pushed_hints {
+        matching_declaration_blocks.push(&hints);
+    }
 
     let style_attr = element
         .attributes
@@ -624,11 +1096,17 @@ fn compute_styles_for_tree(
         matching_declaration_blocks.push(s);
     }
 
-    let style = compute_element_style(&matching_declaration_blocks, inherited_style);
+    let style = Rc::new(compute_element_style(&matching_declaration_blocks, inherited_style));
 
+    filter.push(&element);
     for child in node.children() {
-        compute_styles_for_tree(&child, rules, Some(&style), map);
+        compute_styles_for_tree(&child, rules, ids, Some(&*style), filter, map)?;
     }
+    filter.pop(&element);
 
-    map.insert(&*node.0, style);
+    let id = ids
+        .get(node)
+        .ok_or(crate::Error::Internal("Styling a node without an id"))?;
+    map.insert(id, style);
+    Ok(())
 }
@@ -0,0 +1,248 @@
+//! Presentational hints: Legacy HTML attributes that affect the style of
+//! elements, like `width` on images or `bgcolor` on tables.
+//!
+//! These are mapped to declarations that apply right after the user-agent
+//! rules, so any author rule overrides them.
+//!
+//! https://html.spec.whatwg.org/#presentational-hints
+
+use super::PropertyDeclaration;
+use crate::style::{
+    AspectRatio, BorderStyle, Float, Length, LengthPercentage, LengthPercentageOrAuto, Percentage, Ratio, Size,
+};
+use app_units::Au;
+use kuchiki::{Attributes, ElementData, NodeRef};
+
+/// A dimension value: Either a number of pixels or a percentage.
+///
+/// https://html.spec.whatwg.org/#rules-for-parsing-dimension-values
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Dimension {
+    Pixels(f32),
+    Percentage(f32),
+}
+
+impl Dimension {
+    fn parse(value: &str) -> Option<Self> {
+        let value = value.trim_start();
+        let end = value
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(value.len());
+        let number = value[..end].trim_end_matches('.').parse::<f32>().ok()?;
+        Some(if value[end..].starts_with('%') {
+            Dimension::Percentage(number)
+        } else {
+            Dimension::Pixels(number)
+        })
+    }
+
+    fn to_length_percentage(self) -> LengthPercentage {
+        match self {
+            Dimension::Pixels(px) => LengthPercentage {
+                fixed: Length(Au::from_f32_px(px)),
+                percentage: None,
+            },
+            Dimension::Percentage(p) => LengthPercentage {
+                fixed: Length(Au(0)),
+                percentage: Some(Percentage(p / 100.)),
+            },
+        }
+    }
+}
+
+fn dimension_attribute(attrs: &Attributes, name: &str) -> Option<Dimension> {
+    Dimension::parse(attrs.get(name)?)
+}
+
+/// Like `dimension_attribute`, but ignoring zero values.
+///
+/// https://html.spec.whatwg.org/#rules-for-parsing-non-zero-dimension-values
+fn non_zero_dimension_attribute(attrs: &Attributes, name: &str) -> Option<Dimension> {
+    dimension_attribute(attrs, name).filter(|dimension| match *dimension {
+        Dimension::Pixels(n) | Dimension::Percentage(n) => n != 0.,
+    })
+}
+
+/// Parses a legacy color value, like the one in `bgcolor`.
+///
+/// TODO(emilio): Implement the whole legacy color parsing algorithm, which
+/// accepts all kinds of garbage.
+///
+/// https://html.spec.whatwg.org/#rules-for-parsing-a-legacy-colour-value
+fn legacy_color_attribute(attrs: &Attributes, name: &str) -> Option<cssparser::Color> {
+    let value = attrs.get(name)?.trim();
+    if value.is_empty() || value.eq_ignore_ascii_case("transparent") {
+        return None;
+    }
+    let is_hex = matches!(value.len(), 3 | 6) && value.chars().all(|c| c.is_ascii_hexdigit());
+    let value = if is_hex {
+        format!("#{}", value)
+    } else {
+        value.to_owned()
+    };
+    let mut input = cssparser::ParserInput::new(&value);
+    cssparser::Color::parse(&mut cssparser::Parser::new(&mut input)).ok()
+}
+
+fn pixels(px: u32) -> Length {
+    Length(Au::from_px(px as i32))
+}
+
+fn push_border(hints: &mut Vec<PropertyDeclaration>, width: Length, style: BorderStyle) {
+    hints.extend(vec![
+        PropertyDeclaration::BorderTopWidth(width),
+        PropertyDeclaration::BorderRightWidth(width),
+        PropertyDeclaration::BorderBottomWidth(width),
+        PropertyDeclaration::BorderLeftWidth(width),
+        PropertyDeclaration::BorderTopStyle(style),
+        PropertyDeclaration::BorderRightStyle(style),
+        PropertyDeclaration::BorderBottomStyle(style),
+        PropertyDeclaration::BorderLeftStyle(style),
+    ]);
+}
+
+/// Maps the `width` and `height` attributes to the sizing properties.
+///
+/// https://html.spec.whatwg.org/#map-to-the-dimension-properties
+fn push_dimensions(hints: &mut Vec<PropertyDeclaration>, width: Option<Dimension>, height: Option<Dimension>) {
+    if let Some(width) = width {
+        hints.push(PropertyDeclaration::Width(Size::LengthPercentage(width.to_length_percentage())));
+    }
+    if let Some(height) = height {
+        hints.push(PropertyDeclaration::Height(Size::LengthPercentage(height.to_length_percentage())));
+    }
+}
+
+/// Maps the `width` and `height` attributes of an image-like element to an
+/// `aspect-ratio: auto <width> / <height>` declaration.
+///
+/// https://html.spec.whatwg.org/#map-to-the-aspect-ratio-property-(using-dimension-rules)
+fn push_aspect_ratio(hints: &mut Vec<PropertyDeclaration>, width: Option<Dimension>, height: Option<Dimension>) {
+    if let (Some(Dimension::Pixels(width)), Some(Dimension::Pixels(height))) = (width, height) {
+        hints.push(PropertyDeclaration::AspectRatio(AspectRatio {
+            auto: true,
+            ratio: Some(Ratio { width, height }),
+        }));
+    }
+}
+
+/// Returns the closest `<table>` ancestor of a table cell.
+fn table_for_cell(node: &NodeRef) -> Option<NodeRef> {
+    node.ancestors().find(|ancestor| {
+        ancestor
+            .as_element()
+            .is_some_and(|e| &*e.name.local == "table")
+    })
+}
+
+/// Returns the declarations that the attributes of `element`, which is
+/// `node`, map to.
+pub fn presentational_hints(node: &NodeRef, element: &ElementData) -> Vec<PropertyDeclaration> {
+    let attrs = element.attributes.borrow();
+    let mut hints = vec![];
+    match &*element.name.local {
+        "img" | "video" => {
+            let width = dimension_attribute(&attrs, "width");
+            let height = dimension_attribute(&attrs, "height");
+            push_dimensions(&mut hints, width, height);
+            push_aspect_ratio(&mut hints, width, height);
+            if &*element.name.local == "img" {
+                // https://html.spec.whatwg.org/#attr-img-border
+                if let Some(border) = attrs.get("border").and_then(|b| b.trim().parse::<u32>().ok()) {
+                    push_border(&mut hints, pixels(border), BorderStyle::Solid);
+                }
+                push_float_for_align(&mut hints, &attrs);
+            }
+        },
+        // The size of a canvas is the size of its bitmap, so its attributes
+        // only map to a ratio.
+        "canvas" => {
+            let width = dimension_attribute(&attrs, "width");
+            let height = dimension_attribute(&attrs, "height");
+            push_aspect_ratio(&mut hints, width, height);
+        },
+        "iframe" | "embed" | "object" => {
+            let width = dimension_attribute(&attrs, "width");
+            let height = dimension_attribute(&attrs, "height");
+            push_dimensions(&mut hints, width, height);
+        },
+        "table" => {
+            push_dimensions(&mut hints, non_zero_dimension_attribute(&attrs, "width"), None);
+            // https://html.spec.whatwg.org/#tables-2
+            if let Some(border) = attrs.get("border") {
+                let width = border.trim().parse::<u32>().unwrap_or(1);
+                push_border(&mut hints, pixels(width), BorderStyle::Outset);
+            }
+            match attrs.get("align").map(|a| a.trim().to_ascii_lowercase()).as_deref() {
+                Some("center") => hints.extend(vec![
+                    PropertyDeclaration::MarginLeft(LengthPercentageOrAuto::Auto),
+                    PropertyDeclaration::MarginRight(LengthPercentageOrAuto::Auto),
+                ]),
+                _ => push_float_for_align(&mut hints, &attrs),
+            }
+            if let Some(color) = legacy_color_attribute(&attrs, "bgcolor") {
+                hints.push(PropertyDeclaration::BackgroundColor(color));
+            }
+        },
+        "td" | "th" => {
+            push_dimensions(
+                &mut hints,
+                non_zero_dimension_attribute(&attrs, "width"),
+                non_zero_dimension_attribute(&attrs, "height"),
+            );
+            if let Some(color) = legacy_color_attribute(&attrs, "bgcolor") {
+                hints.push(PropertyDeclaration::BackgroundColor(color));
+            }
+            push_cell_hints_from_table(&mut hints, node);
+        },
+        "tr" | "body" => {
+            if let Some(color) = legacy_color_attribute(&attrs, "bgcolor") {
+                hints.push(PropertyDeclaration::BackgroundColor(color));
+            }
+        },
+        // TODO(emilio): `align` on blocks maps to `text-align`, which we
+        // don't support yet.
+        _ => {},
+    }
+    hints
+}
+
+/// Maps `align=left` and `align=right` to floats.
+fn push_float_for_align(hints: &mut Vec<PropertyDeclaration>, attrs: &Attributes) {
+    let float = match attrs.get("align").map(|a| a.trim().to_ascii_lowercase()).as_deref() {
+        Some("left") => Float::Left,
+        Some("right") => Float::Right,
+        _ => return,
+    };
+    hints.push(PropertyDeclaration::Float(float));
+}
+
+/// Table cells get their padding from the `cellpadding` attribute of their
+/// table, and a border if their table has one.
+///
+/// https://html.spec.whatwg.org/#tables-2
+fn push_cell_hints_from_table(hints: &mut Vec<PropertyDeclaration>, cell: &NodeRef) {
+    let table = match table_for_cell(cell) {
+        Some(table) => table,
+        None => return,
+    };
+    let table_attrs = table.as_element().unwrap().attributes.borrow();
+    if let Some(padding) = table_attrs.get("cellpadding").and_then(|p| p.trim().parse::<u32>().ok()) {
+        let padding = LengthPercentage {
+            fixed: pixels(padding),
+            percentage: None,
+        };
+        hints.extend(vec![
+            PropertyDeclaration::PaddingTop(padding.clone()),
+            PropertyDeclaration::PaddingRight(padding.clone()),
+            PropertyDeclaration::PaddingBottom(padding.clone()),
+            PropertyDeclaration::PaddingLeft(padding),
+        ]);
+    }
+    let has_border = table_attrs
+        .get("border")
+        .is_some_and(|b| b.trim().parse::<u32>() != Ok(0));
+    if has_border {
+        push_border(hints, pixels(1), BorderStyle::Inset);
+    }
+}
@@ -0,0 +1,289 @@
+//! Data structures that avoid testing every selector against every element.
+//!
+//! Rules are bucketed by the id, class or local name in the rightmost compound
+//! selector of each of their selectors, so that an element only looks at the
+//! rules that can possibly match it. Then, a bloom filter of the ancestors of
+//! the element allows rejecting most selectors with descendant or child
+//! combinators without walking up the tree.
+//!
+//! This is a very simplified version of what Servo and Gecko do. Keys are
+//! compared ASCII-case-insensitively, which may yield false positives, but
+//! never false negatives, so the actual selector matching stays the source of
+//! truth.
+
+use cssparser::{Parser, ParserInput, Token};
+use kuchiki::{ElementData, NodeRef};
+use smallvec::SmallVec;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// The bucket a selector goes into, from the most to the least specific.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum BucketKey {
+    Id(String),
+    Class(String),
+    LocalName(String),
+    #[default]
+    Universal,
+}
+
+
+fn hash_key(key: &str) -> u32 {
+    let mut hasher = DefaultHasher::new();
+    key.to_ascii_lowercase().hash(&mut hasher);
+    hasher.finish() as u32
+}
+
+/// The keys of a compound selector that we know how to look up.
+#[derive(Default)]
+struct CompoundKeys {
+    id: Option<String>,
+    classes: SmallVec<[String; 1]>,
+    local_name: Option<String>,
+}
+
+impl CompoundKeys {
+    fn bucket_key(self) -> BucketKey {
+        if let Some(id) = self.id {
+            return BucketKey::Id(id.to_ascii_lowercase());
+        }
+        if let Some(class) = self.classes.into_iter().next() {
+            return BucketKey::Class(class.to_ascii_lowercase());
+        }
+        match self.local_name {
+            Some(local_name) => BucketKey::LocalName(local_name.to_ascii_lowercase()),
+            None => BucketKey::Universal,
+        }
+    }
+
+    fn hashes(&self) -> impl Iterator<Item = u32> + '_ {
+        self.id
+            .iter()
+            .chain(self.classes.iter())
+            .chain(self.local_name.iter())
+            .map(|key| hash_key(key))
+    }
+}
+
+/// The information we need about a single selector to speed up matching.
+#[derive(Debug, Clone, Default)]
+pub struct SelectorHashes {
+    pub bucket: BucketKey,
+    /// Hashes of the ids, classes and local names that ancestors of the
+    /// element must have for the selector to match.
+    ancestor_hashes: SmallVec<[u32; 4]>,
+}
+
+impl SelectorHashes {
+    /// Computes the hashes of each selector in a comma-separated list which
+    /// compiled to `count` selectors.
+    ///
+    /// If the list can't be split into `count` selectors (which shouldn't
+    /// happen), every selector goes into the universal bucket, which is always
+    /// correct.
+    pub fn for_selector_list(text: &str, count: usize) -> Vec<Self> {
+        let mut input = ParserInput::new(text);
+        let mut input = Parser::new(&mut input);
+        let result = input.parse_comma_separated(|input| Ok::<_, cssparser::ParseError<()>>(Self::parse(input)));
+        match result {
+            Ok(hashes) if hashes.len() == count => hashes,
+            _ => vec![Self::default(); count],
+        }
+    }
+
+    /// Walks the compound selectors of a complex selector from left to right.
+    ///
+    /// A compound selector is an ancestor of the element when the combinator
+    /// to its right is a descendant or child combinator (even if there are
+    /// sibling combinators further to the right).
+    fn parse(input: &mut Parser) -> Self {
+        let mut ancestor_hashes = SmallVec::new();
+        let mut compound = CompoundKeys::default();
+        let mut compound_started = false;
+        let mut pending_descendant_combinator = false;
+        let mut after_colon = false;
+        let mut after_dot = false;
+
+        loop {
+            let token = match input.next_including_whitespace() {
+                Ok(token) => token.clone(),
+                Err(..) => break,
+            };
+            let is_ancestor_combinator = match token {
+                Token::WhiteSpace(..) => {
+                    pending_descendant_combinator = compound_started;
+                    continue;
+                },
+                Token::Delim('>') => Some(true),
+                Token::Delim('+') | Token::Delim('~') => Some(false),
+                _ if pending_descendant_combinator => Some(true),
+                _ => None,
+            };
+            if let Some(is_ancestor) = is_ancestor_combinator {
+                if is_ancestor {
+                    ancestor_hashes.extend(compound.hashes());
+                }
+                compound = CompoundKeys::default();
+                compound_started = false;
+                pending_descendant_combinator = false;
+                if let Token::Delim('>') | Token::Delim('+') | Token::Delim('~') = token {
+                    continue;
+                }
+            }
+
+            compound_started = true;
+            match token {
+                Token::Ident(ref name) if after_dot => compound.classes.push(name.to_string()),
+                // Pseudo-classes and pseudo-elements.
+                Token::Ident(..) if after_colon => {},
+                Token::Ident(ref name) => compound.local_name = Some(name.to_string()),
+                Token::IDHash(ref id) => compound.id = Some(id.to_string()),
+                // The previous identifier was a namespace prefix.
+                Token::Delim('|') => compound.local_name = None,
+                _ => {},
+            }
+            after_dot = token == Token::Delim('.');
+            after_colon = token == Token::Colon;
+        }
+
+        Self {
+            bucket: compound.bucket_key(),
+            ancestor_hashes,
+        }
+    }
+
+    /// Whether a selector with these hashes could match an element whose
+    /// ancestors are in `filter`.
+    pub fn might_match(&self, filter: &AncestorFilter) -> bool {
+        self.ancestor_hashes.iter().all(|hash| filter.might_contain(*hash))
+    }
+}
+
+/// Indices of rules in cascade order, bucketed by the `BucketKey` of their
+/// selectors.
+#[derive(Default)]
+pub struct SelectorMap {
+    by_id: HashMap<String, Vec<usize>>,
+    by_class: HashMap<String, Vec<usize>>,
+    by_local_name: HashMap<String, Vec<usize>>,
+    universal: Vec<usize>,
+}
+
+impl SelectorMap {
+    /// Inserts the index of a rule. Indices must be inserted in increasing
+    /// order.
+    pub fn insert(&mut self, key: &BucketKey, index: usize) {
+        let bucket = match *key {
+            BucketKey::Id(ref id) => self.by_id.entry(id.clone()).or_default(),
+            BucketKey::Class(ref class) => self.by_class.entry(class.clone()).or_default(),
+            BucketKey::LocalName(ref name) => self.by_local_name.entry(name.clone()).or_default(),
+            BucketKey::Universal => &mut self.universal,
+        };
+        debug_assert!(bucket.last().is_none_or(|last| *last < index));
+        bucket.push(index);
+    }
+
+    /// Returns the indices of the rules that may match `element`, in cascade
+    /// order.
+    pub fn candidates(&self, element: &ElementData) -> Vec<usize> {
+        let mut candidates = self.universal.clone();
+        candidates.extend(bucket(&self.by_local_name, &element.name.local));
+        let attributes = element.attributes.borrow();
+        if let Some(id) = attributes.get("id") {
+            candidates.extend(bucket(&self.by_id, id));
+        }
+        if let Some(classes) = attributes.get("class") {
+            let mut seen = SmallVec::<[String; 4]>::new();
+            for class in classes.split_ascii_whitespace() {
+                let class = class.to_ascii_lowercase();
+                if seen.contains(&class) {
+                    continue;
+                }
+                candidates.extend(bucket(&self.by_class, &class));
+                seen.push(class);
+            }
+        }
+        candidates.sort_unstable();
+        candidates
+    }
+}
+
+fn bucket<'a>(map: &'a HashMap<String, Vec<usize>>, key: &str) -> &'a [usize] {
+    map.get(&*key.to_ascii_lowercase()).map_or(&[], |indices| &indices[..])
+}
+
+const KEY_BITS: u32 = 12;
+const ARRAY_SIZE: usize = 1 << KEY_BITS;
+const KEY_MASK: u32 = (1 << KEY_BITS) - 1;
+
+/// A counting bloom filter of the ids, classes and local names of the
+/// ancestors of the element being styled.
+pub struct AncestorFilter {
+    counters: Box<[u8; ARRAY_SIZE]>,
+}
+
+impl AncestorFilter {
+    pub fn new() -> Self {
+        Self {
+            counters: Box::new([0; ARRAY_SIZE]),
+        }
+    }
+
+    /// Returns a filter with all the element ancestors of `node`.
+    pub fn for_ancestors_of(node: &NodeRef) -> Self {
+        let mut filter = Self::new();
+        for ancestor in node.ancestors() {
+            if let Some(element) = ancestor.as_element() {
+                filter.push(element);
+            }
+        }
+        filter
+    }
+
+    fn for_each_hash(element: &ElementData, mut f: impl FnMut(u32)) {
+        f(hash_key(&element.name.local));
+        let attributes = element.attributes.borrow();
+        if let Some(id) = attributes.get("id") {
+            f(hash_key(id));
+        }
+        if let Some(classes) = attributes.get("class") {
+            for class in classes.split_ascii_whitespace() {
+                f(hash_key(class));
+            }
+        }
+    }
+
+    fn slots(hash: u32) -> [usize; 2] {
+        [(hash & KEY_MASK) as usize, ((hash >> KEY_BITS) & KEY_MASK) as usize]
+    }
+
+    /// Adds an element, whose descendants are going to be styled.
+    pub fn push(&mut self, element: &ElementData) {
+        let counters = &mut self.counters;
+        Self::for_each_hash(element, |hash| {
+            for slot in &Self::slots(hash) {
+                counters[*slot] = counters[*slot].saturating_add(1);
+            }
+        });
+    }
+
+    /// Removes an element previously added with `push`.
+    pub fn pop(&mut self, element: &ElementData) {
+        let counters = &mut self.counters;
+        Self::for_each_hash(element, |hash| {
+            for slot in &Self::slots(hash) {
+                // Saturated counters can't be decremented, since they may
+                // account for more elements than we know of.
+                if counters[*slot] != u8::MAX {
+                    debug_assert_ne!(counters[*slot], 0, "Popping an element that wasn't pushed?");
+                    counters[*slot] -= 1;
+                }
+            }
+        });
+    }
+
+    fn might_contain(&self, hash: u32) -> bool {
+        Self::slots(hash).iter().all(|slot| self.counters[*slot] != 0)
+    }
+}
@@ -0,0 +1,208 @@
+//! A display list is a flat list of painting commands in physical
+//! coordinates, built out of the fragment tree.
+//!
+//! Items are in painting order, so they can be painted one after the other.
+//!
+//! TODO(emilio): Stacking contexts, and the actual painting order as per
+//! https://drafts.csswg.org/css2/zindex.html
+
+use crate::fonts::font_face::FontFaceSet;
+use crate::fonts::loader::{LoadedFont, Loader};
+use crate::fragment_tree::{BoxDecorations, ChildFragment, ContainerFragmentKind, Fragment, FragmentKind, FragmentTree};
+use crate::style::ComputedStyle;
+use app_units::Au;
+use cssparser::RGBA;
+use euclid::default::{Point2D, Rect, SideOffsets2D, Size2D};
+
+/// A glyph positioned at its origin on the baseline.
+#[derive(Clone, Copy, Debug)]
+pub struct PositionedGlyph {
+    pub glyph_id: u32,
+    pub origin: Point2D<Au>,
+}
+
+pub enum DisplayItem {
+    /// A rectangle filled with a solid color, for backgrounds.
+    SolidColor { rect: Rect<Au>, color: RGBA },
+    /// The borders of a box, where `rect` is the border box.
+    ///
+    /// TODO(emilio): Border styles other than solid, and border-radius.
+    Border {
+        rect: Rect<Au>,
+        widths: SideOffsets2D<Au>,
+        /// The colors of the top, right, bottom and left borders.
+        colors: [RGBA; 4],
+    },
+    /// Clips all the items until the matching `PopClip` to a given rect.
+    PushClip { rect: Rect<Au> },
+    /// Removes the clip pushed by the last `PushClip`.
+    PopClip,
+    /// A run of glyphs that all use the same font.
+    Text {
+        font: LoadedFont,
+        font_size: Au,
+        color: RGBA,
+        glyphs: Vec<PositionedGlyph>,
+    },
+}
+
+pub struct DisplayList {
+    /// The size of the area this display list paints, that is, the size of
+    /// the viewport.
+    pub size: Size2D<Au>,
+    /// The items to paint, in order.
+    pub items: Vec<DisplayItem>,
+}
+
+impl DisplayList {
+    /// Builds the display list for a fragment tree.
+    pub fn build(tree: &FragmentTree) -> Self {
+        let root = &tree.root;
+        let viewport_size = tree.viewport_size;
+        let mut list = DisplayList {
+            size: viewport_size,
+            items: vec![],
+        };
+        let viewport = Rect::new(Point2D::zero(), viewport_size);
+        list.build_child(root, &root.fragment.style, viewport, &tree.font_faces);
+        list
+    }
+
+    /// Builds the items for `child`, given the style and the physical rect of
+    /// its parent, and the font faces of the document it belongs to.
+    fn build_child(
+        &mut self,
+        child: &ChildFragment,
+        parent_style: &ComputedStyle,
+        parent_rect: Rect<Au>,
+        font_faces: &FontFaceSet,
+    ) {
+        let rect = child.physical_rect(parent_style.writing_mode, &parent_rect);
+        self.build_fragment(&child.fragment, rect, font_faces);
+    }
+
+    fn build_fragment(&mut self, fragment: &Fragment, rect: Rect<Au>, font_faces: &FontFaceSet) {
+        match fragment.kind {
+            FragmentKind::TextRun { ref content, ref glyphs, .. } => {
+                self.build_text(&fragment.style, content, glyphs, rect, font_faces);
+            },
+            FragmentKind::Container { ref kind, ref children, ref clip, .. } => {
+                // Line boxes share the style of their block container, and
+                // don't paint anything by themselves.
+                //
+                // TODO(emilio): Paint the ellipsis of truncated lines.
+                if let ContainerFragmentKind::Box { ref decorations } = *kind {
+                    self.build_background_and_borders(decorations, &fragment.style, rect);
+                }
+                // TODO(emilio): Scroll offsets.
+                if let Some(ref clip) = *clip {
+                    let clip = clip
+                        .to_physical(fragment.style.writing_mode, rect.size)
+                        .translate(rect.origin.to_vector());
+                    self.items.push(DisplayItem::PushClip { rect: clip });
+                }
+                // Nested documents paint their text with their own fonts.
+                let font_faces = match *kind {
+                    ContainerFragmentKind::Document { ref font_faces } => &**font_faces,
+                    _ => font_faces,
+                };
+                for child in children.iter() {
+                    self.build_child(child, &fragment.style, rect, font_faces);
+                }
+                if clip.is_some() {
+                    self.items.push(DisplayItem::PopClip);
+                }
+            },
+        }
+    }
+
+    fn build_background_and_borders(
+        &mut self,
+        decorations: &BoxDecorations,
+        style: &ComputedStyle,
+        rect: Rect<Au>,
+    ) {
+        // TODO(emilio): background-clip, background-image...
+        let background_color = decorations.background_color;
+        if background_color.alpha != 0 {
+            self.items.push(DisplayItem::SolidColor { rect, color: background_color });
+        }
+
+        // `none` and `hidden` borders have a zero width already.
+        let border = decorations.border.to_physical(style.writing_mode);
+        let widths = SideOffsets2D::new(
+            border.top.width,
+            border.right.width,
+            border.bottom.width,
+            border.left.width,
+        );
+        if widths == SideOffsets2D::zero() {
+            return;
+        }
+        self.items.push(DisplayItem::Border {
+            rect,
+            widths,
+            colors: [border.top.color, border.right.color, border.bottom.color, border.left.color],
+        });
+    }
+
+    fn build_text(
+        &mut self,
+        style: &ComputedStyle,
+        content: &str,
+        glyphs: &[crate::fonts::shaping::GlyphInfo],
+        rect: Rect<Au>,
+        font_faces: &FontFaceSet,
+    ) {
+        // TODO(emilio): Paint text in vertical writing modes, and synthesized
+        // bold / oblique.
+        if style.writing_mode.is_vertical() {
+            return;
+        }
+
+        // Center the glyphs in the line-height, as per
+        // https://drafts.csswg.org/css2/visudet.html#leading
+        let metrics = style.first_available_font_metrics(font_faces);
+        let ascent = metrics.ascent().0;
+        let descent = metrics.descent().0;
+        let half_leading = (rect.size.height - (ascent - descent)) / 2;
+        let baseline = rect.origin.y + half_leading + ascent;
+
+        // Glyphs don't know about the font they come from, so look it up again
+        // like the shaper does.
+        let mut loader = Loader::new(style, font_faces);
+        let color = style.color;
+        let mut pen = rect.origin.x;
+        let mut current_font = None;
+        let mut current_glyphs = vec![];
+        for glyph in glyphs {
+            let c = content[glyph.byte_offset..].chars().next().unwrap();
+            let font = loader.font_for_character(c);
+            if current_font != Some(font) {
+                if let Some(index) = current_font {
+                    self.items.push(DisplayItem::Text {
+                        font: loader.font_at(index).unwrap().clone(),
+                        font_size: style.font_size.0,
+                        color,
+                        glyphs: std::mem::take(&mut current_glyphs),
+                    });
+                }
+                current_font = Some(font);
+            }
+            current_glyphs.push(PositionedGlyph {
+                glyph_id: glyph.glyph_id,
+                origin: Point2D::new(pen + glyph.offset.x, baseline + glyph.offset.y),
+            });
+            pen += glyph.advance;
+        }
+
+        if let Some(index) = current_font {
+            self.items.push(DisplayItem::Text {
+                font: loader.font_at(index).unwrap().clone(),
+                font_size: style.font_size.0,
+                color,
+                glyphs: current_glyphs,
+            });
+        }
+    }
+}
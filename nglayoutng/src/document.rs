@@ -0,0 +1,185 @@
+//! A document that embedders can lay out, query, mutate and render, without
+//! having to know how the layout tree builder, the layout tree and the
+//! fragment tree fit together.
+
+use crate::display_list::DisplayList;
+use crate::fonts::loader::FontSource;
+use crate::fragment_tree::FragmentTree;
+use crate::layout_tree::builder::LayoutTreeBuilder;
+use crate::query::LayoutResultQuery;
+use crate::Error;
+use app_units::Au;
+use euclid::default::{Rect, Size2D};
+use kuchiki::NodeRef;
+use raqote::DrawTarget;
+use std::io::Read;
+use std::path::Path;
+
+/// The size of the viewport of new documents, in CSS pixels.
+pub const DEFAULT_VIEWPORT_SIZE: (i32, i32) = (800, 600);
+
+/// The geometry of an element after layout, in CSS pixels and relative to
+/// the viewport.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ElementGeometry {
+    /// The union of the border boxes of the fragments of the element, like
+    /// `getBoundingClientRect()`.
+    pub border_box: Rect<f32>,
+    /// The union of the content boxes of the fragments of the element.
+    pub content_box: Rect<f32>,
+    /// The padding box of the first fragment of the element, like
+    /// `clientWidth` and friends.
+    pub client_rect: Rect<f32>,
+}
+
+/// A document, along with its layout tree and the result of its last
+/// layout, which is kept until the document or the viewport change.
+pub struct Document {
+    builder: LayoutTreeBuilder,
+    viewport_size: Size2D<Au>,
+    fragment_tree: Option<FragmentTree>,
+}
+
+impl Document {
+    /// Parses an HTML document and constructs its layout tree.
+    pub fn from_html(reader: &mut impl Read) -> Result<Self, Error> {
+        Self::from_html_with_base_directory(reader, None)
+    }
+
+    /// Like `from_html`, but resolving relative urls against
+    /// `base_directory`, like `LayoutTreeBuilder::new_with_base_directory`.
+    pub fn from_html_with_base_directory(
+        reader: &mut impl Read,
+        base_directory: Option<&Path>,
+    ) -> Result<Self, Error> {
+        Self::from_html_with_font_source(reader, base_directory, FontSource::from_env())
+    }
+
+    /// Like `from_html_with_base_directory`, but loading the fonts that don't
+    /// come from `@font-face` rules from `font_source`.
+    pub fn from_html_with_font_source(
+        reader: &mut impl Read,
+        base_directory: Option<&Path>,
+        font_source: FontSource,
+    ) -> Result<Self, Error> {
+        let mut builder = LayoutTreeBuilder::new_with_font_source(reader, base_directory, font_source)?;
+        builder.construct()?;
+        let (width, height) = DEFAULT_VIEWPORT_SIZE;
+        Ok(Self {
+            builder,
+            viewport_size: Size2D::new(Au::from_px(width), Au::from_px(height)),
+            fragment_tree: None,
+        })
+    }
+
+    pub fn dom(&self) -> &NodeRef {
+        self.builder.dom()
+    }
+
+    /// The builder of the layout tree of this document, for the things that
+    /// this API doesn't cover.
+    pub fn builder(&self) -> &LayoutTreeBuilder {
+        &self.builder
+    }
+
+    pub fn viewport_size(&self) -> Size2D<Au> {
+        self.viewport_size
+    }
+
+    /// Resizes the viewport, which invalidates the last layout.
+    pub fn set_viewport(&mut self, size: Size2D<Au>) {
+        if size != self.viewport_size {
+            self.viewport_size = size;
+            self.fragment_tree = None;
+        }
+    }
+
+    /// Lays out the document if it has changed since the last layout, and
+    /// returns the result.
+    pub fn layout(&mut self) -> &FragmentTree {
+        if self.fragment_tree.is_none() {
+            let quirks_mode = self.builder.dom().as_document().unwrap().quirks_mode();
+            let fragment_tree = self.builder.layout_tree().layout(quirks_mode, self.viewport_size);
+            self.fragment_tree = Some(fragment_tree);
+        }
+        self.fragment_tree.as_ref().unwrap()
+    }
+
+    /// Lays out the document if needed, and returns the geometry of the first
+    /// element that matches `selector`, or `None` if no element matches or
+    /// it generates no boxes.
+    pub fn query_selector_geometry(&mut self, selector: &str) -> Result<Option<ElementGeometry>, Error> {
+        let mut elements = self
+            .builder
+            .dom()
+            .select(selector)
+            .map_err(|()| Error::InvalidSelector(selector.to_owned()))?;
+        let element = match elements.next() {
+            Some(element) => element.as_node().clone(),
+            None => return Ok(None),
+        };
+        self.layout();
+        let query = LayoutResultQuery::new(
+            self.builder.dom_node_ids(),
+            self.builder.principal_boxes(),
+            self.fragment_tree.as_ref().unwrap(),
+        );
+        let border_box = match query.border_box_rect(&element) {
+            Some(rect) => rect,
+            None => return Ok(None),
+        };
+        // Elements with a border box have all the other boxes.
+        Ok(Some(ElementGeometry {
+            border_box,
+            content_box: query.content_box_rect(&element).unwrap(),
+            client_rect: query.client_rect(&element).unwrap(),
+        }))
+    }
+
+    /// Returns a handle to mutate the DOM of the document, which updates the
+    /// layout tree incrementally, and invalidates the last layout.
+    pub fn mutate(&mut self) -> DocumentMutation<'_> {
+        self.fragment_tree = None;
+        DocumentMutation {
+            builder: &mut self.builder,
+        }
+    }
+
+    /// Lays out the document if needed, and paints it.
+    pub fn render(&mut self, device_pixel_ratio: f32) -> DrawTarget {
+        let display_list = DisplayList::build(self.layout());
+        crate::paint::paint(&display_list, device_pixel_ratio)
+    }
+}
+
+/// The DOM mutations that a document supports, which are the ones of
+/// `LayoutTreeBuilder`.
+pub struct DocumentMutation<'a> {
+    builder: &'a mut LayoutTreeBuilder,
+}
+
+impl<'a> DocumentMutation<'a> {
+    pub fn append_child(&mut self, parent: &NodeRef, child: NodeRef) -> Result<(), Error> {
+        self.builder.append_child(parent, child)
+    }
+
+    pub fn insert_before(&mut self, parent: &NodeRef, child: NodeRef, reference: &NodeRef) -> Result<(), Error> {
+        self.builder.insert_before(parent, child, reference)
+    }
+
+    pub fn remove_child(&mut self, child: &NodeRef) -> Result<(), Error> {
+        self.builder.remove_child(child)
+    }
+
+    pub fn set_text_data(&mut self, node: &NodeRef, data: &str) -> Result<(), Error> {
+        self.builder.set_text_data(node, data)
+    }
+
+    pub fn set_attribute(&mut self, element: &NodeRef, name: &str, value: &str) -> Result<(), Error> {
+        self.builder.set_attribute(element, name, value)
+    }
+
+    pub fn remove_attribute(&mut self, element: &NodeRef, name: &str) -> Result<(), Error> {
+        self.builder.remove_attribute(element, name)
+    }
+}
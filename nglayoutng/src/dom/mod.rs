@@ -1,11 +1,15 @@
 //! These are little helpers that will help to get the layout tree builder up
 //! and running.
 
+use crate::css::{self, StylesheetSource};
 use crate::misc::print_tree::PrintTree;
-use html5ever::LocalName;
 use kuchiki::traits::*;
-use kuchiki::{self, NodeData, NodeRef};
+use kuchiki::{self, ElementData, NodeData, NodeRef};
+use std::collections::HashMap;
+use std::fs;
 use std::io::{self, Read};
+use std::path::Path;
+use std::rc::{Rc, Weak};
 
 /// Parses a DOM tree using html5ever and returns the root.
 pub fn build_dom<R>(input: &mut R) -> io::Result<NodeRef>
@@ -15,8 +19,83 @@ where
     kuchiki::parse_html().from_utf8().read_from(input)
 }
 
-fn print_node(node: &NodeRef, print: &mut PrintTree) {
-    print.new_level(match node.data() {
+/// A stable identifier for a DOM node.
+///
+/// Unlike the address of the node, this can't be reused by another node after
+/// the node dies, so it's safe to use as a key in the maps that outlive DOM
+/// mutations, like the style map or the principal boxes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DomNodeId(usize);
+
+impl DomNodeId {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// The ids assigned to the nodes of a document.
+///
+/// kuchiki doesn't give us anywhere to store per-node data, so we need to keep
+/// a side table from node address to id. Entries are validated against a weak
+/// reference to the node, so a dead node whose address has been reused by a
+/// new node can't be confused with it.
+#[derive(Debug, Default)]
+pub struct DomNodeIds {
+    ids: HashMap<*const kuchiki::Node, DomNodeId>,
+    nodes: Vec<Weak<kuchiki::Node>>,
+}
+
+impl DomNodeIds {
+    /// Assigns ids to all the nodes of the subtree rooted at `root`.
+    pub fn new(root: &NodeRef) -> Self {
+        let mut ids = Self::default();
+        ids.assign(root);
+        ids
+    }
+
+    /// Assigns ids to the nodes of the subtree rooted at `root` that don't
+    /// have one already, in tree order.
+    pub fn assign(&mut self, root: &NodeRef) {
+        for node in root.inclusive_descendants() {
+            if self.get(&node).is_some() {
+                continue;
+            }
+            let id = DomNodeId(self.nodes.len());
+            self.nodes.push(Rc::downgrade(&node.0));
+            self.ids.insert(&*node, id);
+        }
+    }
+
+    /// Returns the id of a node, if it has been assigned one.
+    pub fn get(&self, node: &kuchiki::Node) -> Option<DomNodeId> {
+        let id = *self.ids.get(&(node as *const kuchiki::Node))?;
+        let live = self.nodes[id.0].upgrade()?;
+        if !std::ptr::eq(&*live, node) {
+            return None;
+        }
+        Some(id)
+    }
+
+    /// Returns the node with a given id, if it's still alive.
+    pub fn node(&self, id: DomNodeId) -> Option<NodeRef> {
+        self.nodes.get(id.0)?.upgrade().map(NodeRef)
+    }
+
+    /// Forgets the ids of the nodes of the subtree rooted at `root`. The ids
+    /// are never reused.
+    pub fn forget(&mut self, root: &NodeRef) {
+        for node in root.inclusive_descendants() {
+            if let Some(id) = self.get(&node) {
+                self.ids.remove(&(&*node as *const kuchiki::Node));
+                self.nodes[id.0] = Weak::new();
+            }
+        }
+    }
+}
+
+/// Returns a short description of a node, for debugging.
+pub fn node_label(node: &NodeRef) -> String {
+    match node.data() {
         NodeData::Document(..) => "#document".into(),
         NodeData::DocumentFragment => "#document-fragment".into(),
         NodeData::Comment(ref comment) => format!("<!-- {} -->", comment.borrow()),
@@ -30,7 +109,11 @@ fn print_node(node: &NodeRef, print: &mut PrintTree) {
         ),
         NodeData::Text(ref text) => format!("#text {:?}", text.borrow()),
         NodeData::Element(ref element) => format!("<{}>", element.name.local),
-    });
+    }
+}
+
+fn print_node(node: &NodeRef, print: &mut PrintTree) {
+    print.new_level(node_label(node));
 
     for child in node.children() {
         print_node(&child, print);
@@ -45,37 +128,66 @@ pub fn print_dom(root: &NodeRef) {
 }
 
 /// Prints the dom to a particular output.
-pub fn print_dom_to(root: &NodeRef, dest: &mut dyn (::std::io::Write)) {
+pub fn print_dom_to(root: &NodeRef, dest: &mut dyn ::std::io::Write) {
     let mut tree = PrintTree::new("DOM tree", dest);
     print_node(root, &mut tree);
 }
 
-/// Reads all the style sheets in the DOM and returns a CSS string with the
-/// union of them in document order.
-pub fn read_stylesheets(root: &NodeRef) -> String {
-    let mut css = String::new();
-    read_stylesheets_from(&root, &mut css, /* in_sheet = */ false);
-    css
+/// Reads all the style sheets in the DOM and returns them in document order.
+///
+/// Both `<style>` elements and `<link rel=stylesheet>` elements are taken into
+/// account, with the `href` of the latter resolved against `base_directory`.
+/// Sheets that fail to load are ignored.
+pub fn read_stylesheets(root: &NodeRef, base_directory: Option<&Path>) -> Vec<StylesheetSource> {
+    let mut sheets = Vec::new();
+    for node in root.inclusive_descendants() {
+        let element = match node.as_element() {
+            Some(element) => element,
+            None => continue,
+        };
+        if &*element.name.local == "style" {
+            let css = node
+                .children()
+                .filter_map(|child| Some(child.as_text()?.borrow().clone()))
+                .collect();
+            sheets.push(StylesheetSource { css, url: None });
+        } else if is_stylesheet_link(element) {
+            sheets.extend(load_linked_stylesheet(element, base_directory));
+        }
+    }
+    sheets
 }
 
-fn read_stylesheets_from(node: &NodeRef, css: &mut String, mut in_sheet: bool) {
-    match node.data() {
-        NodeData::Document(..) |
-        NodeData::DocumentFragment |
-        NodeData::Comment(..) |
-        NodeData::ProcessingInstruction(..) |
-        NodeData::Doctype(..) => {},
-        NodeData::Text(ref text) => {
-            if in_sheet {
-                css.push_str(&text.borrow());
-            }
-        },
-        NodeData::Element(ref element) => {
-            in_sheet = element.name.local == LocalName::from("style");
-        },
+/// Whether `element` is a `<link>` to a style sheet.
+///
+/// https://html.spec.whatwg.org/#link-type-stylesheet
+fn is_stylesheet_link(element: &ElementData) -> bool {
+    if &*element.name.local != "link" {
+        return false;
     }
+    let attributes = element.attributes.borrow();
+    attributes.get("rel").is_some_and(|rel| {
+        rel.split_ascii_whitespace()
+            .any(|keyword| keyword.eq_ignore_ascii_case("stylesheet"))
+    })
+}
 
-    for child in node.children() {
-        read_stylesheets_from(&child, css, in_sheet)
+/// Loads the style sheet that a `<link rel=stylesheet>` element points to.
+///
+/// TODO(emilio): Honor the `media`, `type` and `disabled` attributes, and
+/// alternate style sheets.
+fn load_linked_stylesheet(element: &ElementData, base_directory: Option<&Path>) -> Option<StylesheetSource> {
+    let attributes = element.attributes.borrow();
+    let href = attributes.get("href")?;
+    let path = crate::misc::url::local_file_path(href, base_directory)?;
+    match fs::read(&path) {
+        Ok(bytes) => Some(StylesheetSource {
+            css: css::decode_stylesheet(&bytes),
+            url: Some(path.display().to_string()),
+        }),
+        Err(error) => {
+            warn!("Failed to load style sheet from {}: {:?}", path.display(), error);
+            None
+        },
     }
 }
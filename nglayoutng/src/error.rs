@@ -0,0 +1,72 @@
+//! The errors that the public API can return.
+
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Reading the document, or a resource it depends on, failed.
+    Io(io::Error),
+    /// A style sheet contains something that couldn't be parsed. The
+    /// offending rule or declaration is dropped.
+    Parse {
+        /// The url of the style sheet, if it's not inline.
+        url: Option<String>,
+        /// The one-based line of the error.
+        line: u32,
+        /// The one-based column of the error.
+        column: u32,
+        message: String,
+    },
+    /// The input uses a feature that isn't supported (yet).
+    Unsupported(String),
+    /// The caller asked for a DOM mutation that makes no sense, like inserting
+    /// a node before one that isn't a child of the parent.
+    InvalidMutation(&'static str),
+    /// The caller passed a selector that couldn't be parsed.
+    InvalidSelector(String),
+    /// An internal invariant doesn't hold, which is a bug.
+    Internal(&'static str),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref error) => write!(f, "I/O error: {}", error),
+            Error::Parse {
+                ref url,
+                line,
+                column,
+                ref message,
+            } => write!(
+                f,
+                "{}:{}:{}: {}",
+                url.as_ref().map_or("<inline>", |url| &**url),
+                line,
+                column,
+                message
+            ),
+            Error::Unsupported(ref what) => write!(f, "Unsupported: {}", what),
+            Error::InvalidMutation(what) => write!(f, "Invalid mutation: {}", what),
+            Error::InvalidSelector(ref selector) => write!(f, "Invalid selector: {}", selector),
+            Error::Internal(what) => write!(f, "Internal error: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Error::Io(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! A simple least-recently-used cache, used to avoid re-shaping identical
+//! text runs over and over.
+//!
+//! Entries live in a slab, linked in a doubly-linked list ordered by recency,
+//! and a hash map points from keys to slab indices, so that both lookups and
+//! evictions are constant time.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// The index of an entry in the slab.
+type Index = usize;
+
+struct Entry<K, V> {
+    key: K,
+    value: V,
+    /// The next more recently used entry, if any.
+    newer: Option<Index>,
+    /// The next less recently used entry, if any.
+    older: Option<Index>,
+}
+
+pub struct LruCache<K, V> {
+    map: HashMap<K, Index>,
+    entries: Vec<Entry<K, V>>,
+    capacity: usize,
+    /// The most recently used entry.
+    newest: Option<Index>,
+    /// The least recently used entry, which is the next one to be evicted.
+    oldest: Option<Index>,
+}
+
+impl<K: Hash + Eq, V> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        debug_assert!(capacity > 0);
+        Self {
+            map: HashMap::with_capacity(capacity),
+            entries: Vec::with_capacity(capacity),
+            capacity,
+            newest: None,
+            oldest: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.entries.clear();
+        self.newest = None;
+        self.oldest = None;
+    }
+
+    /// Removes an entry from the recency list.
+    fn unlink(&mut self, index: Index) {
+        let (newer, older) = {
+            let entry = &self.entries[index];
+            (entry.newer, entry.older)
+        };
+        match newer {
+            Some(newer) => self.entries[newer].older = older,
+            None => self.newest = older,
+        }
+        match older {
+            Some(older) => self.entries[older].newer = newer,
+            None => self.oldest = newer,
+        }
+    }
+
+    /// Inserts an unlinked entry at the front of the recency list.
+    fn push_newest(&mut self, index: Index) {
+        self.entries[index].newer = None;
+        self.entries[index].older = self.newest;
+        match self.newest {
+            Some(newest) => self.entries[newest].newer = Some(index),
+            None => self.oldest = Some(index),
+        }
+        self.newest = Some(index);
+    }
+
+    /// Looks up a given key, and marks it as the most recently used one.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let index = *self.map.get(key)?;
+        if self.newest != Some(index) {
+            self.unlink(index);
+            self.push_newest(index);
+        }
+        Some(&self.entries[index].value)
+    }
+
+    /// Inserts a value in the cache, evicting the least recently used entry if
+    /// the cache is full.
+    pub fn insert(&mut self, key: K, value: V)
+    where
+        K: Clone,
+    {
+        if let Some(&index) = self.map.get(&key) {
+            self.entries[index].value = value;
+            self.unlink(index);
+            self.push_newest(index);
+            return;
+        }
+
+        let entry = Entry {
+            key: key.clone(),
+            value,
+            newer: None,
+            older: None,
+        };
+        let index = if self.entries.len() >= self.capacity {
+            // Reuse the slot of the least recently used entry.
+            let index = self.oldest.expect("A full cache should have entries");
+            self.unlink(index);
+            let evicted = std::mem::replace(&mut self.entries[index], entry);
+            self.map.remove(&evicted.key);
+            index
+        } else {
+            self.entries.push(entry);
+            self.entries.len() - 1
+        };
+        self.map.insert(key, index);
+        self.push_newest(index);
+    }
+}
@@ -0,0 +1,132 @@
+//! The set of web fonts declared by the document via `@font-face` rules.
+//!
+//! https://drafts.csswg.org/css-fonts/#font-face-rule
+
+use super::loader::{self, FontSource, SharedFont};
+use crate::css::{FontFaceRule, FontFaceSource};
+use crate::style::{FontStyle, FontWeight};
+use font_kit::handle::Handle;
+use font_kit::source::SystemSource;
+use std::cell::RefCell;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+struct FontFace {
+    rule: FontFaceRule,
+    /// The loaded font, or `None` if we failed to load any of the sources.
+    /// Populated lazily, the first time the face is used.
+    font: RefCell<Option<Option<Rc<SharedFont>>>>,
+}
+
+/// The font faces of a document, which the layout tree of the document owns,
+/// and which font lookups during layout and painting go through.
+#[derive(Default)]
+pub struct FontFaceSet {
+    faces: Vec<FontFace>,
+    /// The directory against which relative `url()`s are resolved.
+    base_directory: Option<PathBuf>,
+    /// Where the fonts that aren't web fonts come from.
+    font_source: FontSource,
+}
+
+impl fmt::Debug for FontFaceSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.faces.iter().map(|face| &face.rule.family)).finish()
+    }
+}
+
+impl FontFaceSet {
+    pub fn new(rules: Vec<FontFaceRule>, base_directory: Option<&Path>, font_source: FontSource) -> Self {
+        Self {
+            faces: rules
+                .into_iter()
+                .filter(|rule| rule.family.is_some() && !rule.sources.is_empty())
+                .map(|rule| FontFace { rule, font: RefCell::new(None) })
+                .collect(),
+            base_directory: base_directory.map(|d| d.to_owned()),
+            font_source,
+        }
+    }
+
+    pub fn font_source(&self) -> FontSource {
+        self.font_source
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.faces.is_empty()
+    }
+
+    /// Returns the best face for the given family, weight and style, loading
+    /// it if needed.
+    ///
+    /// This is a very simplified version of the font matching algorithm: We
+    /// prefer faces that match the style, then the ones with the closest
+    /// weight, and otherwise the last face declared for the family.
+    ///
+    /// https://drafts.csswg.org/css-fonts/#font-style-matching
+    pub fn find(&self, family: &str, weight: FontWeight, style: FontStyle) -> Option<Rc<SharedFont>> {
+        let mut best = None;
+        let mut best_score = (false, f32::NEG_INFINITY);
+        for (i, face) in self.faces.iter().enumerate() {
+            if !face.rule.family.as_ref().unwrap().eq_ignore_ascii_case(family) {
+                continue;
+            }
+            let score = (face.rule.style == style, -(face.rule.weight.0 - weight.0).abs());
+            if score >= best_score {
+                best = Some(i);
+                best_score = score;
+            }
+        }
+
+        let base_directory = self.base_directory.as_deref();
+        let face = &self.faces[best?];
+        let mut font = face.font.borrow_mut();
+        if font.is_none() {
+            *font = Some(load(&face.rule, base_directory));
+        }
+        font.as_ref().unwrap().clone()
+    }
+}
+
+/// Loads the first source of a font-face rule that we manage to load.
+fn load(rule: &FontFaceRule, base_directory: Option<&Path>) -> Option<Rc<SharedFont>> {
+    for source in &rule.sources {
+        let font = match *source {
+            FontFaceSource::Url(ref url) => load_url(url, base_directory),
+            FontFaceSource::Local(ref name) => load_local(name),
+        };
+        if font.is_some() {
+            return font;
+        }
+    }
+    warn!("Couldn't load any source for @font-face {:?}", rule.family);
+    None
+}
+
+/// Loads a font from a url. Only local files are supported.
+///
+/// The font is shared with other documents that load the same file.
+///
+/// TODO(emilio): Support network loads, and `format()` hints.
+fn load_url(url: &str, base_directory: Option<&Path>) -> Option<Rc<SharedFont>> {
+    let path = match crate::misc::url::local_file_path(url, base_directory) {
+        Some(path) => path,
+        None => {
+            warn!("Unsupported @font-face url: {}", url);
+            return None;
+        },
+    };
+    loader::load_font(Handle::from_path(path, 0))
+}
+
+/// https://drafts.csswg.org/css-fonts/#descdef-font-face-src
+///
+/// local() matches by postscript or full name, we only look at the postscript
+/// name for now.
+fn load_local(name: &str) -> Option<Rc<SharedFont>> {
+    SystemSource::new()
+        .select_by_postscript_name(name)
+        .ok()
+        .and_then(loader::load_font)
+}
@@ -1,10 +1,137 @@
 use crate::style::{ComputedStyle, SingleFontFamily, GenericFamily, FontStyle, FontWeight};
+use super::font_face::FontFaceSet;
+use app_units::Au;
 use font_kit::{
     family_name::FamilyName,
+    handle::Handle,
     loaders::freetype::Font,
     properties::Properties,
     source::SystemSource,
 };
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// An Ahem-compatible font, bundled so that tests can get deterministic
+/// results regardless of the fonts installed in the system. See
+/// `res/generate-ahem.py`.
+static BUNDLED_TEST_FONT: &[u8] = include_bytes!("res/Ahem.ttf");
+
+/// Where the fonts that don't come from `@font-face` rules are loaded from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FontSource {
+    /// The fonts installed in the system.
+    System,
+    /// The bundled test font, which every font lookup resolves to.
+    BundledTestFont,
+}
+
+impl FontSource {
+    /// The bundled test font if the `NGLAYOUTNG_BUNDLED_TEST_FONT` environment
+    /// variable is set, and the system fonts otherwise.
+    pub fn from_env() -> Self {
+        if std::env::var_os("NGLAYOUTNG_BUNDLED_TEST_FONT").is_some() {
+            FontSource::BundledTestFont
+        } else {
+            FontSource::System
+        }
+    }
+}
+
+impl Default for FontSource {
+    fn default() -> Self {
+        Self::from_env()
+    }
+}
+
+/// A loaded font, which all the lookups that resolve to the same face share,
+/// so that its data is only read and parsed once, and so that it has a single
+/// identity for the shaping cache.
+pub struct SharedFont {
+    /// The face parsed for shaping, if the data could be parsed. This borrows
+    /// from `_data`, so it needs to be dropped before it.
+    face: Option<rustybuzz::Face<'static>>,
+    _data: Arc<Vec<u8>>,
+    font: Font,
+}
+
+impl SharedFont {
+    fn new(font: Font) -> Rc<Self> {
+        let data = font.copy_font_data().unwrap_or_default();
+        // SAFETY: The font data is never mutated, and lives in the heap for as
+        // long as `_data` is alive, which outlives `face`.
+        let bytes: &'static [u8] = unsafe { std::slice::from_raw_parts(data.as_ptr(), data.len()) };
+        // TODO(emilio): Use the right face index for font collections.
+        let face = rustybuzz::Face::from_slice(bytes, 0);
+        if face.is_none() {
+            warn!("Couldn't parse font data for {}", font.full_name());
+        }
+        Rc::new(Self { face, _data: data, font })
+    }
+
+    /// The face to shape text with.
+    pub fn shaping_face(&self) -> Option<&rustybuzz::Face<'_>> {
+        self.face.as_ref()
+    }
+}
+
+impl Deref for SharedFont {
+    type Target = Font;
+
+    fn deref(&self) -> &Font {
+        &self.font
+    }
+}
+
+/// The key fonts are interned by.
+#[derive(Debug, Hash, PartialEq, Eq)]
+enum FontKey {
+    /// A font file, by path and index.
+    Path(PathBuf, u32),
+    /// A font in memory, by the address of its data, and index. The interned
+    /// font keeps the data alive, so that the address can't be reused.
+    Memory(usize, u32),
+}
+
+thread_local! {
+    static BUNDLED_FONT: Rc<SharedFont> = SharedFont::new(
+        Font::from_bytes(Arc::new(BUNDLED_TEST_FONT.to_vec()), 0)
+            .expect("The bundled test font should be valid"),
+    );
+
+    /// The fonts loaded so far, so that we don't read the same files over and
+    /// over, and so that each face has a single identity for the shaping
+    /// cache.
+    static FONTS: RefCell<HashMap<FontKey, Rc<SharedFont>>> = RefCell::new(HashMap::new());
+}
+
+fn bundled_test_font() -> Rc<SharedFont> {
+    BUNDLED_FONT.with(|font| font.clone())
+}
+
+/// Loads the font that a handle points to, reusing it if we've loaded it
+/// before.
+pub fn load_font(handle: Handle) -> Option<Rc<SharedFont>> {
+    let key = match handle {
+        Handle::Path { ref path, font_index } => FontKey::Path(path.clone(), font_index),
+        Handle::Memory { ref bytes, font_index } => FontKey::Memory(bytes.as_ptr() as usize, font_index),
+    };
+    if let Some(font) = FONTS.with(|fonts| fonts.borrow().get(&key).cloned()) {
+        return Some(font);
+    }
+    let font = match handle.load() {
+        Ok(font) => SharedFont::new(font),
+        Err(e) => {
+            warn!("Failed to load font {:?}: {:?}", key, e);
+            return None;
+        },
+    };
+    FONTS.with(|fonts| fonts.borrow_mut().insert(key, font.clone()));
+    Some(font)
+}
 
 fn to_font_kit_family(f: &SingleFontFamily) -> FamilyName {
     match *f {
@@ -20,56 +147,173 @@ fn to_font_kit_family(f: &SingleFontFamily) -> FamilyName {
 }
 
 fn properties_for_style(style: &ComputedStyle) -> Properties {
-    use font_kit::properties::{Weight, Style};
+    use font_kit::properties::{Stretch, Style, Weight};
     let mut properties = Properties::new();
     properties
         .style(match style.font_style {
             FontStyle::Normal => Style::Normal,
             FontStyle::Italic => Style::Italic,
         })
-        .weight(match style.font_weight {
-            FontWeight::Normal => Weight::NORMAL,
-            FontWeight::Bold => Weight::BOLD,
-        });
+        .weight(Weight(style.font_weight.0))
+        .stretch(Stretch(style.font_stretch.0));
     properties
 }
 
-pub struct Loader {
+bitflags! {
+    /// The font properties that we need to synthesize because the selected
+    /// face doesn't have them.
+    ///
+    /// https://drafts.csswg.org/css-fonts/#font-synthesis-intro
+    pub struct FontSynthesis: u8 {
+        /// Glyphs are emboldened, by painting them multiple times with a
+        /// small offset, and their advances grow accordingly.
+        const BOLD = 1 << 0;
+        /// Glyphs are skewed to simulate an oblique face.
+        const OBLIQUE = 1 << 1;
+    }
+}
+
+/// The horizontal skew we apply to synthesize oblique faces, as the tangent of
+/// the default oblique angle, 14 degrees.
+///
+/// https://drafts.csswg.org/css-fonts/#valdef-font-style-oblique-angle
+pub const SYNTHETIC_OBLIQUE_SKEW: f32 = 0.249;
+
+impl FontSynthesis {
+    fn needed(font: &Font, requested: &Properties) -> Self {
+        use font_kit::properties::Style;
+        let actual = font.properties();
+        let mut synthesis = FontSynthesis::empty();
+        // Only synthesize bold if the face is not bold-ish itself.
+        if requested.weight.0 >= 600. && actual.weight.0 < 600. {
+            synthesis.insert(FontSynthesis::BOLD);
+        }
+        if requested.style != Style::Normal && actual.style == Style::Normal {
+            synthesis.insert(FontSynthesis::OBLIQUE);
+        }
+        synthesis
+    }
+
+    /// The extra advance that synthetic bold adds to each glyph.
+    pub fn bold_offset(&self, font_size: Au) -> Au {
+        if !self.contains(FontSynthesis::BOLD) {
+            return Au(0);
+        }
+        // This is roughly what browsers do, a sixteenth of the font size,
+        // but at least one pixel.
+        std::cmp::max(font_size / 16, Au::from_px(1))
+    }
+}
+
+/// A font selected by the loader, along with the properties we need to
+/// synthesize for it.
+#[derive(Clone)]
+pub struct LoadedFont {
+    pub font: Rc<SharedFont>,
+    pub synthesis: FontSynthesis,
+}
+
+impl LoadedFont {
+    fn new(font: Rc<SharedFont>, requested: &Properties) -> Self {
+        let synthesis = FontSynthesis::needed(&font, requested);
+        Self { font, synthesis }
+    }
+}
+
+impl Deref for LoadedFont {
+    type Target = Font;
+
+    fn deref(&self) -> &Font {
+        &self.font.font
+    }
+}
+
+/// Looks up a family in the document's font-face set.
+fn font_face_for_family(
+    font_faces: &FontFaceSet,
+    family: &FamilyName,
+    weight: FontWeight,
+    style: FontStyle,
+) -> Option<Rc<SharedFont>> {
+    match *family {
+        FamilyName::Title(ref name) => font_faces.find(name, weight, style),
+        _ => None,
+    }
+}
+
+pub struct Loader<'a> {
     source: SystemSource,
+    /// The font faces of the document we're loading fonts for.
+    font_faces: &'a FontFaceSet,
     family_list: Vec<FamilyName>,
     properties: Properties,
+    font_weight: FontWeight,
+    font_style: FontStyle,
     /// Lazily populated, the first available font as per
     /// https://drafts.csswg.org/css-fonts/#first-available-font
-    first_available_font: Option<Font>,
+    first_available_font: Option<LoadedFont>,
     /// Lazily populated last-resort fallback font.
-    system_fallback: Option<Font>,
+    system_fallback: Option<LoadedFont>,
     /// The set of cached fonts, one entry per entry in `family_list`, computed
     /// on demand.
-    cached_fonts: Vec<Option<Font>>,
+    cached_fonts: Vec<Option<LoadedFont>>,
 }
 
-impl Loader {
-    pub fn new(style: &ComputedStyle) -> Self {
+impl<'a> Loader<'a> {
+    pub fn new(style: &ComputedStyle, font_faces: &'a FontFaceSet) -> Self {
         let source = SystemSource::new();
         let family_list =
             style.font_family.iter().map(to_font_kit_family).collect::<Vec<_>>();
         let properties = properties_for_style(style);
+        if font_faces.font_source() == FontSource::BundledTestFont {
+            // Pre-populate all the fonts with the test font, so that we never
+            // look at the system fonts. Web fonts still take precedence, since
+            // they're deterministic anyway.
+            let font = LoadedFont::new(bundled_test_font(), &properties);
+            let cached_fonts = family_list
+                .iter()
+                .map(|family| {
+                    let face = font_face_for_family(font_faces, family, style.font_weight, style.font_style);
+                    Some(match face {
+                        Some(face) => LoadedFont::new(face, &properties),
+                        None => font.clone(),
+                    })
+                })
+                .collect::<Vec<_>>();
+            return Self {
+                source,
+                font_faces,
+                first_available_font: cached_fonts.first().cloned().unwrap_or_else(|| Some(font.clone())),
+                cached_fonts,
+                family_list,
+                properties,
+                font_weight: style.font_weight,
+                font_style: style.font_style,
+                system_fallback: Some(font),
+            };
+        }
         Self {
             source,
+            font_faces,
             family_list,
             properties,
+            font_weight: style.font_weight,
+            font_style: style.font_style,
             first_available_font: None,
             system_fallback: None,
             cached_fonts: vec![],
         }
     }
 
-    pub fn first_available_font(&mut self) -> &Font {
+    pub fn first_available_font(&mut self) -> &LoadedFont {
         if let Some(ref font) = self.first_available_font {
             return font;
         }
-        if let Ok(handle) = self.source.select_best_match(&self.family_list, &self.properties) {
-            if let Ok(font) = handle.load() {
+        // Go through font_at() rather than asking the system source for the
+        // best match, so that we take web fonts into account.
+        for i in 0..self.family_list.len() {
+            if let Some(font) = self.font_at(i) {
+                let font = font.clone();
                 self.first_available_font = Some(font);
                 return self.first_available_font.as_ref().unwrap()
             }
@@ -80,16 +324,17 @@ impl Loader {
         self.first_available_font.as_ref().unwrap()
     }
 
-    fn fallback_font(&mut self) -> &Font {
+    fn fallback_font(&mut self) -> &LoadedFont {
         if let Some(ref fallback) = self.system_fallback {
             return fallback;
         }
-        let fallback = self.source.select_best_match(&[FamilyName::Serif], &self.properties).unwrap().load().unwrap();
-        self.system_fallback = Some(fallback);
+        let handle = self.source.select_best_match(&[FamilyName::Serif], &self.properties).unwrap();
+        let fallback = load_font(handle).unwrap();
+        self.system_fallback = Some(LoadedFont::new(fallback, &self.properties));
         self.system_fallback.as_ref().unwrap()
     }
 
-    pub fn font_at(&mut self, i: usize) -> Option<&Font> {
+    pub fn font_at(&mut self, i: usize) -> Option<&LoadedFont> {
         if i < self.cached_fonts.len() {
             return self.cached_fonts[i].as_ref();
         }
@@ -100,13 +345,21 @@ impl Loader {
         let family = &self.family_list[i];
         debug_assert_eq!(self.cached_fonts.len(), i, "Should only query fonts in order");
 
+        // The document's font-face set takes precedence over system fonts.
+        //
+        // https://drafts.csswg.org/css-fonts/#font-matching-algorithm
+        let font = font_face_for_family(self.font_faces, family, self.font_weight, self.font_style);
+
         // TODO(emilio): Avoid clone? There seems to be no better API than
         // this...
-        let family = self.source.select_best_match(
-            &[family.clone()],
-            &self.properties,
-        ).ok().and_then(|f| f.load().ok());
-        self.cached_fonts.push(family);
+        let font = font.or_else(|| {
+            self.source.select_best_match(
+                std::slice::from_ref(family),
+                &self.properties,
+            ).ok().and_then(load_font)
+        });
+        let font = font.map(|font| LoadedFont::new(font, &self.properties));
+        self.cached_fonts.push(font);
         self.cached_fonts[i].as_ref()
     }
 
@@ -0,0 +1,106 @@
+//! Text measurement for intrinsic sizing.
+//!
+//! This computes the min-content and max-content contributions of a piece of
+//! text without going through the line breaker or building any fragment.
+//!
+//! https://drafts.csswg.org/css-sizing-3/#intrinsic-sizes
+
+use crate::layout_algorithms::inline::is_between_letters;
+use crate::sizing::MinMaxSizes;
+use crate::style::{ComputedStyle, OverflowWrap, WordBreak};
+use super::font_face::FontFaceSet;
+use app_units::Au;
+use smallbitvec::SmallBitVec;
+use std::cmp;
+
+/// U+00AD SOFT HYPHEN, which is not rendered unless we break after it.
+const SOFT_HYPHEN: char = '\u{ad}';
+
+/// Measures `text`, which is assumed to have gone through white-space
+/// processing already.
+///
+/// The min-content size is the advance of the widest unbreakable segment, and
+/// the max-content size the advance of the widest line, that is, of the widest
+/// segment between forced breaks. Spaces that would hang at the end of a line
+/// are not accounted for in either.
+///
+/// TODO(emilio): Account for hyphenation opportunities and tab stops.
+pub fn measure(text: &str, style: &ComputedStyle, font_faces: &FontFaceSet) -> MinMaxSizes {
+    let white_space = style.white_space;
+    let word_break = style.word_break;
+
+    let mut soft_breaks = SmallBitVec::new();
+    soft_breaks.resize(text.len(), false);
+    let mut forced_breaks = SmallBitVec::new();
+    forced_breaks.resize(text.len(), false);
+
+    let mut breaker = xi_unicode::LineBreakLeafIter::new(text, 0);
+    loop {
+        let (offset, hard_break) = breaker.next(text);
+        if offset >= text.len() {
+            break;
+        }
+        if hard_break {
+            forced_breaks.set(offset, true);
+        } else if white_space.allows_soft_wrap() &&
+            (word_break != WordBreak::KeepAll || !is_between_letters(text, offset))
+        {
+            soft_breaks.set(offset, true);
+        }
+    }
+
+    if white_space.allows_soft_wrap() {
+        // overflow-wrap: anywhere considers the emergency opportunities for
+        // min-content sizes, unlike break-word.
+        let anywhere = style.overflow_wrap == OverflowWrap::Anywhere;
+        for (i, _) in text.char_indices() {
+            if (anywhere && i != 0) ||
+                (word_break == WordBreak::BreakAll && is_between_letters(text, i))
+            {
+                soft_breaks.set(i, true);
+            }
+        }
+    }
+
+    let hang = white_space.trailing_spaces_hang();
+    let mut sizes = MinMaxSizes {
+        min_content: Au(0),
+        max_content: Au(0),
+    };
+    let mut segment = Au(0);
+    let mut line = Au(0);
+    // The advance of the spaces after the last non-space glyph, which only
+    // count if something else follows them in the same segment or line.
+    let mut segment_spaces = Au(0);
+    let mut line_spaces = Au(0);
+    for glyph in super::shaping::shape(text, style, font_faces).glyphs() {
+        let offset = glyph.byte_offset;
+        if forced_breaks[offset] || soft_breaks[offset] {
+            sizes.min_content = cmp::max(sizes.min_content, segment);
+            segment = Au(0);
+            segment_spaces = Au(0);
+        }
+        if forced_breaks[offset] {
+            sizes.max_content = cmp::max(sizes.max_content, line);
+            line = Au(0);
+            line_spaces = Au(0);
+        }
+        let c = text.as_bytes()[offset];
+        if c == b'\n' || text[offset..].starts_with(SOFT_HYPHEN) {
+            continue;
+        }
+        if hang && (c == b' ' || c == b'\t') {
+            segment_spaces += glyph.advance;
+            line_spaces += glyph.advance;
+            continue;
+        }
+        segment += segment_spaces + glyph.advance;
+        line += line_spaces + glyph.advance;
+        segment_spaces = Au(0);
+        line_spaces = Au(0);
+    }
+
+    sizes.min_content = cmp::max(sizes.min_content, segment);
+    sizes.max_content = cmp::max(sizes.max_content, line);
+    sizes
+}
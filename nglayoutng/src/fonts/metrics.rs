@@ -1,11 +1,29 @@
 use crate::Au;
+use crate::logical_geometry::WritingMode;
 use crate::style::{ComputedStyle, Length};
+use super::font_face::FontFaceSet;
 
 pub struct FontMetrics {
     metrics: font_kit::metrics::Metrics,
+    /// The ascent and descent of the font in vertical typographic mode, in
+    /// font units, as per the `vhea` table, if present.
+    vertical_metrics: Option<(f32, f32)>,
     size: Length,
 }
 
+/// Reads the vertical ascent and descent out of the `vhea` table.
+///
+/// https://docs.microsoft.com/en-us/typography/opentype/spec/vhea
+fn vertical_metrics_from_vhea(vhea: &[u8]) -> Option<(f32, f32)> {
+    let read_i16 = |offset: usize| -> Option<i16> {
+        let bytes = vhea.get(offset..offset + 2)?;
+        Some(i16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+    let ascent = read_i16(4)?;
+    let descent = read_i16(6)?;
+    Some((ascent as f32, descent as f32))
+}
+
 impl FontMetrics {
     fn to_length(&self, font_units: f32) -> Length {
         Length(Au::from_f32_px(font_units * self.size.to_f32_px() / self.metrics.units_per_em as f32))
@@ -34,15 +52,56 @@ impl FontMetrics {
     pub fn underline_thickness(&self) -> Length {
         self.to_length(self.metrics.underline_position)
     }
+
+    /// The ascent in vertical typographic mode, that is, the distance from the
+    /// central baseline to the line-right edge.
+    ///
+    /// If the font has no vertical metrics, this is half of the em size.
+    pub fn vertical_ascent(&self) -> Length {
+        match self.vertical_metrics {
+            Some((ascent, _)) => self.to_length(ascent),
+            None => Length(self.size.0 / 2),
+        }
+    }
+
+    /// The descent in vertical typographic mode, which, like `descent()`, is
+    /// negative.
+    pub fn vertical_descent(&self) -> Length {
+        match self.vertical_metrics {
+            Some((_, descent)) => self.to_length(descent),
+            None => Length(-self.size.0 / 2),
+        }
+    }
+
+    /// The ascent in the block axis for a given writing mode.
+    pub fn block_ascent(&self, wm: WritingMode) -> Length {
+        // TODO(emilio): Sideways text should use the horizontal metrics.
+        if wm.is_vertical() {
+            self.vertical_ascent()
+        } else {
+            self.ascent()
+        }
+    }
+
+    /// The descent in the block axis for a given writing mode (negative).
+    pub fn block_descent(&self, wm: WritingMode) -> Length {
+        if wm.is_vertical() {
+            self.vertical_descent()
+        } else {
+            self.descent()
+        }
+    }
 }
 
 impl FontMetrics {
-    pub fn from_style(style: &ComputedStyle) -> Self {
-        let mut loader = super::loader::Loader::new(style);
+    pub fn from_style(style: &ComputedStyle, font_faces: &FontFaceSet) -> Self {
+        let mut loader = super::loader::Loader::new(style, font_faces);
         let font = loader.first_available_font();
         trace!("FontMetrics::from_style() -> {}", font.full_name());
+        let vhea = font.load_font_table(u32::from_be_bytes(*b"vhea"));
         FontMetrics {
             metrics: font.metrics(),
+            vertical_metrics: vhea.and_then(|vhea| vertical_metrics_from_vhea(&vhea)),
             size: style.font_size,
         }
     }
@@ -55,7 +114,7 @@ fn test_font() {
     style.set_named_font_family("Bitstream Vera Sans");
     let style = style.finish(true);
 
-    let metrics = FontMetrics::from_style(&style);
+    let metrics = FontMetrics::from_style(&style, &Default::default());
     panic!(
         "ascent = {}, descent = {}, x-height = {}, cap-height = {}, \
          underline-position = {}, underline-thickness = {}",
@@ -1,3 +1,6 @@
+pub mod cache;
+pub mod font_face;
 pub mod loader;
+pub mod measure;
 pub mod metrics;
 pub mod shaping;
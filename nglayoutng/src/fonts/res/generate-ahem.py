@@ -0,0 +1,175 @@
+#!/usr/bin/env python3
+"""
+Generates Ahem.ttf, a minimal font compatible with the Ahem test font, so that
+layout tests don't depend on the fonts installed on the system.
+
+Like Ahem, the em square is 1000 units, the ascent is 800 and the descent is
+200. Every character maps to a glyph that covers the whole em box, except for
+the space characters, which are blank.
+
+Usage: ./generate-ahem.py > Ahem.ttf
+"""
+
+import struct
+import sys
+
+UNITS_PER_EM = 1000
+ASCENT = 800
+DESCENT = 200
+FAMILY = "Ahem"
+
+# Glyph 0 is .notdef, glyph 1 the full square, glyph 2 the blank space.
+NOTDEF, SQUARE, BLANK = 0, 1, 2
+SPACES = [0x20, 0xA0, 0x2002, 0x2003, 0x2009, 0x3000]
+
+
+def checksum(data):
+    data += b"\0" * (-len(data) % 4)
+    return sum(struct.unpack(">%dI" % (len(data) // 4), data)) & 0xFFFFFFFF
+
+
+def square_glyph():
+    # One contour, four on-curve points, clockwise.
+    points = [(0, -DESCENT), (0, ASCENT), (UNITS_PER_EM, ASCENT), (UNITS_PER_EM, -DESCENT)]
+    data = struct.pack(">hhhhh", 1, 0, -DESCENT, UNITS_PER_EM, ASCENT)
+    data += struct.pack(">H", len(points) - 1)  # endPtsOfContours
+    data += struct.pack(">H", 0)  # instructionLength
+    data += bytes([0x01] * len(points))  # flags: on curve, 16-bit deltas
+    last = 0
+    for x, _ in points:
+        data += struct.pack(">h", x - last)
+        last = x
+    last = 0
+    for _, y in points:
+        data += struct.pack(">h", y - last)
+        last = y
+    return data
+
+
+def glyf_and_loca():
+    glyphs = [square_glyph(), square_glyph(), b""]
+    glyf = b""
+    offsets = []
+    for glyph in glyphs:
+        offsets.append(len(glyf))
+        glyf += glyph + b"\0" * (-len(glyph) % 4)
+    offsets.append(len(glyf))
+    loca = b"".join(struct.pack(">H", offset // 2) for offset in offsets)
+    return glyf, loca, len(glyphs)
+
+
+def cmap():
+    # Format 13 (many-to-one) subtable for the whole Unicode range, with holes
+    # for the space characters.
+    groups = []
+    start = 0x20
+    for space in SPACES:
+        if start < space:
+            groups.append((start, space - 1, SQUARE))
+        groups.append((space, space, BLANK))
+        start = space + 1
+    groups.append((start, 0x10FFFF, SQUARE))
+    subtable = struct.pack(">HHIII", 13, 0, 16 + 12 * len(groups), 0, len(groups))
+    for group in groups:
+        subtable += struct.pack(">III", *group)
+    return struct.pack(">HHHHI", 0, 1, 3, 10, 12) + subtable
+
+
+def name():
+    records = [
+        (1, FAMILY),
+        (2, "Regular"),
+        (4, FAMILY),
+        (6, FAMILY),
+    ]
+    strings = b""
+    entries = b""
+    for name_id, value in records:
+        encoded = value.encode("utf-16-be")
+        entries += struct.pack(">HHHHHH", 3, 1, 0x409, name_id, len(encoded), len(strings))
+        strings += encoded
+    header = struct.pack(">HHH", 0, len(records), 6 + 12 * len(records))
+    return header + entries + strings
+
+
+def os2():
+    return struct.pack(
+        ">HhHHHhhhhhhhhhhh10sIIIIIHHHhhhHHIIhhHHH",
+        4,  # version
+        UNITS_PER_EM,  # xAvgCharWidth
+        400,  # usWeightClass
+        5,  # usWidthClass
+        0,  # fsType
+        UNITS_PER_EM, UNITS_PER_EM, 0, 0,  # subscript
+        UNITS_PER_EM, UNITS_PER_EM, 0, 0,  # superscript
+        50, ASCENT // 2,  # strikeout size / position
+        0,  # sFamilyClass
+        b"\0" * 10,  # panose
+        0, 0, 0, 0,  # ulUnicodeRange
+        0,  # achVendID
+        0x40 | 0x80,  # fsSelection: REGULAR | USE_TYPO_METRICS
+        0x20, 0xFFFF,  # first / last char index
+        ASCENT, -DESCENT, 0,  # typo ascender / descender / line gap
+        ASCENT, DESCENT,  # win ascent / descent
+        1, 0,  # ulCodePageRange
+        ASCENT, ASCENT,  # sxHeight / sCapHeight
+        0, 0x20, 0,  # default / break char, max context
+    )
+
+
+def font():
+    glyf, loca, num_glyphs = glyf_and_loca()
+    tables = {
+        b"OS/2": os2(),
+        b"cmap": cmap(),
+        b"glyf": glyf,
+        b"head": struct.pack(
+            ">IIIIHHqqhhhhHHhhh",
+            0x00010000, 0x00010000, 0, 0x5F0F3CF5, 0x000B, UNITS_PER_EM,
+            0, 0,  # created / modified
+            0, -DESCENT, UNITS_PER_EM, ASCENT,
+            0, 8, 2, 0, 0,
+        ),
+        b"hhea": struct.pack(
+            ">IhhhHhhhhhhhhhhhH",
+            0x00010000, ASCENT, -DESCENT, 0, UNITS_PER_EM,
+            0, 0, UNITS_PER_EM, 1, 0, 0, 0, 0, 0, 0, 0, num_glyphs,
+        ),
+        b"hmtx": struct.pack(">Hh", UNITS_PER_EM, 0) * num_glyphs,
+        b"loca": loca,
+        b"maxp": struct.pack(
+            ">IHHHHHHHHHHHHHH", 0x00010000, num_glyphs, 4, 1, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0,
+        ),
+        b"name": name(),
+        b"post": struct.pack(">IIhhIIIII", 0x00030000, 0, -75, 50, 0, 0, 0, 0, 0),
+    }
+
+    tags = sorted(tables)
+    search_range = 1
+    entry_selector = 0
+    while search_range * 2 <= len(tags):
+        search_range *= 2
+        entry_selector += 1
+    header = struct.pack(
+        ">IHHHH", 0x00010000, len(tags), search_range * 16, entry_selector,
+        (len(tags) - search_range) * 16,
+    )
+
+    offset = len(header) + 16 * len(tags)
+    directory = b""
+    data = b""
+    for tag in tags:
+        table = tables[tag]
+        directory += struct.pack(">4sIII", tag, checksum(table), offset + len(data), len(table))
+        data += table + b"\0" * (-len(table) % 4)
+
+    result = bytearray(header + directory + data)
+    # Fix up head.checkSumAdjustment.
+    head_offset = struct.unpack(">I", directory[16 * tags.index(b"head") + 8:][:4])[0]
+    adjustment = (0xB1B0AFBA - checksum(bytes(result))) & 0xFFFFFFFF
+    result[head_offset + 8:head_offset + 12] = struct.pack(">I", adjustment)
+    return bytes(result)
+
+
+if __name__ == "__main__":
+    sys.stdout.buffer.write(font())
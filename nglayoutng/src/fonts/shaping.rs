@@ -1,9 +1,16 @@
-use crate::style::ComputedStyle;
+use crate::style::{ComputedStyle, Direction, FontVariantCaps};
+use super::cache::LruCache;
+use super::font_face::FontFaceSet;
+use super::loader::{FontSynthesis, LoadedFont, SharedFont};
 use smallvec::SmallVec;
 use euclid::default::Point2D;
 use app_units::Au;
-// use harfbuzz as hb;
-// use unicode_script::Script;
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::rc::Rc;
+use unicode_script::{Script, UnicodeScript};
 
 // TODO: we may want to have one of these per _character_, not per glyph, and
 // use that for efficient lookup (tagging whether we're a ligature start or such
@@ -11,18 +18,31 @@ use app_units::Au;
 //
 // TODO: We definitely want to optimize these for memory usage and common cases
 // where offset is zero.
+#[derive(Clone, Debug)]
 pub struct GlyphInfo {
     /// The actual glyph id of the font.
     pub glyph_id: u32,
     /// Relative position of the glyph.
     pub offset: Point2D<Au>,
-    /// Horizontal advance.
+    /// Advance in the inline axis, which is vertical when shaping for vertical
+    /// writing modes.
     pub advance: Au,
     /// Offset into the text.
     pub byte_offset: usize,
+    /// Whether this glyph needs to be rotated 90 degrees clockwise when
+    /// painting, because it's sideways text in a vertical writing mode.
+    pub sideways: bool,
+    /// The synthetic bold / oblique that needs to be applied when painting
+    /// this glyph. The extra advance for synthetic bold is already accounted
+    /// for in `advance`.
+    pub synthesis: FontSynthesis,
+    /// Whether breaking the line right before this glyph requires re-shaping
+    /// the text around the break, because this glyph is part of a ligature
+    /// or its position depends on the previous glyph (kerning and such).
+    pub unsafe_to_break: bool,
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 struct ShapedTextRun {
     glyphs: SmallVec<[GlyphInfo; 32]>,
 }
@@ -38,36 +58,370 @@ impl ShapedText {
     }
 }
 
+/// The key of the shaping cache. This needs to contain everything that can
+/// affect the result of shaping a given run.
+///
+/// The key only contains a hash of the text, so that looking up a run doesn't
+/// need to allocate. The text is stored in the entry, and checked on lookup.
+#[derive(Clone, Hash, PartialEq, Eq)]
+struct ShapingCacheKey {
+    text_hash: u64,
+    font: FontIdentity,
+    size: Au,
+    direction: Direction,
+    orientation: GlyphOrientation,
+    script: Script,
+    synthesis: FontSynthesis,
+    features: SmallVec<[FontFeature; 2]>,
+    // TODO(emilio): Font variations, language...
+}
+
+struct ShapingCacheEntry {
+    text: Box<str>,
+    run: ShapedTextRun,
+}
+
+/// Identifies a font by its address, which the key keeps alive, so that it
+/// can't be reused by another font while the entry is in the cache.
+///
+/// Note that the font loader returns the same font for a given face when asked
+/// again, see `load_font`.
+#[derive(Clone)]
+struct FontIdentity(Rc<SharedFont>);
+
+impl PartialEq for FontIdentity {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for FontIdentity {}
+
+impl Hash for FontIdentity {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.0).hash(state)
+    }
+}
+
+/// An OpenType feature that applies to the whole run.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+struct FontFeature {
+    tag: [u8; 4],
+    value: u32,
+}
+
+impl FontFeature {
+    fn enabled(tag: &[u8; 4]) -> Self {
+        Self { tag: *tag, value: 1 }
+    }
+
+    fn to_rustybuzz(self) -> rustybuzz::Feature {
+        rustybuzz::Feature::new(rustybuzz::Tag::from_bytes(&self.tag), self.value, ..)
+    }
+}
+
+/// Returns the OpenType features that need to be applied for a given style.
+///
+/// https://drafts.csswg.org/css-fonts/#feature-precedence
+///
+/// TODO(emilio): Synthesize small-caps for fonts without `smcp` / `c2sc`
+/// support, by shaping uppercased text at a reduced size.
+fn font_features(style: &ComputedStyle) -> SmallVec<[FontFeature; 2]> {
+    let mut features = SmallVec::new();
+    // https://drafts.csswg.org/css-fonts/#font-variant-caps-prop
+    match style.font_variant_caps {
+        FontVariantCaps::Normal => {},
+        FontVariantCaps::SmallCaps => {
+            features.push(FontFeature::enabled(b"smcp"));
+        },
+        FontVariantCaps::AllSmallCaps => {
+            features.push(FontFeature::enabled(b"smcp"));
+            features.push(FontFeature::enabled(b"c2sc"));
+        },
+    }
+    // font-feature-settings goes last, since it overrides the features
+    // enabled by other properties.
+    for feature in style.font_feature_settings.0.iter() {
+        features.push(FontFeature { tag: feature.tag, value: feature.value });
+    }
+    features
+}
+
+/// The maximum amount of runs we keep in the shaping cache.
+const SHAPING_CACHE_SIZE: usize = 1024;
+
+thread_local! {
+    static SHAPING_CACHE: RefCell<LruCache<ShapingCacheKey, ShapingCacheEntry>> =
+        RefCell::new(LruCache::new(SHAPING_CACHE_SIZE));
+}
+
+/// Clears the shaping cache of the current thread, e.g., because fonts have
+/// changed.
+pub fn clear_cache() {
+    SHAPING_CACHE.with(|cache| cache.borrow_mut().clear())
+}
+
 // TODO: Split if there's font fallback
 pub fn shape(
     text: &str,
     style: &ComputedStyle,
+    font_faces: &FontFaceSet,
 ) -> ShapedText {
-    let mut loader = super::loader::Loader::new(style);
+    let mut loader = super::loader::Loader::new(style, font_faces);
 
-    // Itemize per font.
-    let mut last_font = std::usize::MAX;
+    // Itemize per font and script.
+    //
+    // Characters with the Common or Inherited scripts (spaces, punctuation,
+    // combining marks...) take the script of the surrounding text, so they
+    // don't split runs.
+    //
+    // https://unicode.org/reports/tr24/#Common
+    //
+    // TODO(emilio): Paired punctuation (brackets and such) should resolve to
+    // the script of the opening character. Also, font fallback should take
+    // the script into account.
+    //
+    // In vertical writing modes, we also itemize per glyph orientation.
+    let mut last_font = usize::MAX;
+    let mut last_script = Script::Common;
+    let mut last_orientation = None;
     let mut start = 0;
     let mut current = 0;
     let mut runs = SmallVec::<[_; 3]>::new();
     for c in text.chars() {
         let font = loader.font_for_character(c);
-        if font != last_font && start != current {
-            runs.push((start..current, font));
+        let script = match c.script() {
+            Script::Common | Script::Inherited => last_script,
+            script => script,
+        };
+        let orientation = GlyphOrientation::for_character(c, style);
+        // A run with only common characters so far just takes the script
+        // of the first real character.
+        let script_changed = script != last_script && last_script != Script::Common;
+        let orientation_changed = last_orientation.is_some_and(|last| last != orientation);
+        if (font != last_font || script_changed || orientation_changed) && start != current {
+            runs.push((start..current, last_font, last_script, last_orientation.unwrap()));
             start = current;
         }
         current += c.len_utf8();
         last_font = font;
+        last_script = script;
+        last_orientation = Some(orientation);
     }
 
     if start != current {
-        runs.push((start..current, last_font));
+        runs.push((start..current, last_font, last_script, last_orientation.unwrap()));
+    }
+
+    let mut shaped = ShapedText::default();
+    for (range, font_index, script, orientation) in runs {
+        let font = loader
+            .font_at(font_index)
+            .expect("font_for_character should return an available font");
+        shaped.0.push(shape_run_cached(text, range, font, script, orientation, style));
     }
+    shaped
+}
+
+/// How glyphs are laid out with respect to the inline axis.
+///
+/// https://drafts.csswg.org/css-writing-modes-3/#text-orientation
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+enum GlyphOrientation {
+    /// Horizontal text, laid out normally.
+    Horizontal,
+    /// Text in a vertical writing mode, typeset upright, in vertical
+    /// typographic mode.
+    Upright,
+    /// Text in a vertical writing mode, typeset as horizontal text, but
+    /// rotated 90 degrees clockwise.
+    Sideways,
+}
 
-    // TODO:
-    // for &(range, font_index) in &runs {
-    //     shape_run(..)
-    // }
+impl GlyphOrientation {
+    fn for_character(c: char, style: &ComputedStyle) -> Self {
+        let wm = style.writing_mode;
+        if !wm.is_vertical() {
+            return GlyphOrientation::Horizontal;
+        }
+        if wm.is_upright() {
+            return GlyphOrientation::Upright;
+        }
+        if wm.is_sideways() {
+            return GlyphOrientation::Sideways;
+        }
+        // text-orientation: mixed.
+        if is_upright_in_mixed_orientation(c) {
+            GlyphOrientation::Upright
+        } else {
+            GlyphOrientation::Sideways
+        }
+    }
+}
+
+/// Returns whether a given character is typeset upright with
+/// `text-orientation: mixed`.
+///
+/// TODO(emilio): This should use the Vertical_Orientation property from UTR50
+/// (https://www.unicode.org/reports/tr50/), this is an approximation based on
+/// the script of the character and the most common CJK blocks.
+fn is_upright_in_mixed_orientation(c: char) -> bool {
+    match c.script() {
+        Script::Han |
+        Script::Hiragana |
+        Script::Katakana |
+        Script::Hangul |
+        Script::Bopomofo |
+        Script::Yi => return true,
+        _ => {},
+    }
+    match c as u32 {
+        // CJK Symbols and Punctuation, Enclosed CJK Letters and Months, CJK
+        // Compatibility.
+        0x3000..=0x303F | 0x3200..=0x33FF => true,
+        // Halfwidth and Fullwidth Forms (only the fullwidth ones).
+        0xFF01..=0xFF60 | 0xFFE0..=0xFFE6 => true,
+        _ => false,
+    }
+}
+
+/// Shapes the `range` of `text` with a given font, going through the shaping
+/// cache.
+fn shape_run_cached(
+    text: &str,
+    range: Range<usize>,
+    font: &LoadedFont,
+    script: Script,
+    orientation: GlyphOrientation,
+    style: &ComputedStyle,
+) -> ShapedTextRun {
+    let text = &text[range.clone()];
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    let key = ShapingCacheKey {
+        text_hash: hasher.finish(),
+        font: FontIdentity(font.font.clone()),
+        size: style.font_size.0,
+        direction: style.direction,
+        orientation,
+        script,
+        synthesis: font.synthesis,
+        features: font_features(style),
+    };
+
+    // Cached runs have byte offsets relative to the start of the run.
+    let rebase = |mut run: ShapedTextRun| {
+        for glyph in &mut run.glyphs {
+            glyph.byte_offset += range.start;
+        }
+        run
+    };
+
+    let cached = SHAPING_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        let entry = cache.get(&key)?;
+        // Different text with the same hash is just a cache miss.
+        if &*entry.text != text {
+            return None;
+        }
+        Some(entry.run.clone())
+    });
+    if let Some(run) = cached {
+        return rebase(run);
+    }
+
+    let run = shape_run(text, font, script, orientation, &key.features, style);
+    let entry = ShapingCacheEntry {
+        text: text.into(),
+        run: run.clone(),
+    };
+    SHAPING_CACHE.with(|cache| cache.borrow_mut().insert(key, entry));
+    rebase(run)
+}
+
+/// Shapes `text`, which is all in the same `script` and `orientation`, with a
+/// given font.
+fn shape_run(
+    text: &str,
+    font: &LoadedFont,
+    script: Script,
+    orientation: GlyphOrientation,
+    features: &[FontFeature],
+    style: &ComputedStyle,
+) -> ShapedTextRun {
+    let face = match font.font.shaping_face() {
+        Some(face) => face,
+        None => return Default::default(),
+    };
+
+    let vertical = orientation == GlyphOrientation::Upright;
+    let mut buffer = rustybuzz::UnicodeBuffer::new();
+    buffer.push_str(text);
+    buffer.set_direction(match style.direction {
+        _ if vertical => rustybuzz::Direction::TopToBottom,
+        Direction::Ltr => rustybuzz::Direction::LeftToRight,
+        Direction::Rtl => rustybuzz::Direction::RightToLeft,
+    });
+    // Common means we couldn't figure out the script at all, let the shaper
+    // guess it then.
+    if script != Script::Common {
+        // short_name() returns the ISO 15924 code of the script.
+        if let Ok(script) = script.short_name().parse() {
+            buffer.set_script(script);
+        }
+    }
+    // TODO(emilio): Use the content language once we support `lang`.
+    buffer.set_language("en".parse().unwrap());
+    // This fills in the script if we haven't set it above.
+    buffer.guess_segment_properties();
+
+    // Positions are returned in font units, we scale them ourselves.
+    let units_per_em = face.units_per_em();
+    let scale = style.font_size.0.to_f32_px() / units_per_em as f32;
+    let to_au = |font_units: i32| Au::from_f32_px(font_units as f32 * scale);
+    let bold_offset = font.synthesis.bold_offset(style.font_size.0);
+
+    let features = features.iter().copied().map(FontFeature::to_rustybuzz).collect::<SmallVec<[_; 2]>>();
+    let output = rustybuzz::shape(face, &features, buffer);
+
+    let mut run = ShapedTextRun::default();
+    for (info, position) in output.glyph_infos().iter().zip(output.glyph_positions()) {
+        // Vertical advances go downwards, and are thus negative.
+        let advance = if vertical {
+            to_au(-position.y_advance)
+        } else {
+            to_au(position.x_advance)
+        };
+        run.glyphs.push(GlyphInfo {
+            glyph_id: info.glyph_id,
+            // The font's y axis goes up.
+            offset: Point2D::new(to_au(position.x_offset), to_au(-position.y_offset)),
+            // Synthetic bold only widens glyphs that advance (so not
+            // combining marks and such).
+            advance: if advance != Au(0) { advance + bold_offset } else { advance },
+            // Clusters are byte offsets into the text we added.
+            byte_offset: info.cluster as usize,
+            sideways: orientation == GlyphOrientation::Sideways,
+            synthesis: font.synthesis,
+            unsafe_to_break: info.unsafe_to_break(),
+        });
+    }
+
+    // The shaper returns the glyphs in visual order, but we want them in logical
+    // order for line-breaking.
+    if !vertical && style.direction == Direction::Rtl {
+        run.glyphs.reverse();
+    }
+
+    // Breaking before a glyph that is not the first of its cluster (in logical
+    // order) would split the cluster, so treat that as unsafe too.
+    let mut last_cluster = None;
+    for glyph in &mut run.glyphs {
+        if last_cluster == Some(glyph.byte_offset) {
+            glyph.unsafe_to_break = true;
+        }
+        last_cluster = Some(glyph.byte_offset);
+    }
 
-    Default::default()
+    run
 }
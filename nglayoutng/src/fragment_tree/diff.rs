@@ -0,0 +1,248 @@
+//! Structural comparison of two fragment trees, for inspecting layout
+//! changes between two documents, or between two layouts of a document.
+//!
+//! Fragments are matched between both trees by their kind, text and pseudo,
+//! not by their layout node, since both trees can come from different layout
+//! trees.
+
+use super::{ChildFragment, Fragment, FragmentKind, FragmentTree};
+use app_units::Au;
+use euclid::default::{Point2D, Rect, Size2D};
+use std::fmt;
+
+/// How a fragment changed from one tree to the other.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FragmentChange {
+    /// The fragment only exists in the new tree, with this border box.
+    Added(Rect<Au>),
+    /// The fragment only exists in the old tree, with this border box.
+    Removed(Rect<Au>),
+    /// The fragment changed its position relative to its parent.
+    Moved { old: Point2D<Au>, new: Point2D<Au> },
+    /// The fragment changed its size.
+    Resized { old: Size2D<Au>, new: Size2D<Au> },
+}
+
+/// A change to a single fragment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FragmentDiff {
+    /// The labels of the fragment and its ancestors, from the root.
+    pub path: Vec<String>,
+    pub change: FragmentChange,
+}
+
+fn px(au: Au) -> f32 {
+    au.to_f32_px()
+}
+
+impl fmt::Display for FragmentDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = self.path.join(" > ");
+        match self.change {
+            FragmentChange::Added(rect) => write!(
+                f,
+                "+ {}: {}x{} at ({}, {})",
+                path,
+                px(rect.size.width),
+                px(rect.size.height),
+                px(rect.origin.x),
+                px(rect.origin.y),
+            ),
+            FragmentChange::Removed(rect) => write!(
+                f,
+                "- {}: {}x{} at ({}, {})",
+                path,
+                px(rect.size.width),
+                px(rect.size.height),
+                px(rect.origin.x),
+                px(rect.origin.y),
+            ),
+            FragmentChange::Moved { old, new } => write!(
+                f,
+                "> {}: moved from ({}, {}) to ({}, {})",
+                path,
+                px(old.x),
+                px(old.y),
+                px(new.x),
+                px(new.y),
+            ),
+            FragmentChange::Resized { old, new } => write!(
+                f,
+                "~ {}: resized from {}x{} to {}x{}",
+                path,
+                px(old.width),
+                px(old.height),
+                px(new.width),
+                px(new.height),
+            ),
+        }
+    }
+}
+
+/// A fragment along with its physical border box, relative to the viewport.
+struct PositionedFragment<'a> {
+    child: &'a ChildFragment,
+    rect: Rect<Au>,
+}
+
+impl<'a> PositionedFragment<'a> {
+    fn root(tree: &'a FragmentTree) -> Self {
+        let wm = tree.root.fragment.style.writing_mode;
+        let viewport = Rect::new(Point2D::zero(), tree.viewport_size);
+        Self {
+            child: &tree.root,
+            rect: tree.root.physical_rect(wm, &viewport),
+        }
+    }
+
+    fn fragment(&self) -> &'a Fragment {
+        &self.child.fragment
+    }
+
+    fn children(&self) -> Vec<PositionedFragment<'a>> {
+        let fragment = self.fragment();
+        let children = match fragment.kind {
+            FragmentKind::Container { ref children, .. } => children,
+            FragmentKind::TextRun { .. } => return vec![],
+        };
+        let wm = fragment.style.writing_mode;
+        children
+            .iter()
+            .map(|child| PositionedFragment {
+                child,
+                rect: child.physical_rect(wm, &self.rect),
+            })
+            .collect()
+    }
+}
+
+/// Returns the changes needed to go from `old` to `new`, in tree order.
+pub fn diff(old: &FragmentTree, new: &FragmentTree) -> Vec<FragmentDiff> {
+    let mut diffs = vec![];
+    let old = PositionedFragment::root(old);
+    let new = PositionedFragment::root(new);
+    let mut path = vec![old.fragment().diff_label()];
+    diff_matched(&old, &new, Point2D::zero(), Point2D::zero(), &mut path, &mut diffs);
+    diffs
+}
+
+/// Compares two fragments that have been matched to each other, and their
+/// descendants. `old_parent_origin` and `new_parent_origin` are the origins of
+/// their parents, so that only the fragments whose position actually changed
+/// are reported as moved, and not all their descendants.
+fn diff_matched(
+    old: &PositionedFragment,
+    new: &PositionedFragment,
+    old_parent_origin: Point2D<Au>,
+    new_parent_origin: Point2D<Au>,
+    path: &mut Vec<String>,
+    diffs: &mut Vec<FragmentDiff>,
+) {
+    let old_position = (old.rect.origin - old_parent_origin).to_point();
+    let new_position = (new.rect.origin - new_parent_origin).to_point();
+    if old_position != new_position {
+        diffs.push(FragmentDiff {
+            path: path.clone(),
+            change: FragmentChange::Moved {
+                old: old_position,
+                new: new_position,
+            },
+        });
+    }
+    if old.rect.size != new.rect.size {
+        diffs.push(FragmentDiff {
+            path: path.clone(),
+            change: FragmentChange::Resized {
+                old: old.rect.size,
+                new: new.rect.size,
+            },
+        });
+    }
+
+    let old_children = old.children();
+    let new_children = new.children();
+    let old_labels = old_children.iter().map(|c| c.fragment().diff_label()).collect::<Vec<_>>();
+    let new_labels = new_children.iter().map(|c| c.fragment().diff_label()).collect::<Vec<_>>();
+
+    let mut report = |path: &mut Vec<String>, child: &PositionedFragment, label: &str, added: bool| {
+        path.push(label.to_owned());
+        diffs.push(FragmentDiff {
+            path: path.clone(),
+            change: if added {
+                FragmentChange::Added(child.rect)
+            } else {
+                FragmentChange::Removed(child.rect)
+            },
+        });
+        path.pop();
+    };
+
+    let mut old_index = 0;
+    let mut new_index = 0;
+    let mut matched = vec![];
+    for (o, n) in longest_common_subsequence(&old_labels, &new_labels) {
+        for i in old_index..o {
+            report(path, &old_children[i], &old_labels[i], false);
+        }
+        for i in new_index..n {
+            report(path, &new_children[i], &new_labels[i], true);
+        }
+        matched.push((o, n));
+        old_index = o + 1;
+        new_index = n + 1;
+    }
+    for i in old_index..old_children.len() {
+        report(path, &old_children[i], &old_labels[i], false);
+    }
+    for i in new_index..new_children.len() {
+        report(path, &new_children[i], &new_labels[i], true);
+    }
+
+    for (o, n) in matched {
+        path.push(old_labels[o].clone());
+        diff_matched(
+            &old_children[o],
+            &new_children[n],
+            old.rect.origin,
+            new.rect.origin,
+            path,
+            diffs,
+        );
+        path.pop();
+    }
+}
+
+/// Returns the pairs of indices of the longest common subsequence of `old`
+/// and `new`, in order.
+///
+/// This is quadratic, but sibling lists are usually short, and this is only
+/// used for debugging.
+fn longest_common_subsequence(old: &[String], new: &[String]) -> Vec<(usize, usize)> {
+    // lengths[i][j] is the length of the longest common subsequence of
+    // old[i..] and new[j..].
+    let mut lengths = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i] == new[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut pairs = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
@@ -1,26 +1,132 @@
+use crate::fonts::font_face::FontFaceSet;
+use crate::fonts::shaping::GlyphInfo;
+use crate::layout_tree::LayoutNodeId;
 use crate::logical_geometry::*;
-use crate::style::ComputedStyle;
+use crate::misc::dot::DotGraph;
+use crate::misc::print_tree::PrintTree;
+use crate::style::{BorderStyle, ComputedStyle};
 use app_units::Au;
+use cssparser::RGBA;
+use euclid::default::{Rect, Size2D};
+use std::ops::Range;
+use std::rc::Rc;
+
+pub mod diff;
+
+/// The result of laying out a whole layout tree.
+#[derive(Debug)]
+pub struct FragmentTree {
+    /// The fragment of the root box, positioned relative to the viewport.
+    pub root: ChildFragment,
+    /// The size of the viewport this tree was laid out in.
+    pub viewport_size: Size2D<Au>,
+    /// The font faces of the document, which the text of the tree needs to
+    /// be painted with.
+    pub font_faces: Rc<FontFaceSet>,
+}
+
+impl FragmentTree {
+    /// Prints the fragment tree to stdout.
+    pub fn print(&self) {
+        self.print_to(&mut std::io::stdout());
+    }
+
+    /// Prints the fragment tree to a particular output.
+    pub fn print_to(&self, dest: &mut dyn std::io::Write) {
+        let mut printer = PrintTree::new("Fragment tree", dest);
+        self.root.print(&mut printer);
+    }
+
+    /// Prints the fragment tree as a Graphviz graph, labeling fragments with
+    /// their physical size, and filling the anonymous ones.
+    pub fn print_dot_to(&self, dest: &mut dyn std::io::Write) {
+        let mut graph = DotGraph::new("Fragment tree", dest);
+        let mut next_id = 0;
+        self.root.print_dot(&mut graph, &mut next_id);
+    }
+}
 
 /// A child fragment contains a given fragment and an offset relative to the
 /// parent fragment.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ChildFragment {
     /// The offset relative to the parent fragment's origin.
     pub offset: LogicalPoint<Au>,
     /// The child fragment itself.
     ///
-    /// TODO(emilio): We might want to refcount fragments or something.
-    pub fragment: Box<Fragment>,
+    /// Fragments are immutable and reference-counted, so that unchanged
+    /// subtrees can be shared across layouts and with layout result caches.
+    pub fragment: Rc<Fragment>,
+}
+
+/// An ellipsis that needs to be rendered at the end of a truncated line, due
+/// to `text-overflow: ellipsis`.
+///
+/// https://drafts.csswg.org/css-overflow-3/#text-overflow
+#[derive(Debug)]
+pub struct LineEllipsis {
+    /// The inline offset relative to the line at which the ellipsis starts.
+    /// Content past this offset is hidden.
+    pub offset: Au,
+    /// The advance of the ellipsis.
+    pub advance: Au,
+}
+
+/// One side of the border of a box, resolved for painting.
+#[derive(Clone, Copy, Debug)]
+pub struct BorderSide {
+    /// The used width, which is zero for `none` and `hidden` borders.
+    pub width: Au,
+    pub style: BorderStyle,
+    pub color: RGBA,
+}
+
+/// The background and borders of a box fragment, with colors resolved.
+#[derive(Debug)]
+pub struct BoxDecorations {
+    pub background_color: RGBA,
+    /// The borders, in the writing mode of the fragment.
+    pub border: LogicalMargin<BorderSide>,
+}
+
+impl BoxDecorations {
+    pub fn from_style(style: &ComputedStyle) -> Self {
+        let widths = style.physical_border_widths();
+        let side = |width, border_style, color| BorderSide {
+            width,
+            style: border_style,
+            color: style.resolve_color(color),
+        };
+        let border = euclid::default::SideOffsets2D::new(
+            side(widths.top, style.border_top_style, &style.border_top_color),
+            side(widths.right, style.border_right_style, &style.border_right_color),
+            side(widths.bottom, style.border_bottom_style, &style.border_bottom_color),
+            side(widths.left, style.border_left_style, &style.border_left_color),
+        );
+        Self {
+            background_color: style.resolve_color(&style.background_color),
+            border: LogicalMargin::from_physical(style.writing_mode, border),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum ContainerFragmentKind {
     Box {
-        // TODO(emilio): Surely stuff will be needed here.
+        decorations: BoxDecorations,
+        // TODO(emilio): Surely more stuff will be needed here.
     },
     Line {
-        // TODO(emilio): Surely stuff will be needed here.
+        /// The ellipsis to render if the line is truncated.
+        ellipsis: Option<LineEllipsis>,
+        // TODO(emilio): Surely more stuff will be needed here.
+    },
+    /// The root of a document nested via an `<iframe>`, which shares the
+    /// style and size of its only child, the root box of the document.
+    Document {
+        /// The font faces of the nested document, which its text needs to be
+        /// painted with.
+        font_faces: Rc<FontFaceSet>,
     },
 }
 
@@ -33,14 +139,66 @@ pub enum ContainerFragmentKind {
 #[derive(Debug)]
 pub enum FragmentKind {
     TextRun {
+        /// The text this fragment renders.
         content: String,
+        /// The byte range of `content` in the text that was shaped, before
+        /// text-transform was applied.
+        ///
+        /// TODO(emilio): This should really map to the text of the layout
+        /// node(s) this fragment comes from.
+        range: Range<usize>,
+        /// The shaped glyphs, with byte offsets relative to `content`.
+        glyphs: Box<[GlyphInfo]>,
     },
     Container {
         kind: ContainerFragmentKind,
         children: Box<[ChildFragment]>,
+        overflow: OverflowAreas,
+        /// The rect this fragment clips its descendants to, relative to its
+        /// origin, if it's a scroll container. This is the padding box.
+        ///
+        /// https://drafts.csswg.org/css-overflow-3/#overflow-control
+        clip: Option<LogicalRect<Au>>,
     },
 }
 
+/// The overflow areas of a container fragment, relative to the origin of its
+/// border box and in its writing mode.
+///
+/// https://drafts.csswg.org/css-overflow-3/#overflow
+#[derive(Debug)]
+pub struct OverflowAreas {
+    /// The area the content of the box can be scrolled to: the border box,
+    /// plus the scrollable overflow of its in-flow descendants.
+    pub scrollable: LogicalRect<Au>,
+    /// The area where the box and its descendants paint.
+    ///
+    /// TODO(emilio): Account for glyph bounds, outlines and box shadows.
+    pub ink: LogicalRect<Au>,
+}
+
+impl OverflowAreas {
+    fn compute(size: LogicalSize<Au>, style: &ComputedStyle, children: &[ChildFragment]) -> Self {
+        let wm = style.writing_mode;
+        let border_box = LogicalRect::from_point_size(wm, LogicalPoint::zero(wm), size);
+        let mut overflow = OverflowAreas {
+            scrollable: border_box,
+            ink: border_box,
+        };
+        for child in children {
+            let child_wm = child.fragment.style.writing_mode;
+            let child_size = child.fragment.size.to_physical(child_wm);
+            let to_parent = |rect: LogicalRect<Au>| {
+                rect.convert(child_wm, wm, child_size).translate(&child.offset)
+            };
+            let (scrollable, ink) = child.fragment.overflow_contribution();
+            overflow.scrollable = overflow.scrollable.union(&to_parent(scrollable));
+            overflow.ink = overflow.ink.union(&to_parent(ink));
+        }
+        overflow
+    }
+}
+
 /// A fragment is part of the result of layout, and it's immutable.
 ///
 /// It contains only the sizing information, children are stored in .
@@ -48,8 +206,154 @@ pub enum FragmentKind {
 pub struct Fragment {
     /// The physical size of this fragment.
     pub size: LogicalSize<Au>,
-    /// The style of this fragment.
-    pub style: ComputedStyle,
+    /// The style of this fragment, shared with the layout node that
+    /// generated it.
+    pub style: Rc<ComputedStyle>,
+    /// The layout node that generated this fragment, if any. Line boxes are
+    /// not generated by any particular node.
+    pub node: Option<LayoutNodeId>,
     /// Which kind of fragment this is.
     pub kind: FragmentKind,
 }
+
+impl Fragment {
+    /// Creates a container fragment, computing its overflow areas out of its
+    /// children.
+    pub fn new_container(
+        size: LogicalSize<Au>,
+        style: Rc<ComputedStyle>,
+        node: Option<LayoutNodeId>,
+        kind: ContainerFragmentKind,
+        children: Box<[ChildFragment]>,
+    ) -> Self {
+        let overflow = OverflowAreas::compute(size, &style, &children);
+        // Line boxes use the style of their block container, but the block
+        // container is the one that clips.
+        let clip = match kind {
+            ContainerFragmentKind::Box { .. } if style.is_scroll_container() => {
+                let wm = style.writing_mode;
+                let border_box = LogicalRect::from_point_size(wm, LogicalPoint::zero(wm), size);
+                Some(border_box - style.border_widths())
+            },
+            _ => None,
+        };
+        Self {
+            size,
+            style,
+            node,
+            kind: FragmentKind::Container { kind, children, overflow, clip },
+        }
+    }
+
+    /// The border box of this fragment, relative to its own origin.
+    pub fn border_box(&self) -> LogicalRect<Au> {
+        let wm = self.style.writing_mode;
+        LogicalRect::from_point_size(wm, LogicalPoint::zero(wm), self.size)
+    }
+
+    /// Returns the scrollable and ink overflow that this fragment contributes
+    /// to its parent, relative to its own origin.
+    ///
+    /// Scroll containers clip their contents, so they only contribute their
+    /// border box.
+    fn overflow_contribution(&self) -> (LogicalRect<Au>, LogicalRect<Au>) {
+        match self.kind {
+            FragmentKind::Container { ref overflow, .. } if !self.style.is_scroll_container() => {
+                (overflow.scrollable, overflow.ink)
+            },
+            _ => (self.border_box(), self.border_box()),
+        }
+    }
+}
+
+impl ChildFragment {
+    /// Returns the physical border box of this fragment, given the writing
+    /// mode and the physical border box of its parent, in the same coordinate
+    /// space as the parent's.
+    pub fn physical_rect(&self, parent_wm: WritingMode, parent_rect: &Rect<Au>) -> Rect<Au> {
+        let wm = self.fragment.style.writing_mode;
+        let size = self.fragment.size.convert(wm, parent_wm);
+        LogicalRect::from_point_size(parent_wm, self.offset, size)
+            .to_physical(parent_wm, parent_rect.size)
+            .translate(parent_rect.origin.to_vector())
+    }
+
+    /// Adds this fragment and its descendants to `graph`, returning the name
+    /// of its node. Fragments are numbered in tree order, since they may not
+    /// come from any layout node.
+    fn print_dot(&self, graph: &mut DotGraph, next_id: &mut usize) -> String {
+        let name = format!("f{}", next_id);
+        *next_id += 1;
+
+        let fragment = &*self.fragment;
+        let size = fragment.size.to_physical(fragment.style.writing_mode);
+        let label = format!(
+            "{}\n{}x{}",
+            fragment.print_label(),
+            size.width.to_f32_px(),
+            size.height.to_f32_px(),
+        );
+        let is_anonymous = fragment.style.pseudo.is_some_and(|p| p.is_anonymous());
+        graph.node(&name, &label, if is_anonymous { Some("lightgrey") } else { None });
+        if let FragmentKind::Container { ref children, .. } = fragment.kind {
+            for child in children.iter() {
+                let child_name = child.print_dot(graph, next_id);
+                graph.edge(&name, &child_name, "");
+            }
+        }
+        name
+    }
+
+    fn print(&self, printer: &mut PrintTree) {
+        printer.new_level(format!("{} {:?} {:?}", self.fragment.print_label(), self.offset, self.fragment.size));
+        if let FragmentKind::Container { ref children, .. } = self.fragment.kind {
+            for child in children.iter() {
+                child.print(printer);
+            }
+        }
+        printer.end_level();
+    }
+}
+
+impl Fragment {
+    fn print_label(&self) -> String {
+        let mut label = self.kind_label();
+        if let Some(node) = self.node {
+            label.push_str(&format!(" - {:?}", node));
+        }
+        self.push_pseudo_label(&mut label);
+        label
+    }
+
+    /// The label used to match fragments when diffing trees, which doesn't
+    /// include the layout node, since it's specific to a layout tree.
+    fn diff_label(&self) -> String {
+        let mut label = self.kind_label();
+        self.push_pseudo_label(&mut label);
+        label
+    }
+
+    fn push_pseudo_label(&self, label: &mut String) {
+        if let Some(pseudo) = self.style.pseudo {
+            label.push_str(&format!(" ({:?})", pseudo));
+        }
+    }
+
+    fn kind_label(&self) -> String {
+        match self.kind {
+            FragmentKind::TextRun { ref content, .. } => format!("TextRun {:?}", content),
+            FragmentKind::Container { ref kind, ref clip, .. } => {
+                let mut label = match *kind {
+                    ContainerFragmentKind::Box { .. } => "Box".to_owned(),
+                    ContainerFragmentKind::Line { ellipsis: None } => "Line".to_owned(),
+                    ContainerFragmentKind::Line { ellipsis: Some(..) } => "Line (ellipsis)".to_owned(),
+                    ContainerFragmentKind::Document { .. } => "Document".to_owned(),
+                };
+                if clip.is_some() {
+                    label.push_str(" (clip)");
+                }
+                label
+            },
+        }
+    }
+}
@@ -1,9 +1,133 @@
 use app_units::Au;
-use crate::fragment_tree::{ChildFragment, Fragment, FragmentKind, ContainerFragmentKind};
-use super::{ConstraintSpace, LayoutContext, LayoutResult};
+use crate::fragment_tree::{BoxDecorations, ChildFragment, Fragment, ContainerFragmentKind};
+use super::{AvailableSize, BlockBreakToken, BreakToken, ConstraintSpace, ConstraintSpaceBuilder, ContainingBlockInfo, LayoutContext, LayoutResult};
 use crate::layout_tree::LayoutNode;
 use crate::logical_geometry::*;
+use crate::sizing::MinMaxSizes;
 use crate::style::*;
+use std::rc::Rc;
+
+/// Returns the inline size of the border box of a block-level box, given its
+/// border and padding.
+pub(super) fn inline_border_box_size(
+    context: &LayoutContext,
+    node: &LayoutNode,
+    bp: &LogicalMargin<Au>,
+    constraints: &ConstraintSpace,
+) -> Au {
+    let style = &node.style;
+    match style.size().inline {
+        Size::Keyword(SizeKeyword::Auto) => constraints.available_size.inline(),
+        Size::Keyword(SizeKeyword::MaxContent) => {
+            node.min_max_sizes(context).max_content + bp.inline_start_end()
+        },
+        Size::Keyword(SizeKeyword::MinContent) => {
+            node.min_max_sizes(context).min_content + bp.inline_start_end()
+        },
+        Size::LengthPercentage(lp) => {
+            let mut size = lp.resolve(constraints.percentage_resolution_size.inline());
+            if style.box_sizing.content_box() {
+                size += bp.inline_start_end();
+            }
+            size
+        }
+    }
+}
+
+/// Returns the block size of the border box of a non-replaced box with an
+/// auto block size and a preferred aspect ratio, given its border box inline
+/// size, or `None` if the ratio doesn't apply.
+///
+/// The ratio applies to the box indicated by `box-sizing`.
+///
+/// https://drafts.csswg.org/css-sizing-4/#aspect-ratio-size-transfers
+pub(super) fn block_border_box_size_from_ratio(
+    node: &LayoutNode,
+    bp: &LogicalMargin<Au>,
+    inline_border_box_size: Au,
+) -> Option<Au> {
+    let style = &node.style;
+    if !matches!(*style.size().block, Size::Keyword(SizeKeyword::Auto)) {
+        return None;
+    }
+    let ratio = node.preferred_aspect_ratio()?;
+    let wm = style.writing_mode;
+    if style.box_sizing.border_box() {
+        return Some(ratio.block_size_from_inline(wm, inline_border_box_size));
+    }
+    let inline_content_size = std::cmp::max(inline_border_box_size - bp.inline_start_end(), Au(0));
+    Some(ratio.block_size_from_inline(wm, inline_content_size) + bp.block_start_end())
+}
+
+/// Returns the block size of the border box of a block-level box, given its
+/// border and padding, and the block size of its contents.
+pub(super) fn block_border_box_size(
+    node: &LayoutNode,
+    bp: &LogicalMargin<Au>,
+    inline_border_box_size: Au,
+    content_block_size: Au,
+    constraints: &ConstraintSpace,
+) -> Au {
+    let style = &node.style;
+    if let Size::LengthPercentage(ref lp) = *style.size().block {
+        if let Some(mut size) = lp.maybe_resolve(constraints.percentage_resolution_size.block) {
+            if style.box_sizing.content_box() {
+                size += bp.block_start_end();
+            }
+            return size;
+        }
+    }
+    let auto_size = content_block_size + bp.block_start_end();
+    // The automatic minimum size keeps the content from overflowing a box
+    // sized by its aspect ratio.
+    match block_border_box_size_from_ratio(node, bp, inline_border_box_size) {
+        Some(size) => std::cmp::max(auto_size, size),
+        None => auto_size,
+    }
+}
+
+/// Returns the constraint space for the children of a block container, given
+/// its border and padding, and the inline size of its border box.
+pub(super) fn children_constraints(
+    node: &LayoutNode,
+    bp: &LogicalMargin<Au>,
+    inline_border_box_size: Au,
+    constraints: &ConstraintSpace,
+) -> ConstraintSpace {
+    let style = &node.style;
+    let wm = style.writing_mode;
+    let mut inline_size = std::cmp::max(inline_border_box_size - bp.inline_start_end(), Au(0));
+    if style.has_block_axis_scrollbar() {
+        // Reserve space for the block-axis scrollbar.
+        //
+        // TODO(emilio): With `overflow: auto` this should only happen if the
+        // content actually overflows, which needs a second layout pass.
+        inline_size = std::cmp::max(inline_size - constraints.scrollbar_width, Au(0));
+    }
+    let block_size = match *style.size().block {
+        Size::LengthPercentage(ref lp) => lp.maybe_resolve(constraints.percentage_resolution_size.block).map(|size| {
+            if style.box_sizing.border_box() {
+                std::cmp::max(size - bp.block_start_end(), Au(0))
+            } else {
+                size
+            }
+        }),
+        Size::Keyword(..) => None,
+    };
+    let available_size = |block_size: Option<Au>| match block_size {
+        Some(block_size) => AvailableSize::definite(wm, LogicalSize::new(wm, inline_size, block_size)),
+        None => AvailableSize::unconstrained_block(wm, inline_size),
+    };
+    let available_block_size = block_size.or_else(|| {
+        let available_block_size = constraints.available_size.block?;
+        Some(std::cmp::max(available_block_size - bp.block_start_end(), Au(0)))
+    });
+    // Percentages only resolve against definite sizes, not against the space
+    // that is left in the fragmentainer.
+    ConstraintSpaceBuilder::for_children_of(constraints, ContainingBlockInfo::new(node, available_size(available_block_size)))
+        .percentage_resolution_size(available_size(block_size))
+        .build()
+}
 
 pub struct BlockFormattingContext<'a, 'b> {
     context: &'a LayoutContext<'b>,
@@ -11,8 +135,13 @@ pub struct BlockFormattingContext<'a, 'b> {
 }
 
 #[derive(Default)]
-struct BlockLayoutState {
+struct BlockLayoutState<'a> {
     current_offset: Au,
+    /// The break token of the previous fragment of the input node, if any.
+    ///
+    /// TODO(fragmentation): Breaks inside block children without an
+    /// independent layout aren't supported.
+    resume_from: Option<&'a BlockBreakToken>,
 }
 
 impl<'a, 'b> BlockFormattingContext<'a, 'b> {
@@ -47,35 +176,23 @@ impl<'a, 'b> BlockFormattingContext<'a, 'b> {
         }
 
         let bp = border + padding;
-        let my_inline_border_box_size = match style.size().inline {
-            Size::Keyword(SizeKeyword::Auto) => constraints.available_size.inline(),
-            Size::Keyword(SizeKeyword::MaxContent) |
-            Size::Keyword(SizeKeyword::MinContent) => {
-                // TODO(minmax)
-                Au(0)
-            },
-            Size::LengthPercentage(lp) => {
-                let mut size = lp.resolve(constraints.percentage_resolution_size.inline());
-                if style.box_sizing.content_box() {
-                    size += bp.inline_start_end();
-                }
-                size
-            }
-        };
+        let my_inline_border_box_size = inline_border_box_size(self.context, node, &bp, constraints);
 
-        let children_constraints = {
-            let mut child_avail_size = constraints.available_size.clone();
-            child_avail_size.shrink_block_size(bp.block_start_end());
-            child_avail_size.shrink_inline_size(bp.inline_start_end());
-            ConstraintSpace {
-                available_size: child_avail_size.clone(),
-                percentage_resolution_size: child_avail_size,
-                containing_block_writing_mode: wm,
-            }
-        };
+        let children_constraints = children_constraints(node, &bp, my_inline_border_box_size, constraints);
 
         let mut children = vec![];
-        for (_id, child) in node.children_and_id(self.context.layout_tree) {
+        let mut resume_from = if self.is_root(node) { state.resume_from } else { None };
+        let mut child_break_token = None;
+        for (id, child) in node.children_and_id(self.context.layout_tree) {
+            if let Some(token) = resume_from {
+                // Children before the break were laid out in a previous
+                // fragment.
+                if id != token.child {
+                    continue;
+                }
+                resume_from = None;
+                child_break_token = token.child_break_token.as_deref();
+            }
             if child.style.is_out_of_flow_positioned() {
                 let _static_pos = LogicalPoint::new(
                     wm,
@@ -89,37 +206,79 @@ impl<'a, 'b> BlockFormattingContext<'a, 'b> {
                 // TODO(floats)
                 continue;
             }
-            let mut child_result = if child.has_independent_layout(self.context) {
-                child.layout(self.context, &children_constraints)
+            let child_wm = child.writing_mode();
+            let child_block_offset = state.current_offset;
+            let mut child_result = if child_wm.is_orthogonal_to(wm) {
+                debug_assert!(child.has_independent_layout(self.context));
+                let constraints = children_constraints.for_orthogonal_child(
+                    child_wm,
+                    self.context.initial_containing_block_size,
+                );
+                child.layout(self.context, &constraints, child_break_token.take())
+            } else if child.has_independent_layout(self.context) {
+                child.layout(self.context, &children_constraints, child_break_token.take())
             } else {
                 debug_assert!(child.is_block_container());
                 self.layout_block_children_of(state, child, &children_constraints)
             };
-            child_result.root_fragment.offset.b = state.current_offset;
-            state.current_offset += child_result.root_fragment.fragment.size.block;
-            children.push(child_result);
+            // The child fragment's size is in its own writing mode, which may
+            // be orthogonal to ours.
+            let child_size = child_result.root_fragment.fragment.size.convert(child_wm, wm);
+            // Children are positioned inside our content box, and the
+            // children of our non-independent descendants have already
+            // advanced the offset, so don't count them twice.
+            child_result.root_fragment.offset = LogicalPoint::new(
+                wm,
+                bp.inline_start,
+                bp.block_start + child_block_offset - start_block_offset,
+            );
+            state.current_offset = child_block_offset + child_size.block;
+            children.push(child_result.root_fragment);
         }
 
+        let content_block_size = state.current_offset - start_block_offset;
+        let block_size = block_border_box_size(node, &bp, my_inline_border_box_size, content_block_size, constraints);
+
         LayoutResult {
             root_fragment: ChildFragment {
                 offset: LogicalPoint::zero(wm),
-                fragment: Box::new(Fragment {
-                    size: LogicalSize::new(wm, my_inline_border_box_size, state.current_offset - start_block_offset),
-                    style: node.style.clone(),
-                    kind: FragmentKind::Container {
-                        kind: ContainerFragmentKind::Line {},
-                        children: Box::new([]),
+                fragment: Rc::new(Fragment::new_container(
+                    LogicalSize::new(wm, my_inline_border_box_size, block_size),
+                    node.style.clone(),
+                    Some(self.context.layout_tree.id_of(node)),
+                    ContainerFragmentKind::Box {
+                        decorations: BoxDecorations::from_style(style),
                     },
-                }),
-            }
+                    children.into_boxed_slice(),
+                )),
+            },
+            break_token: None,
         }
     }
 }
 
 impl<'a, 'b> super::LayoutAlgorithm for BlockFormattingContext<'a, 'b> {
-    fn layout(&mut self, constraints: &ConstraintSpace) -> LayoutResult {
+    /// The intrinsic sizes of a block container are the largest contribution
+    /// of its in-flow children.
+    ///
+    /// TODO(emilio): Floats should contribute too, and be placed next to
+    /// each other in the max-content size.
+    fn min_max_sizes(&mut self) -> MinMaxSizes {
+        let wm = self.input_node.writing_mode();
+        let mut sizes = MinMaxSizes::zero();
+        for child in self.input_node.in_flow_children(self.context.layout_tree) {
+            sizes.max_assign(child.min_max_contribution(self.context, wm));
+        }
+        sizes
+    }
+
+    fn layout(&mut self, constraints: &ConstraintSpace, resume_from: Option<&BreakToken>) -> LayoutResult {
         debug_assert!(!self.input_node.establishes_ifc(self.context.layout_tree));
-        let mut state = BlockLayoutState::default();
+        let resume_from = resume_from.map(|token| token.as_block().expect("Resuming from a non-block break?"));
+        let mut state = BlockLayoutState {
+            resume_from,
+            ..Default::default()
+        };
         self.layout_block_children_of(&mut state, self.input_node, constraints)
     }
 }
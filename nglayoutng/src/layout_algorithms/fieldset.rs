@@ -0,0 +1,144 @@
+//! Layout of fieldsets: The rendered legend is placed over the block-start
+//! border of the fieldset, and the anonymous content box is laid out below
+//! it, inside the fieldset's padding.
+//!
+//! https://html.spec.whatwg.org/#the-fieldset-and-legend-elements
+
+use super::{BreakToken, ConstraintSpace, ConstraintSpaceBuilder, ContainingBlockInfo, LayoutContext, LayoutResult};
+use crate::fragment_tree::{BoxDecorations, ChildFragment, ContainerFragmentKind, Fragment};
+use crate::layout_tree::LayoutNode;
+use crate::logical_geometry::*;
+use crate::sizing::MinMaxSizes;
+use crate::style::PseudoElement;
+use app_units::Au;
+use std::cmp;
+use std::rc::Rc;
+
+pub struct FieldsetLayout<'a, 'b> {
+    context: &'a LayoutContext<'b>,
+    input_node: &'a LayoutNode,
+}
+
+impl<'a, 'b> FieldsetLayout<'a, 'b> {
+    pub fn new(context: &'a LayoutContext<'b>, input_node: &'a LayoutNode) -> Self {
+        debug_assert!(input_node.is_fieldset());
+        Self {
+            context,
+            input_node,
+        }
+    }
+
+    /// Returns the rendered legend and the anonymous content box of the
+    /// fieldset.
+    fn children(&self) -> (Option<&'a LayoutNode>, Option<&'a LayoutNode>) {
+        let mut legend = None;
+        let mut content_box = None;
+        for child in self.input_node.children(self.context.layout_tree) {
+            if child.style.pseudo == Some(PseudoElement::FieldsetContent) {
+                content_box = Some(child);
+            } else {
+                legend = Some(child);
+            }
+        }
+        (legend, content_box)
+    }
+
+    /// Lays out a child of the fieldset, returning its fragment and its size
+    /// in the fieldset's writing mode.
+    fn layout_child(&self, child: &LayoutNode, constraints: &ConstraintSpace) -> (ChildFragment, LogicalSize<Au>) {
+        let wm = self.input_node.writing_mode();
+        let child_wm = child.writing_mode();
+        let result = if child_wm.is_orthogonal_to(wm) {
+            let constraints =
+                constraints.for_orthogonal_child(child_wm, self.context.initial_containing_block_size);
+            child.layout(self.context, &constraints, None)
+        } else {
+            child.layout(self.context, constraints, None)
+        };
+        let size = result.root_fragment.fragment.size.convert(child_wm, wm);
+        (result.root_fragment, size)
+    }
+}
+
+impl<'a, 'b> super::LayoutAlgorithm for FieldsetLayout<'a, 'b> {
+    /// The intrinsic sizes of a fieldset are the largest of the contributions
+    /// of its legend and its content.
+    fn min_max_sizes(&mut self) -> MinMaxSizes {
+        let wm = self.input_node.writing_mode();
+        let (legend, content_box) = self.children();
+        let mut sizes = MinMaxSizes::zero();
+        for child in legend.into_iter().chain(content_box) {
+            sizes.max_assign(child.min_max_contribution(self.context, wm));
+        }
+        sizes
+    }
+
+    /// TODO(emilio): The block-start border should be painted centered on the
+    /// legend, and interrupted behind it. Also, the legend should be sized
+    /// with shrink-to-fit, and its margins and `align` attribute honored.
+    ///
+    /// TODO(fragmentation): Fieldsets are monolithic for now.
+    fn layout(&mut self, constraints: &ConstraintSpace, resume_from: Option<&BreakToken>) -> LayoutResult {
+        debug_assert!(resume_from.is_none(), "Fieldsets don't fragment");
+        let node = self.input_node;
+        let style = &node.style;
+        let wm = style.writing_mode;
+        let border = style.border_widths();
+        let padding = style.padding().map_all(|lp| {
+            lp.resolve(constraints.percentage_resolution_size.inline())
+        });
+        let bp = border + padding;
+        let inline_size = super::block::inline_border_box_size(self.context, node, &bp, constraints);
+
+        let children_constraints = {
+            let mut child_avail_size = constraints.available_size.clone();
+            child_avail_size.shrink_block_size(bp.block_start_end());
+            child_avail_size.shrink_inline_size(bp.inline_start_end());
+            ConstraintSpaceBuilder::for_children_of(constraints, ContainingBlockInfo::new(node, child_avail_size))
+                .build()
+        };
+
+        let (legend, content_box) = self.children();
+        let mut children = vec![];
+
+        // The block-start border area grows to fit the legend if needed, and
+        // the legend is centered on it.
+        let mut block_start_border_area = border.block_start;
+        if let Some(legend) = legend {
+            let (mut fragment, size) = self.layout_child(legend, &children_constraints);
+            block_start_border_area = cmp::max(block_start_border_area, size.block);
+            fragment.offset = LogicalPoint::new(
+                wm,
+                bp.inline_start,
+                (block_start_border_area - size.block) / 2,
+            );
+            children.push(fragment);
+        }
+
+        let content_block_start = block_start_border_area + padding.block_start;
+        let mut content_block_size = Au(0);
+        if let Some(content_box) = content_box {
+            let (mut fragment, size) = self.layout_child(content_box, &children_constraints);
+            fragment.offset = LogicalPoint::new(wm, bp.inline_start, content_block_start);
+            content_block_size = size.block;
+            children.push(fragment);
+        }
+
+        let block_size = content_block_start + content_block_size + padding.block_end + border.block_end;
+        LayoutResult {
+            root_fragment: ChildFragment {
+                offset: LogicalPoint::zero(wm),
+                fragment: Rc::new(Fragment::new_container(
+                    LogicalSize::new(wm, inline_size, block_size),
+                    style.clone(),
+                    Some(self.context.layout_tree.id_of(node)),
+                    ContainerFragmentKind::Box {
+                        decorations: BoxDecorations::from_style(style),
+                    },
+                    children.into_boxed_slice(),
+                )),
+            },
+            break_token: None,
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! Automatic hyphenation, for `hyphens: auto`.
+//!
+//! https://drafts.csswg.org/css-text-3/#hyphenation
+
+use hyphenation::{Hyphenator, Language, Load, Standard};
+
+/// U+00AD SOFT HYPHEN, which is a conditional hyphenation opportunity.
+pub const SOFT_HYPHEN: char = '\u{ad}';
+
+/// U+2010 HYPHEN, which is what we render when breaking at a hyphenation
+/// opportunity.
+///
+/// TODO(emilio): hyphenate-character.
+pub const HYPHEN: &str = "\u{2010}";
+
+thread_local! {
+    // TODO(emilio): We should select the dictionary based on the content
+    // language once we support `lang`, and load dictionaries lazily per
+    // language.
+    static DICTIONARY: Option<Standard> = match Standard::from_embedded(Language::EnglishUS) {
+        Ok(dictionary) => Some(dictionary),
+        Err(e) => {
+            warn!("Failed to load hyphenation dictionary: {:?}", e);
+            None
+        },
+    };
+}
+
+/// Calls `callback` with the byte offsets into `text` where a hyphen could be
+/// inserted, using the dictionary for the content language.
+pub fn for_each_hyphenation_opportunity(text: &str, mut callback: impl FnMut(usize)) {
+    DICTIONARY.with(|dictionary| {
+        let dictionary = match *dictionary {
+            Some(ref d) => d,
+            None => return,
+        };
+
+        // Only hyphenate words, and only those made of letters.
+        let mut word_start = None;
+        let mut hyphenate_word = |start: usize, end: usize| {
+            let word = &text[start..end];
+            for offset in dictionary.hyphenate(word).breaks {
+                callback(start + offset);
+            }
+        };
+        for (i, c) in text.char_indices() {
+            if c.is_alphabetic() {
+                if word_start.is_none() {
+                    word_start = Some(i);
+                }
+                continue;
+            }
+            if let Some(start) = word_start.take() {
+                hyphenate_word(start, i);
+            }
+        }
+        if let Some(start) = word_start {
+            hyphenate_word(start, text.len());
+        }
+    })
+}
@@ -1,11 +1,18 @@
 use app_units::Au;
-use crate::fragment_tree::{ChildFragment, Fragment, FragmentKind, ContainerFragmentKind};
+use crate::fragment_tree::{BoxDecorations, ChildFragment, Fragment, FragmentKind, ContainerFragmentKind, LineEllipsis};
 use crate::logical_geometry::*;
-use crate::style::{ComputedStyle, LengthPercentage, LengthPercentageOrAuto};
-use super::{ConstraintSpace, LayoutContext, LayoutResult};
+use crate::style::{ComputedStyle, Hyphens, LengthPercentage, LengthPercentageOrAuto, Overflow, TextOverflow, TextTransform, WhiteSpace, WordBreak};
+use super::block;
+use super::hyphenation::{self, SOFT_HYPHEN};
+use super::{BreakToken, ConstraintSpace, ConstraintSpaceBuilder, ContainingBlockInfo, LayoutContext, LayoutResult};
 use crate::layout_tree::{LayoutNodeKind, LeafKind, ContainerKind, LayoutNode, LayoutNodeId};
 use smallbitvec::SmallBitVec;
+use crate::fonts::font_face::FontFaceSet;
+use crate::fonts::shaping::GlyphInfo;
+use crate::sizing::MinMaxSizes;
 use std::borrow::Cow;
+use std::ops::Range;
+use std::rc::Rc;
 
 /// A position for a given line break.
 #[derive(Clone)]
@@ -38,6 +45,18 @@ struct OpenInlineBox {
     children: Vec<ChildFragment>,
 }
 
+/// A break opportunity in the text run we're laying out, that we can go back
+/// to if the content after it doesn't fit in the line.
+#[derive(Clone, Copy)]
+struct LineBreakOpportunity {
+    /// The index of the glyph right after the opportunity.
+    glyph_index: usize,
+    /// The number of glyphs of the current segment before the opportunity.
+    segment_glyph_count: usize,
+    /// The inline size of the content of the line before the opportunity.
+    inline_size: Au,
+}
+
 struct LineBreaker<'a, 'b, 'c> {
     fc: &'a InlineFormattingContext<'b, 'c>,
     constraints: &'a ConstraintSpace,
@@ -46,6 +65,15 @@ struct LineBreaker<'a, 'b, 'c> {
     current_line: Vec<ChildFragment>,
     current_line_available_size: Au,
     current_line_max_block_size: Au,
+    /// The advance of the ellipsis if the content of the lines needs to be
+    /// truncated with `text-overflow: ellipsis`, or `None` otherwise.
+    ellipsis_advance: Option<Au>,
+    /// The offset at which we'd put the ellipsis if the current line overflows,
+    /// that is, the end of the last glyph that fits in the line along with the
+    /// ellipsis.
+    current_line_ellipsis_offset: Option<Au>,
+    /// The ellipsis for the current line, if it's been truncated.
+    current_line_ellipsis: Option<LineEllipsis>,
     current_position: InlineItemPosition,
     at_break_opportunity: bool,
     /// An stack of currently open inline boxes.
@@ -54,14 +82,33 @@ struct LineBreaker<'a, 'b, 'c> {
 
 impl<'a, 'b, 'c> LineBreaker<'a, 'b, 'c> {
     fn new(fc: &'a mut InlineFormattingContext<'b, 'c>, constraints: &'a ConstraintSpace) -> Self {
+        // https://drafts.csswg.org/css-text-3/#text-indent-property
+        //
+        // TODO(emilio): text-indent: hanging / each-line.
+        let text_indent = fc.input_node.style.text_indent.resolve(constraints.percentage_resolution_size.inline());
+        // https://drafts.csswg.org/css-overflow-3/#text-overflow
+        //
+        // TODO(emilio): The ellipsis should be rendered with the first
+        // available font that has it, and fall back to three dots otherwise.
+        let ellipsis_advance = {
+            let style = &fc.input_node.style;
+            if style.text_overflow == TextOverflow::Ellipsis && style.overflow_x != Overflow::Visible {
+                Some(text_advance(ELLIPSIS, style, fc.context.layout_tree.font_faces()))
+            } else {
+                None
+            }
+        };
         Self {
             fc,
             constraints,
             lines: vec![],
             consumed_block_offset: Au(0),
             current_line: vec![],
-            current_line_available_size: constraints.available_size.inline(),
+            current_line_available_size: constraints.available_size.inline() - text_indent,
             current_line_max_block_size: Au(0),
+            ellipsis_advance,
+            current_line_ellipsis_offset: None,
+            current_line_ellipsis: None,
             current_position: InlineItemPosition::start(),
             at_break_opportunity: false,
             open_boxes: vec![],
@@ -72,6 +119,10 @@ impl<'a, 'b, 'c> LineBreaker<'a, 'b, 'c> {
         self.fc.input_node.style.writing_mode
     }
 
+    fn font_faces(&self) -> &'c FontFaceSet {
+        self.fc.context.layout_tree.font_faces()
+    }
+
     fn close_box(&mut self) {
         // TODO: We know this is the last fragment of the line, and whether it's
         // the first, but we should keep that information in the fragment too.
@@ -83,15 +134,16 @@ impl<'a, 'b, 'c> LineBreaker<'a, 'b, 'c> {
         let fragment = ChildFragment {
             // XXX: current_inline_offset + margin_inline_start?
             offset: LogicalPoint::zero(wm),
-            fragment: Box::new(Fragment {
+            fragment: Rc::new(Fragment::new_container(
                 // XXX: max height of the fragments + padding + border?
-                size: LogicalSize::zero(wm),
-                style: style.clone(),
-                kind: FragmentKind::Container {
-                    kind: ContainerFragmentKind::Box {},
-                    children,
+                LogicalSize::zero(wm),
+                style.clone(),
+                Some(box_.node),
+                ContainerFragmentKind::Box {
+                    decorations: BoxDecorations::from_style(style),
                 },
-            })
+                children,
+            ))
         };
 
         self.push_fragment_to_line(fragment);
@@ -115,19 +167,20 @@ impl<'a, 'b, 'c> LineBreaker<'a, 'b, 'c> {
 
             let style = &self.fc.context.layout_tree[b.node].style;
             let wm = style.writing_mode;
-            let children = std::mem::replace(&mut b.children, vec![]).into_boxed_slice();
+            let children = std::mem::take(&mut b.children).into_boxed_slice();
             let fragment = ChildFragment {
                 // XXX: current_inline_offset + margin_inline_start?
                 offset: LogicalPoint::zero(wm),
-                fragment: Box::new(Fragment {
+                fragment: Rc::new(Fragment::new_container(
                     // XXX: max height of the fragments + padding + border?
-                    size: LogicalSize::zero(wm),
-                    style: style.clone(),
-                    kind: FragmentKind::Container {
-                        kind: ContainerFragmentKind::Box {},
-                        children,
+                    LogicalSize::zero(wm),
+                    style.clone(),
+                    Some(b.node),
+                    ContainerFragmentKind::Box {
+                        decorations: BoxDecorations::from_style(style),
                     },
-                })
+                    children,
+                ))
             };
 
             b.generated_fragment = true;
@@ -146,7 +199,10 @@ impl<'a, 'b, 'c> LineBreaker<'a, 'b, 'c> {
             return; // XXX Do we need to create empty lines in any case?
         }
 
-        let line_fragments = std::mem::replace(&mut self.current_line, vec![]);
+        // Note that the fragments of the first line already account for
+        // text-indent, since it's subtracted from the available size of the
+        // line, see `line_position`.
+        let line_fragments = std::mem::take(&mut self.current_line);
         let max_block_size = std::mem::replace(&mut self.current_line_max_block_size, Au(0));
 
         // TODO: first-line style if appropriate?
@@ -167,20 +223,30 @@ impl<'a, 'b, 'c> LineBreaker<'a, 'b, 'c> {
 
         self.consumed_block_offset += size.block;
 
+        crate::trace::record(|| crate::trace::TraceEvent::Line {
+            node: self.fc.context.layout_tree.id_of(self.fc.input_node),
+            index: self.lines.len(),
+            block_offset: offset.b,
+            block_size: size.block,
+            fragment_count: line_fragments.len(),
+        });
+
         self.lines.push(ChildFragment {
             offset,
-            fragment: Box::new(Fragment {
+            fragment: Rc::new(Fragment::new_container(
                 size,
-                style: style.clone(),
-                kind: FragmentKind::Container {
-                    kind: ContainerFragmentKind::Line {},
-                    children: line_fragments.into_boxed_slice(),
+                style.clone(),
+                None,
+                ContainerFragmentKind::Line {
+                    ellipsis: self.current_line_ellipsis.take(),
                 },
-            }),
+                line_fragments.into_boxed_slice(),
+            )),
         });
 
         // Go to the next line.
         self.current_line_available_size = self.constraints.available_size.inline();
+        self.current_line_ellipsis_offset = None;
         self.at_break_opportunity = false;
     }
 
@@ -188,8 +254,107 @@ impl<'a, 'b, 'c> LineBreaker<'a, 'b, 'c> {
         unimplemented!()
     }
 
-    fn layout_replaced(&mut self, _: LayoutNodeId) {
-        unimplemented!()
+    /// Places a replaced box in the current line, moving to the next line if
+    /// it doesn't fit.
+    ///
+    /// TODO(emilio): Margins, and vertical-align (replaced boxes sit on the
+    /// baseline, which we don't compute yet).
+    fn layout_replaced(&mut self, id: LayoutNodeId) {
+        let context = self.fc.context;
+        let node = &context.layout_tree[id];
+        let wm = self.wm();
+        let child_wm = node.writing_mode();
+        let containing_block = ContainingBlockInfo::new(self.fc.input_node, self.constraints.available_size.clone());
+        let constraints = ConstraintSpaceBuilder::for_children_of(self.constraints, containing_block)
+            .percentage_resolution_size(self.constraints.percentage_resolution_size.clone())
+            .build();
+        let result = if child_wm.is_orthogonal_to(wm) {
+            let constraints = constraints.for_orthogonal_child(child_wm, context.initial_containing_block_size);
+            node.layout(context, &constraints, None)
+        } else {
+            node.layout(context, &constraints, None)
+        };
+        let size = result.root_fragment.fragment.size.convert(child_wm, wm);
+        if !self.can_fit(size.inline) {
+            self.flush_line();
+        }
+        self.current_line_max_block_size = std::cmp::max(self.current_line_max_block_size, size.block);
+        let fragment = ChildFragment {
+            offset: LogicalPoint::new(wm, self.line_position(Au(0)), Au(0)),
+            fragment: result.root_fragment.fragment,
+        };
+        self.current_line_available_size -= size.inline;
+        // There's a break opportunity after an atomic inline.
+        self.at_break_opportunity = true;
+        self.push_fragment_to_line(fragment);
+    }
+
+    /// Returns the current inline position in the line, given the inline size
+    /// of the content that hasn't been accounted for yet.
+    fn line_position(&self, pending_inline_size: Au) -> Au {
+        self.constraints.available_size.inline() - self.current_line_available_size + pending_inline_size
+    }
+
+    /// Truncates the current line with an ellipsis if needed, given that we're
+    /// about to add content of a given advance.
+    fn maybe_truncate_line(&mut self, pending_inline_size: Au, advance: Au) {
+        let ellipsis_advance = match self.ellipsis_advance {
+            Some(advance) => advance,
+            None => return,
+        };
+        if self.current_line_ellipsis.is_some() {
+            return;
+        }
+        let line_size = self.constraints.available_size.inline();
+        let position = self.line_position(pending_inline_size);
+        if self.current_line_ellipsis_offset.is_none() && position + advance + ellipsis_advance > line_size {
+            self.current_line_ellipsis_offset = Some(position);
+        }
+        if position + advance > line_size {
+            self.current_line_ellipsis = Some(LineEllipsis {
+                offset: self.current_line_ellipsis_offset.unwrap(),
+                advance: ellipsis_advance,
+            });
+        }
+    }
+
+    /// Pushes a fragment for the `range` of the shaped `text`, which goes from
+    /// `start_inline_size` to `end_inline_size` in the current run.
+    #[allow(clippy::too_many_arguments)]
+    fn push_text_fragment(
+        &mut self,
+        node: Option<LayoutNodeId>,
+        style: &Rc<ComputedStyle>,
+        text: &str,
+        original_offsets: &OriginalOffsets,
+        range: Range<usize>,
+        mut glyphs: Vec<GlyphInfo>,
+        start_inline_size: Au,
+        end_inline_size: Au,
+    ) {
+        if range.start == range.end {
+            return;
+        }
+        for glyph in &mut glyphs {
+            glyph.byte_offset -= range.start;
+        }
+        let wm = style.writing_mode;
+        let block_size = style.used_line_height(self.font_faces());
+        self.current_line_max_block_size = std::cmp::max(self.current_line_max_block_size, block_size);
+        let fragment = ChildFragment {
+            offset: LogicalPoint::new(wm, self.line_position(start_inline_size), Au(0)),
+            fragment: Rc::new(Fragment {
+                size: LogicalSize::new(wm, end_inline_size - start_inline_size, block_size),
+                style: style.clone(),
+                node,
+                kind: FragmentKind::TextRun {
+                    content: text[range.clone()].to_owned(),
+                    range: original_offsets.original_range(range),
+                    glyphs: glyphs.into_boxed_slice(),
+                },
+            }),
+        };
+        self.push_fragment_to_line(fragment);
     }
 
     fn can_fit(&self, inline_size: Au) -> bool {
@@ -214,14 +379,30 @@ impl<'a, 'b, 'c> LineBreaker<'a, 'b, 'c> {
         }
     }
 
-    fn layout_inline_box(&mut self, style: &ComputedStyle, start_text_item_text: &str) {
+    fn layout_inline_box(&mut self, style: &Rc<ComputedStyle>, start_text_item_text: &str) {
         let mut paragraph = Cow::Borrowed(start_text_item_text);
 
+        // The text node that the text fragments we generate come from.
+        //
+        // TODO(emilio): A run can span multiple text nodes (e.g., with
+        // `display: contents`), we only point to the first one for now.
+        let mut text_node = match self.fc.items[self.current_position.item_index] {
+            InlineItem::Text(node, ..) => Some(node),
+            _ => None,
+        };
+        // The offsets of the text before text-transform was applied, for all
+        // the text items in the paragraph.
+        let mut original_offsets = match self.fc.items[self.current_position.item_index] {
+            InlineItem::Text(_, _, ref offsets) => offsets.clone(),
+            _ => OriginalOffsets::identity(),
+        };
+        let font_faces = self.font_faces();
+
         // let wm = style.writing_mode;
 
         let margin_start = self.resolve_margin(style.margin().inline_end);
 
-        let mut mbp_start = margin_start +
+        let mbp_start = margin_start +
             style.border_widths().inline_start +
             self.resolve_padding(style.padding().inline_start);
 
@@ -263,11 +444,7 @@ impl<'a, 'b, 'c> LineBreaker<'a, 'b, 'c> {
         let mut advance = 1;
         let mut break_if_not_end = false;
 
-        loop {
-            let following_item = match self.fc.items.get(self.current_position.item_index + advance) {
-                Some(item) => item,
-                None => break,
-            };
+        while let Some(following_item) = self.fc.items.get(self.current_position.item_index + advance) {
 
             if let InlineItem::TagEnd(node) = *following_item {
                 let end_style = &self.fc.context.layout_tree[node].style;
@@ -308,16 +485,18 @@ impl<'a, 'b, 'c> LineBreaker<'a, 'b, 'c> {
                     // loop, or something, to create the right fragment tree.
                 },
                 InlineItem::TagEnd(..) => unreachable!(),
-                InlineItem::Text(node, ref s) => {
+                InlineItem::Text(node, ref s, ref offsets) => {
                     let text_style = &self.fc.context.layout_tree[node].style;
                     if !can_continue_run(style, text_style, /* at_beginning = */ true) {
                         trace!("Can't continue run with text {:?} at start", following_item);
                         break;
                     }
+                    text_node.get_or_insert(node);
+                    original_offsets.append(paragraph.len(), s, offsets);
                     if paragraph.is_empty() {
                         paragraph = Cow::Borrowed(s);
                     } else {
-                        paragraph.to_mut().push_str(&s);
+                        paragraph.to_mut().push_str(s);
                     }
 
                     // This can only really happen with display: contents, and
@@ -325,7 +504,9 @@ impl<'a, 'b, 'c> LineBreaker<'a, 'b, 'c> {
                     // shouldn't matter here.
                     debug_assert!(can_continue_run(style, text_style, /* at_beginning = */ false));
                 },
-                InlineItem::AtomicInline(..) | InlineItem::Replaced(..) => {
+                InlineItem::AtomicInline(..) |
+                InlineItem::Replaced(..) |
+                InlineItem::ForcedLineBreak(..) => {
                     break;
                 }
             }
@@ -346,29 +527,105 @@ impl<'a, 'b, 'c> LineBreaker<'a, 'b, 'c> {
         let mut break_opportunities = SmallBitVec::new();
         break_opportunities.resize(paragraph.len(), false);
 
+        // Positions before which we need to start a new line, because there's
+        // a preserved segment break.
+        let mut forced_breaks = SmallBitVec::new();
+        forced_breaks.resize(paragraph.len(), false);
+
+        // Break opportunities at which we need to insert a hyphen if we
+        // actually break there.
+        let mut hyphenation_opportunities = SmallBitVec::new();
+        hyphenation_opportunities.resize(paragraph.len(), false);
+
+        // Note that `can_continue_run` guarantees that the whole run has the
+        // same white-space, word-break, overflow-wrap and hyphens values.
+        let white_space = style.white_space;
+        let word_break = style.word_break;
+        let overflow_wrap = style.overflow_wrap;
+        let hyphens = style.hyphens;
+
         // Try to grab a whole text run and line-break / shape it.
-        let mut breaker = xi_unicode::LineBreakLeafIter::new(&*paragraph, 0);
-
-        // TODO(emilio): There are optimizations here we could do to
-        // avoid doing this, or do a simplified version of this,
-        // when different white-space values are in-use like nowrap
-        // and so on...
-        trace!("Breaking {:?}", paragraph);
-        loop {
-            // TODO: Account for white-space and other similar shenanigans.
-            let (result, _hard_break) = breaker.next(&*paragraph);
-            if result == paragraph.len() {
-                break;
+        //
+        // TODO(emilio): There are optimizations here we could do to avoid
+        // doing this, or do a simplified version of this, when soft wraps
+        // aren't allowed (we only need to look for preserved newlines then).
+        //
+        // Note that the line breaker can't deal with empty text, which we may
+        // get from empty inline boxes or fully collapsed white space.
+        if !paragraph.is_empty() {
+            let mut breaker = xi_unicode::LineBreakLeafIter::new(&paragraph, 0);
+            trace!("Breaking {:?}", paragraph);
+            loop {
+                let (result, hard_break) = breaker.next(&paragraph);
+                if result == paragraph.len() {
+                    break;
+                }
+                if hard_break {
+                    forced_breaks.set(result, true);
+                } else if white_space.allows_soft_wrap() {
+                    // keep-all suppresses the implicit opportunities between
+                    // letters (mostly relevant for CJK text), but not the ones
+                    // after spaces or punctuation.
+                    if word_break != WordBreak::KeepAll || !is_between_letters(&paragraph, result) {
+                        break_opportunities.set(result, true);
+                    }
+                }
+            }
+        }
+
+        if white_space.allows_soft_wrap() {
+            // With break-spaces there's a soft wrap opportunity after every
+            // preserved space, not only after the last one of a sequence.
+            //
+            // https://drafts.csswg.org/css-text-3/#valdef-white-space-break-spaces
+            if white_space == WhiteSpace::BreakSpaces {
+                for (i, c) in paragraph.char_indices() {
+                    let next = i + c.len_utf8();
+                    if c == ' ' && next < paragraph.len() {
+                        break_opportunities.set(next, true);
+                    }
+                }
+            }
+
+            // With break-all, there's an opportunity between any two
+            // typographic letter units.
+            //
+            // https://drafts.csswg.org/css-text-3/#valdef-word-break-break-all
+            if word_break == WordBreak::BreakAll {
+                for (i, _) in paragraph.char_indices() {
+                    if is_between_letters(&paragraph, i) {
+                        break_opportunities.set(i, true);
+                    }
+                }
+            }
+
+            // Soft hyphens are conditional hyphenation opportunities, which
+            // the line breaker already reports as opportunities after them,
+            // unless hyphenation is disabled altogether.
+            //
+            // https://drafts.csswg.org/css-text-3/#valdef-hyphens-manual
+            for (i, c) in paragraph.char_indices() {
+                let next = i + c.len_utf8();
+                if c != SOFT_HYPHEN || next >= paragraph.len() {
+                    continue;
+                }
+                let allowed = hyphens != Hyphens::None;
+                break_opportunities.set(next, allowed);
+                hyphenation_opportunities.set(next, allowed);
+            }
+
+            if hyphens == Hyphens::Auto {
+                hyphenation::for_each_hyphenation_opportunity(&paragraph, |i| {
+                    break_opportunities.set(i, true);
+                    hyphenation_opportunities.set(i, true);
+                });
             }
-            break_opportunities.set(result, true);
-            // XXX Do we need to use the hard_break bit somehow?
-            // Maybe just truncating the paragraph and carrying on?
         }
 
         if log_enabled!(log::Level::Trace) {
             trace!("Broken:");
             let mut start = 0;
-            for i in 0..paragraph.as_bytes().len() {
+            for i in 0..paragraph.len() {
                 if !break_opportunities[i] {
                     continue;
                 }
@@ -378,21 +635,223 @@ impl<'a, 'b, 'c> LineBreaker<'a, 'b, 'c> {
             trace!("{}", &paragraph[start..]);
         }
 
-        if !self.can_fit(mbp_start) {
-
-        }
-
-        let shaped_runs = crate::fonts::shaping::shape(&paragraph, style);
+        let mut glyphs = crate::fonts::shaping::shape(&paragraph, style, font_faces)
+            .glyphs()
+            .cloned()
+            .collect::<Vec<_>>();
         let mut inline_size = mbp_start;
+        // The segment of the paragraph (and its glyphs) that goes in the
+        // current line, and the inline size at which it starts.
+        let mut segment_start = 0;
+        let mut segment_start_inline_size = mbp_start;
+        let mut segment_glyphs = vec![];
+        // The last break opportunity since the start of the current line, to
+        // go back to if the content after it overflows. If there's none, we
+        // can do an emergency break for overflow-wrap.
+        let mut last_opportunity = None;
+        // The glyph we went back to, and need to break before.
+        let mut break_before_glyph = None;
+        // The advance of the hyphen we'd need to insert when breaking at a
+        // hyphenation opportunity, computed lazily.
+        let mut hyphen_advance = None;
+        // The distance between tab stops and the advance of the space
+        // character, computed lazily.
+        let mut tab_metrics = None;
+        // Whether we've just broken the line before the current glyph, and
+        // re-shaped the text after the break, so we shouldn't try to break
+        // again.
+        let mut resuming_after_break = false;
+        let mut next_glyph_index = 0;
         self.at_break_opportunity = false;
-        for glyph in shaped_runs.glyphs() {
-            inline_size += glyph.advance;
-            if !self.can_fit(inline_size) {
-                // TODO: Create fragments, reset mbp_start, carry on!
+        while let Some(glyph) = glyphs.get(next_glyph_index).cloned() {
+            let glyph_index = next_glyph_index;
+            next_glyph_index += 1;
+            let just_broke = std::mem::replace(&mut resuming_after_break, false);
+            if forced_breaks[glyph.byte_offset] {
+                self.push_text_fragment(
+                    text_node,
+                    style,
+                    &paragraph,
+                    &original_offsets,
+                    segment_start..glyph.byte_offset,
+                    std::mem::take(&mut segment_glyphs),
+                    segment_start_inline_size,
+                    inline_size,
+                );
+                self.flush_line();
+                inline_size = Au(0);
+                segment_start = glyph.byte_offset;
+                segment_start_inline_size = Au(0);
+                last_opportunity = None;
+            }
+            let c = paragraph.as_bytes()[glyph.byte_offset];
+            // The segment break itself is not rendered, and soft hyphens are
+            // only rendered (as a hyphen) if we break after them.
+            if c == b'\n' || paragraph[glyph.byte_offset..].starts_with(SOFT_HYPHEN) {
+                continue;
+            }
+            // Preserved tabs are rendered as an advance to the next tab stop.
+            //
+            // https://drafts.csswg.org/css-text-3/#white-space-phase-2
+            let glyph_advance = if c == b'\t' && !white_space.collapses_spaces() {
+                let (interval, space_advance) = *tab_metrics.get_or_insert_with(|| {
+                    let space_advance = text_advance(" ", style, font_faces);
+                    (style.tab_size.resolve(space_advance), space_advance)
+                });
+                tab_advance(self.line_position(inline_size), interval, space_advance)
+            } else {
+                glyph.advance
+            };
+            // Spaces that hang at the end of the line don't make the content
+            // overflow, so there's no point in breaking before them.
+            let hangs = white_space.trailing_spaces_hang() && (c == b' ' || c == b'\t');
+            self.at_break_opportunity = !just_broke && break_opportunities[glyph.byte_offset];
+            let went_back = break_before_glyph == Some(glyph_index);
+            if went_back {
+                break_before_glyph = None;
+            } else if self.at_break_opportunity && inline_size != Au(0) {
+                last_opportunity = Some(LineBreakOpportunity {
+                    glyph_index,
+                    segment_glyph_count: segment_glyphs.len(),
+                    inline_size,
+                });
+            }
+            if !self.at_break_opportunity &&
+                !just_broke &&
+                last_opportunity.is_none() &&
+                white_space.allows_soft_wrap() &&
+                overflow_wrap.allows_emergency_break() &&
+                inline_size != Au(0) &&
+                !is_space_or_newline(c)
+            {
+                // https://drafts.csswg.org/css-text-3/#overflow-wrap-property
+                self.at_break_opportunity = true;
+            }
+            // If we break at a hyphenation opportunity, the hyphen needs to
+            // fit in the current line.
+            let at_hyphenation_opportunity =
+                self.at_break_opportunity && hyphenation_opportunities[glyph.byte_offset];
+            let needed_size = if at_hyphenation_opportunity {
+                let hyphen_advance = *hyphen_advance.get_or_insert_with(|| {
+                    text_advance(hyphenation::HYPHEN, style, font_faces)
+                });
+                std::cmp::max(glyph_advance, hyphen_advance)
+            } else {
+                glyph_advance
+            };
+            let overflows = !hangs && !self.can_fit(inline_size + needed_size);
+            if !self.at_break_opportunity &&
+                !hangs &&
+                self.current_line_available_size < inline_size + needed_size
+            {
+                // We can't break here, so go back to the last opportunity, if
+                // any, and break there instead.
+                if let Some(opportunity) = last_opportunity.take() {
+                    segment_glyphs.truncate(opportunity.segment_glyph_count);
+                    inline_size = opportunity.inline_size;
+                    next_glyph_index = opportunity.glyph_index;
+                    break_before_glyph = Some(opportunity.glyph_index);
+                    continue;
+                }
+            }
+            if went_back || overflows {
+                // TODO: Reset mbp_start, carry on!
+                let reshape = glyph.unsafe_to_break;
+                if reshape {
+                    // We're breaking in the middle of a ligature or such, so
+                    // the glyphs before the break can't be reused as-is.
+                    // Re-shape from the last glyph that is safe to break
+                    // before, and reuse the rest.
+                    //
+                    // https://bugzilla.mozilla.org/show_bug.cgi?id=479829
+                    let safe_index = segment_glyphs
+                        .iter()
+                        .rposition(|g: &GlyphInfo| !g.unsafe_to_break)
+                        .unwrap_or(0);
+                    if let Some(safe_glyph) = segment_glyphs.get(safe_index) {
+                        let reshape_start = safe_glyph.byte_offset;
+                        for g in segment_glyphs.drain(safe_index..) {
+                            inline_size -= g.advance;
+                        }
+                        for g in shape_range(&paragraph, reshape_start..glyph.byte_offset, style, font_faces) {
+                            inline_size += g.advance;
+                            segment_glyphs.push(g);
+                        }
+                    }
+                }
+                if at_hyphenation_opportunity {
+                    // The hyphen maps to the last character before the break,
+                    // which is the soft hyphen, if any.
+                    let hyphen_offset = paragraph[..glyph.byte_offset]
+                        .char_indices()
+                        .next_back()
+                        .map_or(0, |(i, _)| i);
+                    let hyphen = crate::fonts::shaping::shape(hyphenation::HYPHEN, style, font_faces);
+                    for hyphen_glyph in hyphen.glyphs() {
+                        inline_size += hyphen_glyph.advance;
+                        segment_glyphs.push(GlyphInfo {
+                            byte_offset: hyphen_offset,
+                            ..hyphen_glyph.clone()
+                        });
+                    }
+                }
+                self.push_text_fragment(
+                    text_node,
+                    style,
+                    &paragraph,
+                    &original_offsets,
+                    segment_start..glyph.byte_offset,
+                    std::mem::take(&mut segment_glyphs),
+                    segment_start_inline_size,
+                    inline_size,
+                );
                 self.flush_line();
+                inline_size = Au(0);
+                segment_start = glyph.byte_offset;
+                segment_start_inline_size = Au(0);
+                last_opportunity = None;
+
+                if reshape {
+                    // Now re-shape the text after the break, up to the next
+                    // glyph that is safe to break before, and process the
+                    // resulting glyphs again.
+                    let safe_end = glyphs[glyph_index + 1..]
+                        .iter()
+                        .position(|g| !g.unsafe_to_break)
+                        .map_or(glyphs.len(), |i| glyph_index + 1 + i);
+                    let reshape_end = glyphs.get(safe_end).map_or(paragraph.len(), |g| g.byte_offset);
+                    let mut reshaped = shape_range(&paragraph, glyph.byte_offset..reshape_end, style, font_faces);
+                    // The first glyph after a break is always safe to break
+                    // before.
+                    if let Some(first) = reshaped.first_mut() {
+                        first.unsafe_to_break = false;
+                    }
+                    glyphs.splice(glyph_index..safe_end, reshaped);
+                    next_glyph_index = glyph_index;
+                    resuming_after_break = true;
+                    continue;
+                }
+            }
+            if !hangs {
+                self.maybe_truncate_line(inline_size, glyph_advance);
             }
-            self.at_break_opportunity = break_opportunities[glyph.byte_offset];
+            inline_size += glyph_advance;
+            segment_glyphs.push(GlyphInfo {
+                advance: glyph_advance,
+                ..glyph
+            });
         }
+        self.push_text_fragment(
+            text_node,
+            style,
+            &paragraph,
+            &original_offsets,
+            segment_start..paragraph.len(),
+            segment_glyphs,
+            segment_start_inline_size,
+            inline_size,
+        );
+        self.current_line_available_size -= inline_size;
         // TODO:
         // break_and_shape_text(text, style);
         // advance_as_needed()
@@ -402,9 +861,8 @@ impl<'a, 'b, 'c> LineBreaker<'a, 'b, 'c> {
         self.current_position.item_index += advance;
     }
 
-    fn layout_and_break(&mut self) -> Option<()> {
-        loop {
-            let item = self.fc.items.get(self.current_position.item_index)?;
+    fn layout_and_break(&mut self) {
+        while let Some(item) = self.fc.items.get(self.current_position.item_index) {
             match *item {
                 InlineItem::TagStart(node) => {
                     self.open_boxes.push(OpenInlineBox {
@@ -426,7 +884,11 @@ impl<'a, 'b, 'c> LineBreaker<'a, 'b, 'c> {
                     self.layout_replaced(node);
                     self.current_position.advance_item();
                 },
-                InlineItem::Text(node, ref text) => {
+                InlineItem::ForcedLineBreak(..) => {
+                    self.flush_line();
+                    self.current_position.advance_item();
+                },
+                InlineItem::Text(node, ref text, _) => {
                     self.layout_inline_box(&self.fc.context.layout_tree[node].style, text);
                 },
             }
@@ -435,27 +897,11 @@ impl<'a, 'b, 'c> LineBreaker<'a, 'b, 'c> {
         self.flush_line();
     }
 
-    fn break_and_finish(mut self) -> LayoutResult {
+    /// Breaks the content into lines, and returns them along with their total
+    /// block size.
+    fn break_into_lines(mut self) -> (Vec<ChildFragment>, Au) {
         self.layout_and_break();
-        self.finish()
-    }
-
-    fn finish(self) -> LayoutResult {
-        let wm = self.wm();
-        // TODO: Vertical align, line positioning, block size.
-        LayoutResult {
-            root_fragment: ChildFragment {
-                offset: LogicalPoint::zero(wm),
-                fragment: Box::new(Fragment {
-                    size: LogicalSize::new(wm, self.constraints.available_size.inline(), self.current_line_max_block_size),
-                    style: self.fc.input_node.style.clone(),
-                    kind: FragmentKind::Container {
-                        kind: ContainerFragmentKind::Box {},
-                        children: self.lines.into_boxed_slice(),
-                    },
-                }),
-            },
-        }
+        (self.lines, self.consumed_block_offset)
     }
 }
 
@@ -465,23 +911,144 @@ pub struct InlineFormattingContext<'a, 'b> {
     items: Vec<InlineItem>,
 }
 
+/// A map from byte offsets in the text of an inline item to byte offsets in
+/// the text before text-transform was applied, which is only stored if they
+/// differ (which can happen because case mappings can change the length of the
+/// text, e.g., `ß` uppercases to `SS`).
+///
+/// TODO(emilio): Whitespace collapsing should probably also be reflected here.
+#[derive(Clone, Debug)]
+pub struct OriginalOffsets(Option<Box<[usize]>>);
+
+impl OriginalOffsets {
+    fn identity() -> Self {
+        OriginalOffsets(None)
+    }
+
+    /// Returns the offset in the original text for a given offset in the
+    /// transformed text.
+    fn original_offset(&self, offset: usize) -> usize {
+        match self.0 {
+            Some(ref offsets) => offsets[offset],
+            None => offset,
+        }
+    }
+
+    /// Returns the range in the original text for a given range in the
+    /// transformed text.
+    fn original_range(&self, range: Range<usize>) -> Range<usize> {
+        self.original_offset(range.start)..self.original_offset(range.end)
+    }
+
+    /// Appends the offsets of `text` to these, which map `len` bytes of text,
+    /// so that they map the concatenation of both.
+    fn append(&mut self, len: usize, text: &str, offsets: &OriginalOffsets) {
+        if self.0.is_none() && offsets.0.is_none() {
+            return;
+        }
+        let mut result = match self.0.take() {
+            Some(offsets) => offsets.into_vec(),
+            None => (0..=len).collect(),
+        };
+        // The last offset is the length of the original text, which is where
+        // the offsets of `text` start.
+        let base = result.pop().unwrap();
+        result.extend((0..=text.len()).map(|i| base + offsets.original_offset(i)));
+        self.0 = Some(result.into_boxed_slice());
+    }
+}
+
 /// An item we do inline layout on. Each of these correspond roughly to the
 /// (rendered) DOM.
-#[derive(Debug)]
-enum InlineItem {
-    // The start of a nested inline box.
+#[derive(Clone, Debug)]
+pub enum InlineItem {
+    /// The start of a nested inline box.
     TagStart(LayoutNodeId),
+    /// A text node, with white space already collapsed and text-transform
+    /// applied.
     // TODO(emilio): Probably want to reference-count this somehow, or something
     // something.
-    Text(LayoutNodeId, String),
+    Text(LayoutNodeId, String, OriginalOffsets),
     Replaced(LayoutNodeId),
     AtomicInline(LayoutNodeId),
+    /// A forced line break from a `<br>` element.
+    ForcedLineBreak(LayoutNodeId),
+    /// The end of a nested inline box.
     TagEnd(LayoutNodeId),
 }
 
+impl InlineItem {
+    /// The layout node this item comes from.
+    pub fn node(&self) -> LayoutNodeId {
+        match *self {
+            InlineItem::TagStart(node) |
+            InlineItem::Text(node, ..) |
+            InlineItem::Replaced(node) |
+            InlineItem::AtomicInline(node) |
+            InlineItem::ForcedLineBreak(node) |
+            InlineItem::TagEnd(node) => node,
+        }
+    }
+
+    /// The resolved text of this item, if it's a text item.
+    pub fn text(&self) -> Option<&str> {
+        match *self {
+            InlineItem::Text(_, ref text, _) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// A short description of this item, like `TagStart` or `Text("foo")`.
+    pub fn kind_label(&self) -> String {
+        match *self {
+            InlineItem::TagStart(..) => "TagStart".to_owned(),
+            InlineItem::Text(_, ref text, _) => format!("Text({:?})", text),
+            InlineItem::Replaced(..) => "Replaced".to_owned(),
+            InlineItem::AtomicInline(..) => "AtomicInline".to_owned(),
+            InlineItem::ForcedLineBreak(..) => "ForcedLineBreak".to_owned(),
+            InlineItem::TagEnd(..) => "TagEnd".to_owned(),
+        }
+    }
+}
+
+/// U+200B ZERO WIDTH SPACE.
+const ZWSP: char = '\u{200B}';
+
+/// The state of white space collapsing, which carries over from one item of
+/// an inline formatting context to the next.
+struct CollapseState {
+    /// Whether we've found collapsible spaces that haven't been output yet,
+    /// which are output as a single space before the next character.
+    after_collapsible_space: bool,
+    /// Whether the last non-space character was a segment break, so that
+    /// following spaces are removed.
+    after_break: bool,
+    /// Whether there's a collapsible segment break that hasn't been
+    /// transformed yet, which depends on the next character.
+    pending_break: bool,
+    /// The last character that was output, if any.
+    last_char: Option<char>,
+}
+
+impl CollapseState {
+    /// The state at the start of the formatting context, where spaces are
+    /// removed like after a segment break.
+    ///
+    /// TODO(emilio): This should happen at the start of each line instead,
+    /// during line breaking.
+    fn at_start() -> Self {
+        Self {
+            after_collapsible_space: false,
+            after_break: true,
+            pending_break: false,
+            last_char: None,
+        }
+    }
+}
+
 // https://drafts.csswg.org/css-text-3/#space-discard-set
 fn is_space_discarding(c: char) -> bool {
-    match c {
+    matches!(c,
         '\u{2E80}'..='\u{2EFF}' |
         '\u{2F00}'..='\u{2FDD}' |
         '\u{2FF0}'..='\u{2FFF}' |
@@ -511,18 +1078,99 @@ fn is_space_discarding(c: char) -> bool {
         '\u{2B820}'..='\u{2CEAF}' |
         '\u{2CEB0}'..='\u{2EBEF}' |
         '\u{2F800}'..='\u{2FA1F}' |
-        '\u{30000}'..='\u{3134F}' => true,
-        _ => false
-    }
+        '\u{30000}'..='\u{3134F}'
+    )
 }
 
 // https://searchfox.org/mozilla-central/rev/3d39d3b7dd1b2be30692d4541ea681614e34c786/layout/generic/nsTextFrame.cpp#1826-1827
 // https://drafts.csswg.org/css-text/#boundary-shaping
+/// Shapes the `range` of `text`, returning the glyphs with byte offsets relative
+/// to the whole `text`.
+fn shape_range(
+    text: &str,
+    range: Range<usize>,
+    style: &ComputedStyle,
+    font_faces: &FontFaceSet,
+) -> Vec<GlyphInfo> {
+    crate::fonts::shaping::shape(&text[range.clone()], style, font_faces)
+        .glyphs()
+        .map(|glyph| GlyphInfo {
+            byte_offset: glyph.byte_offset + range.start,
+            ..glyph.clone()
+        })
+        .collect()
+}
+
+/// U+2026 HORIZONTAL ELLIPSIS.
+const ELLIPSIS: &str = "\u{2026}";
+
+/// Returns the total advance of a given string of text.
+fn text_advance(text: &str, style: &ComputedStyle, font_faces: &FontFaceSet) -> Au {
+    crate::fonts::shaping::shape(text, style, font_faces)
+        .glyphs()
+        .fold(Au(0), |size, glyph| size + glyph.advance)
+}
+
+/// Returns the advance of a tab at `position` from the start of the line, given
+/// the distance between tab stops.
+///
+/// https://drafts.csswg.org/css-text-3/#tab-stop
+fn tab_advance(position: Au, interval: Au, space_advance: Au) -> Au {
+    if interval <= Au(0) {
+        return Au(0);
+    }
+    let mut next_stop = Au((position.0 / interval.0 + 1) * interval.0);
+    // If the distance to the next tab stop is less than half of the space
+    // advance, the next tab stop is used instead.
+    if next_stop - position < space_advance / 2 {
+        next_stop += interval;
+    }
+    next_stop - position
+}
+
+/// Whether the position `i` of `s` is between two typographic letter units.
+pub(crate) fn is_between_letters(s: &str, i: usize) -> bool {
+    if i == 0 || i >= s.len() {
+        return false;
+    }
+    let before = s[..i].chars().next_back().unwrap();
+    let after = s[i..].chars().next().unwrap();
+    before.is_alphanumeric() && after.is_alphanumeric()
+}
+
+fn is_space_or_newline(b: u8) -> bool {
+    b == b' ' || b == b'\t' || b == b'\n'
+}
+
 fn can_continue_run(run_style: &ComputedStyle, new_style: &ComputedStyle, at_beginning: bool) -> bool {
     if run_style.writing_mode != new_style.writing_mode {
         return false;
     }
 
+    // Break opportunities are computed for the whole run, so it needs to have
+    // the same wrapping behavior.
+    if run_style.white_space != new_style.white_space ||
+        run_style.word_break != new_style.word_break ||
+        run_style.overflow_wrap != new_style.overflow_wrap ||
+        run_style.hyphens != new_style.hyphens ||
+        run_style.tab_size != new_style.tab_size
+    {
+        return false;
+    }
+
+    // The run is shaped as a whole, with a single font and direction.
+    if run_style.font_size != new_style.font_size ||
+        run_style.font_family != new_style.font_family ||
+        run_style.font_style != new_style.font_style ||
+        run_style.font_weight != new_style.font_weight ||
+        run_style.font_stretch != new_style.font_stretch ||
+        run_style.font_variant_caps != new_style.font_variant_caps ||
+        run_style.font_feature_settings != new_style.font_feature_settings ||
+        run_style.direction != new_style.direction
+    {
+        return false;
+    }
+
     // Any of margin/border/padding separating the two typographic character
     // units in the inline axis is non-zero.
     let margin = new_style.margin();
@@ -540,8 +1188,7 @@ fn can_continue_run(run_style: &ComputedStyle, new_style: &ComputedStyle, at_beg
 
     // TODO: vertical-align is not baseline
     // TODO: The boundary is a bidi isolation boundary.
-    // TODO: line-break / word-break / maybe white-space: nowrap-ness?
-    // TODO: Definitely different fonts and such.
+    // TODO: line-break?
 
     true
 }
@@ -563,7 +1210,10 @@ impl<'a, 'b> InlineFormattingContext<'a, 'b> {
                 LayoutNodeKind::Leaf { ref kind } => {
                     match kind {
                         LeafKind::Replaced { .. } => self.items.push(InlineItem::Replaced(id)),
-                        LeafKind::Text { ref text } => self.items.push(InlineItem::Text(id, text.clone().into())),
+                        LeafKind::Text { ref text } => {
+                            self.items.push(InlineItem::Text(id, text.clone().into(), OriginalOffsets::identity()))
+                        },
+                        LeafKind::LineBreak => self.items.push(InlineItem::ForcedLineBreak(id)),
                     }
                 }
                 LayoutNodeKind::Container { ref kind, .. } => {
@@ -573,7 +1223,7 @@ impl<'a, 'b> InlineFormattingContext<'a, 'b> {
                             self.collect_inline_items_in(child);
                             self.items.push(InlineItem::TagEnd(id));
                         },
-                        ContainerKind::Block { .. } => {
+                        ContainerKind::Block { .. } | ContainerKind::Fieldset => {
                             debug_assert!(
                                 !child.style.display.is_block_outside(),
                                 "Should've been split",
@@ -590,12 +1240,7 @@ impl<'a, 'b> InlineFormattingContext<'a, 'b> {
         }
     }
 
-    fn collapse_spaces_in_string(
-        text: String,
-        collapses_newlines: bool,
-        after_collapsible_space: &mut bool,
-        after_break: &mut bool,
-    ) -> String {
+    fn collapse_spaces_in_string(text: String, collapses_newlines: bool, state: &mut CollapseState) -> String {
         let mut result = String::with_capacity(text.len());
         // The rules are relatively simple:
         //
@@ -615,40 +1260,39 @@ impl<'a, 'b> InlineFormattingContext<'a, 'b> {
         // around segment breaks getting transformed), we only put on the string
         // the characters that _definitely_ end up in it, and flush the
         // characters as needed.
-
-        // Whether we need to insert a collapsible space if we se a
-        // non-collapsible character.
-        let mut last_non_collapsible_char = None;
-        let after_collapsible_space_at_start = *after_collapsible_space;
-        const ZWSP: char = '\u{200B}';
+        //
+        // That means that the single space that remains from a sequence of
+        // collapsible spaces ends up in the item that follows it, if the
+        // sequence crosses an item boundary.
         for c in text.chars() {
             match c {
                 '\t' | ' ' => {
-                    if !*after_break {
-                        *after_collapsible_space = true;
+                    if !state.after_break {
+                        state.after_collapsible_space = true;
                     }
                 },
                 '\n' => {
                     // We found a break, thus previous collapsible space
                     // characters just go away, rule 4 doesn't (necessarily)
                     // apply.
-                    if !collapses_newlines {
+                    if collapses_newlines {
+                        // Breaks at the start are removed like spaces.
+                        state.pending_break = state.last_char.is_some();
+                    } else {
                         result.push('\n');
                     }
-                    *after_break = true;
-                    *after_collapsible_space = false;
+                    state.after_break = true;
+                    state.after_collapsible_space = false;
                 },
                 _ => {
                     // https://drafts.csswg.org/css-text-3/#line-break-transform
-                    if *after_break {
+                    if state.pending_break {
                         debug_assert!(
-                            !*after_collapsible_space,
+                            !state.after_collapsible_space,
                             "Collapsible space after a segment break is removed per rule 1",
                         );
 
-                        // Note that the `!collapses_newlines` case we've
-                        // already handled when finding it.
-                        let suppress = !collapses_newlines || c == ZWSP || last_non_collapsible_char.take().map_or(false, |last| {
+                        let suppress = c == ZWSP || state.last_char.is_some_and(|last| {
                             if last == ZWSP {
                                 return true;
                             }
@@ -656,21 +1300,18 @@ impl<'a, 'b> InlineFormattingContext<'a, 'b> {
                         });
 
                         if !suppress {
-                            *after_collapsible_space = true;
+                            state.after_collapsible_space = true;
                         }
                     }
 
-                    if *after_collapsible_space {
-                        if after_collapsible_space_at_start {
-                            result.push(ZWSP);
-                        } else {
-                            result.push(' ');
-                        }
+                    if state.after_collapsible_space {
+                        result.push(' ');
                     }
                     result.push(c);
-                    *after_break = false;
-                    *after_collapsible_space = false;
-                    last_non_collapsible_char = Some(c);
+                    state.after_break = false;
+                    state.pending_break = false;
+                    state.after_collapsible_space = false;
+                    state.last_char = Some(c);
                 }
             }
         }
@@ -680,24 +1321,23 @@ impl<'a, 'b> InlineFormattingContext<'a, 'b> {
 
     // https://drafts.csswg.org/css-text-3/#white-space-phase-1
     fn collapse_spaces(&mut self) {
-        let mut after_break = true;
-        let mut after_collapsible_space = false;
+        let mut state = CollapseState::at_start();
         for item in &mut self.items {
             let (node, text) = match *item {
                 InlineItem::Replaced(..) |
                 InlineItem::AtomicInline(..) |
+                InlineItem::ForcedLineBreak(..) |
                 InlineItem::TagEnd(..) |
                 InlineItem::TagStart(..) => continue,
-                InlineItem::Text(node, ref mut s) => (node, s),
+                InlineItem::Text(node, ref mut s, _) => (node, s),
             };
 
             let style = &self.context.layout_tree[node].style;
             if style.white_space.collapses_spaces() {
                 let new_text = Self::collapse_spaces_in_string(
-                    std::mem::replace(text, String::new()),
+                    std::mem::take(text),
                     style.white_space.collapses_newlines(),
-                    &mut after_collapsible_space,
-                    &mut after_break,
+                    &mut state,
                 );
                 *text = new_text;
             } else {
@@ -708,15 +1348,81 @@ impl<'a, 'b> InlineFormattingContext<'a, 'b> {
                     match b {
                         b'\t' | b' ' => {},
                         _ => {
-                            after_break = b == b'\n';
+                            state.after_break = b == b'\n';
                             break;
                         }
                     }
                 }
+                if let Some(c) = text.chars().next_back() {
+                    state.last_char = Some(c);
+                }
             }
         }
     }
 
+    fn transform_text_in_string(
+        text: &str,
+        text_transform: TextTransform,
+        after_letter: &mut bool,
+    ) -> (String, OriginalOffsets) {
+        let mut result = String::with_capacity(text.len());
+        let mut offsets = vec![];
+        let mut identity_offsets = true;
+        for (i, c) in text.char_indices() {
+            let start = result.len();
+            let capitalize = text_transform == TextTransform::Capitalize && !*after_letter;
+            match text_transform {
+                TextTransform::Uppercase => result.extend(c.to_uppercase()),
+                TextTransform::Lowercase => result.extend(c.to_lowercase()),
+                // TODO(emilio): This should really use titlecase mappings.
+                TextTransform::Capitalize if capitalize => result.extend(c.to_uppercase()),
+                TextTransform::Capitalize | TextTransform::None => result.push(c),
+            }
+            *after_letter = c.is_alphanumeric();
+            identity_offsets &= result.len() - start == c.len_utf8();
+            offsets.extend(std::iter::repeat_n(i, result.len() - start));
+        }
+
+        if identity_offsets {
+            return (result, OriginalOffsets::identity());
+        }
+        offsets.push(text.len());
+        (result, OriginalOffsets(Some(offsets.into_boxed_slice())))
+    }
+
+    // https://drafts.csswg.org/css-text-3/#text-transform-property
+    //
+    // This happens after collapsing white space, but before line breaking and
+    // shaping, see https://drafts.csswg.org/css-text-3/#order.
+    fn transform_text(&mut self) {
+        // Whether we're in the middle of a word, for capitalize.
+        let mut after_letter = false;
+        for item in &mut self.items {
+            let (node, text, offsets) = match *item {
+                InlineItem::TagEnd(..) |
+                InlineItem::TagStart(..) => continue,
+                InlineItem::Replaced(..) |
+                InlineItem::AtomicInline(..) |
+                InlineItem::ForcedLineBreak(..) => {
+                    after_letter = false;
+                    continue;
+                },
+                InlineItem::Text(node, ref mut s, ref mut offsets) => (node, s, offsets),
+            };
+
+            let text_transform = self.context.layout_tree[node].style.text_transform;
+            if text_transform == TextTransform::None {
+                after_letter = text.chars().next_back().map_or(after_letter, |c| c.is_alphanumeric());
+                continue;
+            }
+
+            let (new_text, new_offsets) =
+                Self::transform_text_in_string(text, text_transform, &mut after_letter);
+            *text = new_text;
+            *offsets = new_offsets;
+        }
+    }
+
     fn split_bidi(&mut self) {
         // TODO: Keep track of bidi levels within the text items.
         // https://drafts.csswg.org/css-writing-modes-3/#bidi-algo
@@ -724,17 +1430,135 @@ impl<'a, 'b> InlineFormattingContext<'a, 'b> {
     }
 
     fn do_layout(&mut self, constraints: &ConstraintSpace) -> LayoutResult {
-        LineBreaker::new(self, constraints).break_and_finish()
+        let node = self.input_node;
+        let style = &node.style;
+        let wm = style.writing_mode;
+        let padding = style.padding().map_all(|lp| {
+            lp.resolve(constraints.percentage_resolution_size.inline())
+        });
+        let bp = style.border_widths() + padding;
+        let inline_size = block::inline_border_box_size(self.context, node, &bp, constraints);
+        let children_constraints = block::children_constraints(node, &bp, inline_size, constraints);
+        let (mut lines, content_block_size) = LineBreaker::new(self, &children_constraints).break_into_lines();
+        // TODO: Vertical align, line positioning.
+        for line in &mut lines {
+            line.offset.i += bp.inline_start;
+            line.offset.b += bp.block_start;
+        }
+        let block_size = block::block_border_box_size(node, &bp, inline_size, content_block_size, constraints);
+        LayoutResult {
+            root_fragment: ChildFragment {
+                offset: LogicalPoint::zero(wm),
+                fragment: Rc::new(Fragment::new_container(
+                    LogicalSize::new(wm, inline_size, block_size),
+                    style.clone(),
+                    Some(self.context.layout_tree.id_of(node)),
+                    ContainerFragmentKind::Box {
+                        decorations: BoxDecorations::from_style(style),
+                    },
+                    lines.into_boxed_slice(),
+                )),
+            },
+            break_token: None,
+        }
+    }
+
+    /// Collects and processes the inline items of this formatting context, if
+    /// we haven't done so already.
+    fn prepare_items(&mut self) {
+        if !self.items.is_empty() {
+            return;
+        }
+        self.collect_inline_items_in(self.input_node);
+        self.collapse_spaces();
+        self.transform_text();
+        self.split_bidi();
+    }
+
+    /// Returns the inline items of this formatting context, after white space
+    /// collapsing, text-transform and bidi splitting, which is what line
+    /// breaking operates on.
+    pub fn items(&mut self) -> &[InlineItem] {
+        self.prepare_items();
+        &self.items
+    }
+
+    /// Computes the min-content and max-content inline sizes of this
+    /// formatting context, measuring the text without doing line-breaking.
+    ///
+    /// TODO(emilio): This measures each text item independently, so it
+    /// doesn't account for shaping or break opportunities across inline box
+    /// boundaries, nor for forced breaks inside preserved text.
+    fn compute_min_max_sizes(&mut self) -> MinMaxSizes {
+        self.prepare_items();
+
+        // Percentages resolve against zero for intrinsic size contributions.
+        fn mbp(margin: &LengthPercentageOrAuto, border: Au, padding: &LengthPercentage) -> Au {
+            let margin = match *margin {
+                LengthPercentageOrAuto::Auto => Au(0),
+                LengthPercentageOrAuto::LengthPercentage(ref lp) => lp.resolve(Au(0)),
+            };
+            margin + border + padding.resolve(Au(0))
+        }
+
+        let tree = self.context.layout_tree;
+        let mut sizes = MinMaxSizes {
+            min_content: Au(0),
+            max_content: Au(0),
+        };
+        let mut line = Au(0);
+        for item in &self.items {
+            match *item {
+                InlineItem::TagStart(node) => {
+                    let style = &tree[node].style;
+                    line += mbp(
+                        style.margin().inline_start,
+                        style.border_widths().inline_start,
+                        style.padding().inline_start,
+                    );
+                },
+                InlineItem::TagEnd(node) => {
+                    let style = &tree[node].style;
+                    line += mbp(
+                        style.margin().inline_end,
+                        style.border_widths().inline_end,
+                        style.padding().inline_end,
+                    );
+                },
+                InlineItem::Text(node, ref text, _) => {
+                    let text_sizes = crate::fonts::measure::measure(text, &tree[node].style, tree.font_faces());
+                    sizes.min_content = std::cmp::max(sizes.min_content, text_sizes.min_content);
+                    line += text_sizes.max_content;
+                },
+                InlineItem::ForcedLineBreak(..) => {
+                    sizes.max_content = std::cmp::max(sizes.max_content, line);
+                    line = Au(0);
+                },
+                InlineItem::Replaced(node) | InlineItem::AtomicInline(node) => {
+                    let contribution = tree[node].min_max_contribution(self.context, self.input_node.writing_mode());
+                    sizes.min_content = std::cmp::max(sizes.min_content, contribution.min_content);
+                    line += contribution.max_content;
+                },
+            }
+        }
+        sizes.max_content = std::cmp::max(sizes.max_content, line);
+        // The max-content size is never smaller than the min-content size.
+        sizes.max_content = std::cmp::max(sizes.max_content, sizes.min_content);
+        sizes
     }
 }
 
 impl<'a, 'b> super::LayoutAlgorithm for InlineFormattingContext<'a, 'b> {
-    fn layout(&mut self, constraints: &ConstraintSpace) -> LayoutResult {
+    fn min_max_sizes(&mut self) -> MinMaxSizes {
+        self.compute_min_max_sizes()
+    }
+
+    /// TODO(fragmentation): Resume from the line after the break.
+    fn layout(&mut self, constraints: &ConstraintSpace, resume_from: Option<&BreakToken>) -> LayoutResult {
         debug_assert!(self.input_node.establishes_ifc(self.context.layout_tree));
+        debug_assert!(resume_from.is_none_or(|token| token.as_inline().is_some()));
 
-        self.collect_inline_items_in(self.input_node);
-        self.collapse_spaces();
-        self.split_bidi();
+        self.prepare_items();
         self.do_layout(constraints)
     }
 }
@@ -1,12 +1,22 @@
 pub mod block;
+pub mod fieldset;
+mod hyphenation;
 pub mod inline;
+pub mod replaced;
 
 use crate::fragment_tree::ChildFragment;
-use crate::layout_tree::LayoutTree;
-use crate::logical_geometry::{LogicalSize, WritingMode};
+use crate::layout_tree::{LayoutNode, LayoutNodeId, LayoutTree};
+use crate::logical_geometry::{LogicalRect, LogicalSize, WritingMode};
+use crate::sizing::MinMaxSizes;
+use crate::style::{Float, Position};
 use app_units::Au;
+use euclid::default::Size2D;
 use html5ever::tree_builder::QuirksMode;
 
+/// The default width of scrollbars, which take space from the content box of
+/// scroll containers.
+pub const DEFAULT_SCROLLBAR_WIDTH: Au = Au(15 * app_units::AU_PER_PX);
+
 /// A struct that contains global information about this layout pass.
 pub struct LayoutContext<'a> {
     /// The quirks mode of the document we're laying out.
@@ -14,9 +24,13 @@ pub struct LayoutContext<'a> {
 
     /// The layout tree.
     pub layout_tree: &'a LayoutTree,
+
+    /// The physical size of the initial containing block, which is the size of
+    /// the viewport.
+    pub initial_containing_block_size: Size2D<Au>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct AvailableSize(LogicalSize<Option<Au>>);
 
 impl std::ops::Deref for AvailableSize {
@@ -49,6 +63,19 @@ impl AvailableSize {
         }
     }
 
+    /// Converts this available size to another writing mode.
+    ///
+    /// If the resulting inline size is indefinite, which happens for
+    /// orthogonal flows when the block size is unconstrained,
+    /// `fallback_inline` is used instead.
+    pub fn convert(&self, from: WritingMode, to: WritingMode, fallback_inline: Au) -> Self {
+        let mut size = self.0.convert(from, to);
+        if size.inline.is_none() {
+            size.inline = Some(fallback_inline);
+        }
+        AvailableSize(size)
+    }
+
     pub fn shrink_inline_size(&mut self, by: Au) {
         let inline = self.0.inline.as_mut().unwrap();
         *inline -= by;
@@ -58,20 +85,408 @@ impl AvailableSize {
     }
 }
 
-/// The constraints we're using for a given layout.
+/// What layout needs to know about a containing block, passed down through
+/// the constraint space instead of being looked up by walking the ancestors
+/// of the box being laid out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContainingBlockInfo {
+    /// The available size for the contents of the containing block, in its
+    /// writing mode.
+    pub size: AvailableSize,
+    pub writing_mode: WritingMode,
+    /// Whether this is the containing block of absolutely positioned
+    /// descendants.
+    pub is_absolute_containing_block: bool,
+    /// Whether this is the containing block of fixed positioned descendants.
+    pub is_fixed_containing_block: bool,
+}
+
+impl ContainingBlockInfo {
+    /// The containing block that `node` establishes for its descendants,
+    /// given the available size for its contents.
+    pub fn new(node: &LayoutNode, size: AvailableSize) -> Self {
+        Self {
+            size,
+            writing_mode: node.writing_mode(),
+            is_absolute_containing_block: node.is_absolute_containing_block(),
+            is_fixed_containing_block: node.is_fixed_containing_block(),
+        }
+    }
+
+    /// The initial containing block, which has the size of the viewport and
+    /// contains everything.
+    ///
+    /// https://drafts.csswg.org/css-display/#initial-containing-block
+    pub fn initial(wm: WritingMode, size: Size2D<Au>) -> Self {
+        Self {
+            size: AvailableSize::definite(wm, LogicalSize::from_physical(wm, size)),
+            writing_mode: wm,
+            is_absolute_containing_block: true,
+            is_fixed_containing_block: true,
+        }
+    }
+}
+
+/// An area taken by a float, which in-flow content has to avoid.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Exclusion {
+    /// The margin box of the float, relative to the block formatting
+    /// context.
+    pub rect: LogicalRect<Au>,
+    pub float: Float,
+}
+
+/// The floats placed so far in a block formatting context.
+///
+/// TODO(floats): Nothing places floats yet, so this is always empty.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExclusionSpace {
+    exclusions: Vec<Exclusion>,
+}
+
+impl ExclusionSpace {
+    pub fn is_empty(&self) -> bool {
+        self.exclusions.is_empty()
+    }
+
+    pub fn add(&mut self, exclusion: Exclusion) {
+        debug_assert_ne!(exclusion.float, Float::None);
+        self.exclusions.push(exclusion);
+    }
+
+    pub fn exclusions(&self) -> &[Exclusion] {
+        &self.exclusions
+    }
+}
+
+/// The fragmentainer (a page or a column) that a box is laid out into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fragmentainer {
+    pub block_size: Au,
+    /// The block offset of the box being laid out from the block-start of
+    /// the fragmentainer.
+    pub block_offset: Au,
+}
+
+/// The constraints we're using for a given layout. Use a
+/// `ConstraintSpaceBuilder` to create one.
+#[derive(Clone, Debug, PartialEq)]
 pub struct ConstraintSpace {
     pub available_size: AvailableSize,
     pub percentage_resolution_size: AvailableSize,
-    pub containing_block_writing_mode: WritingMode,
-    // TODO(emilio): Sure we need to add more stuff here.
+    /// The containing block of the box being laid out, if it's in-flow.
+    pub containing_block: ContainingBlockInfo,
+    /// The containing block of the absolutely positioned boxes laid out with
+    /// these constraints.
+    pub absolute_containing_block: ContainingBlockInfo,
+    /// The containing block of the fixed positioned boxes laid out with
+    /// these constraints.
+    pub fixed_containing_block: ContainingBlockInfo,
+    /// The space that scroll containers reserve for their scrollbars, which
+    /// is zero for overlay scrollbars.
+    pub scrollbar_width: Au,
+    /// The floats of the block formatting context the box is laid out in.
+    pub exclusion_space: ExclusionSpace,
+    /// The fragmentainer the box is laid out into, if it's being fragmented.
+    pub fragmentainer: Option<Fragmentainer>,
+    /// Whether the box being laid out establishes a new formatting context,
+    /// so that its contents don't interact with outside floats and margins.
+    pub is_new_formatting_context: bool,
+    /// The block offset of the box from the start of its block formatting
+    /// context, which is unknown until the margins that may collapse with
+    /// the ones of the box are resolved.
+    pub bfc_block_offset: Option<Au>,
+}
+
+impl ConstraintSpace {
+    /// The writing mode of the containing block of the box being laid out.
+    pub fn containing_block_writing_mode(&self) -> WritingMode {
+        self.containing_block.writing_mode
+    }
+
+    /// Returns the containing block of `child`, a box laid out with these
+    /// constraints, which depends on how it's positioned.
+    pub fn containing_block_for(&self, child: &LayoutNode) -> &ContainingBlockInfo {
+        match child.position() {
+            Position::Absolute => &self.absolute_containing_block,
+            Position::Fixed => &self.fixed_containing_block,
+            Position::Static | Position::Relative | Position::Sticky => &self.containing_block,
+        }
+    }
+
+    /// Returns whether a layout with `self` as the constraints would produce
+    /// the same result as a previous layout with `cached` as constraints.
+    ///
+    /// The percentage resolution size only matters if the box being laid
+    /// out, as per `depends_on_percentage_basis`, resolves percentages
+    /// against it.
+    pub fn is_compatible_with(
+        &self,
+        cached: &ConstraintSpace,
+        depends_on_percentage_basis: impl FnOnce() -> bool,
+    ) -> bool {
+        // The rest of the containing block info only matters for out-of-flow
+        // boxes, which aren't cached separately from their containing block.
+        if self.available_size != cached.available_size ||
+            self.containing_block_writing_mode() != cached.containing_block_writing_mode() ||
+            self.scrollbar_width != cached.scrollbar_width ||
+            self.exclusion_space != cached.exclusion_space ||
+            self.fragmentainer != cached.fragmentainer ||
+            self.is_new_formatting_context != cached.is_new_formatting_context ||
+            self.bfc_block_offset != cached.bfc_block_offset
+        {
+            return false;
+        }
+        self.percentage_resolution_size == cached.percentage_resolution_size ||
+            !depends_on_percentage_basis()
+    }
+
+    /// Returns the constraints for a child whose writing mode is orthogonal
+    /// to the containing block's. The sizes of the returned constraint space
+    /// are in the child's writing mode.
+    ///
+    /// The available inline size of an orthogonal flow is the containing
+    /// block's available block size if definite, or the size of the initial
+    /// containing block otherwise.
+    ///
+    /// https://drafts.csswg.org/css-writing-modes/#orthogonal-auto
+    pub fn for_orthogonal_child(&self, child_wm: WritingMode, icb_size: Size2D<Au>) -> Self {
+        let wm = self.containing_block_writing_mode();
+        debug_assert!(child_wm.is_orthogonal_to(wm));
+        let fallback_inline = LogicalSize::from_physical(child_wm, icb_size).inline;
+        ConstraintSpace {
+            available_size: self.available_size.convert(wm, child_wm, fallback_inline),
+            percentage_resolution_size: self
+                .percentage_resolution_size
+                .convert(wm, child_wm, fallback_inline),
+            ..self.clone()
+        }
+    }
+}
+
+/// Builds the constraints for a layout, so that all the layout algorithms
+/// fill them the same way.
+pub struct ConstraintSpaceBuilder {
+    space: ConstraintSpace,
+}
+
+impl ConstraintSpaceBuilder {
+    /// Starts the constraints of a box that is the root of a layout, like the
+    /// root box or a box being measured, with `containing_block` as the
+    /// containing block of all its descendants.
+    ///
+    /// The box establishes a new formatting context, and both the available
+    /// size and the percentage resolution size are the size of the
+    /// containing block unless overridden.
+    pub fn new(containing_block: ContainingBlockInfo) -> Self {
+        Self {
+            space: ConstraintSpace {
+                available_size: containing_block.size.clone(),
+                percentage_resolution_size: containing_block.size.clone(),
+                absolute_containing_block: containing_block.clone(),
+                fixed_containing_block: containing_block.clone(),
+                containing_block,
+                scrollbar_width: DEFAULT_SCROLLBAR_WIDTH,
+                exclusion_space: ExclusionSpace::default(),
+                fragmentainer: None,
+                is_new_formatting_context: true,
+                bfc_block_offset: Some(Au(0)),
+            },
+        }
+    }
+
+    /// Starts the constraints of the children of a box laid out with
+    /// `parent`, where `containing_block` describes that box.
+    ///
+    /// The children share the formatting context of the parent, and
+    /// `containing_block` becomes the containing block of absolutely and
+    /// fixed positioned descendants if it establishes one for them.
+    pub fn for_children_of(parent: &ConstraintSpace, containing_block: ContainingBlockInfo) -> Self {
+        let mut space = parent.clone();
+        space.available_size = containing_block.size.clone();
+        space.percentage_resolution_size = containing_block.size.clone();
+        if containing_block.is_absolute_containing_block {
+            space.absolute_containing_block = containing_block.clone();
+        }
+        if containing_block.is_fixed_containing_block {
+            space.fixed_containing_block = containing_block.clone();
+        }
+        space.containing_block = containing_block;
+        space.is_new_formatting_context = false;
+        space.bfc_block_offset = None;
+        Self { space }
+    }
+
+    pub fn available_size(mut self, available_size: AvailableSize) -> Self {
+        self.space.available_size = available_size;
+        self
+    }
+
+    pub fn percentage_resolution_size(mut self, percentage_resolution_size: AvailableSize) -> Self {
+        self.space.percentage_resolution_size = percentage_resolution_size;
+        self
+    }
+
+    pub fn scrollbar_width(mut self, scrollbar_width: Au) -> Self {
+        self.space.scrollbar_width = scrollbar_width;
+        self
+    }
+
+    pub fn exclusion_space(mut self, exclusion_space: ExclusionSpace) -> Self {
+        self.space.exclusion_space = exclusion_space;
+        self
+    }
+
+    pub fn fragmentainer(mut self, fragmentainer: Option<Fragmentainer>) -> Self {
+        self.space.fragmentainer = fragmentainer;
+        self
+    }
+
+    /// Sets whether the box establishes a new formatting context. Floats
+    /// outside of a new formatting context don't affect its contents.
+    pub fn new_formatting_context(mut self, is_new_formatting_context: bool) -> Self {
+        self.space.is_new_formatting_context = is_new_formatting_context;
+        if is_new_formatting_context {
+            self.space.exclusion_space = ExclusionSpace::default();
+        }
+        self
+    }
+
+    pub fn bfc_block_offset(mut self, bfc_block_offset: Option<Au>) -> Self {
+        self.space.bfc_block_offset = bfc_block_offset;
+        self
+    }
+
+    pub fn build(self) -> ConstraintSpace {
+        self.space
+    }
+}
+
+/// Where a layout algorithm stopped because it ran out of space in the
+/// fragmentainer, so that layout can resume from there in the next one.
+///
+/// TODO(fragmentation): Nothing breaks yet, so no layout returns a break
+/// token.
+#[derive(Clone, Debug, PartialEq, BreakToken)]
+pub enum BreakToken {
+    Block(BlockBreakToken),
+    Inline(InlineBreakToken),
+}
+
+/// A break inside a block container, or another box with block-level
+/// children.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockBreakToken {
+    /// The child from which layout resumes.
+    pub child: LayoutNodeId,
+    /// Where layout resumes inside `child`, if it broke too.
+    pub child_break_token: Option<Box<BreakToken>>,
+    /// The block size of the box that previous fragments took.
+    pub consumed_block_size: Au,
+}
+
+/// A break between two lines of an inline formatting context.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InlineBreakToken {
+    /// The index of the inline item from which layout resumes.
+    pub item_index: usize,
+    /// The offset into the text of that item, if it's a text item.
+    pub text_offset: usize,
 }
 
 /// A layout result for a given layout algorithm.
+#[derive(Clone, Debug)]
 pub struct LayoutResult {
     /// The main fragment this layout pass has generated.
     pub root_fragment: ChildFragment,
+    /// Where the layout of the next fragment of the box resumes, if the box
+    /// didn't fit in the fragmentainer.
+    pub break_token: Option<BreakToken>,
+}
+
+/// The layout algorithms that lay out the contents of a box with an
+/// independent layout. New formatting contexts plug in here.
+///
+/// TODO(emilio): Flex, grid and table layout, once `display` supports them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LayoutAlgorithmKind {
+    /// A block container with block-level children.
+    Block,
+    /// A block container with inline-level children.
+    Inline,
+    Fieldset,
+    Replaced,
+}
+
+impl LayoutAlgorithmKind {
+    /// Returns the algorithm that lays out `node`, if it has an independent
+    /// layout.
+    ///
+    /// Inline boxes, text, and block containers that don't establish a
+    /// formatting context are laid out by the algorithm of their formatting
+    /// context instead.
+    pub fn for_node(node: &LayoutNode, tree: &LayoutTree) -> Option<Self> {
+        if node.is_replaced() {
+            return Some(LayoutAlgorithmKind::Replaced);
+        }
+        if node.is_fieldset() {
+            return Some(LayoutAlgorithmKind::Fieldset);
+        }
+        if node.establishes_ifc(tree) {
+            return Some(LayoutAlgorithmKind::Inline);
+        }
+        if node.is_block_container() && node.establishes_bfc(tree) {
+            return Some(LayoutAlgorithmKind::Block);
+        }
+        None
+    }
+
+    /// Computes the intrinsic sizes of `node` with this algorithm.
+    pub fn min_max_sizes(self, context: &LayoutContext, node: &LayoutNode) -> MinMaxSizes {
+        match self {
+            LayoutAlgorithmKind::Block => block::BlockFormattingContext::new(context, node).min_max_sizes(),
+            LayoutAlgorithmKind::Inline => inline::InlineFormattingContext::new(context, node).min_max_sizes(),
+            LayoutAlgorithmKind::Fieldset => fieldset::FieldsetLayout::new(context, node).min_max_sizes(),
+            LayoutAlgorithmKind::Replaced => replaced::ReplacedLayout::new(context, node).min_max_sizes(),
+        }
+    }
+
+    /// Lays out `node` with this algorithm.
+    pub fn layout(
+        self,
+        context: &LayoutContext,
+        node: &LayoutNode,
+        constraints: &ConstraintSpace,
+        resume_from: Option<&BreakToken>,
+    ) -> LayoutResult {
+        match self {
+            LayoutAlgorithmKind::Block => {
+                block::BlockFormattingContext::new(context, node).layout(constraints, resume_from)
+            },
+            LayoutAlgorithmKind::Inline => {
+                inline::InlineFormattingContext::new(context, node).layout(constraints, resume_from)
+            },
+            LayoutAlgorithmKind::Fieldset => {
+                fieldset::FieldsetLayout::new(context, node).layout(constraints, resume_from)
+            },
+            LayoutAlgorithmKind::Replaced => {
+                replaced::ReplacedLayout::new(context, node).layout(constraints, resume_from)
+            },
+        }
+    }
 }
 
+/// A layout algorithm runs in two phases: Measuring, where the intrinsic
+/// sizes of the node are computed without laying it out, and placing, where
+/// the node is laid out with the final constraints.
 pub trait LayoutAlgorithm {
-    fn layout(&mut self, constraints: &ConstraintSpace) -> LayoutResult;
+    /// Computes the min-content and max-content inline sizes of the content
+    /// box of the node.
+    ///
+    /// https://drafts.csswg.org/css-sizing-3/#intrinsic-sizes
+    fn min_max_sizes(&mut self) -> MinMaxSizes;
+
+    /// Lays out the node, or its next fragment if `resume_from` is the break
+    /// token of the previous one.
+    fn layout(&mut self, constraints: &ConstraintSpace, resume_from: Option<&BreakToken>) -> LayoutResult;
 }
@@ -0,0 +1,179 @@
+//! Sizing of replaced boxes: Their used size comes from the sizing
+//! properties, falling back to their intrinsic dimensions, and an aspect ratio
+//! transfers sizes from one axis to the other.
+//!
+//! https://drafts.csswg.org/css2/visudet.html#inline-replaced-width
+//! https://drafts.csswg.org/css2/visudet.html#inline-replaced-height
+//!
+//! The document of an `<iframe>` is laid out using the content box of the
+//! replaced box as its viewport, and its fragments are nested inside the
+//! fragment of the iframe.
+
+use super::{BreakToken, ConstraintSpace, LayoutContext, LayoutResult};
+use crate::fragment_tree::{BoxDecorations, ChildFragment, ContainerFragmentKind, Fragment};
+use crate::layout_tree::{LayoutNode, LayoutNodeKind, LeafKind};
+use crate::logical_geometry::*;
+use crate::sizing::MinMaxSizes;
+use crate::style::{LengthPercentage, Size};
+use app_units::Au;
+use euclid::default::Size2D;
+use std::rc::Rc;
+
+pub struct ReplacedLayout<'a, 'b> {
+    context: &'a LayoutContext<'b>,
+    input_node: &'a LayoutNode,
+}
+
+impl<'a, 'b> ReplacedLayout<'a, 'b> {
+    pub fn new(context: &'a LayoutContext<'b>, input_node: &'a LayoutNode) -> Self {
+        debug_assert!(input_node.is_replaced());
+        Self {
+            context,
+            input_node,
+        }
+    }
+
+    fn intrinsic_size(&self) -> LogicalSize<Au> {
+        match self.input_node.kind {
+            LayoutNodeKind::Leaf {
+                kind: LeafKind::Replaced { intrinsic_size, .. },
+            } => LogicalSize::from_physical(self.input_node.writing_mode(), intrinsic_size),
+            _ => unreachable!("Not a replaced box?"),
+        }
+    }
+
+    /// Lays out the document of an `<iframe>`, if any, with a viewport of a
+    /// given physical size.
+    ///
+    /// TODO(emilio): The nested document should be clipped to the content
+    /// box.
+    fn layout_nested_document(&self, viewport_size: Size2D<Au>) -> Option<ChildFragment> {
+        let document = match self.input_node.kind {
+            LayoutNodeKind::Leaf {
+                kind: LeafKind::Replaced { nested_document: Some(ref document), .. },
+            } => document,
+            _ => return None,
+        };
+        let quirks_mode = document.dom.as_document()?.quirks_mode();
+        let tree = document.layout_tree.layout(quirks_mode, viewport_size);
+        // Wrap the root box so that the text of the nested document is
+        // painted with its own font faces.
+        let root = &tree.root.fragment;
+        Some(ChildFragment {
+            offset: LogicalPoint::zero(root.style.writing_mode),
+            fragment: Rc::new(Fragment::new_container(
+                root.size,
+                root.style.clone(),
+                None,
+                ContainerFragmentKind::Document {
+                    font_faces: tree.font_faces.clone(),
+                },
+                vec![tree.root.clone()].into_boxed_slice(),
+            )),
+        })
+    }
+
+    /// Resolves a specified size to a content box size, or `None` if it
+    /// behaves as `auto`.
+    ///
+    /// TODO(emilio): min-content / max-content should also behave as auto,
+    /// and min / max sizes should be honored.
+    fn resolve_size(&self, size: &Size, percentage_basis: Option<Au>, bp: Au) -> Option<Au> {
+        let lp: &LengthPercentage = match *size {
+            Size::LengthPercentage(ref lp) => lp,
+            Size::Keyword(..) => return None,
+        };
+        let mut size = lp.maybe_resolve(percentage_basis)?;
+        if self.input_node.style.box_sizing.border_box() {
+            size = std::cmp::max(size - bp, Au(0));
+        }
+        Some(size)
+    }
+
+    /// Returns the used size of the content box.
+    fn content_size(&self, specified: LogicalSize<Option<Au>>) -> LogicalSize<Au> {
+        let wm = self.input_node.writing_mode();
+        let intrinsic = self.intrinsic_size();
+        let ratio = self.input_node.preferred_aspect_ratio();
+        let (inline, block) = match (specified.inline, specified.block) {
+            (Some(inline), Some(block)) => (inline, block),
+            (Some(inline), None) => {
+                let block = ratio.map_or(intrinsic.block, |r| r.block_size_from_inline(wm, inline));
+                (inline, block)
+            },
+            (None, Some(block)) => {
+                let inline = ratio.map_or(intrinsic.inline, |r| r.inline_size_from_block(wm, block));
+                (inline, block)
+            },
+            (None, None) => (intrinsic.inline, intrinsic.block),
+        };
+        LogicalSize::new(wm, inline, block)
+    }
+}
+
+impl<'a, 'b> super::LayoutAlgorithm for ReplacedLayout<'a, 'b> {
+    /// The intrinsic inline size of a replaced box is its intrinsic size,
+    /// unless a fixed block size is transferred through its aspect ratio.
+    fn min_max_sizes(&mut self) -> MinMaxSizes {
+        let style = &self.input_node.style;
+        let bp = style.border_widths() + style.padding().map_all(|lp| lp.resolve(Au(0)));
+        let block = self.resolve_size(style.size().block, None, bp.block_start_end());
+        let specified = LogicalSize::new(self.input_node.writing_mode(), None, block);
+        MinMaxSizes::from(self.content_size(specified).inline)
+    }
+
+    fn layout(&mut self, constraints: &ConstraintSpace, resume_from: Option<&BreakToken>) -> LayoutResult {
+        debug_assert!(resume_from.is_none(), "Replaced boxes are monolithic");
+        let node = self.input_node;
+        let style = &node.style;
+        let wm = style.writing_mode;
+        let border = style.border_widths();
+        let padding = style.padding().map_all(|lp| {
+            lp.resolve(constraints.percentage_resolution_size.inline())
+        });
+        let bp = border + padding;
+
+        let size = style.size();
+        let specified = LogicalSize::new(
+            wm,
+            self.resolve_size(
+                size.inline,
+                constraints.percentage_resolution_size.inline,
+                bp.inline_start_end(),
+            ),
+            self.resolve_size(
+                size.block,
+                constraints.percentage_resolution_size.block,
+                bp.block_start_end(),
+            ),
+        );
+        let content_size = self.content_size(specified);
+        let children = match self.layout_nested_document(content_size.to_physical(wm)) {
+            Some(mut root) => {
+                root.offset = LogicalPoint::new(wm, bp.inline_start, bp.block_start);
+                vec![root]
+            },
+            None => vec![],
+        };
+
+        LayoutResult {
+            root_fragment: ChildFragment {
+                offset: LogicalPoint::zero(wm),
+                fragment: Rc::new(Fragment::new_container(
+                    LogicalSize::new(
+                        wm,
+                        content_size.inline + bp.inline_start_end(),
+                        content_size.block + bp.block_start_end(),
+                    ),
+                    style.clone(),
+                    Some(self.context.layout_tree.id_of(node)),
+                    ContainerFragmentKind::Box {
+                        decorations: BoxDecorations::from_style(style),
+                    },
+                    children.into_boxed_slice(),
+                )),
+            },
+            break_token: None,
+        }
+    }
+}
@@ -3,6 +3,7 @@
 use super::super::*;
 use super::*;
 use crate::style::*;
+use std::rc::Rc;
 
 pub struct BlockInside;
 
@@ -38,20 +39,30 @@ pub fn legalize_insertion_point(tree: &LayoutTree, ip: InsertionPoint) -> Insert
 impl BlockInside {
     fn inline_wrapper(tree: &mut LayoutTree) -> LayoutNodeId {
         tree.alloc(LayoutNode::new_container(
-            ComputedStyle::for_inline_inside_block_wrapper(),
+            Rc::new(ComputedStyle::for_inline_inside_block_wrapper()),
             ContainerKind::block(),
         ))
     }
 
     fn wrap_inlines_in_anon_blocks(tree: &mut LayoutTree, ip: InsertionPoint) -> InsertionPoint {
-        let trailing_anon_block = Self::inline_wrapper(tree);
         // Move all our inline children after ip.prev_sibling to an anonymous
-        // block.
-        tree.move_children_to(ip.parent, trailing_anon_block, ip.prev_sibling);
-        if ip.prev_sibling.is_none() {
-            tree.insert_unchecked(trailing_anon_block, ip);
-            return ip;
-        }
+        // block, if there's any. We don't want an empty wrapper if we're
+        // inserting after the last child.
+        let has_trailing_inlines = match ip.prev_sibling {
+            Some(prev_sibling) => tree[prev_sibling].next_sibling.is_some(),
+            None => true,
+        };
+        let trailing_anon_block = if has_trailing_inlines {
+            let trailing_anon_block = Self::inline_wrapper(tree);
+            tree.move_children_to(ip.parent, trailing_anon_block, ip.prev_sibling);
+            if ip.prev_sibling.is_none() {
+                tree.insert_unchecked(trailing_anon_block, ip);
+                return ip;
+            }
+            Some(trailing_anon_block)
+        } else {
+            None
+        };
 
         // Move the rest of the inline kids into another anon block.
         let heading_anon_block = Self::inline_wrapper(tree);
@@ -64,13 +75,15 @@ impl BlockInside {
             },
         );
 
-        tree.insert_unchecked(
-            trailing_anon_block,
-            InsertionPoint {
-                parent: ip.parent,
-                prev_sibling: Some(heading_anon_block),
-            },
-        );
+        if let Some(trailing_anon_block) = trailing_anon_block {
+            tree.insert_unchecked(
+                trailing_anon_block,
+                InsertionPoint {
+                    parent: ip.parent,
+                    prev_sibling: Some(heading_anon_block),
+                },
+            );
+        }
 
         InsertionPoint {
             parent: ip.parent,
@@ -129,17 +142,91 @@ impl BlockInside {
         None
     }
 
-    /// Removes from a non-anonymous block.
+    /// Cleans up the inline wrappers of `ip.parent` after a removal at `ip`.
+    ///
+    /// The two children around `ip` get merged if they're both inline
+    /// wrappers, so that there are never two contiguous anonymous blocks, and
+    /// if the only child left is an inline wrapper, then it's not needed
+    /// anymore, since there are no blocks to separate the inlines from.
+    pub fn remove_unneeded_inline_wrappers(tree: &mut LayoutTree, ip: InsertionPoint) {
+        Self::merge_inline_wrappers_around(tree, ip);
+
+        let only_child = match tree[ip.parent].first_child() {
+            Some(child) if tree[child].next_sibling.is_none() => child,
+            _ => return,
+        };
+        if !Self::is_inline_wrapper(tree, only_child) {
+            return;
+        }
+        tree.detach_unchecked(only_child);
+        tree.move_children_to(only_child, ip.parent, None);
+        tree.destroy(only_child);
+    }
+
+    fn merge_inline_wrappers_around(tree: &mut LayoutTree, ip: InsertionPoint) {
+        let prev = match ip.prev_sibling {
+            Some(prev) => prev,
+            None => return,
+        };
+        let next = match tree[prev].next_sibling {
+            Some(next) => next,
+            None => return,
+        };
+        if !Self::is_inline_wrapper(tree, prev) || !Self::is_inline_wrapper(tree, next) {
+            return;
+        }
+
+        while let Some(child) = tree[next].first_child() {
+            tree.detach_unchecked(child);
+            let prev_sibling = tree[prev].last_child();
+            tree.insert_unchecked(
+                child,
+                InsertionPoint {
+                    parent: prev,
+                    prev_sibling,
+                },
+            );
+        }
+        tree.detach_unchecked(next);
+        tree.destroy(next);
+    }
+
+    /// Removes a node from an anonymous block wrapping inline content, and
+    /// removes the wrapper too if it becomes empty.
+    pub fn detach_from_inline_wrapper(
+        tree: &mut LayoutTree,
+        wrapper: LayoutNodeId,
+        node_to_remove: LayoutNodeId,
+    ) -> InsertionPoint {
+        debug_assert!(Self::is_inline_wrapper(tree, wrapper));
+        let ip = tree.detach_unchecked(node_to_remove).unwrap();
+        if tree[wrapper].has_children() {
+            return ip;
+        }
+
+        let wrapper_ip = tree.detach_unchecked(wrapper).unwrap();
+        tree.destroy(wrapper);
+        Self::remove_unneeded_inline_wrappers(tree, wrapper_ip);
+        wrapper_ip
+    }
+
+    /// Removes from a non-anonymous block, or from the anonymous content box
+    /// of a fieldset, which behaves like one.
     pub fn detach(
         tree: &mut LayoutTree,
         parent: LayoutNodeId,
         node_to_remove: LayoutNodeId,
     ) -> InsertionPoint {
-        assert!(!tree[parent].is_anonymous());
+        assert!(
+            !tree[parent].is_anonymous() ||
+                tree[parent].style.pseudo == Some(PseudoElement::FieldsetContent)
+        );
 
-        // TODO(emilio): Merge / remove anon boxes as needed if the node removed
-        // is a block that is around two blocks-wrapping-inlines.
-        tree.detach_unchecked(node_to_remove).unwrap()
+        let ip = tree.detach_unchecked(node_to_remove).unwrap();
+        if !tree[node_to_remove].style.display.is_inline_outside() {
+            Self::remove_unneeded_inline_wrappers(tree, ip);
+        }
+        ip
     }
 
     /// Processes an insertion inside a block-inside container, and returns the
@@ -0,0 +1,86 @@
+//! Insertion and removal for a fieldset.
+//!
+//! A fieldset box contains at most two children: Its rendered legend, which
+//! goes first, and an anonymous fieldset content box with everything else.
+//!
+//! https://html.spec.whatwg.org/#the-fieldset-and-legend-elements
+
+use super::super::*;
+use super::block::BlockInside;
+use super::*;
+use crate::style::*;
+use std::rc::Rc;
+
+pub struct FieldsetInside;
+
+impl FieldsetInside {
+    /// Inserts the anonymous content box of a fieldset that has just been
+    /// allocated.
+    pub fn insert_content_box(tree: &mut LayoutTree, fieldset: LayoutNodeId) {
+        debug_assert!(tree[fieldset].is_fieldset());
+        debug_assert!(!tree[fieldset].has_children());
+        let content_box = tree.alloc(LayoutNode::new_container(
+            Rc::new(ComputedStyle::for_fieldset_content()),
+            ContainerKind::block(),
+        ));
+        tree.insert_unchecked(
+            content_box,
+            InsertionPoint {
+                parent: fieldset,
+                prev_sibling: None,
+            },
+        );
+    }
+
+    pub fn content_box(tree: &LayoutTree, fieldset: LayoutNodeId) -> LayoutNodeId {
+        let content_box = tree[fieldset].last_child().expect("Fieldset without content box");
+        debug_assert_eq!(tree[content_box].style.pseudo, Some(PseudoElement::FieldsetContent));
+        content_box
+    }
+
+    pub fn rendered_legend(tree: &LayoutTree, fieldset: LayoutNodeId) -> Option<LayoutNodeId> {
+        let first_child = tree[fieldset].first_child()?;
+        if first_child == Self::content_box(tree, fieldset) {
+            return None;
+        }
+        Some(first_child)
+    }
+
+    /// Processes an insertion inside a fieldset: The first legend candidate
+    /// becomes the rendered legend, and everything else goes into the content
+    /// box.
+    ///
+    /// Note that this relies on legends being inserted in tree order. The
+    /// builder reconstructs the whole fieldset when a legend is inserted or
+    /// removed dynamically, since that can change which one is rendered.
+    pub fn insertion(tree: &mut LayoutTree, node: &LayoutNode, ip: InsertionPoint) -> InsertionPoint {
+        let fieldset = ip.parent;
+        if node.is_rendered_legend_candidate() && Self::rendered_legend(tree, fieldset).is_none() {
+            return InsertionPoint {
+                parent: fieldset,
+                prev_sibling: None,
+            };
+        }
+
+        let content_box = Self::content_box(tree, fieldset);
+        // Siblings that aren't in the content box (that is, the legend) are
+        // before all of its children.
+        let prev_sibling = ip.prev_sibling.filter(|prev| Some(*prev) != Self::rendered_legend(tree, fieldset));
+        BlockInside::insertion(
+            tree,
+            node,
+            InsertionPoint {
+                parent: content_box,
+                prev_sibling,
+            },
+        )
+        .unwrap()
+    }
+
+    /// Removes the rendered legend from a fieldset. The content box is only
+    /// removed along with the fieldset.
+    pub fn detach(tree: &mut LayoutTree, node_to_remove: LayoutNodeId) -> InsertionPoint {
+        debug_assert!(tree[node_to_remove].is_rendered_legend(tree));
+        tree.detach_unchecked(node_to_remove).unwrap()
+    }
+}
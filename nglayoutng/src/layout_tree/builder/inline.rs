@@ -5,6 +5,7 @@
 use super::super::*;
 use super::*;
 use crate::style::*;
+use std::rc::Rc;
 
 pub struct InlineInside;
 
@@ -13,12 +14,14 @@ impl InlineInside {
         // TODO(emilio): Should we somehow tag it as anonymous? Can we
         // otherwise know when to tear it down?
         let style = tree[of].style.clone();
-        LayoutNode::new_container(style, ContainerKind::inline())
+        let mut continuation = LayoutNode::new_container(style, ContainerKind::inline());
+        continuation.dom_node = tree[of].dom_node.clone();
+        continuation
     }
 
     fn block_wrapper() -> LayoutNode {
         LayoutNode::new_container(
-            ComputedStyle::for_ib_split_block_wrapper(),
+            Rc::new(ComputedStyle::for_ib_split_block_wrapper()),
             ContainerKind::block(),
         )
     }
@@ -38,11 +41,102 @@ impl InlineInside {
             return ip;
         }
 
-        // TODO: Remove wrapper and merge following inline into previous inline.
-        //
-        // If there are no more inlines, then remove ib-split wrapper entirely.
+        // The wrapper is empty, so the inline doesn't need to be split around
+        // it anymore: Remove the wrapper, and merge the continuation that
+        // follows it back into the previous inline.
+        let prev_inline = tree[block_wrapper]
+            .prev_ib_sibling()
+            .expect("Block wrappers are always preceded by an inline");
+        let continuation = tree[block_wrapper]
+            .next_ib_sibling()
+            .expect("There should always be a trailing inline in a block-inside wrapper");
+
+        // Note that detaching the wrapper takes care of the inline wrappers
+        // around the inline and its continuation, which may now be adjacent,
+        // or not needed at all.
+        tree.detach(block_wrapper);
+        tree.destroy(block_wrapper);
+
+        let split_point = tree[prev_inline].last_child();
+        while let Some(child) = tree[continuation].first_child() {
+            tree.detach_unchecked(child);
+            let prev_sibling = tree[prev_inline].last_child();
+            tree.insert_unchecked(
+                child,
+                InsertionPoint {
+                    parent: prev_inline,
+                    prev_sibling,
+                },
+            );
+        }
+
+        let next_block_wrapper = tree[continuation].next_ib_sibling();
+        tree[prev_inline].set_next_ib_sibling(next_block_wrapper);
+        if let Some(next_block_wrapper) = next_block_wrapper {
+            tree[next_block_wrapper].set_prev_ib_sibling(Some(prev_inline));
+        }
+        tree.detach(continuation);
+        tree.destroy(continuation);
 
-        unimplemented!()
+        InsertionPoint {
+            parent: prev_inline,
+            prev_sibling: split_point,
+        }
+    }
+
+    /// Removes a node from an inline continuation, that is, from any of the
+    /// inlines that follow a block wrapper in an ib-split.
+    ///
+    /// If the continuation becomes empty and it's not the last one, then it
+    /// no longer separates the block wrappers around it, so they get merged
+    /// and the continuation is removed. The last continuation always stays,
+    /// since block wrappers need a trailing inline.
+    pub fn remove_from_inline_continuation(
+        tree: &mut LayoutTree,
+        continuation: LayoutNodeId,
+        node_to_remove: LayoutNodeId,
+    ) -> InsertionPoint {
+        let ip = tree.detach_unchecked(node_to_remove).unwrap();
+        if tree[continuation].has_children() {
+            return ip;
+        }
+
+        let next_block_wrapper = match tree[continuation].next_ib_sibling() {
+            Some(wrapper) => wrapper,
+            None => return ip,
+        };
+        let prev_block_wrapper = tree[continuation]
+            .prev_ib_sibling()
+            .expect("Continuations are always preceded by a block wrapper");
+        let next_continuation = tree[next_block_wrapper]
+            .next_ib_sibling()
+            .expect("There should always be a trailing inline in a block-inside wrapper");
+
+        while let Some(child) = tree[next_block_wrapper].first_child() {
+            tree.detach_unchecked(child);
+            let prev_sibling = tree[prev_block_wrapper].last_child();
+            tree.insert_unchecked(
+                child,
+                InsertionPoint {
+                    parent: prev_block_wrapper,
+                    prev_sibling,
+                },
+            );
+        }
+
+        tree.register_ib_split(prev_block_wrapper, next_continuation);
+        for node in [continuation, next_block_wrapper].iter().cloned() {
+            tree.detach(node);
+            tree.destroy(node);
+        }
+
+        // The position the node was at is now between the contents of the
+        // merged block wrapper, so the closest thing is the start of the next
+        // continuation.
+        InsertionPoint {
+            parent: next_continuation,
+            prev_sibling: None,
+        }
     }
 
     /// Removes from a non-anonymous inline.
@@ -53,6 +147,10 @@ impl InlineInside {
     ) -> InsertionPoint {
         assert!(!tree[parent].is_anonymous());
 
+        if tree[parent].prev_ib_sibling().is_some() {
+            return Self::remove_from_inline_continuation(tree, parent, node_to_remove);
+        }
+
         // NOTE(emilio): I think there's nothing special to do here if we're
         // split. In that case, we have a block sibling and arbitrary
         // continuations, but the original inline box needs to remain (even if
@@ -73,31 +171,25 @@ impl InlineInside {
             None => return ip,
         };
 
-        loop {
-            let maybe_parent = tree[prev_sibling].parent.unwrap();
-            if maybe_parent == parent {
-                break;
-            }
-
+        let maybe_parent = tree[prev_sibling].parent.unwrap();
+        if maybe_parent != parent {
             if tree[maybe_parent].is_inline() {
                 parent = maybe_parent;
-                break;
+            } else {
+                let pseudo = tree[maybe_parent].style.pseudo.expect("Expected an anonymous box");
+                assert_eq!(pseudo, PseudoElement::BlockInsideInlineWrapper);
+
+                // Insert in the following inline.
+                let next_ib_sibling =
+                    tree[maybe_parent].next_ib_sibling().expect("There should always be a trailing inline in a block-inside wrapper");
+
+                return InsertionPoint {
+                    parent: next_ib_sibling,
+                    prev_sibling: None,
+                };
             }
-
-            let pseudo = tree[maybe_parent].style.pseudo.expect("Expected an anonymous box");
-            assert_eq!(pseudo, PseudoElement::BlockInsideInlineWrapper);
-
-            // Insert in the following inline.
-            let next_ib_sibling =
-                tree[maybe_parent].next_ib_sibling().expect("There should always be a trailing inline in a block-inside wrapper");
-
-            return InsertionPoint {
-                parent: next_ib_sibling,
-                prev_sibling: None,
-            };
         }
 
-
         InsertionPoint {
             parent,
             prev_sibling: Some(prev_sibling),
@@ -1,27 +1,41 @@
-use super::{ContainerKind, LayoutNode, LayoutNodeId, LayoutNodeKind, LayoutTree, LeafKind};
+use super::{ContainerKind, LayoutNode, LayoutNodeId, LayoutNodeKind, LayoutTree, LeafKind, Validation};
 use crate::css;
-use crate::dom;
-use crate::style::ComputedStyle;
-use app_units::Au;
-use euclid::default::Size2D;
+use crate::dom::{self, DomNodeId, DomNodeIds};
+use crate::fonts::font_face::FontFaceSet;
+use crate::fonts::loader::FontSource;
+use crate::style::{ComputedStyle, Overflow, PseudoElement, StyleDifference};
+use crate::Error;
 
 use kuchiki::{self, NodeData, NodeRef};
 
 use std::collections::HashMap;
-use std::io::{self, Read};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 pub mod block;
+pub mod fieldset;
 pub mod inline;
+mod replaced;
 // pub mod table;
 
+/// Whether `text` consists only of document white space.
+///
+/// https://infra.spec.whatwg.org/#ascii-whitespace
+fn is_whitespace_only(text: &str) -> bool {
+    text.bytes().all(|b| matches!(b, b' ' | b'\t' | b'\n' | b'\r' | b'\x0c'))
+}
+
 trait NodeMapHelpers<V> {
-    fn for_node(&self, node: &kuchiki::Node) -> Option<&V>;
+    fn for_node(&self, ids: &DomNodeIds, node: &kuchiki::Node) -> Option<&V>;
 }
 
-impl<V> NodeMapHelpers<V> for HashMap<*const kuchiki::Node, V> {
+impl<V> NodeMapHelpers<V> for HashMap<DomNodeId, V> {
     #[inline(always)]
-    fn for_node(&self, node: &kuchiki::Node) -> Option<&V> {
-        self.get(&(node as *const kuchiki::Node))
+    fn for_node(&self, ids: &DomNodeIds, node: &kuchiki::Node) -> Option<&V> {
+        self.get(&ids.get(node)?)
     }
 }
 
@@ -30,27 +44,53 @@ impl<V> NodeMapHelpers<V> for HashMap<*const kuchiki::Node, V> {
 /// This is needed to handle additions and removals to the DOM tree, and in
 /// a real browser should be kept somewhere holding off the node.
 ///
-/// Note that for now layout nodes don't have a back-reference to the DOM...
-/// We may need to add one, eventually, maybe?
-pub type PrincipalBoxes = HashMap<*const kuchiki::Node, LayoutNodeId>;
+/// Layout nodes have a back-reference to the DOM node that generated them
+/// (see `LayoutNode::dom_node`), but that can't be used to go the other way
+/// around.
+pub type PrincipalBoxes = HashMap<DomNodeId, LayoutNodeId>;
+
+/// How long each phase of building a layout tree took.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct BuildTimings {
+    pub dom_parsing: Duration,
+    /// Includes loading the style sheets linked from the document.
+    pub css_parsing: Duration,
+    pub style_computation: Duration,
+    pub box_construction: Duration,
+}
 
 /// The LayoutTreeBuilder gets a DOM and style tree and outputs a LayoutTree.
 ///
-/// For now it doesn't care at all about any dynamic change or anything, and
-/// assumes the HTML doesn't contain stuff that we don't support, like IB splits
-/// or what not.
+/// The tree can either be built in one go with `build()`, or constructed with
+/// `construct()` and then updated incrementally as the DOM is mutated via
+/// `append_child()`, `insert_before()` and `remove_child()`.
 pub struct LayoutTreeBuilder {
     dom: NodeRef,
+    dom_node_ids: DomNodeIds,
+    style_rules: css::RuleSet,
     styles: css::StyleMap,
+    /// The directory against which relative urls in the document are
+    /// resolved.
+    base_directory: Option<PathBuf>,
     layout_tree: LayoutTree,
     principal_boxes: PrincipalBoxes,
+    /// How many documents this one is nested in, through iframes.
+    document_depth: usize,
+    /// The errors found while parsing the style sheets of the document.
+    stylesheet_errors: Vec<Error>,
+    timings: BuildTimings,
 }
 
+/// Iframes nested deeper than this don't load their documents, which prevents
+/// documents that embed themselves from recursing forever.
+const MAX_NESTED_DOCUMENT_DEPTH: usize = 10;
+
 #[derive(Debug)]
 pub struct LayoutTreeBuilderResult {
     pub principal_boxes: PrincipalBoxes,
     pub layout_tree: LayoutTree,
     pub dom: NodeRef,
+    pub dom_node_ids: DomNodeIds,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -60,45 +100,511 @@ pub struct InsertionPoint {
 }
 
 impl LayoutTreeBuilder {
-    pub fn new(input: &mut impl Read) -> io::Result<Self> {
+    pub fn new(input: &mut impl Read) -> Result<Self, Error> {
+        Self::new_with_base_directory(input, None)
+    }
+
+    /// Creates a builder for a document, resolving relative urls like the ones
+    /// in `@font-face` rules or in the `src` of images against
+    /// `base_directory`.
+    pub fn new_with_base_directory(
+        input: &mut impl Read,
+        base_directory: Option<&Path>,
+    ) -> Result<Self, Error> {
+        Self::new_with_font_source(input, base_directory, FontSource::from_env())
+    }
+
+    /// Like `new_with_base_directory`, but loading the fonts that don't come
+    /// from `@font-face` rules from `font_source`.
+    pub fn new_with_font_source(
+        input: &mut impl Read,
+        base_directory: Option<&Path>,
+        font_source: FontSource,
+    ) -> Result<Self, Error> {
         use std::fs;
-        use std::path::Path;
 
+        let mut timings = BuildTimings::default();
+        let start = Instant::now();
         let dom = dom::build_dom(input)?;
-        let css = dom::read_stylesheets(&dom);
-
-        let ua_sheet = fs::read_to_string(
-            Path::new(env!("CARGO_MANIFEST_DIR"))
-                .join("src")
-                .join("css")
-                .join("res")
-                .join("ua.css"),
-        )?;
-
-        let mut style_rules = css::parse_css(&ua_sheet);
-        style_rules.extend(css::parse_css(&css));
-        let styles = css::compute_styles(&dom, &style_rules);
+        timings.dom_parsing = start.elapsed();
+
+        let start = Instant::now();
+        let sheets = dom::read_stylesheets(&dom, base_directory);
+
+        let ua_sheet_path = css::ua_stylesheet_path();
+        let ua_sheet = css::StylesheetSource {
+            css: fs::read_to_string(&ua_sheet_path)?,
+            url: Some(ua_sheet_path.display().to_string()),
+        };
+
+        let ua_sheet = css::parse_stylesheets(&[ua_sheet], css::Origin::UserAgent);
+        let author_sheet = css::parse_stylesheets(&sheets, css::Origin::Author);
+        let mut style_rules = ua_sheet.rules;
+        style_rules.extend(author_sheet.rules);
+        let style_rules = css::RuleSet::new(style_rules);
+        let mut stylesheet_errors = ua_sheet.errors;
+        stylesheet_errors.extend(author_sheet.errors);
+        for error in &stylesheet_errors {
+            warn!("{}", error);
+        }
+        let font_faces = FontFaceSet::new(author_sheet.font_faces, base_directory, font_source);
+        timings.css_parsing = start.elapsed();
+
+        let start = Instant::now();
+        let dom_node_ids = DomNodeIds::new(&dom);
+        let styles = css::compute_styles(&dom, &style_rules, &dom_node_ids)?;
+        timings.style_computation = start.elapsed();
         Ok(Self {
             dom,
+            dom_node_ids,
+            style_rules,
             styles,
-            layout_tree: LayoutTree::new(),
+            base_directory: base_directory.map(|d| d.to_owned()),
+            layout_tree: LayoutTree::new(Rc::new(font_faces)),
             principal_boxes: Default::default(),
+            document_depth: 0,
+            stylesheet_errors,
+            timings,
         })
     }
 
     /// Builds the whole layout tree.
-    ///
-    /// Obviously this will need to become incremental and such.
-    pub fn build(mut self) -> LayoutTreeBuilderResult {
-        assert!(self.dom.as_document().is_some());
+    pub fn build(mut self) -> Result<LayoutTreeBuilderResult, Error> {
+        self.construct()?;
+        Ok(self.finish())
+    }
+
+    /// Constructs the layout tree for the whole document, keeping the builder
+    /// around so that the tree can be updated after DOM mutations.
+    pub fn construct(&mut self) -> Result<(), Error> {
+        if self.dom.as_document().is_none() {
+            return Err(Error::Internal("The DOM root is not a document"));
+        }
+        if self.layout_tree.root_node().has_children() {
+            return Err(Error::InvalidMutation("Constructed twice"));
+        }
+        let start = Instant::now();
         for child in self.dom.children() {
-            self.insert_node(&child);
+            self.insert_node(&child)?;
         }
+        self.update_viewport_style();
+        self.timings.box_construction = start.elapsed();
+        Ok(())
+    }
 
+    pub fn finish(self) -> LayoutTreeBuilderResult {
         LayoutTreeBuilderResult {
             layout_tree: self.layout_tree,
             principal_boxes: self.principal_boxes,
             dom: self.dom,
+            dom_node_ids: self.dom_node_ids,
+        }
+    }
+
+    pub fn dom(&self) -> &NodeRef {
+        &self.dom
+    }
+
+    pub fn layout_tree(&self) -> &LayoutTree {
+        &self.layout_tree
+    }
+
+    /// Sets how much the layout tree checks itself while boxes are inserted.
+    pub fn set_validation(&mut self, validation: Validation) {
+        self.layout_tree.set_validation(validation);
+    }
+
+    pub fn principal_boxes(&self) -> &PrincipalBoxes {
+        &self.principal_boxes
+    }
+
+    pub fn dom_node_ids(&self) -> &DomNodeIds {
+        &self.dom_node_ids
+    }
+
+    /// Returns the computed style of a DOM node, whether or not it generates
+    /// any box.
+    pub fn computed_style(&self, node: &kuchiki::Node) -> Option<&Rc<ComputedStyle>> {
+        self.styles.for_node(&self.dom_node_ids, node)
+    }
+
+    /// Serializes the resolved value of the property `name` of a DOM node,
+    /// like `getComputedStyle(node).getPropertyValue(name)`. Returns `None` if
+    /// the node has no style, and an empty string for unknown properties.
+    pub fn computed_style_property(&self, node: &kuchiki::Node, name: &str) -> Option<String> {
+        Some(self.computed_style(node)?.serialize_property(name))
+    }
+
+    /// How long building the layout tree has taken so far. Box construction
+    /// is only timed for `construct()`, not for later DOM mutations.
+    pub fn timings(&self) -> &BuildTimings {
+        &self.timings
+    }
+
+    /// The errors found while parsing the style sheets of the document. The
+    /// rules that caused them have been dropped.
+    pub fn stylesheet_errors(&self) -> &[Error] {
+        &self.stylesheet_errors
+    }
+
+    /// Appends `child` to the children of `parent`, removing it from its
+    /// previous position first if needed, and updates the layout tree
+    /// accordingly.
+    ///
+    /// https://dom.spec.whatwg.org/#dom-node-appendchild
+    pub fn append_child(&mut self, parent: &NodeRef, child: NodeRef) -> Result<(), Error> {
+        self.remove_child_if_needed(&child)?;
+        parent.append(child.clone());
+        self.node_inserted(&child)
+    }
+
+    /// Inserts `child` into `parent`, right before `reference`, which must be
+    /// a child of `parent`.
+    ///
+    /// https://dom.spec.whatwg.org/#dom-node-insertbefore
+    pub fn insert_before(&mut self, parent: &NodeRef, child: NodeRef, reference: &NodeRef) -> Result<(), Error> {
+        if !reference.parent().is_some_and(|p| p == *parent) {
+            return Err(Error::InvalidMutation("Reference node is not a child of the parent"));
+        }
+        self.remove_child_if_needed(&child)?;
+        reference.insert_before(child.clone());
+        self.node_inserted(&child)
+    }
+
+    /// Removes `child` from its parent, tearing down the boxes for it and its
+    /// descendants.
+    ///
+    /// https://dom.spec.whatwg.org/#dom-node-removechild
+    pub fn remove_child(&mut self, child: &NodeRef) -> Result<(), Error> {
+        if child.parent().is_none() {
+            return Err(Error::InvalidMutation("Removing a node without a parent"));
+        }
+        self.remove_boxes(child);
+        for node in child.inclusive_descendants() {
+            if let Some(id) = self.dom_node_ids.get(&node) {
+                self.principal_boxes.remove(&id);
+                self.styles.remove(&id);
+            }
+        }
+        self.dom_node_ids.forget(child);
+        let next_sibling = child.next_sibling();
+        let dependent_parent = Self::parent_depending_on(child);
+        child.detach();
+        if let Some(parent) = dependent_parent {
+            self.reconstruct_boxes(&parent)?;
+        }
+        self.reconstruct_whitespace_from(next_sibling)?;
+        self.update_viewport_style();
+        Ok(())
+    }
+
+    /// Replaces the data of a DOM text node, updating its box accordingly.
+    ///
+    /// https://dom.spec.whatwg.org/#concept-cd-replace
+    pub fn set_text_data(&mut self, node: &NodeRef, data: &str) -> Result<(), Error> {
+        let old_data = node
+            .as_text()
+            .ok_or(Error::InvalidMutation("Setting the text data of a non-text node"))?
+            .replace(data.to_owned());
+
+        // Whether the text is whitespace-only affects which anonymous boxes
+        // are needed around it, so just rebuild the box in that case.
+        if is_whitespace_only(&old_data) != is_whitespace_only(data) {
+            return self.reconstruct_boxes(node);
+        }
+
+        let id = match self.principal_boxes.for_node(&self.dom_node_ids, node) {
+            Some(id) => *id,
+            None => return Ok(()),
+        };
+
+        match self.layout_tree[id].kind {
+            LayoutNodeKind::Leaf {
+                kind: LeafKind::Text { ref mut text },
+            } => *text = data.into(),
+            _ => return Err(Error::Internal("Text node with a non-text box")),
+        }
+        self.layout_tree.mark_needs_layout(id);
+        Ok(())
+    }
+
+    /// Sets an attribute of an element, and restyles it and its descendants.
+    pub fn set_attribute(&mut self, element: &NodeRef, name: &str, value: &str) -> Result<(), Error> {
+        element
+            .as_element()
+            .ok_or(Error::InvalidMutation("Setting an attribute on a non-element"))?
+            .attributes
+            .borrow_mut()
+            .insert(name, value.to_owned());
+        self.attribute_changed(element)
+    }
+
+    /// Removes an attribute of an element, and restyles it and its
+    /// descendants.
+    pub fn remove_attribute(&mut self, element: &NodeRef, name: &str) -> Result<(), Error> {
+        let removed = element
+            .as_element()
+            .ok_or(Error::InvalidMutation("Removing an attribute from a non-element"))?
+            .attributes
+            .borrow_mut()
+            .remove(name);
+        if removed.is_some() {
+            self.attribute_changed(element)?;
+        }
+        Ok(())
+    }
+
+    /// Restyles an element after one of its attributes has changed, and
+    /// updates the layout tree with the new styles.
+    ///
+    /// TODO(emilio): Attribute changes can also affect the style of siblings
+    /// and their descendants, via sibling combinators.
+    fn attribute_changed(&mut self, element: &NodeRef) -> Result<(), Error> {
+        let old_styles: css::StyleMap = element
+            .inclusive_descendants()
+            .filter_map(|node| {
+                let id = self.dom_node_ids.get(&node)?;
+                Some((id, self.styles.get(&id)?.clone()))
+            })
+            .collect();
+        self.restyle_subtree(element)?;
+        // Toggling the `open` attribute of a details element changes which of
+        // its children render.
+        if Self::is_element(element, "details") {
+            self.reconstruct_boxes(element)?;
+        } else {
+            self.process_style_changes(element, &old_styles)?;
+        }
+        self.update_viewport_style();
+        Ok(())
+    }
+
+    fn restyle_subtree(&mut self, root: &NodeRef) -> Result<(), Error> {
+        css::compute_styles_for_subtree(root, &self.style_rules, &self.dom_node_ids, &mut self.styles)
+    }
+
+    fn process_style_changes(&mut self, node: &NodeRef, old_styles: &css::StyleMap) -> Result<(), Error> {
+        let new_style = match self.styles.for_node(&self.dom_node_ids, node) {
+            Some(style) => style.clone(),
+            // Elements in unstyled subtrees, or non-element nodes.
+            None => return Ok(()),
+        };
+        let difference = match old_styles.for_node(&self.dom_node_ids, node) {
+            Some(old_style) => old_style.difference(&new_style),
+            None => StyleDifference::ReconstructBoxes,
+        };
+
+        match difference {
+            StyleDifference::Equal => {},
+            StyleDifference::UpdateStyle => {
+                if let Some(id) = self.principal_boxes.for_node(&self.dom_node_ids, node) {
+                    let id = *id;
+                    let box_style = self.box_style(node, &new_style);
+                    self.update_box_styles(id, &box_style);
+                }
+            },
+            StyleDifference::ReconstructBoxes => return self.reconstruct_boxes(node),
+        }
+
+        // Even if our style didn't change, the style of our descendants may
+        // have, if they match selectors that depend on our attributes.
+        for child in node.children() {
+            if child.as_text().is_none() {
+                self.process_style_changes(&child, old_styles)?;
+                continue;
+            }
+            if difference == StyleDifference::Equal {
+                continue;
+            }
+            if let Some(id) = self.principal_boxes.for_node(&self.dom_node_ids, &child) {
+                let id = *id;
+                let text_style = Rc::new(new_style.inherited().finish(false));
+                self.layout_tree.set_style(id, text_style);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the root element of the document, if any.
+    fn root_element(&self) -> Option<NodeRef> {
+        self.dom.children().find(|child| child.as_element().is_some())
+    }
+
+    /// Returns the element whose `overflow` propagates to the viewport: The
+    /// root element, unless it's an `<html>` element with `overflow: visible`,
+    /// in which case it's its `<body>` child.
+    ///
+    /// https://drafts.csswg.org/css-overflow/#overflow-propagation
+    fn viewport_overflow_source(&self, root: &NodeRef) -> Option<NodeRef> {
+        let root_style = self.styles.for_node(&self.dom_node_ids, root)?;
+        if root_style.overflow_x != Overflow::Visible || !Self::is_element(root, "html") {
+            return Some(root.clone());
+        }
+        root.children().find(|child| Self::is_element(child, "body"))
+    }
+
+    /// Returns the style of the principal box of an element with a given
+    /// computed style, which has `overflow: visible` if its overflow has been
+    /// propagated to the viewport.
+    fn box_style(&self, node: &NodeRef, style: &Rc<ComputedStyle>) -> Rc<ComputedStyle> {
+        if style.overflow_x == Overflow::Visible {
+            return style.clone();
+        }
+        let is_overflow_source = self
+            .root_element()
+            .and_then(|root| self.viewport_overflow_source(&root))
+            .is_some_and(|source| source == *node);
+        if !is_overflow_source {
+            return style.clone();
+        }
+        Rc::new(style.with_overflow_propagated_to_viewport())
+    }
+
+    /// Propagates the writing mode, direction and overflow of the root element
+    /// (or the `<body>`, for overflow) to the viewport, and updates the boxes
+    /// of those elements, since where overflow propagates from may have
+    /// changed.
+    fn update_viewport_style(&mut self) {
+        let root = self.root_element();
+        let root_style = root
+            .as_ref()
+            .and_then(|root| self.styles.for_node(&self.dom_node_ids, root));
+        let viewport_style = match root_style {
+            Some(root_style) => {
+                let overflow_source = self
+                    .viewport_overflow_source(root.as_ref().unwrap())
+                    .and_then(|source| self.styles.for_node(&self.dom_node_ids, &source));
+                ComputedStyle::for_viewport_propagating_from(root_style, overflow_source.map(|s| &**s))
+            },
+            None => ComputedStyle::for_viewport(),
+        };
+        let viewport = self.layout_tree.root();
+        if *self.layout_tree[viewport].style != viewport_style {
+            self.layout_tree.set_style(viewport, Rc::new(viewport_style));
+        }
+
+        let root = match root {
+            Some(root) => root,
+            None => return,
+        };
+        let body = root.children().find(|child| Self::is_element(child, "body"));
+        for element in std::iter::once(root).chain(body) {
+            let id = match self.principal_boxes.for_node(&self.dom_node_ids, &element) {
+                Some(id) => *id,
+                None => continue,
+            };
+            let box_style = match self.styles.for_node(&self.dom_node_ids, &element) {
+                Some(style) => self.box_style(&element, style),
+                None => continue,
+            };
+            if *self.layout_tree[id].style != *box_style {
+                self.update_box_styles(id, &box_style);
+            }
+        }
+    }
+
+    /// Updates the style of a box, and of all its inline continuations.
+    fn update_box_styles(&mut self, id: LayoutNodeId, style: &Rc<ComputedStyle>) {
+        let mut current = Some(id);
+        while let Some(id) = current {
+            // Skip the anonymous block wrappers of an ib-split.
+            if self.layout_tree[id].is_anonymous() {
+                self.layout_tree.mark_needs_layout(id);
+            } else {
+                self.layout_tree.set_style(id, style.clone());
+            }
+            current = self.layout_tree[id].next_ib_sibling();
+        }
+    }
+
+    /// Throws away the boxes of a node and its descendants, and constructs
+    /// them again.
+    fn reconstruct_boxes(&mut self, node: &NodeRef) -> Result<(), Error> {
+        // Which legend or summary is rendered depends on all the others.
+        if let Some(parent) = Self::parent_depending_on(node) {
+            return self.reconstruct_boxes(&parent);
+        }
+        self.remove_boxes(node);
+        for node in node.inclusive_descendants() {
+            if let Some(id) = self.dom_node_ids.get(&node) {
+                self.principal_boxes.remove(&id);
+            }
+        }
+        self.insert_node(node)
+    }
+
+    fn remove_child_if_needed(&mut self, child: &NodeRef) -> Result<(), Error> {
+        if child.parent().is_some() {
+            self.remove_child(child)?;
+        }
+        Ok(())
+    }
+
+    /// Styles a node that has just been inserted in the DOM, and inserts it
+    /// and its descendants in the layout tree.
+    ///
+    /// TODO(emilio): Siblings of the node may need to be restyled too, if
+    /// they match structural selectors like `:first-child`.
+    fn node_inserted(&mut self, node: &NodeRef) -> Result<(), Error> {
+        self.dom_node_ids.assign(node);
+        self.restyle_subtree(node)?;
+        if let Some(parent) = Self::parent_depending_on(node) {
+            self.reconstruct_boxes(&parent)?;
+        } else {
+            self.insert_node(node)?;
+        }
+        self.reconstruct_whitespace_from(node.next_sibling())?;
+        self.update_viewport_style();
+        Ok(())
+    }
+
+    /// Whether whitespace-only text generates a box depends on its previous
+    /// sibling, so the whitespace that follows a node that has been inserted
+    /// or removed needs to be reconsidered.
+    fn reconstruct_whitespace_from(&mut self, mut next: Option<NodeRef>) -> Result<(), Error> {
+        while let Some(node) = next {
+            match node.data() {
+                NodeData::Text(ref text) => {
+                    if !is_whitespace_only(&text.borrow()) {
+                        break;
+                    }
+                    self.reconstruct_boxes(&node)?;
+                },
+                NodeData::Element(..) => break,
+                _ => {},
+            }
+            next = node.next_sibling();
+        }
+        Ok(())
+    }
+
+    /// Removes all the boxes generated by `node` and its descendants from the
+    /// layout tree.
+    fn remove_boxes(&mut self, node: &NodeRef) {
+        let id = match self.principal_boxes.for_node(&self.dom_node_ids, node) {
+            Some(id) => *id,
+            None => {
+                // display: contents nodes may have boxes for their children.
+                for child in node.children() {
+                    self.remove_boxes(&child);
+                }
+                return;
+            },
+        };
+
+        // Inlines that have been split by a block have continuations (and
+        // block wrappers) that are not descendants of the principal box.
+        //
+        // TODO(emilio): Splits of ancestor inlines that were caused by a block
+        // descendant of `node` should be undone too.
+        let mut boxes = vec![id];
+        while let Some(next) = self.layout_tree[*boxes.last().unwrap()].next_ib_sibling() {
+            boxes.push(next);
+        }
+
+        for id in boxes {
+            self.layout_tree.detach(id);
+            self.layout_tree.destroy(id);
         }
     }
 
@@ -118,10 +624,11 @@ impl LayoutTreeBuilder {
             if dom_parent.as_document().is_some() {
                 return Some(self.layout_tree.root());
             }
-            let layout_parent = match self.principal_boxes.for_node(&dom_parent) {
+            let ids = &self.dom_node_ids;
+            let layout_parent = match self.principal_boxes.for_node(ids, &dom_parent) {
                 Some(node) => *node,
                 None => {
-                    if !self.styles.for_node(&dom_parent)?.display.is_contents() {
+                    if !self.styles.for_node(ids, &dom_parent)?.display.is_contents() {
                         return None;
                     }
                     dom_parent = dom_parent.parent()?;
@@ -139,10 +646,10 @@ impl LayoutTreeBuilder {
     /// recursing down into display contents nodes but not recursing up.
     fn find_insertion_prev_sibling(&self, mut node: NodeRef) -> Option<LayoutNodeId> {
         loop {
-            if let Some(node) = self.principal_boxes.for_node(&node) {
+            if let Some(node) = self.principal_boxes.for_node(&self.dom_node_ids, &node) {
                 return Some(*node);
             }
-            if let Some(style) = self.styles.for_node(&node) {
+            if let Some(style) = self.styles.for_node(&self.dom_node_ids, &node) {
                 if style.display.is_contents() {
                     if let Some(last) = node.last_child() {
                         // Drill down if possible.
@@ -167,7 +674,7 @@ impl LayoutTreeBuilder {
         // parents.
         let mut parent = node.parent()?;
         loop {
-            if !self.styles.for_node(&parent)?.display.is_contents() {
+            if !self.styles.for_node(&self.dom_node_ids, &parent)?.display.is_contents() {
                 return None;
             }
             if let Some(prev_sibling) = parent.previous_sibling() {
@@ -183,76 +690,97 @@ impl LayoutTreeBuilder {
     /// accounting for any kind of anonymous boxes or anything of the sort).
     fn dom_insertion_point(&self, node: &NodeRef) -> Option<InsertionPoint> {
         let parent = self.dom_insertion_parent(node)?;
-        let prev_sibling = self.dom_insertion_prev_sibling(node);
+        // The first children of a summary go after its disclosure triangle.
+        let prev_sibling = self
+            .dom_insertion_prev_sibling(node)
+            .or_else(|| self.marker_box(parent));
         Some(InsertionPoint {
             parent,
             prev_sibling,
         })
     }
 
-    fn insert_node_children(&mut self, parent: &NodeRef) {
+    fn insert_node_children(&mut self, parent: &NodeRef) -> Result<(), Error> {
         // TODO(emilio): Pseudo-elements, though not a big deal for the
         // prototype at least, I guess.
         for node in parent.children() {
-            self.insert_node(&node);
+            self.insert_node(&node)?;
         }
+        Ok(())
     }
 
     /// Tries to insert a node in the layout tree.
-    fn insert_node(&mut self, node: &NodeRef) {
+    fn insert_node(&mut self, node: &NodeRef) -> Result<(), Error> {
+        if Self::is_hidden_by_details(node) {
+            return Ok(());
+        }
+
         let insertion_point = match self.dom_insertion_point(node) {
             Some(ip) => ip,
-            None => return,
+            None => return Ok(()),
         };
 
         let is_text = node.as_text().is_some();
-        let style = if is_text {
-            let parent = node.parent().unwrap();
-            self.styles.for_node(&parent)
+        let styled_node = if is_text {
+            node.parent()
+                .ok_or(Error::Internal("Inserting a text node without a parent"))?
         } else {
-            self.styles.for_node(&node)
-        }
-        .expect("Node should be styled if we found an insertion point for it");
+            node.clone()
+        };
+        let style = self
+            .styles
+            .for_node(&self.dom_node_ids, &styled_node)
+            .ok_or(Error::Internal("Node should be styled if we found an insertion point for it"))?;
 
-        let text_style;
-        let style = if is_text {
-            text_style = style.inherited().finish(false);
-            &text_style
+        let box_style = if is_text {
+            Rc::new(style.inherited().finish(false))
         } else {
-            style
+            self.box_style(node, style)
         };
+        let style = &box_style;
 
-        let new_box = match self.construct_box_for(node, style, &insertion_point) {
+        let mut new_box = match self.construct_box_for(node, style, &insertion_point) {
             Some(node) => node,
             None => {
                 if style.display.is_contents() {
-                    self.insert_node_children(node);
+                    self.insert_node_children(node)?;
                 }
-                return;
+                return Ok(());
             },
         };
 
+        new_box.dom_node = Some(Rc::downgrade(&node.0));
         let id = match self.layout_tree.insert(new_box, insertion_point) {
             Some(id) => id,
-            None => return,
+            None => return Ok(()),
         };
 
-        self.principal_boxes.insert(&**node, id);
-        self.insert_node_children(node);
+        let dom_id = self
+            .dom_node_ids
+            .get(node)
+            .ok_or(Error::Internal("Inserting a node without an id"))?;
+        self.principal_boxes.insert(dom_id, id);
+        if self.layout_tree[id].is_container() {
+            self.insert_marker(node, id);
+        }
+        self.insert_node_children(node)
     }
 
     /// This constructs the box for an object, but doesn't insert it yet.
     fn construct_box_for(
         &self,
         node: &NodeRef,
-        style: &ComputedStyle,
-        _insertion_point: &InsertionPoint,
+        style: &Rc<ComputedStyle>,
+        insertion_point: &InsertionPoint,
     ) -> Option<LayoutNode> {
         if style.display.is_none() || style.display.is_contents() {
             return None;
         }
 
         if let Some(text) = node.as_text() {
+            if self.is_collapsible_whitespace_between_blocks(&text.borrow(), style, insertion_point) {
+                return None;
+            }
             return Some(LayoutNode::new_leaf(
                 style.clone(),
                 LeafKind::Text {
@@ -261,16 +789,25 @@ impl LayoutTreeBuilder {
             ));
         }
 
-        // TODO(emilio): This needs to handle a lot more cases: Form controls,
-        // <fieldset>, <br> & such, <svg>, etc...
-        if let Some(intrinsic_size) = Self::replaced_size(node) {
+        if Self::is_line_break(node) {
+            return Some(LayoutNode::new_leaf(style.clone(), LeafKind::LineBreak));
+        }
+
+        // TODO(emilio): This needs to handle a lot more cases: <video>, <object>...
+        if let Some(dimensions) = self.replaced_dimensions(node, style) {
             return Some(LayoutNode::new_leaf(
                 style.clone(),
-                LeafKind::Replaced { intrinsic_size },
+                LeafKind::Replaced {
+                    intrinsic_size: dimensions.size,
+                    intrinsic_ratio: dimensions.ratio,
+                    nested_document: self.nested_document(node),
+                },
             ));
         }
 
-        let container_kind = if style.display.is_inline_inside() {
+        let container_kind = if Self::is_element(node, "fieldset") {
+            ContainerKind::Fieldset
+        } else if style.display.is_inline_inside() {
             ContainerKind::inline()
         } else {
             ContainerKind::block()
@@ -278,27 +815,165 @@ impl LayoutTreeBuilder {
         Some(LayoutNode::new_container(style.clone(), container_kind))
     }
 
-    fn replaced_size(node: &NodeRef) -> Option<Size2D<Au>> {
-        use html5ever::LocalName;
-        let element = node.as_element()?;
-        if element.name.local != LocalName::from("img") {
+    /// Whether a text node is collapsible whitespace that wouldn't render, and
+    /// would only cause anonymous blocks to be generated around it: That is,
+    /// whitespace that is not preceded by inline content in a block container.
+    ///
+    /// This needs to be reconsidered when the previous sibling changes.
+    ///
+    /// https://drafts.csswg.org/css2/visuren.html#anonymous
+    fn is_collapsible_whitespace_between_blocks(
+        &self,
+        text: &str,
+        style: &ComputedStyle,
+        insertion_point: &InsertionPoint,
+    ) -> bool {
+        if !is_whitespace_only(text) || !style.white_space.collapses_newlines() {
+            return false;
+        }
+        let parent = &self.layout_tree[insertion_point.parent];
+        if !parent.is_block_container() && !parent.is_fieldset() {
+            return false;
+        }
+        match insertion_point.prev_sibling {
+            Some(prev) => !self.layout_tree[prev].style.display.is_inline_outside(),
+            None => true,
+        }
+    }
+
+    fn is_element(node: &NodeRef, local_name: &str) -> bool {
+        node.as_element()
+            .is_some_and(|element| &*element.name.local == local_name)
+    }
+
+    fn is_line_break(node: &NodeRef) -> bool {
+        Self::is_element(node, "br")
+    }
+
+    /// Returns the parent of `node` if the boxes of the parent depend on
+    /// `node` and its siblings: Fieldsets render their first legend
+    /// specially, and details elements their first summary.
+    fn parent_depending_on(node: &NodeRef) -> Option<NodeRef> {
+        let parent_name = if Self::is_element(node, "legend") {
+            "fieldset"
+        } else if Self::is_element(node, "summary") {
+            "details"
+        } else {
+            return None;
+        };
+        node.parent().filter(|parent| Self::is_element(parent, parent_name))
+    }
+
+    /// Returns the `<details>` that `node` is the summary of, if any.
+    ///
+    /// https://html.spec.whatwg.org/#summary-for-its-parent-details
+    fn details_for_summary(node: &NodeRef) -> Option<NodeRef> {
+        let details = node.parent().filter(|parent| Self::is_element(parent, "details"))?;
+        let first_summary = details.children().find(|child| Self::is_element(child, "summary"))?;
+        if first_summary != *node {
             return None;
         }
+        Some(details)
+    }
+
+    /// Whether `node` is a child of a closed `<details>` other than its
+    /// summary, and thus doesn't render.
+    ///
+    /// https://html.spec.whatwg.org/#the-details-and-summary-elements
+    fn is_hidden_by_details(node: &NodeRef) -> bool {
+        let details = match node.parent() {
+            Some(parent) if Self::is_element(&parent, "details") => parent,
+            _ => return false,
+        };
+        let open = details.as_element().unwrap().attributes.borrow().contains("open");
+        !open && Self::details_for_summary(node).is_none()
+    }
+
+    /// Returns the disclosure triangle box of a summary, given the box of the
+    /// summary.
+    fn marker_box(&self, summary_box: LayoutNodeId) -> Option<LayoutNodeId> {
+        let mut current = self.layout_tree[summary_box].first_child()?;
+        loop {
+            let node = &self.layout_tree[current];
+            if node.style.pseudo == Some(PseudoElement::Marker) {
+                return Some(current);
+            }
+            // The marker may be wrapped in an anonymous block.
+            if !node.is_anonymous() {
+                return None;
+            }
+            current = node.first_child()?;
+        }
+    }
+
+    /// Inserts the disclosure triangle of the summary whose box is `id`,
+    /// which points down if its details element is open.
+    ///
+    /// TODO(emilio): This should be a real `::marker`, styleable by authors.
+    fn insert_marker(&mut self, summary: &NodeRef, id: LayoutNodeId) {
+        let details = match Self::details_for_summary(summary) {
+            Some(details) => details,
+            None => return,
+        };
+        let open = details.as_element().unwrap().attributes.borrow().contains("open");
+        let style = Rc::new(self.layout_tree[id].style.for_marker());
+        let text = if open { "\u{25be} " } else { "\u{25b8} " };
+        let marker = LayoutNode::new_leaf(style, LeafKind::Text { text: text.into() });
+        self.layout_tree.insert(
+            marker,
+            InsertionPoint {
+                parent: id,
+                prev_sibling: None,
+            },
+        );
+    }
+
+    /// Loads and constructs the layout tree of the document that an
+    /// `<iframe>` points to.
+    ///
+    /// TODO(emilio): Changes to the `src` attribute should reload the
+    /// document.
+    fn nested_document(&self, node: &NodeRef) -> Option<Box<LayoutTreeBuilderResult>> {
+        if !Self::is_element(node, "iframe") || self.document_depth >= MAX_NESTED_DOCUMENT_DEPTH {
+            return None;
+        }
+        let src = node.as_element()?.attributes.borrow().get("src")?.to_owned();
+        let base_directory = self.base_directory.as_deref();
+        let path = crate::misc::url::local_file_path(&src, base_directory)?;
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("Failed to load nested document {}: {:?}", path.display(), e);
+                return None;
+            },
+        };
+
+        let font_source = self.layout_tree.font_faces().font_source();
+        let mut builder = match Self::new_with_font_source(&mut file, path.parent(), font_source) {
+            Ok(builder) => builder,
+            Err(e) => {
+                warn!("Failed to parse nested document {}: {}", path.display(), e);
+                return None;
+            },
+        };
+        builder.document_depth = self.document_depth + 1;
+        match builder.build() {
+            Ok(result) => Some(Box::new(result)),
+            Err(e) => {
+                warn!("Failed to construct nested document {}: {}", path.display(), e);
+                None
+            },
+        }
+    }
 
-        // NOTE(emilio): Pretty much intentionally oversimplified.
-        let attrs = element.attributes.borrow();
-        let width = attrs
-            .get("width")
-            .and_then(|w| w.parse::<i32>().ok())
-            .map(|w| Au::from_f32_px(w as f32))
-            .filter(|w| *w >= Au(0))
-            .unwrap_or(Au::from_f32_px(150.0));
-        let height = attrs
-            .get("height")
-            .and_then(|h| h.parse::<i32>().ok())
-            .map(|h| Au::from_f32_px(h as f32))
-            .filter(|h| *h >= Au(0))
-            .unwrap_or(Au::from_f32_px(150.0));
-        Some(Size2D::new(width, height))
+    fn replaced_dimensions(&self, node: &NodeRef, style: &ComputedStyle) -> Option<replaced::IntrinsicDimensions> {
+        let base_directory = self.base_directory.as_deref();
+        replaced::intrinsic_dimensions(
+            node,
+            node.as_element()?,
+            style,
+            self.layout_tree.font_faces(),
+            base_directory,
+        )
     }
 }
@@ -0,0 +1,287 @@
+//! Intrinsic sizes of the elements that generate replaced boxes.
+//!
+//! Form controls are not really replaced elements as per the spec, but since
+//! we don't render their contents (nor have any of the native widgets they'd
+//! need), it's the most reasonable approximation to get pages with them to
+//! lay out somewhat sensibly.
+//!
+//! https://html.spec.whatwg.org/#replaced-elements
+
+use crate::fonts::font_face::FontFaceSet;
+use crate::style::{ComputedStyle, Ratio};
+use app_units::Au;
+use euclid::default::Size2D;
+use kuchiki::{Attributes, ElementData, NodeRef};
+use std::path::Path;
+
+/// The intrinsic dimensions of a replaced box.
+///
+/// https://drafts.csswg.org/css-images-3/#natural-dimensions
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct IntrinsicDimensions {
+    pub size: Size2D<Au>,
+    /// The natural aspect ratio, if any. Form controls don't have one, so
+    /// their intrinsic size is just a default.
+    pub ratio: Option<Ratio>,
+}
+
+impl IntrinsicDimensions {
+    fn without_ratio(size: Size2D<Au>) -> Self {
+        Self { size, ratio: None }
+    }
+}
+
+/// Returns the intrinsic dimensions of `element` if it generates a replaced
+/// box.
+///
+/// Relative urls, like the `src` of images, are resolved against
+/// `base_directory`, and the sizes of form controls depend on the fonts in
+/// `font_faces`.
+pub fn intrinsic_dimensions(
+    node: &NodeRef,
+    element: &ElementData,
+    style: &ComputedStyle,
+    font_faces: &FontFaceSet,
+    base_directory: Option<&Path>,
+) -> Option<IntrinsicDimensions> {
+    let attrs = element.attributes.borrow();
+    let local = &*element.name.local;
+    Some(if local == "img" {
+        image_dimensions(&attrs, base_directory)
+    } else if local == "svg" {
+        svg_dimensions(&attrs)
+    } else if local == "canvas" {
+        canvas_dimensions(&attrs)
+    } else if local == "iframe" {
+        // https://html.spec.whatwg.org/#the-iframe-element-2
+        IntrinsicDimensions::without_ratio(default_object_size())
+    } else if local == "input" {
+        IntrinsicDimensions::without_ratio(input_size(&attrs, style, font_faces))
+    } else if local == "textarea" {
+        IntrinsicDimensions::without_ratio(textarea_size(&attrs, style, font_faces))
+    } else if local == "select" {
+        IntrinsicDimensions::without_ratio(select_size(node, &attrs, style, font_faces))
+    } else {
+        return None;
+    })
+}
+
+/// Parses a non-negative integer attribute.
+///
+/// https://html.spec.whatwg.org/#rules-for-parsing-non-negative-integers
+fn non_negative_integer_attribute(attrs: &Attributes, name: &str) -> Option<u32> {
+    attrs.get(name)?.trim().parse::<u32>().ok()
+}
+
+/// Like `non_negative_integer_attribute`, but for attributes that must be
+/// greater than zero, like `cols`, `rows`, or `size`.
+fn positive_integer_attribute(attrs: &Attributes, name: &str) -> Option<u32> {
+    non_negative_integer_attribute(attrs, name).filter(|value| *value > 0)
+}
+
+/// The average advance of a character, which we approximate with the fallback
+/// value of the `ch` unit, since we don't want to go through the font just to
+/// construct the box.
+///
+/// https://drafts.csswg.org/css-values/#ch
+fn average_char_width(style: &ComputedStyle) -> Au {
+    style.font_size.0 / 2
+}
+
+/// Returns the natural size of the image an `<img>` points to, if we manage to
+/// decode it.
+///
+/// Only local files are supported, and only the header of the image is read.
+#[cfg(feature = "image")]
+fn natural_image_size(attrs: &Attributes, base_directory: Option<&Path>) -> Option<Size2D<Au>> {
+    let src = attrs.get("src")?;
+    let path = crate::misc::url::local_file_path(src, base_directory)?;
+    match image::image_dimensions(&path) {
+        Ok((width, height)) => Some(Size2D::new(Au::from_px(width as i32), Au::from_px(height as i32))),
+        Err(e) => {
+            warn!("Failed to decode image {}: {:?}", path.display(), e);
+            None
+        },
+    }
+}
+
+#[cfg(not(feature = "image"))]
+fn natural_image_size(_: &Attributes, _: Option<&Path>) -> Option<Size2D<Au>> {
+    None
+}
+
+/// Returns the intrinsic dimensions of an `<img>`, which are the natural
+/// dimensions of the image.
+///
+/// NOTE(emilio): Images that fail to load are 150x150 and have no natural
+/// aspect ratio. The `width` and `height` attributes are presentational hints,
+/// applied during layout like the rest of the sizing properties.
+fn image_dimensions(attrs: &Attributes, base_directory: Option<&Path>) -> IntrinsicDimensions {
+    match natural_image_size(attrs, base_directory) {
+        Some(size) => IntrinsicDimensions {
+            size,
+            ratio: Ratio::from_size(size),
+        },
+        None => IntrinsicDimensions::without_ratio(Size2D::new(Au::from_px(150), Au::from_px(150))),
+    }
+}
+
+/// The size replaced elements get when they don't have any better one.
+///
+/// https://drafts.csswg.org/css-images-3/#default-object-size
+fn default_object_size() -> Size2D<Au> {
+    Size2D::new(Au::from_px(300), Au::from_px(150))
+}
+
+/// Parses the `width` or `height` attribute of an `<svg>`, which is a CSS
+/// length.
+///
+/// TODO(emilio): Support units other than pixels, and percentages.
+fn svg_length_attribute(attrs: &Attributes, name: &str) -> Option<Au> {
+    let value = attrs.get(name)?.trim();
+    let value = value.strip_suffix("px").unwrap_or(value);
+    let value = value.trim_end().parse::<f32>().ok()?;
+    if value < 0. || !value.is_finite() {
+        return None;
+    }
+    Some(Au::from_f32_px(value))
+}
+
+/// Returns the ratio of the `viewBox` attribute of an `<svg>`.
+///
+/// https://svgwg.org/svg2-draft/coords.html#ViewBoxAttribute
+fn view_box_ratio(attrs: &Attributes) -> Option<Ratio> {
+    let values = attrs
+        .get("viewBox")?
+        .split(|c: char| c == ',' || c.is_ascii_whitespace())
+        .filter(|value| !value.is_empty())
+        .map(|value| value.parse::<f32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    if values.len() != 4 || values[2] < 0. || values[3] < 0. {
+        return None;
+    }
+    let ratio = Ratio {
+        width: values[2],
+        height: values[3],
+    };
+    if ratio.is_degenerate() {
+        return None;
+    }
+    Some(ratio)
+}
+
+/// Returns the intrinsic dimensions of an `<svg>`: Its size comes from the
+/// `width` and `height` attributes, and its ratio from the `viewBox`, or from
+/// the attributes if there's no `viewBox`.
+///
+/// https://svgwg.org/specs/integration/#svg-css-sizing
+///
+/// TODO(emilio): We don't render the contents of the image.
+fn svg_dimensions(attrs: &Attributes) -> IntrinsicDimensions {
+    let width = svg_length_attribute(attrs, "width");
+    let height = svg_length_attribute(attrs, "height");
+    let ratio = view_box_ratio(attrs).or_else(|| Ratio::from_size(Size2D::new(width?, height?)));
+    let height_for = |width: Au, ratio: Ratio| width.scale_by(ratio.height / ratio.width);
+    let width_for = |height: Au, ratio: Ratio| height.scale_by(ratio.width / ratio.height);
+    let default_size = default_object_size();
+    let size = match (width, height, ratio) {
+        (Some(width), Some(height), _) => Size2D::new(width, height),
+        (Some(width), None, Some(ratio)) => Size2D::new(width, height_for(width, ratio)),
+        (None, Some(height), Some(ratio)) => Size2D::new(width_for(height, ratio), height),
+        (None, None, Some(ratio)) => Size2D::new(default_size.width, height_for(default_size.width, ratio)),
+        (width, height, None) => Size2D::new(
+            width.unwrap_or(default_size.width),
+            height.unwrap_or(default_size.height),
+        ),
+    };
+    IntrinsicDimensions { size, ratio }
+}
+
+/// Returns the intrinsic dimensions of a `<canvas>`, which are those of its
+/// bitmap.
+///
+/// https://html.spec.whatwg.org/#attr-canvas-width
+fn canvas_dimensions(attrs: &Attributes) -> IntrinsicDimensions {
+    let default_size = default_object_size();
+    let dimension = |name, default| {
+        non_negative_integer_attribute(attrs, name).map_or(default, |value| Au::from_px(value as i32))
+    };
+    let size = Size2D::new(
+        dimension("width", default_size.width),
+        dimension("height", default_size.height),
+    );
+    IntrinsicDimensions {
+        size,
+        ratio: Ratio::from_size(size),
+    }
+}
+
+/// The size of a single-line text field, or a button, `chars` characters wide.
+fn text_field_size(chars: u32, style: &ComputedStyle, font_faces: &FontFaceSet) -> Size2D<Au> {
+    Size2D::new(average_char_width(style) * chars as i32, style.used_line_height(font_faces))
+}
+
+/// https://html.spec.whatwg.org/#the-input-element
+///
+/// TODO(emilio): Render the value, and the native appearance of the widgets.
+fn input_size(attrs: &Attributes, style: &ComputedStyle, font_faces: &FontFaceSet) -> Size2D<Au> {
+    let ty = attrs.get("type").unwrap_or("text").to_ascii_lowercase();
+    match &*ty {
+        // These sizes are the ones that other engines use, roughly.
+        "checkbox" | "radio" => Size2D::new(Au::from_px(13), Au::from_px(13)),
+        "range" => Size2D::new(Au::from_px(129), Au::from_px(16)),
+        "color" => Size2D::new(Au::from_px(44), Au::from_px(23)),
+        "button" | "submit" | "reset" => {
+            let default_label = match &*ty {
+                "submit" => "Submit",
+                "reset" => "Reset",
+                _ => "",
+            };
+            let label = attrs.get("value").unwrap_or(default_label);
+            text_field_size(label.chars().count() as u32, style, font_faces)
+        },
+        // https://html.spec.whatwg.org/#attr-input-size
+        _ => text_field_size(positive_integer_attribute(attrs, "size").unwrap_or(20), style, font_faces),
+    }
+}
+
+/// https://html.spec.whatwg.org/#the-textarea-element
+fn textarea_size(attrs: &Attributes, style: &ComputedStyle, font_faces: &FontFaceSet) -> Size2D<Au> {
+    let cols = positive_integer_attribute(attrs, "cols").unwrap_or(20);
+    let rows = positive_integer_attribute(attrs, "rows").unwrap_or(2);
+    Size2D::new(
+        average_char_width(style) * cols as i32,
+        style.used_line_height(font_faces) * rows as i32,
+    )
+}
+
+/// https://html.spec.whatwg.org/#the-select-element
+fn select_size(
+    node: &NodeRef,
+    attrs: &Attributes,
+    style: &ComputedStyle,
+    font_faces: &FontFaceSet,
+) -> Size2D<Au> {
+    let longest_option = node
+        .descendants()
+        .filter(|descendant| {
+            descendant
+                .as_element()
+                .is_some_and(|e| &*e.name.local == "option")
+        })
+        .map(|option| option.text_contents().trim().chars().count())
+        .max()
+        .unwrap_or(0);
+
+    // Leave room for the drop-down arrow, or the scrollbar of the list box.
+    let width = average_char_width(style) * longest_option as i32 + style.font_size.0;
+
+    // https://html.spec.whatwg.org/#concept-select-size
+    let multiple = attrs.contains("multiple");
+    let rows = match positive_integer_attribute(attrs, "size") {
+        Some(size) => size,
+        None if multiple => 4,
+        None => 1,
+    };
+    Size2D::new(width, style.used_line_height(font_faces) * rows as i32)
+}
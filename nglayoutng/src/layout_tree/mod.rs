@@ -2,25 +2,44 @@ pub mod builder;
 
 use self::builder::InsertionPoint;
 use crate::allocator;
-use crate::fragment_tree::ChildFragment;
-use crate::layout_tree::builder::{inline::InlineInside, block::BlockInside};
-use crate::layout_algorithms::{AvailableSize, ConstraintSpace, LayoutAlgorithm, LayoutResult, LayoutContext};
-use crate::layout_algorithms::block::BlockFormattingContext;
-use crate::layout_algorithms::inline::InlineFormattingContext;
+use crate::fonts::font_face::FontFaceSet;
+use crate::fragment_tree::FragmentTree;
+use crate::layout_tree::builder::{inline::InlineInside, block::BlockInside, fieldset::FieldsetInside};
+use crate::layout_algorithms::inline::{InlineFormattingContext, InlineItem};
+use crate::layout_algorithms::{AvailableSize, BreakToken, ConstraintSpace, ConstraintSpaceBuilder, ContainingBlockInfo, LayoutAlgorithmKind, LayoutResult, LayoutContext};
 use crate::logical_geometry::{LogicalSize, WritingMode};
+use crate::misc::dot::DotGraph;
 use crate::misc::print_tree::PrintTree;
+use crate::sizing::MinMaxSizes;
 use crate::style::{self, ComputedStyle, Display, DisplayInside, PseudoElement};
+use crate::trace::{self, TraceEvent};
 use app_units::Au;
 use euclid::default::Size2D;
 use html5ever::tree_builder::QuirksMode;
+use kuchiki::NodeRef;
+use std::cell::{Cell, RefCell};
+use std::rc::{Rc, Weak};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub struct LayoutNodeId(usize);
+/// The id of a node in a `LayoutTree`. Using the id of a node after it's been
+/// destroyed panics, even if its slot has been reused for another node.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct LayoutNodeId(allocator::Id);
+
+fn dot_node_name(id: LayoutNodeId) -> String {
+    format!("n{}_{}", id.0.index(), id.0.generation())
+}
 
-#[derive(Debug)]
 pub enum LeafKind {
     Text { text: Box<str> },
-    Replaced { intrinsic_size: Size2D<Au> },
+    Replaced {
+        intrinsic_size: Size2D<Au>,
+        intrinsic_ratio: Option<style::Ratio>,
+        /// The document an `<iframe>` displays, which is laid out with the
+        /// content box of this box as its viewport.
+        nested_document: Option<Box<builder::LayoutTreeBuilderResult>>,
+    },
+    /// A forced line break, generated by `<br>`.
+    LineBreak,
 }
 
 #[derive(Clone, PartialEq, Eq)]
@@ -33,6 +52,30 @@ pub enum ContainerKind {
         prev_ib_sibling: Option<LayoutNodeId>,
         next_ib_sibling: Option<LayoutNodeId>,
     },
+    /// A fieldset, whose children are its rendered legend, if any, and an
+    /// anonymous fieldset content box.
+    ///
+    /// https://html.spec.whatwg.org/#the-fieldset-and-legend-elements
+    Fieldset,
+}
+
+impl std::fmt::Debug for LeafKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            LeafKind::Text { ref text } => f.debug_struct("Text").field("text", text).finish(),
+            LeafKind::Replaced { ref intrinsic_size, ref intrinsic_ratio, ref nested_document } => {
+                let mut s = f.debug_struct("Replaced");
+                s.field("intrinsic_size", intrinsic_size);
+                s.field("intrinsic_ratio", intrinsic_ratio);
+                // Nested documents are too big to print inline.
+                if nested_document.is_some() {
+                    s.field("nested_document", &format_args!(".."));
+                }
+                s.finish()
+            },
+            LeafKind::LineBreak => f.write_str("LineBreak"),
+        }
+    }
 }
 
 impl std::fmt::Debug for ContainerKind {
@@ -58,6 +101,7 @@ impl std::fmt::Debug for ContainerKind {
                         .finish()
                 }
             }
+            ContainerKind::Fieldset => f.write_str("Fieldset"),
         }
     }
 }
@@ -84,6 +128,10 @@ impl ContainerKind {
     pub fn is_inline(&self) -> bool {
         matches!(*self, Self::Inline { .. })
     }
+
+    pub fn is_fieldset(&self) -> bool {
+        matches!(*self, Self::Fieldset)
+    }
 }
 
 #[derive(Debug)]
@@ -94,12 +142,76 @@ pub enum LayoutNodeKind {
     Container {
         first_child: Option<LayoutNodeId>,
         last_child: Option<LayoutNodeId>,
-        // TODO(emilio): Put OOFs parented to me in here? Or collect them during
-        // layout?
+        /// Cached information about the children, so that layout doesn't need
+        /// to walk them.
+        children_info: ChildrenInfo,
+        /// The out-of-flow descendants (floats and absolutely positioned
+        /// boxes) whose containing block is this box, in no particular order.
+        oof_descendants: Vec<LayoutNodeId>,
         kind: ContainerKind,
     },
 }
 
+/// Information about the children of a container, kept up to date as children
+/// are inserted and detached.
+///
+/// This keeps counts rather than flags, so that detaching a child doesn't
+/// need to look at its siblings to know whether a flag still holds. It relies
+/// on changes to `display`, `position` and `float` reconstructing the boxes
+/// of the element, so that whether a child is in-flow or inline-level doesn't
+/// change while it's in the tree.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ChildrenInfo {
+    /// The number of children.
+    pub count: usize,
+    /// The number of in-flow children.
+    pub in_flow_count: usize,
+    /// The number of in-flow children that are inline-level.
+    pub in_flow_inline_count: usize,
+    /// The last in-flow child.
+    pub last_in_flow_child: Option<LayoutNodeId>,
+}
+
+impl ChildrenInfo {
+    /// Whether any in-flow child is inline-level.
+    pub fn has_in_flow_inline_children(&self) -> bool {
+        self.in_flow_inline_count != 0
+    }
+}
+
+bitflags! {
+    /// Flags that track which parts of the tree need to be laid out again,
+    /// and state derived from the style of a node and its position in the
+    /// tree that layout checks often.
+    pub struct LayoutNodeFlags: u16 {
+        /// The node needs to be laid out again.
+        const NEEDS_LAYOUT = 1 << 0;
+        /// Some descendant of the node needs to be laid out again.
+        const DESCENDANT_NEEDS_LAYOUT = 1 << 1;
+        /// The intrinsic sizes of the node need to be computed again.
+        const NEEDS_INTRINSIC_SIZE = 1 << 2;
+        /// The node is an anonymous box.
+        const IS_ANONYMOUS = 1 << 3;
+        /// The node establishes a new block formatting context. Only
+        /// meaningful while the node is in the tree.
+        const ESTABLISHES_BFC = 1 << 4;
+        /// The node is part of an ib split: An inline split by a block-level
+        /// descendant, one of its continuations, or one of the anonymous
+        /// blocks wrapping the block-level descendants.
+        const IS_IB_SPLIT = 1 << 5;
+        /// The node is the containing block of some out-of-flow descendant.
+        const HAS_OOF_DESCENDANTS = 1 << 6;
+    }
+}
+
+/// The result of the last layout of a node, along with the constraints it
+/// was laid out with.
+#[derive(Debug)]
+struct CachedLayout {
+    constraints: ConstraintSpace,
+    result: LayoutResult,
+}
+
 #[derive(Copy, Clone, PartialEq)]
 pub enum PrintId {
     No,
@@ -115,22 +227,125 @@ pub enum PrintId {
 /// This is the CSS2 concept of "box", minus dimensions.
 #[derive(Debug)]
 pub struct LayoutNode {
-    pub style: ComputedStyle,
+    /// The style of this box, shared with the fragments it generates.
+    pub style: Rc<ComputedStyle>,
     pub parent: Option<LayoutNodeId>,
     pub next_sibling: Option<LayoutNodeId>,
     pub prev_sibling: Option<LayoutNodeId>,
     pub kind: LayoutNodeKind,
+    /// The DOM node that generated this box, if any. Anonymous boxes don't
+    /// have one, and inline continuations share the one of their inline.
+    pub dom_node: Option<Weak<kuchiki::Node>>,
+    flags: Cell<LayoutNodeFlags>,
+    /// For out-of-flow boxes, the containing block whose `oof_descendants`
+    /// this box is in.
+    registered_containing_block: Option<LayoutNodeId>,
+    cached_layout: RefCell<Option<CachedLayout>>,
+    cached_min_max_sizes: Cell<Option<MinMaxSizes>>,
 }
 
 impl LayoutNode {
-    fn new(style: ComputedStyle, kind: LayoutNodeKind) -> Self {
+    fn new(style: Rc<ComputedStyle>, kind: LayoutNodeKind) -> Self {
+        let mut flags = LayoutNodeFlags::NEEDS_LAYOUT | LayoutNodeFlags::NEEDS_INTRINSIC_SIZE;
+        flags.set(LayoutNodeFlags::IS_ANONYMOUS, style.pseudo.is_some_and(|p| p.is_anonymous()));
         Self {
             style,
             parent: None,
             prev_sibling: None,
             next_sibling: None,
             kind,
+            dom_node: None,
+            flags: Cell::new(flags),
+            registered_containing_block: None,
+            cached_layout: RefCell::new(None),
+            cached_min_max_sizes: Cell::new(None),
+        }
+    }
+
+    /// Returns the DOM node that generated this box, if any and if it's still
+    /// alive.
+    pub fn dom_node(&self) -> Option<NodeRef> {
+        self.dom_node.as_ref()?.upgrade().map(NodeRef)
+    }
+
+    pub fn flags(&self) -> LayoutNodeFlags {
+        self.flags.get()
+    }
+
+    fn insert_flags(&self, flags: LayoutNodeFlags) {
+        self.flags.set(self.flags.get() | flags);
+    }
+
+    fn set_flags(&self, flags: LayoutNodeFlags, value: bool) {
+        let mut new_flags = self.flags.get();
+        new_flags.set(flags, value);
+        self.flags.set(new_flags);
+    }
+
+    /// Recomputes the flags that depend on the style of the node, and on its
+    /// parent.
+    fn update_style_flags(&self, tree: &LayoutTree) {
+        self.set_flags(LayoutNodeFlags::IS_ANONYMOUS, self.style.pseudo.is_some_and(|p| p.is_anonymous()));
+        self.set_flags(LayoutNodeFlags::ESTABLISHES_BFC, self.compute_establishes_bfc(tree));
+    }
+
+    fn update_ib_split_flag(&self) {
+        self.set_flags(
+            LayoutNodeFlags::IS_IB_SPLIT,
+            self.prev_ib_sibling().is_some() || self.next_ib_sibling().is_some(),
+        );
+    }
+
+    fn needs_layout(&self) -> bool {
+        self.flags()
+            .intersects(LayoutNodeFlags::NEEDS_LAYOUT | LayoutNodeFlags::DESCENDANT_NEEDS_LAYOUT)
+    }
+
+    /// Marks this node and all its descendants that are laid out as part of
+    /// it as clean, after laying it out.
+    ///
+    /// Descendants that establish a formatting context are laid out
+    /// independently, and clear their own flags.
+    fn clear_layout_flags(&self, tree: &LayoutTree) {
+        self.flags
+            .set(self.flags() - LayoutNodeFlags::NEEDS_LAYOUT - LayoutNodeFlags::DESCENDANT_NEEDS_LAYOUT);
+        for child in self.children(tree) {
+            if !child.establishes_formatting_context(tree) {
+                child.clear_layout_flags(tree);
+            }
+        }
+    }
+
+    /// Returns whether laying out this node resolves percentages against the
+    /// percentage resolution size of its constraint space.
+    ///
+    /// This is the case if either the node itself or any of the descendants
+    /// that are laid out as part of it (in the same formatting context) have
+    /// percentage sizes.
+    ///
+    /// TODO(emilio): This is a conservative approximation: Block descendants
+    /// resolve percentages against the available size instead.
+    fn depends_on_percentage_basis(&self, tree: &LayoutTree) -> bool {
+        if self.style.has_percentage_sizes() {
+            return true;
         }
+        self.children(tree).any(|child| {
+            !child.establishes_formatting_context(tree) && child.depends_on_percentage_basis(tree)
+        })
+    }
+
+    /// Returns the result of the last layout of this node, if the node is
+    /// clean and it was laid out with compatible constraints.
+    fn cached_layout_result(&self, tree: &LayoutTree, constraints: &ConstraintSpace) -> Option<LayoutResult> {
+        if self.needs_layout() {
+            return None;
+        }
+        let cache = self.cached_layout.borrow();
+        let cache = cache.as_ref()?;
+        if !constraints.is_compatible_with(&cache.constraints, || self.depends_on_percentage_basis(tree)) {
+            return None;
+        }
+        Some(cache.result.clone())
     }
 
     fn prev_ib_sibling(&self) -> Option<LayoutNodeId> {
@@ -139,6 +354,7 @@ impl LayoutNode {
                 match *kind {
                     ContainerKind::Block { prev_ib_sibling, .. } |
                     ContainerKind::Inline { prev_ib_sibling, .. } => prev_ib_sibling,
+                    ContainerKind::Fieldset => None,
                 }
             }
             LayoutNodeKind::Leaf { .. } => None,
@@ -151,14 +367,44 @@ impl LayoutNode {
                 match *kind {
                     ContainerKind::Block { next_ib_sibling, .. } |
                     ContainerKind::Inline { next_ib_sibling, .. } => next_ib_sibling,
+                    ContainerKind::Fieldset => None,
                 }
             }
             LayoutNodeKind::Leaf { .. } => None,
         }
     }
 
+    fn set_prev_ib_sibling(&mut self, sibling: Option<LayoutNodeId>) {
+        match self.kind {
+            LayoutNodeKind::Container { ref mut kind, .. } => match *kind {
+                ContainerKind::Block { ref mut prev_ib_sibling, .. } |
+                ContainerKind::Inline { ref mut prev_ib_sibling, .. } => *prev_ib_sibling = sibling,
+                ContainerKind::Fieldset => unreachable!("Fieldsets are never split"),
+            },
+            LayoutNodeKind::Leaf { .. } => unreachable!(),
+        }
+        self.update_ib_split_flag();
+    }
+
+    fn set_next_ib_sibling(&mut self, sibling: Option<LayoutNodeId>) {
+        match self.kind {
+            LayoutNodeKind::Container { ref mut kind, .. } => match *kind {
+                ContainerKind::Block { ref mut next_ib_sibling, .. } |
+                ContainerKind::Inline { ref mut next_ib_sibling, .. } => *next_ib_sibling = sibling,
+                ContainerKind::Fieldset => unreachable!("Fieldsets are never split"),
+            },
+            LayoutNodeKind::Leaf { .. } => unreachable!(),
+        }
+        self.update_ib_split_flag();
+    }
+
     fn is_anonymous(&self) -> bool {
-        self.style.pseudo.map_or(false, |p| p.is_anonymous())
+        self.flags().contains(LayoutNodeFlags::IS_ANONYMOUS)
+    }
+
+    /// Whether this box is part of an ib split.
+    pub fn is_ib_split(&self) -> bool {
+        self.flags().contains(LayoutNodeFlags::IS_IB_SPLIT)
     }
 
     pub fn is_container(&self) -> bool {
@@ -166,15 +412,39 @@ impl LayoutNode {
     }
 
     pub fn is_block_container(&self) -> bool {
-        self.container_kind().map_or(false, |k| k.is_block())
+        self.container_kind().is_some_and(|k| k.is_block())
     }
 
     pub fn is_inline(&self) -> bool {
-        self.container_kind().map_or(false, |k| k.is_inline())
+        self.container_kind().is_some_and(|k| k.is_inline())
+    }
+
+    pub fn is_fieldset(&self) -> bool {
+        self.container_kind().is_some_and(|k| k.is_fieldset())
+    }
+
+    /// Whether this box has been generated by a `<legend>` element, and could
+    /// be the rendered legend of a fieldset.
+    ///
+    /// https://html.spec.whatwg.org/#rendered-legend
+    pub fn is_rendered_legend_candidate(&self) -> bool {
+        if self.style.is_floating() || self.style.is_out_of_flow_positioned() {
+            return false;
+        }
+        self.dom_node().is_some_and(|node| {
+            node.as_element()
+                .is_some_and(|element| &*element.name.local == "legend")
+        })
+    }
+
+    /// Whether this is the rendered legend of a fieldset.
+    pub fn is_rendered_legend(&self, tree: &LayoutTree) -> bool {
+        self.parent.is_some_and(|parent| tree[parent].is_fieldset()) &&
+            self.style.pseudo != Some(PseudoElement::FieldsetContent)
     }
 
     pub fn is_inline_continuation(&self, tree: &LayoutTree) -> bool {
-        self.is_inline() && self.prev_sibling.map_or(false, |sibling| tree[sibling].style.pseudo == Some(PseudoElement::BlockInsideInlineWrapper))
+        self.is_inline() && self.prev_sibling.is_some_and(|sibling| tree[sibling].style.pseudo == Some(PseudoElement::BlockInsideInlineWrapper))
     }
 
     fn container_kind(&self) -> Option<ContainerKind> {
@@ -202,6 +472,18 @@ impl LayoutNode {
         !self.is_out_of_flow()
     }
 
+    /// Returns whether a node in the tree establishes a new block formatting
+    /// context, which is computed when the node is inserted or restyled.
+    pub fn establishes_bfc(&self, tree: &LayoutTree) -> bool {
+        let establishes_bfc = self.flags().contains(LayoutNodeFlags::ESTABLISHES_BFC);
+        debug_assert!(
+            self.parent.is_none() || establishes_bfc == self.compute_establishes_bfc(tree),
+            "Stale flags: {:?}",
+            self
+        );
+        establishes_bfc
+    }
+
     /// https://drafts.csswg.org/css2/visuren.html#block-formatting
     ///
     /// > Floats, absolutely positioned elements, block containers (such as
@@ -209,11 +491,19 @@ impl LayoutNode {
     /// > boxes, and block boxes with 'overflow' other than 'visible' (except
     /// > when that value has been propagated to the viewport) establish new
     /// > block formatting contexts for their contents.
-    pub fn establishes_bfc(&self) -> bool {
+    fn compute_establishes_bfc(&self, tree: &LayoutTree) -> bool {
         use crate::style::Overflow;
 
         // The root always establishes an (initial) BFC.
-        if self.parent.is_none() {
+        let parent = match self.parent {
+            Some(parent) => parent,
+            None => return true,
+        };
+
+        // Orthogonal flows establish an independent formatting context.
+        //
+        // https://drafts.csswg.org/css-writing-modes/#orthogonal-flows
+        if self.writing_mode().is_orthogonal_to(tree[parent].writing_mode()) {
             return true;
         }
 
@@ -221,6 +511,14 @@ impl LayoutNode {
             return true;
         }
 
+        // Fieldsets and their rendered legends establish an independent
+        // formatting context.
+        //
+        // https://html.spec.whatwg.org/#the-fieldset-and-legend-elements
+        if self.is_fieldset() || self.is_rendered_legend(tree) {
+            return true;
+        }
+
         // These always stablish a new bfc.
         let display = self.display();
         if display.inside() == DisplayInside::FlowRoot {
@@ -230,8 +528,8 @@ impl LayoutNode {
         // Style guarantees that for the Visible case, overflow-x is equal to
         // overflow-y.
         //
-        // TODO: Maybe implement the 'has been propagated to the viewport'
-        // thing.
+        // If the overflow of this box has been propagated to the viewport, the
+        // builder has already given it a style with `overflow: visible`.
         //
         // TODO: Overflow::Clip doesn't cause a bfc either afaict.
         if display.is_block_outside() && self.style.overflow_x != Overflow::Visible {
@@ -250,14 +548,17 @@ impl LayoutNode {
         if !self.is_block_container() {
             return false;
         }
-        match self.in_flow_children(tree).next() {
-            Some(c) => c.style.display.is_inline_outside(),
-            None => false,
-        }
+        // Blocks never mix in-flow inline-level and block-level children, so
+        // this is the same as checking the first in-flow child.
+        debug_assert_eq!(
+            self.children_info().has_in_flow_inline_children(),
+            self.in_flow_children(tree).next().is_some_and(|c| c.style.display.is_inline_outside())
+        );
+        self.children_info().has_in_flow_inline_children()
     }
 
     pub fn establishes_formatting_context(&self, tree: &LayoutTree) -> bool {
-        self.establishes_bfc() || self.establishes_ifc(tree)
+        self.establishes_bfc(tree) || self.establishes_ifc(tree)
     }
 
     /// Whether this is a replaced box.
@@ -265,26 +566,179 @@ impl LayoutNode {
         matches!(self.kind, LayoutNodeKind::Leaf { kind: LeafKind::Replaced { .. } })
     }
 
+    /// Returns the aspect ratio this box should be sized with, if any: The
+    /// natural aspect ratio of replaced boxes if `aspect-ratio` contains
+    /// `auto`, or the ratio specified by `aspect-ratio` otherwise.
+    ///
+    /// https://drafts.csswg.org/css-sizing-4/#aspect-ratio
+    pub fn preferred_aspect_ratio(&self) -> Option<style::Ratio> {
+        let aspect_ratio = self.style.aspect_ratio;
+        if aspect_ratio.auto {
+            if let LayoutNodeKind::Leaf {
+                kind: LeafKind::Replaced { intrinsic_ratio: Some(ratio), .. },
+            } = self.kind
+            {
+                return Some(ratio);
+            }
+        }
+        aspect_ratio.ratio.filter(|ratio| !ratio.is_degenerate())
+    }
+
     /// Whether this box has an independent layout, that is, whether it's a
     /// formatting context or an atomic box.
     pub fn has_independent_layout(&self, context: &LayoutContext) -> bool {
-        self.establishes_formatting_context(context.layout_tree) || self.is_replaced()
+        self.layout_algorithm(context.layout_tree).is_some()
+    }
+
+    /// Returns the algorithm that lays out this box, if it has an independent
+    /// layout.
+    pub fn layout_algorithm(&self, tree: &LayoutTree) -> Option<LayoutAlgorithmKind> {
+        LayoutAlgorithmKind::for_node(self, tree)
     }
 
-    pub fn layout(&self, context: &LayoutContext, constraints: &ConstraintSpace) -> LayoutResult {
+    /// Lays out this node, or its next fragment if `resume_from` is the break
+    /// token of the previous one, reusing the result of the last layout if
+    /// possible.
+    pub fn layout(
+        &self,
+        context: &LayoutContext,
+        constraints: &ConstraintSpace,
+        resume_from: Option<&BreakToken>,
+    ) -> LayoutResult {
         debug_assert!(self.has_independent_layout(context));
-        if self.establishes_ifc(context.layout_tree) {
-            return InlineFormattingContext::new(context, self).layout(constraints);
+        // Looking up the id isn't free, so only do it when tracing.
+        let id = || context.layout_tree.id_of(self);
+        // Only the first fragment of a box is cached.
+        if resume_from.is_some() {
+            trace::record(|| TraceEvent::layout(id(), constraints, true));
+            let result = self.layout_without_cache(context, constraints, resume_from);
+            trace::record(|| TraceEvent::layout_result(id(), &result));
+            return result;
+        }
+        if let Some(result) = self.cached_layout_result(context.layout_tree, constraints) {
+            trace::record(|| TraceEvent::CacheHit { node: id() });
+            return result;
         }
 
-        if self.establishes_bfc() {
-            return BlockFormattingContext::new(context, self).layout(constraints);
+        trace::record(|| TraceEvent::layout(id(), constraints, false));
+        let result = self.layout_without_cache(context, constraints, None);
+        trace::record(|| TraceEvent::layout_result(id(), &result));
+        *self.cached_layout.borrow_mut() = Some(CachedLayout {
+            constraints: constraints.clone(),
+            result: result.clone(),
+        });
+        self.clear_layout_flags(context.layout_tree);
+        result
+    }
+
+    /// Returns the min-content and max-content inline sizes of the content
+    /// box of this node, in its own writing mode.
+    ///
+    /// The result is cached until the node is marked as needing its intrinsic
+    /// sizes computed again.
+    pub fn min_max_sizes(&self, context: &LayoutContext) -> MinMaxSizes {
+        let record = |sizes: MinMaxSizes, cached| {
+            trace::record(|| TraceEvent::MinMaxSizes {
+                node: context.layout_tree.id_of(self),
+                min_content: sizes.min_content,
+                max_content: sizes.max_content,
+                cached,
+            })
+        };
+        if !self.flags().contains(LayoutNodeFlags::NEEDS_INTRINSIC_SIZE) {
+            if let Some(sizes) = self.cached_min_max_sizes.get() {
+                record(sizes, true);
+                return sizes;
+            }
+        }
+
+        let sizes = match self.layout_algorithm(context.layout_tree) {
+            Some(algorithm) => algorithm.min_max_sizes(context, self),
+            // Block containers in the formatting context of their parent are
+            // measured like one that establishes it.
+            None if self.is_block_container() => LayoutAlgorithmKind::Block.min_max_sizes(context, self),
+            // Inline boxes and text are measured as part of their inline
+            // formatting context.
+            None => MinMaxSizes::zero(),
+        };
+
+        record(sizes, false);
+        self.cached_min_max_sizes.set(Some(sizes));
+        self.flags.set(self.flags() - LayoutNodeFlags::NEEDS_INTRINSIC_SIZE);
+        sizes
+    }
+
+    /// Returns the contribution of this box to the intrinsic sizes of a
+    /// parent with writing mode `parent_wm`, that is, the intrinsic sizes of
+    /// its margin box.
+    ///
+    /// Percentages resolve against zero for this purpose.
+    ///
+    /// https://drafts.csswg.org/css-sizing-3/#intrinsic-contribution
+    pub fn min_max_contribution(&self, context: &LayoutContext, parent_wm: WritingMode) -> MinMaxSizes {
+        let wm = self.writing_mode();
+        if wm.is_orthogonal_to(parent_wm) {
+            // The contribution of an orthogonal flow is its block size, once
+            // laid out with the available size it'd get with an indefinite
+            // available block size.
+            //
+            // https://drafts.csswg.org/css-writing-modes/#orthogonal-auto
+            let inline = LogicalSize::from_physical(wm, context.initial_containing_block_size).inline;
+            let constraints =
+                ConstraintSpaceBuilder::new(ContainingBlockInfo::initial(parent_wm, context.initial_containing_block_size))
+                    .available_size(AvailableSize::unconstrained_block(wm, inline))
+                    .percentage_resolution_size(AvailableSize::unconstrained_block(wm, inline))
+                    .build();
+            let result = self.layout(context, &constraints, None);
+            return MinMaxSizes::from(result.root_fragment.fragment.size.block);
+        }
+
+        let style = &self.style;
+        let border = style.border_widths();
+        let padding = style.padding().map_all(|lp| lp.resolve(Au(0)));
+        let bp = (border + padding).inline_start_end();
+        let margin = style.margin().map_all(|m| match **m {
+            style::LengthPercentageOrAuto::LengthPercentage(ref lp) => lp.resolve(Au(0)),
+            style::LengthPercentageOrAuto::Auto => Au(0),
+        });
+
+        // Fixed sizes override the content-based sizes, percentages behave
+        // as auto.
+        let fixed_border_box_size = |size: &style::Size| match *size {
+            style::Size::LengthPercentage(ref lp) if !lp.has_percentage() => {
+                let mut size = lp.fixed.0;
+                if style.box_sizing.content_box() {
+                    size += bp;
+                }
+                Some(size)
+            },
+            _ => None,
+        };
+
+        let mut sizes = match fixed_border_box_size(style.size().inline) {
+            Some(size) => MinMaxSizes::from(size),
+            None => self.min_max_sizes(context) + bp,
+        };
+        if let Some(max) = fixed_border_box_size(style.max_size().inline) {
+            sizes.min_content = std::cmp::min(sizes.min_content, max);
+            sizes.max_content = std::cmp::min(sizes.max_content, max);
         }
-        if self.is_replaced() {
-            // TODO(replaced)
+        if let Some(min) = fixed_border_box_size(style.min_size().inline) {
+            sizes.max_assign(MinMaxSizes::from(min));
+        }
+        sizes + margin.inline_start_end()
+    }
+
+    fn layout_without_cache(
+        &self,
+        context: &LayoutContext,
+        constraints: &ConstraintSpace,
+        resume_from: Option<&BreakToken>,
+    ) -> LayoutResult {
+        match self.layout_algorithm(context.layout_tree) {
+            Some(algorithm) => algorithm.layout(context, self, constraints, resume_from),
+            None => unreachable!("Laying out a box without an independent layout: {:?}", self),
         }
-        // TODO(ifc)
-        unimplemented!()
     }
 
     fn ancestors<'tree>(&self, tree: &'tree LayoutTree) -> AncestorIterator<'tree> {
@@ -294,7 +748,7 @@ impl LayoutNode {
         }
     }
 
-    fn is_absolute_containing_block(&self) -> bool {
+    pub fn is_absolute_containing_block(&self) -> bool {
         if self.is_fixed_containing_block() {
             return true;
         }
@@ -302,11 +756,13 @@ impl LayoutNode {
         self.position() != style::Position::Static
     }
 
-    fn is_fixed_containing_block(&self) -> bool {
+    pub fn is_fixed_containing_block(&self) -> bool {
         if self.parent.is_none() {
             return true;
         }
-        // TODO(emilio): transform / will-change: transform /  filters, etc.
+        // TODO(emilio): Boxes with a transform or a filter, or with
+        // `will-change` for them, are containing blocks for fixed descendants
+        // too, once we support those properties.
         false
     }
 
@@ -342,6 +798,10 @@ impl LayoutNode {
         self.containing_block_chain(tree).next()
     }
 
+    /// Walks up the ancestors of this box to find its containing blocks.
+    ///
+    /// During layout, the `ContainingBlockInfo`s of the constraint space
+    /// should be used instead.
     pub fn containing_block_chain<'tree>(
         &'tree self,
         tree: &'tree LayoutTree,
@@ -360,13 +820,16 @@ impl LayoutNode {
 
         if print_id == PrintId::Yes {
             label.push_str(&format!(" - {:?}", id));
+            if let Some(node) = self.dom_node() {
+                label.push_str(&format!(" - {}", crate::dom::node_label(&node)));
+            }
         }
 
         if self.is_out_of_flow() {
             label.push_str(" (oof)");
         }
 
-        if self.establishes_bfc() {
+        if self.establishes_bfc(tree) {
             label.push_str(" (bfc)");
         }
 
@@ -396,16 +859,31 @@ impl LayoutNode {
         printer.end_level();
     }
 
-    pub fn new_leaf(style: ComputedStyle, kind: LeafKind) -> Self {
+    fn print_dot(&self, tree: &LayoutTree, id: LayoutNodeId, graph: &mut DotGraph) {
+        let name = dot_node_name(id);
+        let fill_color = if self.is_anonymous() { Some("lightgrey") } else { None };
+        graph.node(&name, &self.print_label(id, PrintId::Yes, tree), fill_color);
+        if let Some(next) = self.next_ib_sibling() {
+            graph.edge(&name, &dot_node_name(next), "style=dashed, color=blue, label=\"ib\"");
+        }
+        for (child_id, child) in self.children_and_id(tree) {
+            graph.edge(&name, &dot_node_name(child_id), "");
+            child.print_dot(tree, child_id, graph);
+        }
+    }
+
+    pub fn new_leaf(style: Rc<ComputedStyle>, kind: LeafKind) -> Self {
         Self::new(style, LayoutNodeKind::Leaf { kind })
     }
 
-    pub fn new_container(style: ComputedStyle, kind: ContainerKind) -> Self {
+    pub fn new_container(style: Rc<ComputedStyle>, kind: ContainerKind) -> Self {
         Self::new(
             style,
             LayoutNodeKind::Container {
                 first_child: None,
                 last_child: None,
+                children_info: ChildrenInfo::default(),
+                oof_descendants: Vec::new(),
                 kind,
             },
         )
@@ -442,6 +920,39 @@ impl LayoutNode {
         }
     }
 
+    pub fn children_info(&self) -> ChildrenInfo {
+        match self.kind {
+            LayoutNodeKind::Container { children_info, .. } => children_info,
+            LayoutNodeKind::Leaf { .. } => ChildrenInfo::default(),
+        }
+    }
+
+    /// The number of children of this node, in constant time.
+    pub fn child_count(&self) -> usize {
+        self.children_info().count
+    }
+
+    /// The last in-flow child of this node, in constant time.
+    pub fn last_in_flow_child(&self) -> Option<LayoutNodeId> {
+        self.children_info().last_in_flow_child
+    }
+
+    /// The floats and absolutely positioned boxes that have this box as their
+    /// containing block, in no particular order.
+    pub fn oof_descendants(&self) -> &[LayoutNodeId] {
+        match self.kind {
+            LayoutNodeKind::Container { ref oof_descendants, .. } => oof_descendants,
+            LayoutNodeKind::Leaf { .. } => &[],
+        }
+    }
+
+    fn oof_descendants_mut(&mut self) -> &mut Vec<LayoutNodeId> {
+        match self.kind {
+            LayoutNodeKind::Container { ref mut oof_descendants, .. } => oof_descendants,
+            LayoutNodeKind::Leaf { .. } => unreachable!("Leaves can't contain out-of-flow boxes"),
+        }
+    }
+
     pub fn next_sibling<'tree>(&self, tree: &'tree LayoutTree) -> Option<&'tree LayoutNode> {
         Some(&tree[self.next_sibling?])
     }
@@ -545,20 +1056,95 @@ where
     }
 }
 
+/// How much the tree checks its own invariants when nodes are inserted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Validation {
+    /// No checks.
+    None,
+    /// Only the links between the inserted node, its parent and its siblings,
+    /// which is constant time.
+    Local,
+    /// The whole subtree of the parent the node is inserted into, before and
+    /// after the insertion. This makes building a tree quadratic, but catches
+    /// bugs closer to where they happen.
+    Full,
+}
+
+impl Default for Validation {
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            Validation::Local
+        } else {
+            Validation::None
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct LayoutTree {
     nodes: allocator::Allocator<LayoutNode>,
     root: LayoutNodeId,
+    validation: Validation,
+    /// The `@font-face` rules of the document, which text in this tree is
+    /// shaped and painted with.
+    font_faces: Rc<FontFaceSet>,
 }
 
 impl LayoutTree {
-    pub fn new() -> Self {
-        let root = LayoutNode::new_container(ComputedStyle::for_viewport(), ContainerKind::block());
+    pub fn new(font_faces: Rc<FontFaceSet>) -> Self {
+        let root = LayoutNode::new_container(Rc::new(ComputedStyle::for_viewport()), ContainerKind::block());
+
+        root.insert_flags(LayoutNodeFlags::ESTABLISHES_BFC);
 
         let mut nodes = allocator::Allocator::default();
         let root = LayoutNodeId(nodes.allocate(root));
 
-        Self { nodes, root }
+        Self {
+            nodes,
+            root,
+            validation: Validation::default(),
+            font_faces,
+        }
+    }
+
+    pub fn font_faces(&self) -> &Rc<FontFaceSet> {
+        &self.font_faces
+    }
+
+    /// Changes the style of a node, keeping the flags of the node and its
+    /// children up-to-date, and marks it as needing layout.
+    ///
+    /// The change must not affect the shape of the tree, see
+    /// `StyleDifference::ReconstructBoxes`.
+    pub fn set_style(&mut self, id: LayoutNodeId, style: Rc<ComputedStyle>) {
+        self[id].style = style;
+        self[id].update_style_flags(self);
+        // Whether the children are orthogonal to us may have changed.
+        for child in self[id].children(self) {
+            child.update_style_flags(self);
+        }
+        self.mark_needs_layout(id);
+    }
+
+    pub fn validation(&self) -> Validation {
+        self.validation
+    }
+
+    /// Sets how much checking happens on insertions. `assert_consistent` can
+    /// still be used to check the whole tree regardless of this.
+    pub fn set_validation(&mut self, validation: Validation) {
+        self.validation = validation;
+    }
+
+    /// Iterates over all the allocated nodes, whether they're attached to the
+    /// tree or not, in no particular order.
+    pub fn iter_nodes(&self) -> impl Iterator<Item = (LayoutNodeId, &LayoutNode)> {
+        self.nodes.iter().map(|(id, node)| (LayoutNodeId(id), node))
+    }
+
+    /// Whether `id` refers to a node that hasn't been destroyed.
+    pub fn contains(&self, id: LayoutNodeId) -> bool {
+        self.nodes.contains(id.0)
     }
 
     fn non_anonymous_ancestor(&self, mut id: LayoutNodeId) -> Option<LayoutNodeId> {
@@ -582,6 +1168,7 @@ impl LayoutTree {
                     ContainerKind::Inline { ref mut next_ib_sibling, ..  } => {
                         *next_ib_sibling = Some(next);
                     }
+                    ContainerKind::Fieldset => unreachable!(),
                 }
             }
             _ => unreachable!(),
@@ -594,14 +1181,99 @@ impl LayoutTree {
                     ContainerKind::Inline { ref mut prev_ib_sibling, ..  } => {
                         *prev_ib_sibling = Some(prev);
                     }
+                    ContainerKind::Fieldset => unreachable!(),
                 }
             }
             _ => unreachable!(),
         }
+
+        self[prev].update_ib_split_flag();
+        self[next].update_ib_split_flag();
     }
 
     pub fn assert_consistent(&self) {
         self.assert_subtree_consistent(self.root);
+
+        // Nodes that have been detached but not destroyed would otherwise go
+        // unnoticed until the tree is dropped.
+        for (id, node) in self.iter_nodes() {
+            let mut current = id;
+            while let Some(parent) = self[current].parent {
+                current = parent;
+            }
+            assert_eq!(current, self.root, "{:?} is allocated, but not in the tree", id);
+
+            let expected_containing_block = if node.is_out_of_flow() {
+                self.containing_block_id(id)
+            } else {
+                None
+            };
+            assert_eq!(
+                node.registered_containing_block, expected_containing_block,
+                "Out-of-flow box {:?} registered with the wrong containing block",
+                id
+            );
+            for descendant in node.oof_descendants() {
+                assert_eq!(self[*descendant].registered_containing_block, Some(id));
+            }
+            assert_eq!(
+                node.flags().contains(LayoutNodeFlags::HAS_OOF_DESCENDANTS),
+                !node.oof_descendants().is_empty()
+            );
+        }
+    }
+
+    /// Returns the id of the containing block of a node.
+    fn containing_block_id(&self, id: LayoutNodeId) -> Option<LayoutNodeId> {
+        let mut current = self[id].parent;
+        while let Some(ancestor) = current {
+            if self[ancestor].is_containing_block_for(&self[id]) {
+                return Some(ancestor);
+            }
+            current = self[ancestor].parent;
+        }
+        None
+    }
+
+    /// Registers the out-of-flow boxes in the subtree of `root` with their
+    /// containing blocks, which may have changed if the subtree has been
+    /// moved.
+    ///
+    /// Newly inserted nodes have no children, so this is usually constant
+    /// time.
+    fn update_oof_registrations(&mut self, root: LayoutNodeId) {
+        if self[root].is_out_of_flow() {
+            let containing_block = self.containing_block_id(root);
+            if self[root].registered_containing_block != containing_block {
+                self.unregister_oof(root);
+                if let Some(containing_block) = containing_block {
+                    self[containing_block].oof_descendants_mut().push(root);
+                    self[containing_block].insert_flags(LayoutNodeFlags::HAS_OOF_DESCENDANTS);
+                }
+                self[root].registered_containing_block = containing_block;
+            }
+        }
+        let mut child = self[root].first_child();
+        while let Some(id) = child {
+            self.update_oof_registrations(id);
+            child = self[id].next_sibling;
+        }
+    }
+
+    fn unregister_oof(&mut self, id: LayoutNodeId) {
+        let containing_block = match self[id].registered_containing_block.take() {
+            Some(containing_block) => containing_block,
+            None => return,
+        };
+        let containing_block = &mut self[containing_block];
+        let descendants = containing_block.oof_descendants_mut();
+        let index = descendants
+            .iter()
+            .position(|descendant| *descendant == id)
+            .expect("Registered out-of-flow box not in its containing block");
+        descendants.swap_remove(index);
+        let has_oof_descendants = !descendants.is_empty();
+        containing_block.set_flags(LayoutNodeFlags::HAS_OOF_DESCENDANTS, has_oof_descendants);
     }
 
     fn last_inline_continuation(&self, inline: LayoutNodeId) -> LayoutNodeId {
@@ -623,6 +1295,26 @@ impl LayoutTree {
         current
     }
 
+    /// Checks that the links between `node`, its parent and its siblings
+    /// agree with each other, without looking at the rest of the tree.
+    fn assert_links_consistent(&self, node: LayoutNodeId) {
+        let parent = self[node].parent.expect("Node should have a parent");
+        match self[node].prev_sibling {
+            Some(prev) => {
+                assert_eq!(self[prev].parent, Some(parent), "Unexpected parent of prev_sibling of {:?}", node);
+                assert_eq!(self[prev].next_sibling, Some(node), "Unexpected next_sibling of prev_sibling of {:?}", node);
+            },
+            None => assert_eq!(self[parent].first_child(), Some(node), "Unexpected first_child of {:?}", parent),
+        }
+        match self[node].next_sibling {
+            Some(next) => {
+                assert_eq!(self[next].parent, Some(parent), "Unexpected parent of next_sibling of {:?}", node);
+                assert_eq!(self[next].prev_sibling, Some(node), "Unexpected prev_sibling of next_sibling of {:?}", node);
+            },
+            None => assert_eq!(self[parent].last_child(), Some(node), "Unexpected last_child of {:?}", parent),
+        }
+    }
+
     fn assert_subtree_consistent(&self, root: LayoutNodeId) {
         let mut expected_count = 0;
 
@@ -636,6 +1328,18 @@ impl LayoutTree {
                 id,
                 child
             );
+            assert_eq!(
+                child.flags().contains(LayoutNodeFlags::ESTABLISHES_BFC),
+                child.compute_establishes_bfc(self),
+                "Stale ESTABLISHES_BFC flag, child {:?}",
+                id
+            );
+            assert_eq!(
+                child.is_ib_split(),
+                child.prev_ib_sibling().is_some() || child.next_ib_sibling().is_some(),
+                "Stale IS_IB_SPLIT flag, child {:?}",
+                id
+            );
             assert_eq!(
                 child.prev_sibling, expected_prev_sibling,
                 "Unexpected prev_sibling, child {:?} - {:#?} - {:#?}",
@@ -666,6 +1370,21 @@ impl LayoutTree {
 
         assert_eq!(reverse_count, expected_count);
 
+        let mut expected_info = ChildrenInfo::default();
+        for (id, child) in self[root].children_and_id(self) {
+            expected_info.count += 1;
+            if child.is_in_flow() {
+                expected_info.in_flow_count += 1;
+                expected_info.last_in_flow_child = Some(id);
+                if child.style.display.is_inline_outside() {
+                    expected_info.in_flow_inline_count += 1;
+                }
+            }
+        }
+        if self[root].is_container() {
+            assert_eq!(self[root].children_info(), expected_info, "Stale children info for {:?}", root);
+        }
+
         match self[root].container_kind() {
             None => {},
             Some(ContainerKind::Block { .. }) => {
@@ -691,6 +1410,14 @@ impl LayoutTree {
                     );
                 }
             },
+            Some(ContainerKind::Fieldset) => {
+                // The content box is only missing while the fieldset is being
+                // created.
+                if let Some(content_box) = self[root].last_child() {
+                    assert_eq!(self[content_box].style.pseudo, Some(PseudoElement::FieldsetContent));
+                }
+                assert!(expected_count <= 2, "More than a legend and a content box in a fieldset");
+            },
         }
     }
 
@@ -702,6 +1429,43 @@ impl LayoutTree {
         &self[self.root]
     }
 
+    /// Returns the id of a node that is in this tree.
+    ///
+    /// This is linear in the number of siblings of the node.
+    pub fn id_of(&self, node: &LayoutNode) -> LayoutNodeId {
+        let parent = match node.parent {
+            Some(parent) => parent,
+            None => {
+                debug_assert!(std::ptr::eq(node, self.root_node()), "Node not in the tree");
+                return self.root;
+            },
+        };
+        self[parent]
+            .children_and_id(self)
+            .find(|&(_, child)| std::ptr::eq(child, node))
+            .map(|(id, _)| id)
+            .expect("Node not in its parent's child list?")
+    }
+
+    /// Marks a node as needing layout, and its ancestors as having a
+    /// descendant that needs layout.
+    ///
+    /// The intrinsic sizes of all of them may change as a result, too.
+    pub fn mark_needs_layout(&self, id: LayoutNodeId) {
+        self[id].insert_flags(LayoutNodeFlags::NEEDS_LAYOUT | LayoutNodeFlags::NEEDS_INTRINSIC_SIZE);
+        let ancestor_flags =
+            LayoutNodeFlags::DESCENDANT_NEEDS_LAYOUT | LayoutNodeFlags::NEEDS_INTRINSIC_SIZE;
+        let mut current = self[id].parent;
+        while let Some(ancestor) = current {
+            let ancestor = &self[ancestor];
+            if ancestor.flags().contains(ancestor_flags) {
+                break;
+            }
+            ancestor.insert_flags(ancestor_flags);
+            current = ancestor.parent;
+        }
+    }
+
     /// Allocates a node inside the tree. This node _must_ be inserted in the
     /// layout tree.
     #[must_use]
@@ -720,8 +1484,12 @@ impl LayoutTree {
         let ip = match container_kind {
             ContainerKind::Inline { .. } => InlineInside::insertion(self, &node, ip)?,
             ContainerKind::Block { .. } => BlockInside::insertion(self, &node, ip)?,
+            ContainerKind::Fieldset => FieldsetInside::insertion(self, &node, ip),
         };
         let id = self.alloc(node);
+        if self[id].is_fieldset() {
+            FieldsetInside::insert_content_box(self, id);
+        }
         self.insert_unchecked(id, ip);
         Some(id)
     }
@@ -761,12 +1529,15 @@ impl LayoutTree {
             first_sibling_to_move
         };
 
+        let new_last_in_flow_child = self.last_in_flow_sibling_up_to(from_sibling);
         let mut current = first_sibling_to_move;
         while let Some(child) = current {
+            self.update_children_info_for_removal(from_node, child, Some(new_last_in_flow_child));
+
             // Un-parent the child, save next sibling so that we can
             // continue the loop.
             let child_prev_sibling = {
-                let mut child = &mut self[child];
+                let child = &mut self[child];
                 assert_eq!(child.parent, Some(from_node));
                 child.parent = None;
 
@@ -788,7 +1559,9 @@ impl LayoutTree {
 
     pub fn insert_unchecked(&mut self, node_id: LayoutNodeId, ip: InsertionPoint) {
         trace!("Inserting {:?} into {:?}", node_id, ip);
-        self.assert_subtree_consistent(ip.parent);
+        if self.validation == Validation::Full {
+            self.assert_subtree_consistent(ip.parent);
+        }
 
         {
             let node = &self[node_id];
@@ -806,7 +1579,7 @@ impl LayoutTree {
         };
 
         {
-            let mut node = &mut self[node_id];
+            let node = &mut self[node_id];
             node.parent = Some(ip.parent);
             node.prev_sibling = ip.prev_sibling;
             node.next_sibling = new_next_sibling;
@@ -820,11 +1593,24 @@ impl LayoutTree {
             self[next_sibling].prev_sibling = Some(node_id);
         }
 
+        self.mark_needs_layout(node_id);
+        self[node_id].update_style_flags(self);
+
+        let is_in_flow = self[node_id].is_in_flow();
+        let is_inline = self[node_id].style.display.is_inline_outside();
+        // The node is the last in-flow child unless an in-flow sibling
+        // follows it, which is usually found right away.
+        let is_last_in_flow = is_in_flow &&
+            !self[node_id]
+                .following_siblings_and_id(self)
+                .any(|(_, sibling)| sibling.is_in_flow());
+
         let parent = &mut self[ip.parent];
         match parent.kind {
             LayoutNodeKind::Container {
                 ref mut first_child,
                 ref mut last_child,
+                ref mut children_info,
                 ..
             } => {
                 if ip.prev_sibling.is_none() {
@@ -833,11 +1619,27 @@ impl LayoutTree {
                 if *last_child == ip.prev_sibling {
                     *last_child = Some(node_id);
                 }
+                children_info.count += 1;
+                if is_in_flow {
+                    children_info.in_flow_count += 1;
+                    if is_inline {
+                        children_info.in_flow_inline_count += 1;
+                    }
+                }
+                if is_last_in_flow {
+                    children_info.last_in_flow_child = Some(node_id);
+                }
             },
             LayoutNodeKind::Leaf { .. } => unreachable!(),
         }
 
-        self.assert_subtree_consistent(ip.parent);
+        self.update_oof_registrations(node_id);
+
+        match self.validation {
+            Validation::None => {},
+            Validation::Local => self.assert_links_consistent(node_id),
+            Validation::Full => self.assert_subtree_consistent(ip.parent),
+        }
     }
 
     /// Detaches a node from the tree, without detaching its children or
@@ -858,14 +1660,13 @@ impl LayoutTree {
                     InlineInside::detach_from_ib_split_block_wrapper(self, parent, node_to_remove)
                 }
                 PseudoElement::InlineContinuation => {
-                    // InlineInside::remove_from_inline_continuation(self, parent, node_to_remove);
-                    unimplemented!()
+                    InlineInside::remove_from_inline_continuation(self, parent, node_to_remove)
                 },
                 PseudoElement::InlineInsideBlockWrapper => {
-                    // BlockInside::detach_from_inline_wrapper(self, parent, node_to_remove)
-                    unimplemented!()
+                    BlockInside::detach_from_inline_wrapper(self, parent, node_to_remove)
                 }
-                PseudoElement::Before | PseudoElement::After => {
+                PseudoElement::FieldsetContent => BlockInside::detach(self, parent, node_to_remove),
+                PseudoElement::Before | PseudoElement::After | PseudoElement::Marker => {
                     unreachable!("These are not anonymous boxes")
                 }
             };
@@ -874,10 +1675,20 @@ impl LayoutTree {
         match self[parent].container_kind().unwrap() {
             ContainerKind::Block { .. } => BlockInside::detach(self, parent, node_to_remove),
             ContainerKind::Inline { .. } => InlineInside::detach(self, parent, node_to_remove),
+            ContainerKind::Fieldset => FieldsetInside::detach(self, node_to_remove),
         }
     }
 
     pub fn detach_unchecked(&mut self, node_to_remove: LayoutNodeId) -> Option<InsertionPoint> {
+        if let Some(parent) = self[node_to_remove].parent {
+            let new_last_in_flow_child = if self[parent].last_in_flow_child() == Some(node_to_remove) {
+                Some(self.last_in_flow_sibling_up_to(self[node_to_remove].prev_sibling))
+            } else {
+                None
+            };
+            self.update_children_info_for_removal(parent, node_to_remove, new_last_in_flow_child);
+        }
+
         let (prev_sibling, parent, next_sibling) = {
             let node = &mut self[node_to_remove];
             (node.prev_sibling.take(), node.parent.take(), node.next_sibling.take())
@@ -919,6 +1730,10 @@ impl LayoutTree {
             }
         }
 
+        if let Some(parent) = parent {
+            self.mark_needs_layout(parent);
+        }
+
         parent.map(|parent| {
             InsertionPoint {
                 parent,
@@ -927,6 +1742,50 @@ impl LayoutTree {
         })
     }
 
+    /// Returns `node` if it's in-flow, or otherwise its closest in-flow
+    /// previous sibling.
+    fn last_in_flow_sibling_up_to(&self, node: Option<LayoutNodeId>) -> Option<LayoutNodeId> {
+        let node = node?;
+        if self[node].is_in_flow() {
+            return Some(node);
+        }
+        self[node]
+            .prev_siblings_and_id(self)
+            .find(|(_, sibling)| sibling.is_in_flow())
+            .map(|(id, _)| id)
+    }
+
+    /// Updates the children info of `parent` before `child` is detached from
+    /// it. `new_last_in_flow_child` is the last in-flow child that `parent`
+    /// will have afterwards, if it changes.
+    fn update_children_info_for_removal(
+        &mut self,
+        parent: LayoutNodeId,
+        child: LayoutNodeId,
+        new_last_in_flow_child: Option<Option<LayoutNodeId>>,
+    ) {
+        let is_in_flow = self[child].is_in_flow();
+        let is_inline = self[child].style.display.is_inline_outside();
+        let parent = &mut self[parent];
+        match parent.kind {
+            LayoutNodeKind::Container {
+                ref mut children_info, ..
+            } => {
+                children_info.count -= 1;
+                if is_in_flow {
+                    children_info.in_flow_count -= 1;
+                    if is_inline {
+                        children_info.in_flow_inline_count -= 1;
+                    }
+                }
+                if let Some(last_in_flow_child) = new_last_in_flow_child {
+                    children_info.last_in_flow_child = last_in_flow_child;
+                }
+            },
+            LayoutNodeKind::Leaf { .. } => unreachable!(),
+        }
+    }
+
     pub fn destroy(&mut self, node_to_remove: LayoutNodeId) {
         // Recursively tear down the children.
         let mut child = self[node_to_remove].first_child();
@@ -937,6 +1796,16 @@ impl LayoutTree {
 
         // Detach the node from the tree.
         self.detach_unchecked(node_to_remove);
+        self.unregister_oof(node_to_remove);
+        // Any descendants still registered with us have been detached from
+        // the subtree, and will register again when they're inserted.
+        let stale_descendants = match self[node_to_remove].kind {
+            LayoutNodeKind::Container { ref mut oof_descendants, .. } => std::mem::take(oof_descendants),
+            LayoutNodeKind::Leaf { .. } => Vec::new(),
+        };
+        for descendant in stale_descendants {
+            self[descendant].registered_containing_block = None;
+        }
 
         // And de-allocate the node.
         let removed_node = self.nodes.deallocate(node_to_remove.0);
@@ -963,11 +1832,78 @@ impl LayoutTree {
         self[self.root].print(self, self.root, &mut printer, print_id);
     }
 
+    /// Prints the layout tree as a Graphviz graph, with ib-split siblings
+    /// linked, and anonymous boxes filled.
+    pub fn print_dot_to(&self, dest: &mut dyn std::io::Write) {
+        let mut graph = DotGraph::new("Layout tree", dest);
+        self[self.root].print_dot(self, self.root, &mut graph);
+    }
+
+    /// Returns the inline items of every inline formatting context in the
+    /// tree, in tree order, along with the node establishing each of them.
+    pub fn inline_items(
+        &self,
+        quirks_mode: QuirksMode,
+        viewport_size: Size2D<Au>,
+    ) -> Vec<(LayoutNodeId, Vec<InlineItem>)> {
+        fn collect(
+            context: &LayoutContext,
+            id: LayoutNodeId,
+            result: &mut Vec<(LayoutNodeId, Vec<InlineItem>)>,
+        ) {
+            let node = &context.layout_tree[id];
+            if node.establishes_ifc(context.layout_tree) {
+                let mut ifc = InlineFormattingContext::new(context, node);
+                result.push((id, ifc.items().to_vec()));
+            }
+            for (child_id, _) in node.children_and_id(context.layout_tree) {
+                collect(context, child_id, result);
+            }
+        }
+
+        let context = LayoutContext {
+            quirks_mode,
+            layout_tree: self,
+            initial_containing_block_size: viewport_size,
+        };
+        let mut result = vec![];
+        collect(&context, self.root, &mut result);
+        result
+    }
+
+    /// Prints the inline items of every inline formatting context in the tree
+    /// to a particular output.
+    pub fn print_inline_items_to(
+        &self,
+        quirks_mode: QuirksMode,
+        viewport_size: Size2D<Au>,
+        dest: &mut dyn std::io::Write,
+    ) {
+        let mut printer = PrintTree::new("Inline items", dest);
+        for (id, items) in self.inline_items(quirks_mode, viewport_size) {
+            printer.new_level(self[id].print_label(id, PrintId::Yes, self));
+            for item in items {
+                // The resolved text is more interesting than the text node.
+                if item.text().is_some() {
+                    printer.add_item(item.kind_label());
+                    continue;
+                }
+                let node_label = match self[item.node()].dom_node() {
+                    Some(dom_node) => crate::dom::node_label(&dom_node),
+                    None => format!("{:?}", item.node()),
+                };
+                printer.add_item(format!("{} - {}", item.kind_label(), node_label));
+            }
+            printer.end_level();
+        }
+    }
+
     /// Actually runs layout on the tree!
-    pub fn layout(&self, quirks_mode: QuirksMode, viewport_size: Size2D<Au>) -> ChildFragment {
+    pub fn layout(&self, quirks_mode: QuirksMode, viewport_size: Size2D<Au>) -> FragmentTree {
         let context = LayoutContext {
             quirks_mode,
             layout_tree: self,
+            initial_containing_block_size: viewport_size,
         };
 
         let root = self.root_node();
@@ -978,17 +1914,20 @@ impl LayoutTree {
             viewport_size.width
         };
 
-        let percentage_resolution_size = LogicalSize::from_physical(wm, viewport_size);
-        let constraints = ConstraintSpace {
-            available_size: AvailableSize::unconstrained_block(wm, available_inline_size),
-            percentage_resolution_size: AvailableSize::definite(wm, percentage_resolution_size),
-            containing_block_writing_mode: wm,
-        };
+        // Percentages resolve against the size of the initial containing
+        // block, which is the default.
+        let constraints = ConstraintSpaceBuilder::new(ContainingBlockInfo::initial(wm, viewport_size))
+            .available_size(AvailableSize::unconstrained_block(wm, available_inline_size))
+            .build();
 
-        let result = root.layout(&context, &constraints);
+        let result = root.layout(&context, &constraints, None);
 
-        // assert!(result.break_token.is_none(), "How did we fragment with unconstrained block size?");
-        result.root_fragment
+        debug_assert!(result.break_token.is_none(), "How did we fragment with unconstrained block size?");
+        FragmentTree {
+            root: result.root_fragment,
+            viewport_size,
+            font_faces: self.font_faces.clone(),
+        }
     }
 }
 
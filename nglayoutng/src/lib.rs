@@ -10,15 +10,26 @@ extern crate matches;
 extern crate nglayoutng_derive;
 
 pub mod allocator;
+pub mod animate;
 pub mod css;
+pub mod display_list;
+pub mod document;
 pub mod dom;
+pub mod error;
 pub mod fonts;
 pub mod fragment_tree;
 pub mod layout_algorithms;
 pub mod layout_tree;
 pub mod logical_geometry;
+pub mod memory;
 mod misc;
+pub mod paint;
+pub mod query;
 pub mod sizing;
 pub mod style;
+pub mod trace;
+pub mod transitions;
+pub mod wpt;
 
 pub use app_units::Au;
+pub use self::error::Error;
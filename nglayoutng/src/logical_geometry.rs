@@ -24,7 +24,6 @@ pub enum InlineBaseDirection {
 
 // TODO: improve the readability of the WritingMode serialization, refer to the Debug:fmt()
 bitflags!(
-    #[cfg_attr(feature = "servo", derive(MallocSizeOf, Serialize))]
     #[repr(C)]
     pub struct WritingMode: u8 {
         /// A vertical writing mode; writing-mode is vertical-rl,
@@ -163,6 +162,15 @@ impl WritingMode {
         self.intersects(WritingMode::VERTICAL)
     }
 
+    /// Whether the block axis of this writing mode is perpendicular to the
+    /// block axis of `other`.
+    ///
+    /// https://drafts.csswg.org/css-writing-modes/#orthogonal-flows
+    #[inline]
+    pub fn is_orthogonal_to(&self, other: WritingMode) -> bool {
+        self.is_vertical() != other.is_vertical()
+    }
+
     #[inline]
     pub fn is_horizontal(&self) -> bool {
         !self.is_vertical()
@@ -196,6 +204,61 @@ impl WritingMode {
         self.intersects(WritingMode::UPRIGHT)
     }
 
+    /// Assuming .is_vertical(), are the line-over / line-under sides inverted
+    /// with respect to block-start / block-end?
+    #[inline]
+    pub fn is_line_inverted(&self) -> bool {
+        self.intersects(WritingMode::LINE_INVERTED)
+    }
+
+    /// https://drafts.csswg.org/css-writing-modes/#line-over
+    #[inline]
+    pub fn line_over_physical_side(&self) -> PhysicalSide {
+        if self.is_line_inverted() {
+            self.block_end_physical_side()
+        } else {
+            self.block_start_physical_side()
+        }
+    }
+
+    /// https://drafts.csswg.org/css-writing-modes/#line-under
+    #[inline]
+    pub fn line_under_physical_side(&self) -> PhysicalSide {
+        if self.is_line_inverted() {
+            self.block_start_physical_side()
+        } else {
+            self.block_end_physical_side()
+        }
+    }
+
+    /// https://drafts.csswg.org/css-writing-modes/#line-left
+    ///
+    /// Note that this depends on the orientation of the text: in vertical
+    /// modes the line-left side is the top, except for sideways-lr, where the
+    /// text is rotated counter-clockwise, and thus it's the bottom.
+    #[inline]
+    pub fn line_left_physical_side(&self) -> PhysicalSide {
+        if self.is_horizontal() {
+            return PhysicalSide::Left;
+        }
+        if self.intersects(WritingMode::VERTICAL_SIDEWAYS) && self.is_vertical_lr() {
+            PhysicalSide::Bottom
+        } else {
+            PhysicalSide::Top
+        }
+    }
+
+    /// https://drafts.csswg.org/css-writing-modes/#line-right
+    #[inline]
+    pub fn line_right_physical_side(&self) -> PhysicalSide {
+        match self.line_left_physical_side() {
+            PhysicalSide::Left => PhysicalSide::Right,
+            PhysicalSide::Top => PhysicalSide::Bottom,
+            PhysicalSide::Bottom => PhysicalSide::Top,
+            PhysicalSide::Right => PhysicalSide::Left,
+        }
+    }
+
     /// https://drafts.csswg.org/css-writing-modes/#logical-to-physical
     ///
     /// | Return  | line-left is… | line-right is… |
@@ -353,12 +416,10 @@ impl fmt::Display for WritingMode {
 /// In non-debug builds, make this storage zero-size and the checks no-ops.
 #[cfg(not(debug_assertions))]
 #[derive(Clone, Copy, Eq, PartialEq)]
-#[cfg_attr(feature = "servo", derive(Serialize))]
 struct DebugWritingMode;
 
 #[cfg(debug_assertions)]
 #[derive(Clone, Copy, Eq, PartialEq)]
-#[cfg_attr(feature = "servo", derive(Serialize))]
 struct DebugWritingMode {
     mode: WritingMode,
 }
@@ -391,7 +452,7 @@ impl DebugWritingMode {
 
     #[inline]
     fn new(mode: WritingMode) -> DebugWritingMode {
-        DebugWritingMode { mode: mode }
+        DebugWritingMode { mode }
     }
 }
 
@@ -409,7 +470,6 @@ impl Debug for DebugWritingMode {
 
 // Used to specify the logical direction.
 #[derive(Clone, Copy, Debug, PartialEq)]
-#[cfg_attr(feature = "servo", derive(Serialize))]
 pub enum Direction {
     Inline,
     Block,
@@ -417,7 +477,6 @@ pub enum Direction {
 
 /// A 2D size in flow-relative dimensions
 #[derive(Clone, Copy, Eq, PartialEq)]
-#[cfg_attr(feature = "servo", derive(Serialize))]
 pub struct LogicalSize<T> {
     pub inline: T, // inline-size, a.k.a. logical width, a.k.a. measure
     pub block: T,  // block-size, a.k.a. logical height, a.k.a. extent
@@ -450,8 +509,8 @@ impl<T> LogicalSize<T> {
     #[inline]
     pub fn new(mode: WritingMode, inline: T, block: T) -> LogicalSize<T> {
         LogicalSize {
-            inline: inline,
-            block: block,
+            inline,
+            block,
             debug_writing_mode: DebugWritingMode::new(mode),
         }
     }
@@ -560,7 +619,6 @@ impl<T: Sub<T, Output = T>> Sub for LogicalSize<T> {
 
 /// A 2D point in flow-relative dimensions
 #[derive(Clone, Copy, Eq, PartialEq)]
-#[cfg_attr(feature = "servo", derive(Serialize))]
 pub struct LogicalPoint<T> {
     /// inline-axis coordinate
     pub i: T,
@@ -798,7 +856,6 @@ impl<T: Copy + Sub<T, Output = T>> Sub<LogicalSize<T>> for LogicalPoint<T> {
 /// or a combination of those.
 /// A positive "margin" can be added to a rectangle to obtain a bigger rectangle.
 #[derive(Clone, Copy, Eq, PartialEq)]
-#[cfg_attr(feature = "servo", derive(Serialize))]
 pub struct LogicalMargin<T> {
     pub block_start: T,
     pub inline_end: T,
@@ -850,10 +907,10 @@ impl<T> LogicalMargin<T> {
         inline_start: T,
     ) -> LogicalMargin<T> {
         LogicalMargin {
-            block_start: block_start,
-            inline_end: inline_end,
-            block_end: block_end,
-            inline_start: inline_start,
+            block_start,
+            inline_end,
+            block_end,
+            inline_start,
             debug_writing_mode: DebugWritingMode::new(mode),
         }
     }
@@ -1171,7 +1228,6 @@ impl<T: Sub<T, Output = T>> Sub for LogicalMargin<T> {
 
 /// A rectangle in flow-relative dimensions
 #[derive(Clone, Copy, Eq, PartialEq)]
-#[cfg_attr(feature = "servo", derive(Serialize))]
 pub struct LogicalRect<T> {
     pub start: LogicalPoint<T>,
     pub size: LogicalSize<T>,
@@ -1230,8 +1286,8 @@ impl<T: Copy> LogicalRect<T> {
         start.debug_writing_mode.check(mode);
         size.debug_writing_mode.check(mode);
         LogicalRect {
-            start: start,
-            size: size,
+            start,
+            size,
             debug_writing_mode: DebugWritingMode::new(mode),
         }
     }
@@ -1386,6 +1442,60 @@ impl<T: Copy + Ord + Add<T, Output = T> + Sub<T, Output = T>> LogicalRect<T> {
             debug_writing_mode: self.debug_writing_mode,
         }
     }
+
+    /// Returns the intersection of both rects, or `None` if they don't
+    /// overlap.
+    #[inline]
+    pub fn intersection(&self, other: &LogicalRect<T>) -> Option<LogicalRect<T>> {
+        self.debug_writing_mode
+            .check_debug(other.debug_writing_mode);
+
+        let inline_start = max(self.start.i, other.start.i);
+        let block_start = max(self.start.b, other.start.b);
+        let inline_end = min(self.inline_end(), other.inline_end());
+        let block_end = min(self.block_end(), other.block_end());
+        if inline_end <= inline_start || block_end <= block_start {
+            return None;
+        }
+
+        Some(LogicalRect {
+            start: LogicalPoint {
+                i: inline_start,
+                b: block_start,
+                debug_writing_mode: self.debug_writing_mode,
+            },
+            size: LogicalSize {
+                inline: inline_end - inline_start,
+                block: block_end - block_start,
+                debug_writing_mode: self.debug_writing_mode,
+            },
+            debug_writing_mode: self.debug_writing_mode,
+        })
+    }
+
+    #[inline]
+    pub fn intersects(&self, other: &LogicalRect<T>) -> bool {
+        self.intersection(other).is_some()
+    }
+
+    /// Whether the point is inside this rect. The end edges are not
+    /// considered part of the rect.
+    #[inline]
+    pub fn contains(&self, point: &LogicalPoint<T>) -> bool {
+        self.debug_writing_mode
+            .check_debug(point.debug_writing_mode);
+        self.start.i <= point.i &&
+            point.i < self.inline_end() &&
+            self.start.b <= point.b &&
+            point.b < self.block_end()
+    }
+}
+
+impl<T: Copy + Zero + PartialOrd> LogicalRect<T> {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        !(self.size.inline > T::zero() && self.size.block > T::zero())
+    }
 }
 
 impl<T: Copy + Add<T, Output = T> + Sub<T, Output = T>> Add<LogicalMargin<T>> for LogicalRect<T> {
@@ -0,0 +1,129 @@
+//! Rough accounting of the memory that layout uses, to know where sharing and
+//! interning would pay off.
+//!
+//! The numbers only account for the structs themselves and the heap
+//! allocations that are easy to measure (text, children arrays, glyphs). The
+//! heap allocations that computed styles own, like font family lists, and the
+//! shaping cache, are not accounted for yet.
+
+use crate::fonts::shaping::GlyphInfo;
+use crate::fragment_tree::{ChildFragment, Fragment, FragmentKind, FragmentTree};
+use crate::layout_tree::{LayoutNode, LayoutNodeKind, LayoutTree, LeafKind};
+use crate::misc::print_tree::PrintTree;
+use crate::style::ComputedStyle;
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::rc::Rc;
+
+/// The number of bytes used by each category of layout data.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// The layout nodes of the layout tree, and of the documents nested in it.
+    pub nodes: usize,
+    /// The distinct computed styles that layout nodes and fragments point to.
+    pub styles: usize,
+    /// The text of text boxes and text fragments.
+    pub text: usize,
+    /// The distinct fragments, and the arrays of their children.
+    pub fragments: usize,
+    /// The glyphs of the shaped text runs in text fragments.
+    pub shaped_runs: usize,
+}
+
+impl MemoryReport {
+    pub fn total(&self) -> usize {
+        self.nodes + self.styles + self.text + self.fragments + self.shaped_runs
+    }
+
+    /// Prints the report to stdout.
+    pub fn print(&self) {
+        self.print_to(&mut std::io::stdout());
+    }
+
+    /// Prints the report to a particular output.
+    pub fn print_to(&self, dest: &mut dyn std::io::Write) {
+        let mut printer = PrintTree::new("Memory report", dest);
+        printer.add_item(format!("nodes: {} bytes", self.nodes));
+        printer.add_item(format!("styles: {} bytes", self.styles));
+        printer.add_item(format!("text: {} bytes", self.text));
+        printer.add_item(format!("fragments: {} bytes", self.fragments));
+        printer.add_item(format!("shaped runs: {} bytes", self.shaped_runs));
+        printer.add_item(format!("total: {} bytes", self.total()));
+    }
+}
+
+/// Walks the layout tree, and the fragment tree resulting from laying it out
+/// if any, and reports how much memory they use.
+///
+/// Styles and fragments that are shared are only counted once.
+pub fn memory_report(layout_tree: &LayoutTree, fragment_tree: Option<&FragmentTree>) -> MemoryReport {
+    let mut reporter = Reporter::default();
+    reporter.add_layout_tree(layout_tree);
+    if let Some(fragment_tree) = fragment_tree {
+        reporter.add_child_fragment(&fragment_tree.root);
+    }
+    reporter.report
+}
+
+#[derive(Default)]
+struct Reporter {
+    report: MemoryReport,
+    seen_styles: HashSet<*const ComputedStyle>,
+    seen_fragments: HashSet<*const Fragment>,
+}
+
+impl Reporter {
+    fn add_style(&mut self, style: &Rc<ComputedStyle>) {
+        if self.seen_styles.insert(&**style) {
+            self.report.styles += size_of::<ComputedStyle>();
+        }
+    }
+
+    fn add_layout_tree(&mut self, tree: &LayoutTree) {
+        for (_, node) in tree.iter_nodes() {
+            self.add_layout_node(node);
+        }
+    }
+
+    fn add_layout_node(&mut self, node: &LayoutNode) {
+        self.report.nodes += size_of::<LayoutNode>();
+        self.add_style(&node.style);
+        match node.kind {
+            LayoutNodeKind::Leaf {
+                kind: LeafKind::Text { ref text },
+            } => self.report.text += text.len(),
+            LayoutNodeKind::Leaf {
+                kind: LeafKind::Replaced {
+                    nested_document: Some(ref document),
+                    ..
+                },
+            } => self.add_layout_tree(&document.layout_tree),
+            _ => {},
+        }
+    }
+
+    fn add_child_fragment(&mut self, child: &ChildFragment) {
+        let fragment = &child.fragment;
+        if !self.seen_fragments.insert(&**fragment) {
+            return;
+        }
+        self.report.fragments += size_of::<Fragment>();
+        self.add_style(&fragment.style);
+        match fragment.kind {
+            FragmentKind::TextRun {
+                ref content,
+                ref glyphs,
+                ..
+            } => {
+                self.report.text += content.capacity();
+                self.report.shaped_runs += glyphs.len() * size_of::<GlyphInfo>();
+            },
+            FragmentKind::Container { ref children, .. } => {
+                self.report.fragments += children.len() * size_of::<ChildFragment>();
+                for child in children.iter() {
+                    self.add_child_fragment(child);
+                }
+            },
+        }
+    }
+}
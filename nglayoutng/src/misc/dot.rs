@@ -0,0 +1,63 @@
+//! A writer of Graphviz graphs, which make some tree structures (like ib
+//! splits) easier to reason about than the output of `PrintTree`.
+//!
+//! https://graphviz.org/doc/info/lang.html
+
+use std::io::Write;
+
+/// Escapes `text` as a double-quoted Graphviz string.
+fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            },
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A directed graph which is written as nodes and edges are added, and closed
+/// when dropped.
+pub struct DotGraph<'a> {
+    output: &'a mut dyn Write,
+}
+
+impl<'a> DotGraph<'a> {
+    pub fn new(title: &str, output: &'a mut dyn Write) -> Self {
+        writeln!(output, "digraph {} {{", quote(title)).unwrap();
+        writeln!(output, "  node [shape=box, fontname=monospace];").unwrap();
+        Self { output }
+    }
+
+    /// Adds a node, filled with `fill_color` if given.
+    pub fn node(&mut self, id: &str, label: &str, fill_color: Option<&str>) {
+        write!(self.output, "  {} [label={}", id, quote(label)).unwrap();
+        if let Some(color) = fill_color {
+            write!(self.output, ", style=filled, fillcolor={}", quote(color)).unwrap();
+        }
+        writeln!(self.output, "];").unwrap();
+    }
+
+    /// Adds an edge between two nodes, with the given extra attributes, like
+    /// `style=dashed`.
+    pub fn edge(&mut self, from: &str, to: &str, attributes: &str) {
+        if attributes.is_empty() {
+            writeln!(self.output, "  {} -> {};", from, to).unwrap();
+        } else {
+            writeln!(self.output, "  {} -> {} [{}];", from, to, attributes).unwrap();
+        }
+    }
+}
+
+impl<'a> Drop for DotGraph<'a> {
+    fn drop(&mut self) {
+        writeln!(self.output, "}}").unwrap();
+    }
+}
@@ -1 +1,3 @@
+pub mod dot;
 pub mod print_tree;
+pub mod url;
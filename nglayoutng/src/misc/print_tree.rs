@@ -50,13 +50,13 @@ impl<'a> PrintTree<'a> {
         self.print_level_prefix();
         writeln!(self.output, "\u{251C}\u{2500} {}", title).unwrap();
 
-        self.level = self.level + 1;
+        self.level += 1;
     }
 
     /// Ascend one level in the tree.
     pub fn end_level(&mut self) {
         self.flush_queued_item("\u{2514}\u{2500}");
-        self.level = self.level - 1;
+        self.level -= 1;
     }
 
     /// Add an item to the current level in the tree.
@@ -0,0 +1,19 @@
+//! Resolution of the urls that documents reference, like the ones of web
+//! fonts or images.
+
+use std::path::{Path, PathBuf};
+
+/// Returns the path of the local file that `url` points to, resolving it
+/// against `base_directory` if relative, or `None` if it's not a local file.
+///
+/// TODO(emilio): Support network loads.
+pub fn local_file_path(url: &str, base_directory: Option<&Path>) -> Option<PathBuf> {
+    if url.contains("://") && !url.starts_with("file://") {
+        return None;
+    }
+    let path = Path::new(url.trim_start_matches("file://"));
+    Some(match base_directory {
+        Some(base) if path.is_relative() => base.join(path),
+        _ => path.to_owned(),
+    })
+}
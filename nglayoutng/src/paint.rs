@@ -0,0 +1,121 @@
+//! Rasterization of display lists, mostly useful for debugging and testing.
+
+use crate::display_list::{DisplayItem, DisplayList};
+use app_units::Au;
+use cssparser::RGBA;
+use euclid::default::Rect;
+use raqote::{DrawOptions, DrawTarget, PathBuilder, Point, SolidSource, Source, Transform};
+use std::io;
+use std::path::Path;
+
+fn solid_source(color: RGBA) -> Source<'static> {
+    Source::Solid(SolidSource::from_unpremultiplied_argb(
+        color.alpha,
+        color.red,
+        color.green,
+        color.blue,
+    ))
+}
+
+fn fill_rect(target: &mut DrawTarget, rect: Rect<Au>, color: RGBA) {
+    if rect.is_empty() {
+        return;
+    }
+    target.fill_rect(
+        rect.origin.x.to_f32_px(),
+        rect.origin.y.to_f32_px(),
+        rect.size.width.to_f32_px(),
+        rect.size.height.to_f32_px(),
+        &solid_source(color),
+        &DrawOptions::new(),
+    );
+}
+
+/// Paints a display list into a new draw target, with a white background.
+///
+/// The display list is in CSS pixels, each of which takes
+/// `device_pixel_ratio` pixels in each direction of the target.
+pub fn paint(list: &DisplayList, device_pixel_ratio: f32) -> DrawTarget {
+    let mut target = DrawTarget::new(
+        (list.size.width.to_f32_px() * device_pixel_ratio).ceil() as i32,
+        (list.size.height.to_f32_px() * device_pixel_ratio).ceil() as i32,
+    );
+    target.clear(SolidSource::from_unpremultiplied_argb(0xff, 0xff, 0xff, 0xff));
+    target.set_transform(&Transform::scale(device_pixel_ratio, device_pixel_ratio));
+
+    for item in &list.items {
+        match *item {
+            DisplayItem::SolidColor { rect, color } => fill_rect(&mut target, rect, color),
+            DisplayItem::Border { rect, widths, ref colors } => {
+                // TODO(emilio): Corners should be split diagonally between
+                // adjacent sides.
+                let [top, right, bottom, left] = *colors;
+                let inner_height = rect.size.height - widths.top - widths.bottom;
+                fill_rect(
+                    &mut target,
+                    Rect::new(rect.origin, euclid::size2(rect.size.width, widths.top)),
+                    top,
+                );
+                fill_rect(
+                    &mut target,
+                    Rect::new(
+                        euclid::point2(rect.max_x() - widths.right, rect.origin.y + widths.top),
+                        euclid::size2(widths.right, inner_height),
+                    ),
+                    right,
+                );
+                fill_rect(
+                    &mut target,
+                    Rect::new(
+                        euclid::point2(rect.origin.x, rect.max_y() - widths.bottom),
+                        euclid::size2(rect.size.width, widths.bottom),
+                    ),
+                    bottom,
+                );
+                fill_rect(
+                    &mut target,
+                    Rect::new(
+                        euclid::point2(rect.origin.x, rect.origin.y + widths.top),
+                        euclid::size2(widths.left, inner_height),
+                    ),
+                    left,
+                );
+            },
+            DisplayItem::PushClip { rect } => {
+                let mut path = PathBuilder::new();
+                path.rect(
+                    rect.origin.x.to_f32_px(),
+                    rect.origin.y.to_f32_px(),
+                    rect.size.width.to_f32_px(),
+                    rect.size.height.to_f32_px(),
+                );
+                target.push_clip(&path.finish());
+            },
+            DisplayItem::PopClip => target.pop_clip(),
+            DisplayItem::Text { ref font, font_size, color, ref glyphs } => {
+                let ids = glyphs.iter().map(|g| g.glyph_id).collect::<Vec<_>>();
+                let positions = glyphs
+                    .iter()
+                    .map(|g| Point::new(g.origin.x.to_f32_px(), g.origin.y.to_f32_px()))
+                    .collect::<Vec<_>>();
+                target.draw_glyphs(
+                    &font.font,
+                    font_size.to_f32_px(),
+                    &ids,
+                    &positions,
+                    &solid_source(color),
+                    &DrawOptions::new(),
+                );
+            },
+        }
+    }
+
+    target
+}
+
+/// Paints a display list and writes the result as a PNG image to `path`.
+pub fn paint_to_png(list: &DisplayList, device_pixel_ratio: f32, path: &Path) -> io::Result<()> {
+    paint(list, device_pixel_ratio)
+        .write_png(path)
+        .map_err(io::Error::other)
+}
@@ -0,0 +1,177 @@
+//! Geometry queries on the result of layout, the equivalent of
+//! `getBoundingClientRect()` and friends.
+//!
+//! https://drafts.csswg.org/cssom-view/#extensions-to-the-element-interface
+
+use crate::dom::DomNodeIds;
+use crate::fragment_tree::{ChildFragment, ContainerFragmentKind, Fragment, FragmentKind, FragmentTree};
+use crate::layout_tree::builder::PrincipalBoxes;
+use crate::layout_tree::LayoutNodeId;
+use crate::logical_geometry::{LogicalMargin, WritingMode};
+use crate::style::PseudoElement;
+use app_units::Au;
+use euclid::default::{Point2D, Rect};
+use std::collections::HashMap;
+
+/// A fragment, along with the information needed to answer queries about it.
+struct QueriedFragment<'a> {
+    fragment: &'a Fragment,
+    /// The physical border box of the fragment, relative to the viewport.
+    rect: Rect<Au>,
+    /// The size against which percentages in the fragment's padding resolve.
+    percentage_basis: Au,
+}
+
+impl<'a> QueriedFragment<'a> {
+    fn border(&self) -> LogicalMargin<Au> {
+        match self.fragment.kind {
+            FragmentKind::Container {
+                kind: ContainerFragmentKind::Box { ref decorations },
+                ..
+            } => decorations.border.map_all(|side| side.width),
+            _ => self.fragment.style.border_widths(),
+        }
+    }
+
+    fn border_padding(&self) -> LogicalMargin<Au> {
+        let padding = self.fragment.style.padding().map_all(|lp| lp.resolve(self.percentage_basis));
+        self.border() + padding
+    }
+
+    /// Returns the rect that results from removing `margin` from the border
+    /// box of this fragment.
+    fn deflated_rect(&self, margin: LogicalMargin<Au>) -> Rect<Au> {
+        let wm = self.fragment.style.writing_mode;
+        (self.fragment.border_box() - margin)
+            .to_physical(wm, self.rect.size)
+            .translate(self.rect.origin.to_vector())
+    }
+}
+
+/// Answers geometry queries about DOM nodes after layout.
+pub struct LayoutResultQuery<'a> {
+    dom_node_ids: &'a DomNodeIds,
+    principal_boxes: &'a PrincipalBoxes,
+    /// The fragments that each layout node has generated, in tree order.
+    fragments: HashMap<LayoutNodeId, Vec<QueriedFragment<'a>>>,
+}
+
+fn viewport_inline_size(wm: WritingMode, viewport: &Rect<Au>) -> Au {
+    if wm.is_vertical() {
+        viewport.size.height
+    } else {
+        viewport.size.width
+    }
+}
+
+fn to_px(rect: Rect<Au>) -> Rect<f32> {
+    Rect::new(
+        Point2D::new(rect.origin.x.to_f32_px(), rect.origin.y.to_f32_px()),
+        euclid::size2(rect.size.width.to_f32_px(), rect.size.height.to_f32_px()),
+    )
+}
+
+impl<'a> LayoutResultQuery<'a> {
+    pub fn new(
+        dom_node_ids: &'a DomNodeIds,
+        principal_boxes: &'a PrincipalBoxes,
+        fragment_tree: &'a FragmentTree,
+    ) -> Self {
+        let mut query = Self {
+            dom_node_ids,
+            principal_boxes,
+            fragments: HashMap::new(),
+        };
+        let root = &fragment_tree.root;
+        let wm = root.fragment.style.writing_mode;
+        let viewport = Rect::new(Point2D::zero(), fragment_tree.viewport_size);
+        let rect = root.physical_rect(wm, &viewport);
+        query.collect(root, rect, viewport_inline_size(wm, &viewport));
+        query
+    }
+
+    fn collect(&mut self, child: &'a ChildFragment, rect: Rect<Au>, percentage_basis: Au) {
+        let fragment = &*child.fragment;
+        let queried = QueriedFragment {
+            fragment,
+            rect,
+            percentage_basis,
+        };
+
+        // Children of boxes resolve percentages against the content box of
+        // the box, children of lines against the same size as the line.
+        //
+        // TODO(emilio): This is not right for out-of-flow children, nor in
+        // orthogonal flows.
+        let children_percentage_basis = match fragment.kind {
+            FragmentKind::Container {
+                kind: ContainerFragmentKind::Box { .. },
+                ..
+            } => fragment.size.inline - queried.border_padding().inline_start_end(),
+            _ => percentage_basis,
+        };
+
+        if let Some(node) = fragment.node {
+            self.fragments.entry(node).or_default().push(queried);
+        }
+
+        if let FragmentKind::Container { ref children, .. } = fragment.kind {
+            let wm = fragment.style.writing_mode;
+            for child in children.iter() {
+                // Nested documents have their own layout nodes, which can't
+                // be queried from this document.
+                if child.fragment.style.pseudo == Some(PseudoElement::Viewport) {
+                    continue;
+                }
+                let child_rect = child.physical_rect(wm, &rect);
+                self.collect(child, child_rect, children_percentage_basis);
+            }
+        }
+    }
+
+    fn fragments_for(&self, node: &kuchiki::Node) -> Option<&[QueriedFragment<'a>]> {
+        let id = self.principal_boxes.get(&self.dom_node_ids.get(node)?)?;
+        // TODO(emilio): Account for the continuations of inlines that have
+        // been split by a block.
+        let fragments = self.fragments.get(id)?;
+        if fragments.is_empty() {
+            return None;
+        }
+        Some(fragments)
+    }
+
+    fn union_of(&self, node: &kuchiki::Node, rect_for: impl Fn(&QueriedFragment) -> Rect<Au>) -> Option<Rect<f32>> {
+        let fragments = self.fragments_for(node)?;
+        let rect = fragments[1..]
+            .iter()
+            .fold(rect_for(&fragments[0]), |rect, fragment| rect.union(&rect_for(fragment)));
+        Some(to_px(rect))
+    }
+
+    /// Returns the union of the border boxes of all the fragments of a node,
+    /// in CSS pixels and relative to the viewport, or `None` if the node
+    /// generates no fragments.
+    ///
+    /// https://drafts.csswg.org/cssom-view/#dom-element-getboundingclientrect
+    pub fn border_box_rect(&self, node: &kuchiki::Node) -> Option<Rect<f32>> {
+        self.union_of(node, |fragment| fragment.rect)
+    }
+
+    /// Returns the union of the content boxes of all the fragments of a
+    /// node, in CSS pixels and relative to the viewport.
+    pub fn content_box_rect(&self, node: &kuchiki::Node) -> Option<Rect<f32>> {
+        self.union_of(node, |fragment| fragment.deflated_rect(fragment.border_padding()))
+    }
+
+    /// Returns the padding box of the first fragment of a node, in CSS pixels
+    /// and relative to the viewport.
+    ///
+    /// TODO(emilio): This should exclude the scrollbar of scroll containers,
+    /// and be empty for inline boxes.
+    ///
+    /// https://drafts.csswg.org/cssom-view/#dom-element-clientwidth
+    pub fn client_rect(&self, node: &kuchiki::Node) -> Option<Rect<f32>> {
+        let fragment = &self.fragments_for(node)?[0];
+        Some(to_px(fragment.deflated_rect(fragment.border())))
+    }
+}
@@ -11,11 +11,44 @@ pub enum ResolvedSize {
 }
 
 /// The min and max content sizes. These are always inline sizes.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct MinMaxSizes {
     pub min_content: Au,
     pub max_content: Au,
 }
 
+impl MinMaxSizes {
+    pub fn zero() -> Self {
+        Self::from(Au(0))
+    }
+
+    /// Makes each of the sizes the maximum of itself and the one in `other`.
+    pub fn max_assign(&mut self, other: Self) {
+        self.min_content = std::cmp::max(self.min_content, other.min_content);
+        self.max_content = std::cmp::max(self.max_content, other.max_content);
+    }
+}
+
+impl From<Au> for MinMaxSizes {
+    fn from(size: Au) -> Self {
+        Self {
+            min_content: size,
+            max_content: size,
+        }
+    }
+}
+
+impl std::ops::Add<Au> for MinMaxSizes {
+    type Output = Self;
+
+    fn add(self, size: Au) -> Self {
+        Self {
+            min_content: self.min_content + size,
+            max_content: self.max_content + size,
+        }
+    }
+}
+
 /// Computes the preferred size of a given box.
 pub fn pref_size(
     _style: &ComputedStyle,
@@ -26,7 +59,7 @@ pub fn pref_size(
     use crate::style::{Size, SizeKeyword};
 
     let cb_size = constraints.cb_size().convert(
-        constraints.containing_block_writing_mode,
+        constraints.containing_block_writing_mode(),
         style.writing_mode,
     );
 
@@ -1,7 +1,10 @@
+use crate::css::{PropertyMetadata, PROPERTIES};
+use crate::fonts::font_face::FontFaceSet;
 use crate::logical_geometry::{self, LogicalMargin, LogicalSize};
 use app_units::Au;
-use cssparser::{Color, RGBA};
+use cssparser::{Color, ToCss, RGBA};
 use euclid::default::{SideOffsets2D, Size2D};
+use std::fmt::{self, Write};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum DisplayInside {
@@ -43,6 +46,10 @@ impl Display {
         Self::new(DisplayOutside::Inline, DisplayInside::Flow)
     }
 
+    fn flow_root() -> Self {
+        Self::new(DisplayOutside::Block, DisplayInside::FlowRoot)
+    }
+
     pub fn parse<'i, 't>(
         input: &mut cssparser::Parser<'i, 't>,
     ) -> Result<Self, crate::css::ParseError<'i>> {
@@ -54,7 +61,7 @@ impl Display {
             "none" => Self::new(DisplayOutside::None, DisplayInside::None),
             "block" => Self::block(),
             "inline" => Self::inline(),
-            "flow-root" => Self::new(DisplayOutside::Block, DisplayInside::FlowRoot),
+            "flow-root" => Self::flow_root(),
             "inline-block" => Self::new(DisplayOutside::Inline, DisplayInside::FlowRoot),
             "list-item" => Self::new_list_item(DisplayOutside::Block, DisplayInside::Flow, true),
             _ => return Err(location.new_unexpected_token_error(
@@ -119,7 +126,21 @@ impl Display {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword)]
+impl ToCss for Display {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        dest.write_str(match (self.outside, self.inside, self.is_list_item) {
+            (DisplayOutside::None, ..) => "none",
+            (DisplayOutside::Contents, ..) => "contents",
+            (DisplayOutside::Block, DisplayInside::Flow, true) => "list-item",
+            (DisplayOutside::Block, DisplayInside::FlowRoot, _) => "flow-root",
+            (DisplayOutside::Block, ..) => "block",
+            (DisplayOutside::Inline, DisplayInside::FlowRoot, _) => "inline-block",
+            (DisplayOutside::Inline, ..) => "inline",
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword, ToCss, Animate)]
 pub enum WhiteSpace {
     Normal,
     Pre,
@@ -132,10 +153,7 @@ pub enum WhiteSpace {
 impl WhiteSpace {
     // https://drafts.csswg.org/css-text-3/#white-space-phase-1
     pub fn collapses_spaces(self) -> bool {
-        match self {
-            Self::Normal | Self::Nowrap | Self::PreLine => true,
-            _ => false,
-        }
+        matches!(self, Self::Normal | Self::Nowrap | Self::PreLine)
     }
 
     // https://drafts.csswg.org/css-text-3/#line-break-transform
@@ -148,9 +166,84 @@ impl WhiteSpace {
             }
         }
     }
+
+    // https://drafts.csswg.org/css-text-3/#white-space-property
+    pub fn allows_soft_wrap(self) -> bool {
+        match self {
+            Self::Pre | Self::Nowrap => false,
+            Self::Normal | Self::PreWrap | Self::BreakSpaces | Self::PreLine => true,
+        }
+    }
+
+    /// Whether spaces at the end of the line hang, that is, whether they're
+    /// not considered when measuring the line for fit.
+    ///
+    /// Collapsible spaces are removed at the end of the line (which is
+    /// effectively the same for line-breaking purposes), and preserved spaces
+    /// hang with `pre-wrap`, but with `break-spaces` they take space and can
+    /// wrap.
+    ///
+    /// https://drafts.csswg.org/css-text-3/#white-space-phase-2
+    pub fn trailing_spaces_hang(self) -> bool {
+        self != Self::BreakSpaces
+    }
+}
+
+// https://drafts.csswg.org/css-text-3/#word-break-property
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword, ToCss, Animate)]
+pub enum WordBreak {
+    Normal,
+    BreakAll,
+    KeepAll,
+}
+
+// https://drafts.csswg.org/css-text-3/#overflow-wrap-property
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword, ToCss, Animate)]
+pub enum OverflowWrap {
+    Normal,
+    BreakWord,
+    Anywhere,
+}
+
+impl OverflowWrap {
+    /// Whether an otherwise unbreakable sequence of characters may be broken
+    /// at an arbitrary point if there are no otherwise-acceptable break points
+    /// in the line.
+    ///
+    /// Note that `break-word` and `anywhere` only differ in whether these
+    /// opportunities are considered for min-content intrinsic sizes.
+    pub fn allows_emergency_break(self) -> bool {
+        self != Self::Normal
+    }
+}
+
+// https://drafts.csswg.org/css-text-3/#text-transform-property
+//
+// TODO(emilio): full-width / full-size-kana.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword, ToCss, Animate)]
+pub enum TextTransform {
+    None,
+    Uppercase,
+    Lowercase,
+    Capitalize,
+}
+
+// https://drafts.csswg.org/css-overflow-3/#text-overflow
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword, ToCss, Animate)]
+pub enum TextOverflow {
+    Clip,
+    Ellipsis,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword)]
+// https://drafts.csswg.org/css-text-3/#hyphens-property
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword, ToCss, Animate)]
+pub enum Hyphens {
+    None,
+    Manual,
+    Auto,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword, ToCss, Animate)]
 pub enum BoxSizing {
     ContentBox,
     BorderBox,
@@ -166,7 +259,7 @@ impl BoxSizing {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword, ToCss, Animate)]
 pub enum Position {
     Static,
     Absolute,
@@ -184,20 +277,20 @@ impl Position {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Keyword, ToCss, Animate)]
 pub enum Direction {
     Ltr,
     Rtl,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword, ToCss, Animate)]
 pub enum Float {
     Left,
     Right,
     None,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword, ToCss, Animate)]
 pub enum Overflow {
     Visible,
     Hidden,
@@ -205,7 +298,7 @@ pub enum Overflow {
     Auto,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword, ToCss, Animate)]
 pub enum Clear {
     None,
     Left,
@@ -213,7 +306,7 @@ pub enum Clear {
     Both,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword, ToCss, Animate)]
 pub enum BorderStyle {
     None,
     Solid,
@@ -227,7 +320,15 @@ pub enum BorderStyle {
     Outset,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword)]
+impl BorderStyle {
+    /// Whether this style suppresses the border entirely.
+    #[inline]
+    pub fn is_none_or_hidden(self) -> bool {
+        matches!(self, BorderStyle::None | BorderStyle::Hidden)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword, ToCss, Animate)]
 pub enum WritingMode {
     HorizontalTb,
     VerticalRl,
@@ -236,28 +337,355 @@ pub enum WritingMode {
     SidewaysLr,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword, ToCss, Animate)]
 pub enum TextOrientation {
     Mixed,
     Upright,
     Sideways,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword)]
-pub enum FontWeight {
-    Normal,
-    Bold,
-    // TODO: Numbers
+/// The size of the `medium` font-size keyword, which is also the initial value
+/// of font-size, in CSS pixels.
+const MEDIUM_FONT_SIZE_PX: f32 = 16.;
+
+/// The ratio between adjacent sizes for `larger` and `smaller`.
+///
+/// https://drafts.csswg.org/css-fonts/#relative-size-value
+const RELATIVE_FONT_SIZE_RATIO: f32 = 1.2;
+
+// https://drafts.csswg.org/css-fonts/#absolute-size-value
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword, ToCss, Animate)]
+pub enum AbsoluteFontSize {
+    XxSmall,
+    XSmall,
+    Small,
+    Medium,
+    Large,
+    XLarge,
+    XxLarge,
+    XxxLarge,
+}
+
+impl AbsoluteFontSize {
+    /// https://drafts.csswg.org/css-fonts/#absolute-size-mapping
+    fn to_computed_value(self) -> Length {
+        let ratio = match self {
+            AbsoluteFontSize::XxSmall => 3. / 5.,
+            AbsoluteFontSize::XSmall => 3. / 4.,
+            AbsoluteFontSize::Small => 8. / 9.,
+            AbsoluteFontSize::Medium => 1.,
+            AbsoluteFontSize::Large => 6. / 5.,
+            AbsoluteFontSize::XLarge => 3. / 2.,
+            AbsoluteFontSize::XxLarge => 2.,
+            AbsoluteFontSize::XxxLarge => 3.,
+        };
+        Length(Au::from_f32_px(MEDIUM_FONT_SIZE_PX * ratio))
+    }
+}
+
+/// https://drafts.csswg.org/css-fonts/#font-size-prop
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SpecifiedFontSize {
+    Absolute(AbsoluteFontSize),
+    Larger,
+    Smaller,
+    Length(Length),
+    /// A multiple of the parent's font size, from either `em` units or
+    /// percentages.
+    Relative(f32),
+}
+
+impl SpecifiedFontSize {
+    pub fn parse<'i>(
+        input: &mut cssparser::Parser<'i, '_>,
+    ) -> Result<Self, crate::css::ParseError<'i>> {
+        if let Ok(size) = input.try_parse(AbsoluteFontSize::parse) {
+            return Ok(SpecifiedFontSize::Absolute(size));
+        }
+        if input.try_parse(|i| i.expect_ident_matching("larger")).is_ok() {
+            return Ok(SpecifiedFontSize::Larger);
+        }
+        if input.try_parse(|i| i.expect_ident_matching("smaller")).is_ok() {
+            return Ok(SpecifiedFontSize::Smaller);
+        }
+
+        let location = input.current_source_location();
+        let em = input.try_parse(|i| -> Result<f32, crate::css::ParseError<'i>> {
+            match *i.next()? {
+                cssparser::Token::Dimension { ref unit, value, .. }
+                    if unit.eq_ignore_ascii_case("em") => Ok(value),
+                ref t => Err(location.new_unexpected_token_error(t.clone())),
+            }
+        });
+        let size = if let Ok(em) = em {
+            SpecifiedFontSize::Relative(em)
+        } else if let Ok(percentage) = input.try_parse(|i| i.expect_percentage()) {
+            SpecifiedFontSize::Relative(percentage)
+        } else {
+            SpecifiedFontSize::Length(crate::css::parse_length(input)?)
+        };
+        let negative = match size {
+            SpecifiedFontSize::Relative(r) => r < 0.,
+            SpecifiedFontSize::Length(l) => l.0 < Au(0),
+            _ => false,
+        };
+        if negative {
+            return Err(location.new_custom_error(crate::css::Error::NegativeValue));
+        }
+        Ok(size)
+    }
+
+    pub fn to_computed_value(&self, parent: &MutableComputedStyle) -> Length {
+        let parent_size = parent.font_size.0;
+        match *self {
+            SpecifiedFontSize::Absolute(size) => size.to_computed_value(),
+            SpecifiedFontSize::Larger => Length(parent_size.scale_by(RELATIVE_FONT_SIZE_RATIO)),
+            SpecifiedFontSize::Smaller => Length(parent_size.scale_by(1. / RELATIVE_FONT_SIZE_RATIO)),
+            SpecifiedFontSize::Length(length) => length,
+            SpecifiedFontSize::Relative(ratio) => Length(parent_size.scale_by(ratio)),
+        }
+    }
+}
+
+/// A computed font weight, in the range [1, 1000].
+///
+/// https://drafts.csswg.org/css-fonts-4/#font-weight-prop
+#[derive(Debug, Copy, Clone, PartialEq, Animate)]
+pub struct FontWeight(pub f32);
+
+impl FontWeight {
+    pub const NORMAL: Self = FontWeight(400.);
+    pub const BOLD: Self = FontWeight(700.);
+
+    /// Parses an absolute font weight (that is, not `bolder` or `lighter`).
+    pub fn parse<'i>(
+        input: &mut cssparser::Parser<'i, '_>,
+    ) -> Result<Self, crate::css::ParseError<'i>> {
+        let location = input.current_source_location();
+        if let Ok(weight) = input.try_parse(|i| i.expect_number()) {
+            if !(1. ..=1000.).contains(&weight) {
+                return Err(location.new_custom_error(crate::css::Error::OutOfRangeValue));
+            }
+            return Ok(FontWeight(weight));
+        }
+        let ident = input.expect_ident()?;
+        Ok(match_ignore_ascii_case! { &ident,
+            "normal" => Self::NORMAL,
+            "bold" => Self::BOLD,
+            _ => return Err(location.new_unexpected_token_error(
+                cssparser::Token::Ident(ident.clone())
+            )),
+        })
+    }
+
+    /// https://drafts.csswg.org/css-fonts-4/#relative-weights
+    pub fn bolder(self) -> Self {
+        FontWeight(if self.0 < 350. {
+            400.
+        } else if self.0 < 550. {
+            700.
+        } else {
+            self.0.max(900.)
+        })
+    }
+
+    /// https://drafts.csswg.org/css-fonts-4/#relative-weights
+    pub fn lighter(self) -> Self {
+        FontWeight(if self.0 < 100. {
+            self.0
+        } else if self.0 < 550. {
+            100.
+        } else if self.0 < 750. {
+            400.
+        } else {
+            700.
+        })
+    }
+}
+
+impl ToCss for FontWeight {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        write!(dest, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SpecifiedFontWeight {
+    Absolute(FontWeight),
+    Bolder,
+    Lighter,
+}
+
+impl SpecifiedFontWeight {
+    pub fn parse<'i>(
+        input: &mut cssparser::Parser<'i, '_>,
+    ) -> Result<Self, crate::css::ParseError<'i>> {
+        if let Ok(weight) = input.try_parse(FontWeight::parse) {
+            return Ok(SpecifiedFontWeight::Absolute(weight));
+        }
+        let location = input.current_source_location();
+        let ident = input.expect_ident()?;
+        Ok(match_ignore_ascii_case! { &ident,
+            "bolder" => SpecifiedFontWeight::Bolder,
+            "lighter" => SpecifiedFontWeight::Lighter,
+            _ => return Err(location.new_unexpected_token_error(
+                cssparser::Token::Ident(ident.clone())
+            )),
+        })
+    }
+
+    pub fn to_computed_value(&self, parent: &MutableComputedStyle) -> FontWeight {
+        match *self {
+            SpecifiedFontWeight::Absolute(weight) => weight,
+            SpecifiedFontWeight::Bolder => parent.font_weight.bolder(),
+            SpecifiedFontWeight::Lighter => parent.font_weight.lighter(),
+        }
+    }
+}
+
+/// A computed font stretch, as a ratio of the normal width (so 1.0 means
+/// 100%).
+///
+/// https://drafts.csswg.org/css-fonts-4/#font-stretch-prop
+#[derive(Debug, Copy, Clone, PartialEq, Animate)]
+pub struct FontStretch(pub f32);
+
+impl FontStretch {
+    pub const NORMAL: Self = FontStretch(1.);
+
+    pub fn parse<'i>(
+        input: &mut cssparser::Parser<'i, '_>,
+    ) -> Result<Self, crate::css::ParseError<'i>> {
+        let location = input.current_source_location();
+        if let Ok(percentage) = input.try_parse(|i| i.expect_percentage()) {
+            if percentage < 0. {
+                return Err(location.new_custom_error(crate::css::Error::NegativeValue));
+            }
+            return Ok(FontStretch(percentage));
+        }
+        Self::parse_keyword(input)
+    }
+
+    /// Parses only the keyword values, which is what the `font` shorthand
+    /// accepts.
+    pub fn parse_keyword<'i>(
+        input: &mut cssparser::Parser<'i, '_>,
+    ) -> Result<Self, crate::css::ParseError<'i>> {
+        let location = input.current_source_location();
+        let ident = input.expect_ident()?;
+        Ok(FontStretch(match_ignore_ascii_case! { &ident,
+            "ultra-condensed" => 0.5,
+            "extra-condensed" => 0.625,
+            "condensed" => 0.75,
+            "semi-condensed" => 0.875,
+            "normal" => 1.,
+            "semi-expanded" => 1.125,
+            "expanded" => 1.25,
+            "extra-expanded" => 1.5,
+            "ultra-expanded" => 2.,
+            _ => return Err(location.new_unexpected_token_error(
+                cssparser::Token::Ident(ident.clone())
+            )),
+        }))
+    }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword)]
+impl ToCss for FontStretch {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        Percentage(self.0).to_css(dest)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword, ToCss, Animate)]
 pub enum FontStyle {
     Normal,
     Italic,
     // TODO: Oblique <angle>
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword)]
+// https://drafts.csswg.org/css-fonts/#font-variant-caps-prop
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Keyword, ToCss, Animate)]
+pub enum FontVariantCaps {
+    Normal,
+    SmallCaps,
+    AllSmallCaps,
+    // TODO: petite-caps, all-petite-caps, unicase, titling-caps.
+}
+
+/// https://drafts.csswg.org/css-fonts/#feature-tag-value
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FeatureTagValue {
+    /// The OpenType feature tag, four ASCII characters.
+    pub tag: [u8; 4],
+    pub value: u32,
+}
+
+impl FeatureTagValue {
+    pub fn parse<'i>(
+        input: &mut cssparser::Parser<'i, '_>,
+    ) -> Result<Self, crate::css::ParseError<'i>> {
+        let location = input.current_source_location();
+        let tag = input.expect_string()?;
+        // Feature tags are exactly four printable ASCII characters.
+        let bytes = tag.as_bytes();
+        if bytes.len() != 4 || bytes.iter().any(|b| *b < 0x20 || *b > 0x7e) {
+            return Err(location.new_custom_error(crate::css::Error::InvalidFeatureTag));
+        }
+        let tag = [bytes[0], bytes[1], bytes[2], bytes[3]];
+
+        let location = input.current_source_location();
+        let value = if let Ok(value) = input.try_parse(|i| i.expect_integer()) {
+            if value < 0 {
+                return Err(location.new_custom_error(crate::css::Error::NegativeValue));
+            }
+            value as u32
+        } else if input.try_parse(|i| i.expect_ident_matching("off")).is_ok() {
+            0
+        } else {
+            let _ = input.try_parse(|i| i.expect_ident_matching("on"));
+            1
+        };
+        Ok(FeatureTagValue { tag, value })
+    }
+}
+
+impl ToCss for FeatureTagValue {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        // The tag is validated to be printable ASCII when parsing.
+        cssparser::serialize_string(std::str::from_utf8(&self.tag).unwrap(), dest)?;
+        if self.value != 1 {
+            write!(dest, " {}", self.value)?;
+        }
+        Ok(())
+    }
+}
+
+// https://drafts.csswg.org/css-fonts/#font-feature-settings-prop
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FontFeatureSettings(pub Box<[FeatureTagValue]>);
+
+impl FontFeatureSettings {
+    pub fn parse<'i>(
+        input: &mut cssparser::Parser<'i, '_>,
+    ) -> Result<Self, crate::css::ParseError<'i>> {
+        if input.try_parse(|i| i.expect_ident_matching("normal")).is_ok() {
+            return Ok(Default::default());
+        }
+        let features = input.parse_comma_separated(FeatureTagValue::parse)?;
+        Ok(FontFeatureSettings(features.into_boxed_slice()))
+    }
+}
+
+impl ToCss for FontFeatureSettings {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        if self.0.is_empty() {
+            return dest.write_str("normal");
+        }
+        comma_separated_to_css(&self.0, dest)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword, ToCss, Animate)]
 pub enum GenericFamily {
     Serif,
     SansSerif,
@@ -319,7 +747,7 @@ impl SingleFontFamily {
             let ident = input.expect_ident()?;
             serialize_quoted = serialize_quoted || ident.contains(' ');
             value.push(' ');
-            value.push_str(&ident);
+            value.push_str(ident);
         }
 
         while let Ok(ident) = input.try_parse(|i| i.expect_ident_cloned()) {
@@ -343,6 +771,27 @@ impl SingleFontFamily {
     }
 }
 
+impl ToCss for SingleFontFamily {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        let family = match *self {
+            SingleFontFamily::Generic(ref generic) => return generic.to_css(dest),
+            SingleFontFamily::Named(ref family) => family,
+        };
+        match family.syntax {
+            FontFamilyNameSyntax::Quoted => cssparser::serialize_string(&family.name, dest),
+            FontFamilyNameSyntax::Identifiers => {
+                for (i, ident) in family.name.split(' ').enumerate() {
+                    if i != 0 {
+                        dest.write_char(' ')?;
+                    }
+                    cssparser::serialize_identifier(ident, dest)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FontFamilyList(Box<[SingleFontFamily]>);
 
@@ -350,7 +799,7 @@ impl std::ops::Deref for FontFamilyList {
     type Target = [SingleFontFamily];
 
     fn deref(&self) -> &Self::Target {
-        &*self.0
+        &self.0
     }
 }
 
@@ -363,11 +812,33 @@ impl FontFamilyList {
     }
 }
 
+impl ToCss for FontFamilyList {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        comma_separated_to_css(&self.0, dest)
+    }
+}
+
+fn comma_separated_to_css<T: ToCss, W: Write>(items: &[T], dest: &mut W) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i != 0 {
+            dest.write_str(", ")?;
+        }
+        item.to_css(dest)?;
+    }
+    Ok(())
+}
+
 /// A percentage in the range 0.0..1.0.
 #[derive(Default, Debug, Copy, Clone, PartialEq)]
 pub struct Percentage(pub f32);
 
-#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+impl ToCss for Percentage {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        write!(dest, "{}%", self.0 * 100.)
+    }
+}
+
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Animate)]
 pub struct Length(pub Au);
 
 impl Length {
@@ -387,6 +858,12 @@ impl std::fmt::Display for Length {
     }
 }
 
+impl ToCss for Length {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        write!(dest, "{}", self)
+    }
+}
+
 #[derive(Default, Debug, Clone, PartialEq)]
 pub struct LengthPercentage {
     pub fixed: Length,
@@ -397,6 +874,10 @@ impl LengthPercentage {
     pub fn is_zero(&self) -> bool {
         self.fixed.is_zero() && self.percentage.is_none()
     }
+
+    pub fn has_percentage(&self) -> bool {
+        self.percentage.is_some()
+    }
 }
 
 impl LengthPercentage {
@@ -419,9 +900,33 @@ impl LengthPercentage {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl ToCss for LengthPercentage {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        let percentage = match self.percentage {
+            Some(percentage) => percentage,
+            None => return self.fixed.to_css(dest),
+        };
+        if self.fixed.is_zero() {
+            return percentage.to_css(dest);
+        }
+        // This is the only calc() syntax we can parse.
+        dest.write_str("calc(")?;
+        self.fixed.to_css(dest)?;
+        if percentage.0 < 0. {
+            dest.write_str(" - ")?;
+            Percentage(-percentage.0).to_css(dest)?;
+        } else {
+            dest.write_str(" + ")?;
+            percentage.to_css(dest)?;
+        }
+        dest.write_char(')')
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, ToCss, Animate, Default)]
 pub enum LengthPercentageOrAuto {
     LengthPercentage(LengthPercentage),
+    #[default]
     Auto,
 }
 
@@ -440,30 +945,28 @@ impl LengthPercentageOrAuto {
     pub fn is_zero_or_auto(&self) -> bool {
         self.is_auto() || self.is_zero()
     }
-}
 
-impl Default for LengthPercentageOrAuto {
-    fn default() -> Self {
-        LengthPercentageOrAuto::Auto
+    pub fn has_percentage(&self) -> bool {
+        match *self {
+            LengthPercentageOrAuto::LengthPercentage(ref lp) => lp.has_percentage(),
+            LengthPercentageOrAuto::Auto => false,
+        }
     }
 }
 
+
 /// https://drafts.csswg.org/css-sizing/#sizing-properties
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Keyword, ToCss, Animate, Default)]
 pub enum SizeKeyword {
+    #[default]
     Auto,
     MinContent,
     MaxContent,
 }
 
-impl Default for SizeKeyword {
-    fn default() -> Self {
-        SizeKeyword::Auto
-    }
-}
 
 /// https://drafts.csswg.org/css-sizing/#sizing-properties
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, ToCss, Animate)]
 pub enum Size {
     LengthPercentage(LengthPercentage),
     Keyword(SizeKeyword),
@@ -476,6 +979,133 @@ impl Default for Size {
     }
 }
 
+impl Size {
+    pub fn has_percentage(&self) -> bool {
+        match *self {
+            Size::LengthPercentage(ref lp) => lp.has_percentage(),
+            Size::Keyword(..) => false,
+        }
+    }
+}
+
+/// https://drafts.csswg.org/css-values-4/#ratios
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ratio {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Ratio {
+    pub fn parse<'i>(
+        input: &mut cssparser::Parser<'i, '_>,
+    ) -> Result<Self, crate::css::ParseError<'i>> {
+        let location = input.current_source_location();
+        let width = input.expect_number()?;
+        let height = if input.try_parse(|i| i.expect_delim('/')).is_ok() {
+            input.expect_number()?
+        } else {
+            1.
+        };
+        if width < 0. || height < 0. {
+            return Err(location.new_custom_error(crate::css::Error::NegativeValue));
+        }
+        Ok(Self { width, height })
+    }
+
+    /// Returns the ratio of a physical size, or `None` if it'd be degenerate.
+    pub fn from_size(size: Size2D<Au>) -> Option<Self> {
+        let ratio = Self {
+            width: size.width.to_f32_px(),
+            height: size.height.to_f32_px(),
+        };
+        if ratio.is_degenerate() {
+            return None;
+        }
+        Some(ratio)
+    }
+
+    /// A ratio with a zero (or infinite) component, which behaves as if
+    /// there was no ratio at all.
+    ///
+    /// https://drafts.csswg.org/css-values-4/#degenerate-ratio
+    pub fn is_degenerate(&self) -> bool {
+        !self.width.is_normal() || !self.height.is_normal()
+    }
+
+    /// Returns the block size that corresponds to `inline_size` in the
+    /// writing mode `wm`.
+    pub fn block_size_from_inline(&self, wm: logical_geometry::WritingMode, inline_size: Au) -> Au {
+        debug_assert!(!self.is_degenerate());
+        if wm.is_vertical() {
+            inline_size.scale_by(self.width / self.height)
+        } else {
+            inline_size.scale_by(self.height / self.width)
+        }
+    }
+
+    /// Returns the inline size that corresponds to `block_size` in the
+    /// writing mode `wm`.
+    pub fn inline_size_from_block(&self, wm: logical_geometry::WritingMode, block_size: Au) -> Au {
+        debug_assert!(!self.is_degenerate());
+        if wm.is_vertical() {
+            block_size.scale_by(self.height / self.width)
+        } else {
+            block_size.scale_by(self.width / self.height)
+        }
+    }
+}
+
+impl ToCss for Ratio {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        write!(dest, "{} / {}", self.width, self.height)
+    }
+}
+
+/// https://drafts.csswg.org/css-sizing-4/#aspect-ratio
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AspectRatio {
+    /// Whether the natural aspect ratio of replaced elements takes precedence
+    /// over `ratio`.
+    pub auto: bool,
+    pub ratio: Option<Ratio>,
+}
+
+impl AspectRatio {
+    pub fn auto() -> Self {
+        Self { auto: true, ratio: None }
+    }
+
+    /// Parses `auto || <ratio>`.
+    pub fn parse<'i>(
+        input: &mut cssparser::Parser<'i, '_>,
+    ) -> Result<Self, crate::css::ParseError<'i>> {
+        let mut auto = input.try_parse(|i| i.expect_ident_matching("auto")).is_ok();
+        let ratio = input.try_parse(Ratio::parse).ok();
+        if ratio.is_some() && !auto {
+            auto = input.try_parse(|i| i.expect_ident_matching("auto")).is_ok();
+        }
+        if !auto && ratio.is_none() {
+            return Err(input.new_custom_error(crate::css::Error::InvalidAspectRatio));
+        }
+        Ok(Self { auto, ratio })
+    }
+}
+
+impl ToCss for AspectRatio {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        if self.auto {
+            dest.write_str("auto")?;
+        }
+        if let Some(ref ratio) = self.ratio {
+            if self.auto {
+                dest.write_char(' ')?;
+            }
+            ratio.to_css(dest)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum PseudoElement {
     Before,
@@ -488,6 +1118,16 @@ pub enum PseudoElement {
     BlockInsideInlineWrapper,
     /// An anonymous inline box for the continuation of an inline.
     InlineContinuation,
+    /// The anonymous box that contains everything in a fieldset but its
+    /// rendered legend.
+    ///
+    /// https://html.spec.whatwg.org/#anonymous-fieldset-content-box
+    FieldsetContent,
+    /// The disclosure triangle of a `<summary>`, which behaves like its
+    /// `::marker`.
+    ///
+    /// https://html.spec.whatwg.org/#the-details-and-summary-elements
+    Marker,
 }
 
 impl PseudoElement {
@@ -495,16 +1135,17 @@ impl PseudoElement {
     #[inline]
     pub fn is_anonymous(self) -> bool {
         match self {
-            PseudoElement::Before | PseudoElement::After => false,
+            PseudoElement::Before | PseudoElement::After | PseudoElement::Marker => false,
             PseudoElement::Viewport |
             PseudoElement::InlineInsideBlockWrapper |
             PseudoElement::InlineContinuation |
-            PseudoElement::BlockInsideInlineWrapper => true,
+            PseudoElement::BlockInsideInlineWrapper |
+            PseudoElement::FieldsetContent => true,
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Animate)]
 pub enum LineHeight {
     Normal,
     Number(f32),
@@ -525,23 +1166,98 @@ impl LineHeight {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl ToCss for LineHeight {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        match *self {
+            LineHeight::Normal => dest.write_str("normal"),
+            LineHeight::Number(number) => write!(dest, "{}", number),
+            LineHeight::Length(ref lp) => lp.to_css(dest),
+        }
+    }
+}
+
+// https://drafts.csswg.org/css-text-3/#tab-size-property
+#[derive(Debug, Copy, Clone, PartialEq, Animate)]
+pub enum TabSize {
+    /// A multiple of the advance width of the space character.
+    Number(f32),
+    Length(Length),
+}
+
+impl TabSize {
+    pub fn parse<'i>(
+        input: &mut cssparser::Parser<'i, '_>,
+    ) -> Result<Self, crate::css::ParseError<'i>> {
+        let location = input.current_source_location();
+        let tab_size = match input.try_parse(|i| i.expect_number()) {
+            Ok(number) => TabSize::Number(number),
+            Err(..) => TabSize::Length(crate::css::parse_length(input)?),
+        };
+        let negative = match tab_size {
+            TabSize::Number(n) => n < 0.,
+            TabSize::Length(l) => l.0 < Au(0),
+        };
+        if negative {
+            return Err(location.new_custom_error(crate::css::Error::NegativeValue));
+        }
+        Ok(tab_size)
+    }
+
+    /// Returns the distance between tab stops, given the advance of the space
+    /// character.
+    pub fn resolve(&self, space_advance: Au) -> Au {
+        match *self {
+            TabSize::Number(n) => space_advance.scale_by(n),
+            TabSize::Length(l) => l.0,
+        }
+    }
+}
+
+impl ToCss for TabSize {
+    fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        match *self {
+            TabSize::Number(number) => write!(dest, "{}", number),
+            TabSize::Length(length) => length.to_css(dest),
+        }
+    }
+}
+
+/// How much work a style change requires.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StyleDifference {
+    /// The styles are equal.
+    Equal,
+    /// The style of the existing boxes can be updated in place.
+    UpdateStyle,
+    /// The boxes need to be destroyed and constructed again, since the
+    /// change affects the shape of the layout tree.
+    ReconstructBoxes,
+}
+
+#[derive(Debug, Clone, PartialEq, Animate)]
 pub struct MutableComputedStyle {
+    #[animate(discrete)]
     pub pseudo: Option<PseudoElement>,
+    #[animate(discrete)]
     pub writing_mode: logical_geometry::WritingMode,
 
+    #[animate(discrete)]
     pub display: Display,
     /// The original display value of the item.
     ///
     /// Needed to compute hypothetical positions of abspos elements.
+    #[animate(discrete)]
     pub original_display: Display,
     pub computed_writing_mode: WritingMode,
     pub position: Position,
     pub box_sizing: BoxSizing,
+    #[animate(discrete)]
+    pub aspect_ratio: AspectRatio,
     pub float: Float,
     pub clear: Clear,
     pub overflow_x: Overflow,
     pub overflow_y: Overflow,
+    pub text_overflow: TextOverflow,
     pub direction: Direction,
     pub text_orientation: TextOrientation,
 
@@ -588,11 +1304,22 @@ pub struct MutableComputedStyle {
     pub left: LengthPercentage,
 
     pub white_space: WhiteSpace,
+    pub word_break: WordBreak,
+    pub overflow_wrap: OverflowWrap,
+    pub hyphens: Hyphens,
+    pub text_indent: LengthPercentage,
+    pub text_transform: TextTransform,
+    pub tab_size: TabSize,
 
     pub font_size: Length,
+    #[animate(discrete)]
     pub font_family: FontFamilyList,
     pub font_style: FontStyle,
     pub font_weight: FontWeight,
+    pub font_stretch: FontStretch,
+    pub font_variant_caps: FontVariantCaps,
+    #[animate(discrete)]
+    pub font_feature_settings: FontFeatureSettings,
     pub line_height: LineHeight,
 }
 
@@ -641,6 +1368,12 @@ impl ::std::ops::Deref for ComputedStyle {
     }
 }
 
+impl crate::animate::Animate for ComputedStyle {
+    fn animate(&self, other: &Self, progress: f32) -> Result<Self, ()> {
+        Ok(ComputedStyle(self.0.animate(&other.0, progress)?))
+    }
+}
+
 impl MutableComputedStyle {
     pub fn is_floating(&self) -> bool {
         self.float != Float::None
@@ -655,12 +1388,70 @@ impl MutableComputedStyle {
     }
 
     pub fn is_ib_split_wrapper(&self) -> bool {
-        self.pseudo
-            .map_or(false, |p| p == PseudoElement::BlockInsideInlineWrapper)
+        self.pseudo == Some(PseudoElement::BlockInsideInlineWrapper)
     }
 }
 
 impl ComputedStyle {
+    /// Computes the work needed to go from `self` to `new_style`.
+    pub fn difference(&self, new_style: &Self) -> StyleDifference {
+        if self == new_style {
+            return StyleDifference::Equal;
+        }
+        // These determine the kind of box we generate and where in the tree
+        // it goes.
+        if self.display != new_style.display ||
+            self.original_display != new_style.original_display ||
+            self.float != new_style.float ||
+            self.position != new_style.position
+        {
+            return StyleDifference::ReconstructBoxes;
+        }
+        StyleDifference::UpdateStyle
+    }
+
+    /// Serializes the resolved value of a longhand, like
+    /// `getComputedStyle(element).getPropertyValue(name)`, or returns an
+    /// empty string if it's not a longhand we support. Flow-relative
+    /// properties resolve against the writing mode of this style.
+    ///
+    /// TODO(emilio): The resolved value of the sizes, margins, paddings and
+    /// insets of an element with a box is its used value, but the style
+    /// doesn't know about layout, so those serialize their computed value.
+    ///
+    /// https://drafts.csswg.org/cssom/#resolved-value
+    pub fn serialize_property(&self, name: &str) -> String {
+        match PropertyMetadata::find(name) {
+            Some(property) => (property.serialize)(self),
+            None => String::new(),
+        }
+    }
+
+    /// Serializes the resolved value of all the physical longhands of this
+    /// style as a declaration block, with one declaration per line.
+    ///
+    /// Like `getComputedStyle`, properties are sorted by name.
+    ///
+    /// https://drafts.csswg.org/cssom/#dom-window-getcomputedstyle
+    pub fn to_css<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        let mut longhands = PROPERTIES.iter().filter(|p| !p.logical).collect::<Vec<_>>();
+        longhands.sort_by_key(|p| p.name);
+        for property in longhands {
+            dest.write_str(property.name)?;
+            dest.write_str(": ")?;
+            dest.write_str(&(property.serialize)(self))?;
+            dest.write_str(";\n")?;
+        }
+        Ok(())
+    }
+
+    /// Like `to_css`, but returns the declaration block as a string.
+    pub fn to_css_block(&self) -> String {
+        let mut css = String::new();
+        self.to_css(&mut css).unwrap();
+        css
+    }
+
     pub fn initial() -> MutableComputedStyle {
         let direction = Direction::Ltr;
         let text_orientation = TextOrientation::Mixed;
@@ -682,10 +1473,12 @@ impl ComputedStyle {
             original_display: Display::inline(),
             position: Position::Static,
             box_sizing: BoxSizing::ContentBox,
+            aspect_ratio: AspectRatio::auto(),
             float: Float::None,
             clear: Clear::None,
             overflow_x: Overflow::Visible,
             overflow_y: Overflow::Visible,
+            text_overflow: TextOverflow::Clip,
 
             width: Default::default(),
             height: Default::default(),
@@ -701,10 +1494,10 @@ impl ComputedStyle {
             padding_bottom: Default::default(),
             padding_left: Default::default(),
 
-            margin_top: Default::default(),
-            margin_right: Default::default(),
-            margin_bottom: Default::default(),
-            margin_left: Default::default(),
+            margin_top: LengthPercentageOrAuto::LengthPercentage(Default::default()),
+            margin_right: LengthPercentageOrAuto::LengthPercentage(Default::default()),
+            margin_bottom: LengthPercentageOrAuto::LengthPercentage(Default::default()),
+            margin_left: LengthPercentageOrAuto::LengthPercentage(Default::default()),
 
             border_top_width: Default::default(),
             border_right_width: Default::default(),
@@ -727,34 +1520,75 @@ impl ComputedStyle {
             left: Default::default(),
 
             white_space: WhiteSpace::Normal,
-
-            font_size: Length(Au::from_px(16)),
+            word_break: WordBreak::Normal,
+            overflow_wrap: OverflowWrap::Normal,
+            hyphens: Hyphens::Manual,
+            text_indent: Default::default(),
+            text_transform: TextTransform::None,
+            tab_size: TabSize::Number(8.),
+
+            font_size: Length(Au::from_f32_px(MEDIUM_FONT_SIZE_PX)),
             font_family: FontFamilyList(Box::new([SingleFontFamily::Generic(GenericFamily::Serif)])),
             font_style: FontStyle::Normal,
-            font_weight: FontWeight::Normal,
+            font_weight: FontWeight::NORMAL,
+            font_stretch: FontStretch::NORMAL,
+            font_variant_caps: FontVariantCaps::Normal,
+            font_feature_settings: Default::default(),
             line_height: LineHeight::Normal,
         }
     }
 
+    /// Returns the style a child of an element with this style starts with,
+    /// before applying its own declarations: inherited properties come from
+    /// this style, and the rest have their initial value.
+    ///
+    /// The inherited properties are the ones marked as such in
+    /// `PropertyDeclaration`.
     pub fn inherited(&self) -> MutableComputedStyle {
+        let mut style = Self::initial();
+        crate::css::PropertyDeclaration::inherit(&mut style, self);
+        // Not a property, but derived from inherited properties.
+        style.writing_mode = self.writing_mode;
+        style
+    }
+
+    pub fn for_viewport() -> Self {
+        Self::new_anonymous(PseudoElement::Viewport, Display::block())
+    }
+
+    /// Returns the style of the viewport of a document whose root element has
+    /// the style `root`, and whose overflow propagates from `overflow_source`,
+    /// if any.
+    ///
+    /// https://drafts.csswg.org/css-overflow/#overflow-propagation
+    /// https://drafts.csswg.org/css-writing-modes/#principal-flow
+    pub fn for_viewport_propagating_from(root: &ComputedStyle, overflow_source: Option<&ComputedStyle>) -> Self {
+        let (overflow_x, overflow_y) = overflow_source.map_or((Overflow::Visible, Overflow::Visible), |source| {
+            (source.overflow_x, source.overflow_y)
+        });
         MutableComputedStyle {
-            direction: self.direction,
-            writing_mode: self.writing_mode,
-            text_orientation: self.text_orientation,
-            computed_writing_mode: self.computed_writing_mode,
-            color: self.color,
-            white_space: self.white_space,
-            font_size: self.font_size,
-            font_family: self.font_family.clone(),
-            font_style: self.font_style,
-            font_weight: self.font_weight,
-            line_height: self.line_height.clone(),
+            pseudo: Some(PseudoElement::Viewport),
+            display: Display::block(),
+            original_display: Display::block(),
+            direction: root.direction,
+            writing_mode: root.writing_mode,
+            text_orientation: root.text_orientation,
+            computed_writing_mode: root.computed_writing_mode,
+            overflow_x,
+            overflow_y,
             ..Self::initial()
         }
+        .finish(false)
     }
 
-    pub fn for_viewport() -> Self {
-        Self::new_anonymous(PseudoElement::Viewport, Display::block())
+    /// Returns this style with `overflow: visible`, which is the used value of
+    /// `overflow` on the element it has been propagated to the viewport from.
+    pub fn with_overflow_propagated_to_viewport(&self) -> Self {
+        ComputedStyle(MutableComputedStyle {
+            overflow_x: Overflow::Visible,
+            overflow_y: Overflow::Visible,
+            ..self.0.clone()
+        })
     }
 
     pub fn for_ib_split_block_wrapper() -> Self {
@@ -765,6 +1599,19 @@ impl ComputedStyle {
         Self::new_anonymous(PseudoElement::InlineInsideBlockWrapper, Display::block())
     }
 
+    pub fn for_fieldset_content() -> Self {
+        Self::new_anonymous(PseudoElement::FieldsetContent, Display::flow_root())
+    }
+
+    /// Returns the style of the marker of a box with this style.
+    pub fn for_marker(&self) -> Self {
+        MutableComputedStyle {
+            pseudo: Some(PseudoElement::Marker),
+            ..self.inherited()
+        }
+        .finish(false)
+    }
+
     pub fn new_anonymous(pseudo: PseudoElement, display: Display) -> Self {
         debug_assert!(pseudo.is_anonymous());
         MutableComputedStyle {
@@ -794,12 +1641,23 @@ impl ComputedStyle {
         )
     }
 
-    fn physical_border_widths(&self) -> SideOffsets2D<Au> {
+    /// Returns the used border widths, which are zero for borders with
+    /// `border-style: none` or `hidden`.
+    ///
+    /// https://drafts.csswg.org/css-backgrounds/#border-width
+    pub fn physical_border_widths(&self) -> SideOffsets2D<Au> {
+        let width = |width: &Length, style: BorderStyle| {
+            if style.is_none_or_hidden() {
+                Au(0)
+            } else {
+                width.0
+            }
+        };
         SideOffsets2D::new(
-            self.border_top_width.0,
-            self.border_right_width.0,
-            self.border_bottom_width.0,
-            self.border_left_width.0,
+            width(&self.border_top_width, self.border_top_style),
+            width(&self.border_right_width, self.border_right_style),
+            width(&self.border_bottom_width, self.border_bottom_style),
+            width(&self.border_left_width, self.border_left_style),
         )
     }
 
@@ -839,7 +1697,76 @@ impl ComputedStyle {
         LogicalMargin::from_physical(self.writing_mode, self.physical_border_widths())
     }
 
-    pub fn first_available_font_metrics(&self) -> crate::fonts::metrics::FontMetrics {
-        crate::fonts::metrics::FontMetrics::from_style(self)
+    /// Whether any of the properties that resolve percentages against the
+    /// containing block size has a percentage value.
+    pub fn has_percentage_sizes(&self) -> bool {
+        let sizes = [
+            &self.width,
+            &self.height,
+            &self.min_width,
+            &self.min_height,
+            &self.max_width,
+            &self.max_height,
+        ];
+        let padding = self.physical_padding();
+        let margin = self.physical_margin();
+        sizes.iter().any(|s| s.has_percentage()) ||
+            [padding.top, padding.right, padding.bottom, padding.left]
+                .iter()
+                .any(|p| p.has_percentage()) ||
+            [margin.top, margin.right, margin.bottom, margin.left]
+                .iter()
+                .any(|m| m.has_percentage()) ||
+            self.text_indent.has_percentage()
+    }
+
+    /// Whether this box clips its contents and can be scrolled.
+    ///
+    /// There's no need to look at both axes, since `overflow` computes to
+    /// `auto` on one axis when the other isn't `visible`.
+    ///
+    /// https://drafts.csswg.org/css-overflow-3/#scroll-container
+    pub fn is_scroll_container(&self) -> bool {
+        self.overflow_x != Overflow::Visible
+    }
+
+    /// Whether this scroll container has a scrollbar to scroll in the block
+    /// axis, which takes space from its content box. `overflow: hidden`
+    /// doesn't.
+    pub fn has_block_axis_scrollbar(&self) -> bool {
+        let overflow = if self.writing_mode.is_vertical() {
+            self.overflow_x
+        } else {
+            self.overflow_y
+        };
+        overflow == Overflow::Scroll || overflow == Overflow::Auto
+    }
+
+    /// Resolves `currentcolor` to the value of the `color` property.
+    pub fn resolve_color(&self, color: &Color) -> RGBA {
+        match *color {
+            Color::RGBA(rgba) => rgba,
+            Color::CurrentColor => self.color,
+        }
+    }
+
+    pub fn first_available_font_metrics(&self, font_faces: &FontFaceSet) -> crate::fonts::metrics::FontMetrics {
+        crate::fonts::metrics::FontMetrics::from_style(self, font_faces)
+    }
+
+    /// Returns the used value of line-height.
+    ///
+    /// https://drafts.csswg.org/css2/visudet.html#propdef-line-height
+    pub fn used_line_height(&self, font_faces: &FontFaceSet) -> Au {
+        match self.line_height {
+            LineHeight::Normal => {
+                // TODO(emilio): Account for the line gap of the font.
+                let metrics = self.first_available_font_metrics(font_faces);
+                let wm = self.writing_mode;
+                metrics.block_ascent(wm).0 - metrics.block_descent(wm).0
+            },
+            LineHeight::Number(n) => self.font_size.0.scale_by(n),
+            LineHeight::Length(ref lp) => lp.resolve(self.font_size.0),
+        }
     }
 }
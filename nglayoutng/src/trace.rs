@@ -0,0 +1,217 @@
+//! An opt-in structured trace of the decisions layout makes, so that complex
+//! layout bugs can be inspected offline.
+//!
+//! Unlike `log`, which is meant for humans, a trace is a list of
+//! `TraceEvent`s that can be written as JSON lines, one object per event,
+//! with an `event` field and the fields of the event. Nodes are identified
+//! like in `LayoutTree::print_with_ids`.
+//!
+//! Tracing is per-thread, and only enabled while inside `collect`.
+
+use crate::layout_algorithms::{AvailableSize, ConstraintSpace, LayoutResult};
+use crate::layout_tree::LayoutNodeId;
+use app_units::Au;
+use std::cell::RefCell;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Write};
+
+thread_local! {
+    static EVENTS: RefCell<Option<Vec<TraceEvent>>> = const { RefCell::new(None) };
+}
+
+/// Something layout decided.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TraceEvent {
+    /// A box with an independent layout is being laid out with these
+    /// constraints.
+    Layout {
+        node: LayoutNodeId,
+        available_inline_size: Option<Au>,
+        available_block_size: Option<Au>,
+        percentage_inline_size: Option<Au>,
+        percentage_block_size: Option<Au>,
+        is_new_formatting_context: bool,
+        bfc_block_offset: Option<Au>,
+        /// The block size of the fragmentainer, if fragmenting.
+        fragmentainer_block_size: Option<Au>,
+        /// Whether layout resumes from a break token.
+        resuming: bool,
+    },
+    /// The last layout result of a box was reused.
+    CacheHit { node: LayoutNodeId },
+    /// A box was laid out, and got this size, in its own writing mode.
+    LayoutResult {
+        node: LayoutNodeId,
+        inline_size: Au,
+        block_size: Au,
+        has_break_token: bool,
+    },
+    /// The intrinsic sizes of a box were computed, or reused if `cached`.
+    MinMaxSizes {
+        node: LayoutNodeId,
+        min_content: Au,
+        max_content: Au,
+        cached: bool,
+    },
+    /// A line box was finished by the inline formatting context of `node`.
+    Line {
+        node: LayoutNodeId,
+        index: usize,
+        block_offset: Au,
+        block_size: Au,
+        fragment_count: usize,
+    },
+}
+
+impl TraceEvent {
+    /// Returns the `Layout` event for laying out `node` with `constraints`.
+    pub(crate) fn layout(node: LayoutNodeId, constraints: &ConstraintSpace, resuming: bool) -> Self {
+        fn sizes(size: &AvailableSize) -> (Option<Au>, Option<Au>) {
+            (size.inline, size.block)
+        }
+        let (available_inline_size, available_block_size) = sizes(&constraints.available_size);
+        let (percentage_inline_size, percentage_block_size) = sizes(&constraints.percentage_resolution_size);
+        TraceEvent::Layout {
+            node,
+            available_inline_size,
+            available_block_size,
+            percentage_inline_size,
+            percentage_block_size,
+            is_new_formatting_context: constraints.is_new_formatting_context,
+            bfc_block_offset: constraints.bfc_block_offset,
+            fragmentainer_block_size: constraints.fragmentainer.as_ref().map(|f| f.block_size),
+            resuming,
+        }
+    }
+
+    /// Returns the `LayoutResult` event for laying out `node`.
+    pub(crate) fn layout_result(node: LayoutNodeId, result: &LayoutResult) -> Self {
+        let size = &result.root_fragment.fragment.size;
+        TraceEvent::LayoutResult {
+            node,
+            inline_size: size.inline,
+            block_size: size.block,
+            has_break_token: result.break_token.is_some(),
+        }
+    }
+
+    /// The node this event is about.
+    pub fn node(&self) -> LayoutNodeId {
+        match *self {
+            TraceEvent::Layout { node, .. } |
+            TraceEvent::CacheHit { node } |
+            TraceEvent::LayoutResult { node, .. } |
+            TraceEvent::MinMaxSizes { node, .. } |
+            TraceEvent::Line { node, .. } => node,
+        }
+    }
+
+    /// Serializes this event as a single-line JSON object. Lengths are in CSS
+    /// pixels, and unknown lengths are `null`.
+    pub fn to_json(&self) -> String {
+        let mut json = JsonObject::new(match *self {
+            TraceEvent::Layout { .. } => "layout",
+            TraceEvent::CacheHit { .. } => "cache-hit",
+            TraceEvent::LayoutResult { .. } => "layout-result",
+            TraceEvent::MinMaxSizes { .. } => "min-max-sizes",
+            TraceEvent::Line { .. } => "line",
+        });
+        json.raw("node", &format!("\"{:?}\"", self.node()));
+        match *self {
+            TraceEvent::Layout {
+                available_inline_size,
+                available_block_size,
+                percentage_inline_size,
+                percentage_block_size,
+                is_new_formatting_context,
+                bfc_block_offset,
+                fragmentainer_block_size,
+                resuming,
+                ..
+            } => {
+                json.length("available_inline_size", available_inline_size);
+                json.length("available_block_size", available_block_size);
+                json.length("percentage_inline_size", percentage_inline_size);
+                json.length("percentage_block_size", percentage_block_size);
+                json.raw("is_new_formatting_context", &is_new_formatting_context.to_string());
+                json.length("bfc_block_offset", bfc_block_offset);
+                json.length("fragmentainer_block_size", fragmentainer_block_size);
+                json.raw("resuming", &resuming.to_string());
+            },
+            TraceEvent::CacheHit { .. } => {},
+            TraceEvent::LayoutResult { inline_size, block_size, has_break_token, .. } => {
+                json.length("inline_size", Some(inline_size));
+                json.length("block_size", Some(block_size));
+                json.raw("has_break_token", &has_break_token.to_string());
+            },
+            TraceEvent::MinMaxSizes { min_content, max_content, cached, .. } => {
+                json.length("min_content", Some(min_content));
+                json.length("max_content", Some(max_content));
+                json.raw("cached", &cached.to_string());
+            },
+            TraceEvent::Line { index, block_offset, block_size, fragment_count, .. } => {
+                json.raw("index", &index.to_string());
+                json.length("block_offset", Some(block_offset));
+                json.length("block_size", Some(block_size));
+                json.raw("fragment_count", &fragment_count.to_string());
+            },
+        }
+        json.finish()
+    }
+}
+
+/// A tiny writer of flat JSON objects, which is all we need for events.
+struct JsonObject(String);
+
+impl JsonObject {
+    fn new(event: &str) -> Self {
+        JsonObject(format!("{{\"event\":\"{}\"", event))
+    }
+
+    fn raw(&mut self, key: &str, value: &str) {
+        write!(self.0, ",\"{}\":{}", key, value).unwrap();
+    }
+
+    fn length(&mut self, key: &str, value: Option<Au>) {
+        match value {
+            Some(au) => self.raw(key, &au.to_f32_px().to_string()),
+            None => self.raw(key, "null"),
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.0.push('}');
+        self.0
+    }
+}
+
+/// Whether layout decisions are being traced on this thread.
+pub fn is_enabled() -> bool {
+    EVENTS.with(|events| events.borrow().is_some())
+}
+
+/// Records an event, if tracing. The event is only built if needed.
+pub(crate) fn record(event: impl FnOnce() -> TraceEvent) {
+    EVENTS.with(|events| {
+        if let Some(ref mut events) = *events.borrow_mut() {
+            events.push(event());
+        }
+    })
+}
+
+/// Runs `f` recording the layout decisions it makes, and returns them in the
+/// order they were made.
+pub fn collect<R>(f: impl FnOnce() -> R) -> (R, Vec<TraceEvent>) {
+    let previous = EVENTS.with(|events| events.replace(Some(vec![])));
+    let result = f();
+    let events = EVENTS.with(|events| events.replace(previous));
+    (result, events.unwrap())
+}
+
+/// Writes `events` as JSON lines.
+pub fn write_json_lines(events: &[TraceEvent], dest: &mut dyn Write) -> io::Result<()> {
+    for event in events {
+        writeln!(dest, "{}", event.to_json())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,51 @@
+//! Transitions between two computed styles, which can be sampled at any
+//! point in time to test how layout reacts to animated styles.
+//!
+//! https://drafts.csswg.org/css-transitions/
+
+use crate::animate::Animate;
+use crate::style::ComputedStyle;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Returns the style at `progress` between `from`, at 0, and `to`, at 1.
+/// Properties that can't be interpolated flip at the midpoint.
+pub fn interpolate_style(from: &ComputedStyle, to: &ComputedStyle, progress: f32) -> ComputedStyle {
+    from.animate(to, progress).unwrap()
+}
+
+/// A linear transition of all the properties of a style.
+///
+/// TODO(emilio): Timing functions, delays, and transitioning only some
+/// properties.
+#[derive(Debug, Clone)]
+pub struct Transition {
+    pub from: Rc<ComputedStyle>,
+    pub to: Rc<ComputedStyle>,
+    pub duration: Duration,
+}
+
+impl Transition {
+    pub fn new(from: Rc<ComputedStyle>, to: Rc<ComputedStyle>, duration: Duration) -> Self {
+        Self { from, to, duration }
+    }
+
+    /// Returns the progress of the transition after `elapsed` time, between 0
+    /// and 1.
+    pub fn progress_at(&self, elapsed: Duration) -> f32 {
+        if elapsed >= self.duration {
+            return 1.;
+        }
+        elapsed.as_secs_f32() / self.duration.as_secs_f32()
+    }
+
+    /// Whether the transition has finished after `elapsed` time.
+    pub fn is_finished_at(&self, elapsed: Duration) -> bool {
+        elapsed >= self.duration
+    }
+
+    /// Returns the style after `elapsed` time.
+    pub fn style_at(&self, elapsed: Duration) -> ComputedStyle {
+        interpolate_style(&self.from, &self.to, self.progress_at(elapsed))
+    }
+}
@@ -0,0 +1,195 @@
+//! Support for the assertions of web-platform-tests, so that both our own
+//! tests and a checkout of WPT can be run against the engine.
+//!
+//! https://web-platform-tests.org/writing-tests/
+
+use crate::fragment_tree::FragmentTree;
+use crate::layout_tree::builder::LayoutTreeBuilderResult;
+use crate::query::LayoutResultQuery;
+use crate::style::Position;
+use euclid::default::Point2D;
+use kuchiki::NodeRef;
+use raqote::DrawTarget;
+
+/// The tolerance of check-layout assertions, in CSS pixels.
+pub const CHECK_LAYOUT_EPSILON: f32 = 0.02;
+
+/// Returns the origin of the padding box of the nearest positioned ancestor
+/// of `node`, which is what `offsetLeft` and `offsetTop` are relative to, if
+/// any.
+fn offset_parent_origin(
+    result: &LayoutTreeBuilderResult,
+    query: &LayoutResultQuery,
+    node: &NodeRef,
+) -> Option<Point2D<f32>> {
+    node.ancestors().find_map(|ancestor| {
+        let id = result.principal_boxes.get(&result.dom_node_ids.get(&ancestor)?)?;
+        if result.layout_tree[*id].style.position == Position::Static {
+            return None;
+        }
+        Some(query.client_rect(&ancestor)?.origin)
+    })
+}
+
+/// The elements with check-layout annotations in a document.
+fn annotated_elements(dom: &NodeRef) -> Vec<NodeRef> {
+    dom.select("[data-expected-width], [data-expected-height], [data-offset-x], [data-offset-y]")
+        .unwrap()
+        .map(|element| element.as_node().clone())
+        .collect()
+}
+
+/// Whether a document has check-layout annotations.
+pub fn has_check_layout_annotations(dom: &NodeRef) -> bool {
+    !annotated_elements(dom).is_empty()
+}
+
+/// Checks the geometry of the elements annotated like in WPT's
+/// `check-layout-th.js`, with `data-expected-width`, `data-expected-height`,
+/// `data-offset-x` and `data-offset-y` attributes, and returns the assertions
+/// that failed.
+///
+/// Offsets are relative to the nearest positioned ancestor, or to the
+/// viewport if there's none. `fragment_tree` is `None` if layout failed.
+pub fn check_layout(result: &LayoutTreeBuilderResult, fragment_tree: Option<&FragmentTree>) -> Vec<String> {
+    let elements = annotated_elements(&result.dom);
+    if elements.is_empty() {
+        return vec![];
+    }
+    let fragment_tree = match fragment_tree {
+        Some(tree) => tree,
+        None => return vec!["Layout panicked, can't check the layout assertions".to_owned()],
+    };
+
+    let query = LayoutResultQuery::new(&result.dom_node_ids, &result.principal_boxes, fragment_tree);
+    let mut failures = vec![];
+    for node in elements {
+        let label = crate::dom::node_label(&node);
+        let rect = match query.border_box_rect(&node) {
+            Some(rect) => rect,
+            None => {
+                failures.push(format!("{} has no box", label));
+                continue;
+            },
+        };
+        let origin = offset_parent_origin(result, &query, &node).unwrap_or_else(Point2D::zero);
+        let element = node.as_element().unwrap();
+        let attributes = element.attributes.borrow();
+        for &(name, actual) in &[
+            ("data-expected-width", rect.size.width),
+            ("data-expected-height", rect.size.height),
+            ("data-offset-x", rect.origin.x - origin.x),
+            ("data-offset-y", rect.origin.y - origin.y),
+        ] {
+            let expected = match attributes.get(name) {
+                Some(expected) => expected,
+                None => continue,
+            };
+            match expected.trim().parse::<f32>() {
+                Ok(expected) if (expected - actual).abs() <= CHECK_LAYOUT_EPSILON => {},
+                Ok(expected) => failures.push(format!("{} {}: expected {}, got {}", label, name, expected, actual)),
+                Err(..) => failures.push(format!("{} {}: invalid value {:?}", label, name, expected)),
+            }
+        }
+    }
+    failures
+}
+
+/// How two renderings differ.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ImageDifference {
+    /// The maximum difference of any color channel of a pixel.
+    pub max_difference: u32,
+    /// The number of different pixels.
+    pub total_pixels: usize,
+}
+
+/// Compares two renderings pixel by pixel, returning `None` if they have
+/// different sizes.
+pub fn compare_images(a: &DrawTarget, b: &DrawTarget) -> Option<ImageDifference> {
+    if (a.width(), a.height()) != (b.width(), b.height()) {
+        return None;
+    }
+    let mut difference = ImageDifference::default();
+    for (a, b) in a.get_data().iter().zip(b.get_data()) {
+        let pixel_difference = (0..4)
+            .map(|channel| {
+                let shift = channel * 8;
+                (((a >> shift) & 0xff) as i32 - ((b >> shift) & 0xff) as i32).unsigned_abs()
+            })
+            .max()
+            .unwrap();
+        if pixel_difference != 0 {
+            difference.max_difference = std::cmp::max(difference.max_difference, pixel_difference);
+            difference.total_pixels += 1;
+        }
+    }
+    Some(difference)
+}
+
+/// How much a reftest may differ from its reference, from a
+/// `<meta name=fuzzy content="maxDifference=0-2;totalPixels=0-10">` in the
+/// test. Ranges are taken as their upper bound.
+///
+/// https://web-platform-tests.org/writing-tests/reftests.html#fuzzy-matching
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Fuzz {
+    pub max_difference: u32,
+    pub total_pixels: usize,
+}
+
+impl Fuzz {
+    /// Reads the fuzz that a test allows, which is none if it has no fuzzy
+    /// annotation.
+    pub fn from_document(dom: &NodeRef) -> Result<Self, String> {
+        let mut fuzz = Fuzz::default();
+        let meta = match dom.select_first("meta[name=fuzzy]") {
+            Ok(meta) => meta,
+            Err(..) => return Ok(fuzz),
+        };
+        let attributes = meta.attributes.borrow();
+        let content = attributes.get("content").unwrap_or("");
+        for part in content.split(';') {
+            let mut parts = part.splitn(2, '=');
+            let name = parts.next().unwrap().trim();
+            let value = parts.next().unwrap_or("").trim();
+            let max = value.rsplit('-').next().unwrap().trim();
+            let invalid = |_| format!("Invalid fuzzy annotation {:?}", part);
+            match name {
+                "maxDifference" => fuzz.max_difference = max.parse().map_err(invalid)?,
+                "totalPixels" => fuzz.total_pixels = max.parse().map_err(invalid)?,
+                _ => return Err(format!("Unknown fuzzy annotation {:?}", part)),
+            }
+        }
+        Ok(fuzz)
+    }
+
+    /// Whether a rendering that differs from its reference by `difference`
+    /// passes.
+    pub fn allows(&self, difference: &ImageDifference) -> bool {
+        difference.max_difference <= self.max_difference && difference.total_pixels <= self.total_pixels
+    }
+}
+
+/// Whether a reftest should render like its reference or not.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReferenceKind {
+    Match,
+    Mismatch,
+}
+
+/// Returns the references of a reftest, from its `<link rel=match>` and
+/// `<link rel=mismatch>` elements, with their URL as written.
+pub fn references(dom: &NodeRef) -> Vec<(ReferenceKind, String)> {
+    dom.select("link[rel=match], link[rel=mismatch]")
+        .unwrap()
+        .filter_map(|link| {
+            let attributes = link.attributes.borrow();
+            let kind = match attributes.get("rel")? {
+                "match" => ReferenceKind::Match,
+                _ => ReferenceKind::Mismatch,
+            };
+            Some((kind, attributes.get("href")?.to_owned()))
+        })
+        .collect()
+}
@@ -2,12 +2,30 @@ extern crate diff;
 extern crate nglayoutng;
 
 use app_units::Au;
+use nglayoutng::allocator::Allocator;
+use nglayoutng::css;
+use nglayoutng::display_list::DisplayList;
 use nglayoutng::dom;
+use nglayoutng::fonts::loader::FontSource;
+use nglayoutng::fragment_tree::{Fragment, FragmentKind};
+use nglayoutng::layout_algorithms::{
+    AvailableSize, ConstraintSpaceBuilder, ContainingBlockInfo, Fragmentainer, LayoutAlgorithmKind, LayoutContext,
+};
 use nglayoutng::layout_tree::builder::{LayoutTreeBuilder, LayoutTreeBuilderResult};
-use nglayoutng::layout_tree::PrintId;
+use nglayoutng::layout_tree::{LayoutNodeFlags, LayoutNodeKind, LeafKind, PrintId, Validation};
+use nglayoutng::logical_geometry::{LogicalPoint, LogicalRect, LogicalSize, WritingMode};
+use nglayoutng::memory::memory_report;
+use nglayoutng::paint;
+use nglayoutng::style::{
+    Length, LengthPercentage, LengthPercentageOrAuto, Overflow, Percentage, Size, SizeKeyword, WhiteSpace,
+};
+use nglayoutng::wpt::{check_layout, compare_images, Fuzz};
+use nglayoutng::Error;
+use cssparser::ToCss;
 use std::fs::{self, File};
 use std::io::{Cursor, Write};
 use std::path::Path;
+use std::rc::Rc;
 
 fn print_diff(actual: &str, expected: &str, label: &str) {
     if actual == expected {
@@ -16,7 +34,7 @@ fn print_diff(actual: &str, expected: &str, label: &str) {
 
     println!("{}", label);
     println!("diff expected generated");
-    for diff in diff::lines(&expected, &actual) {
+    for diff in diff::lines(expected, actual) {
         match diff {
             diff::Result::Left(l) => println!("-{}", l),
             diff::Result::Both(l, _) => println!(" {}", l),
@@ -25,10 +43,277 @@ fn print_diff(actual: &str, expected: &str, label: &str) {
     }
 }
 
+/// The expected outcome of a test in `tests/html`, from
+/// `tests/expectations.txt`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Expectation {
+    /// The test matches its expectations.
+    Pass,
+    /// The test doesn't match its expectations, which are left untouched.
+    Fail,
+    /// Layout panics, so there's no fragment tree to compare.
+    Panic,
+}
+
+/// The fonts the tests in `tests/html` are laid out with.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum TestFonts {
+    /// The bundled Ahem-compatible font, which the expectations are generated
+    /// with.
+    Bundled,
+    /// The fonts of the system, if `NGLAYOUTNG_SYSTEM_FONTS` is set. Lengths
+    /// derived from font metrics are replaced by symbolic tokens, and the
+    /// expectations are never overridden.
+    System,
+}
+
+impl TestFonts {
+    fn from_env() -> Self {
+        if std::env::var_os("NGLAYOUTNG_SYSTEM_FONTS").is_some() {
+            TestFonts::System
+        } else {
+            TestFonts::Bundled
+        }
+    }
+
+    fn font_source(self) -> FontSource {
+        match self {
+            TestFonts::Bundled => FontSource::BundledTestFont,
+            TestFonts::System => FontSource::System,
+        }
+    }
+}
+
+/// The token font-derived lengths are replaced with.
+const FONT_TOKEN: &str = "<font>";
+
+/// Replaces every `<number>px` length of `line` outside of string literals
+/// with the result of `map`.
+fn map_lengths(line: &str, map: &dyn Fn(f64) -> String) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        if in_string {
+            in_string = escaped || c != '"';
+            escaped = !escaped && c == '\\';
+        } else if c == '"' {
+            in_string = true;
+        } else if c == '-' || c.is_ascii_digit() {
+            let starts_number = result.chars().next_back().is_none_or(|p| !p.is_ascii_digit() && p != '.');
+            let end = rest[1..].find(|c: char| !c.is_ascii_digit() && c != '.').map_or(rest.len(), |i| i + 1);
+            if starts_number && rest[end..].starts_with("px") {
+                if let Ok(px) = rest[..end].parse::<f64>() {
+                    result.push_str(&map(px));
+                    rest = &rest[end + 2..];
+                    continue;
+                }
+            }
+        }
+        result.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    result
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// Returns the index of the line closing the block opened at `start`.
+fn block_end(lines: &[String], start: usize) -> usize {
+    let indent = indentation(&lines[start]);
+    (start + 1..lines.len())
+        .find(|&i| indentation(&lines[i]) == indent && lines[i].trim_start().starts_with(['}', ']']))
+        .unwrap_or(lines.len() - 1)
+}
+
+/// Returns whether the `ChildFragment` opened at `start` is a line box.
+fn is_line_box(lines: &[String], start: usize) -> bool {
+    // The kind of the fragment is two levels deeper than the child.
+    let kind = indentation(&lines[start]) + 8;
+    lines[start].trim() == "ChildFragment {" &&
+        (start..block_end(lines, start))
+            .find(|&i| indentation(&lines[i]) == kind && lines[i].trim().starts_with("kind: "))
+            .is_some_and(|i| lines[i].trim() == "kind: Container {" && lines[i + 1].trim() == "kind: Line {")
+}
+
+/// Replaces each run of sibling line boxes with a single `Lines` entry with
+/// their text, since where lines break depends on the font.
+fn collapse_line_boxes(lines: Vec<String>) -> Vec<String> {
+    let mut result = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        if !is_line_box(&lines, i) {
+            result.push(lines[i].clone());
+            i += 1;
+            continue;
+        }
+        let indent = indentation(&lines[i]);
+        let mut text = String::new();
+        while i < lines.len() && indentation(&lines[i]) == indent && is_line_box(&lines, i) {
+            let end = block_end(&lines, i);
+            for line in &lines[i..end] {
+                let content = line.trim().strip_prefix("content: \"").and_then(|c| c.strip_suffix("\","));
+                text.push_str(content.unwrap_or_default());
+            }
+            i = end + 1;
+        }
+        result.push(format!("{}Lines {{ text: \"{}\" }},", " ".repeat(indent), text));
+    }
+    result
+}
+
+/// Normalizes a `{:#?}`-printed fragment tree, so that it can be compared
+/// with its expectations across platforms.
+///
+/// Lengths are rounded to CSS pixels, which absorbs the sub-pixel differences
+/// of font rasterization and rounding. With system fonts, line boxes are
+/// collapsed into their text, and the sizes, overflow areas and clip rects of
+/// the boxes that contain text, and the offsets of the boxes that come after
+/// text, are replaced by a symbolic token.
+fn normalize_fragment_tree(tree: &str, fonts: TestFonts) -> String {
+    let lines = tree
+        .lines()
+        .map(|line| map_lengths(line, &|px| format!("{}px", px.round() as i64)))
+        .collect::<Vec<_>>();
+    if fonts == TestFonts::Bundled {
+        return lines.join("\n");
+    }
+
+    let mut lines = collapse_line_boxes(lines);
+    let is_text = |line: &str| line.trim_start().starts_with("Lines {");
+    for start in 0..lines.len() {
+        if lines[start].trim() != "ChildFragment {" {
+            continue;
+        }
+        let end = block_end(&lines, start);
+        let indent = indentation(&lines[start]);
+        // The fields of the fragment are two levels deeper than the child.
+        let fields = indent + 8;
+        let contains_text = lines[start + 1..end].iter().any(|line| is_text(line));
+        // The siblings before this one are in the same list of children.
+        let siblings_start = (0..start).rev().find(|&i| indentation(&lines[i]) < indent).unwrap_or(0);
+        let after_text = lines[siblings_start..start].iter().any(|line| is_text(line));
+
+        for line in &mut lines[start..end] {
+            let field = line.trim();
+            let tokenize = if field.starts_with("offset: ") {
+                after_text && indentation(line) == indent + 4
+            } else if field.starts_with("size: ") {
+                contains_text && indentation(line) == fields
+            } else if field.starts_with("scrollable: ") || field.starts_with("ink: ") || field.starts_with("LogicalRect(") {
+                // The overflow areas and the clip rect of the fragment.
+                contains_text && indentation(line) == fields + 8
+            } else {
+                false
+            };
+            if tokenize {
+                *line = map_lengths(line, &|_| FONT_TOKEN.to_owned());
+            }
+        }
+    }
+    lines.join("\n")
+}
+
+#[test]
+fn fragment_trees_are_normalized_for_comparison() {
+    let tree = r#"ChildFragment {
+    offset: LogicalPoint(H LTR (i0px, b0px)),
+    fragment: Fragment {
+        size: LogicalSize(H LTR, i800px×b17.983333333333334px),
+        kind: Container {
+            children: [
+                ChildFragment {
+                    offset: LogicalPoint(H LTR (i8.016666666666667px, b0px)),
+                    fragment: Fragment {
+                        size: LogicalSize(H LTR, i40.5px×b17.983333333333334px),
+                        kind: Container {
+                            kind: Line {
+                                ellipsis: None,
+                            },
+                            children: [
+                                ChildFragment {
+                                    offset: LogicalPoint(H LTR (i0px, b1.2px)),
+                                    fragment: Fragment {
+                                        size: LogicalSize(H LTR, i40.5px×b16px),
+                                        kind: TextRun {
+                                            content: "2.5px ",
+                                            glyphs: [
+                                                GlyphInfo {
+                                                    advance: 8.1px,
+                                                },
+                                            ],
+                                        },
+                                    },
+                                },
+                            ],
+                        },
+                    },
+                },
+                ChildFragment {
+                    offset: LogicalPoint(H LTR (i0px, b17.983333333333334px)),
+                    fragment: Fragment {
+                        size: LogicalSize(H LTR, i40.5px×b17.983333333333334px),
+                        kind: Container {
+                            kind: Line {
+                                ellipsis: None,
+                            },
+                            children: [
+                                ChildFragment {
+                                    offset: LogicalPoint(H LTR (i0px, b1.2px)),
+                                    fragment: Fragment {
+                                        size: LogicalSize(H LTR, i24.3px×b16px),
+                                        kind: TextRun {
+                                            content: "\"a\"",
+                                            glyphs: [],
+                                        },
+                                    },
+                                },
+                            ],
+                        },
+                    },
+                },
+                ChildFragment {
+                    offset: LogicalPoint(H LTR (i0px, b35.96666666666667px)),
+                    fragment: Fragment {
+                        size: LogicalSize(H LTR, i800px×b10px),
+                        kind: Container {
+                            children: [],
+                        },
+                    },
+                },
+            ],
+        },
+    },
+}"#;
+
+    let bundled = normalize_fragment_tree(tree, TestFonts::Bundled);
+    assert!(bundled.contains("i800px×b18px"));
+    assert!(bundled.contains("(i8px, b0px)"));
+    assert!(bundled.contains("i41px×b16px"));
+    assert!(bundled.contains("content: \"2.5px \""));
+    assert!(bundled.contains("advance: 8px"));
+    assert_eq!(normalize_fragment_tree(&bundled, TestFonts::Bundled), bundled);
+
+    let system = normalize_fragment_tree(tree, TestFonts::System);
+    assert!(system.contains("size: LogicalSize(H LTR, i<font>×b<font>)"));
+    assert!(system.contains("offset: LogicalPoint(H LTR (i0px, b0px))"));
+    assert!(system.contains("Lines { text: \"2.5px \\\"a\\\"\" },"));
+    assert!(!system.contains("advance"));
+    // The box after the lines moves with them, but keeps its size.
+    assert!(system.contains("offset: LogicalPoint(H LTR (i<font>, b<font>))"));
+    assert!(system.contains("size: LogicalSize(H LTR, i800px×b10px)"));
+    assert_eq!(normalize_fragment_tree(&system, TestFonts::System), system);
+}
+
 fn compare_with_reference(
     test_path: &str,
     expectations_directory: &str,
     result: LayoutTreeBuilderResult,
+    expectation: Expectation,
+    fonts: TestFonts,
 ) {
     let test_name = Path::new(test_path).file_name().unwrap().to_str().unwrap();
     let expectations = Path::new(expectations_directory);
@@ -38,7 +323,10 @@ fn compare_with_reference(
 
     let expected_dom = fs::read_to_string(&dom_expectations).unwrap_or_default();
     let expected_layout_tree = fs::read_to_string(&layout_tree_expectations).unwrap_or_default();
-    let expected_fragment_tree = fs::read_to_string(&fragment_tree_expectations).unwrap_or_default();
+    let expected_fragment_tree = normalize_fragment_tree(
+        &fs::read_to_string(&fragment_tree_expectations).unwrap_or_default(),
+        fonts,
+    );
 
     let dom = {
         let mut dom = Cursor::new(Vec::new());
@@ -51,23 +339,63 @@ fn compare_with_reference(
         String::from_utf8(layout.into_inner()).unwrap()
     };
 
-    // Layout is expected to panic in a bunch of cases for now.
-    //
-    // TODO(emilio): Remove catch_unwind when stuff is more stable..
-    let fragment_tree = {
+    // Only catch the panics of the tests that are expected to panic, so that
+    // new ones aren't masked.
+    let laid_out_tree = {
         let quirks_mode = result.dom.as_document().unwrap().quirks_mode();
         let viewport = euclid::Size2D::new(Au::from_f32_px(800.0), Au::from_f32_px(600.0));
         let layout_tree = &result.layout_tree;
-        std::panic::catch_unwind(|| {
-            let tree = layout_tree.layout(quirks_mode, viewport);
-            format!("{:#?}", tree)
-        }).unwrap_or_default()
+        if expectation == Expectation::Panic {
+            let layout = std::panic::AssertUnwindSafe(|| layout_tree.layout(quirks_mode, viewport));
+            let tree = std::panic::catch_unwind(layout).ok();
+            assert!(
+                tree.is_none(),
+                "{} was expected to panic but didn't, update tests/expectations.txt",
+                test_name,
+            );
+            tree
+        } else {
+            Some(layout_tree.layout(quirks_mode, viewport))
+        }
     };
+    let fragment_tree = laid_out_tree
+        .as_ref()
+        .map(|tree| normalize_fragment_tree(&format!("{:#?}", tree.root), fonts))
+        .unwrap_or_default();
 
-    if dom == expected_dom && layout_tree == expected_layout_tree && fragment_tree == expected_fragment_tree {
+    // The check-layout assertions of the tests are written against the
+    // metrics of the bundled font.
+    let layout_failures = match fonts {
+        TestFonts::Bundled => check_layout(&result, laid_out_tree.as_ref()),
+        TestFonts::System => vec![],
+    };
+    for failure in &layout_failures {
+        println!("{}", failure);
+    }
+
+    let matches_expectations =
+        dom == expected_dom && layout_tree == expected_layout_tree && fragment_tree == expected_fragment_tree;
+    if expectation == Expectation::Fail {
+        assert!(
+            !matches_expectations || !layout_failures.is_empty(),
+            "{} was expected to fail but passed, update tests/expectations.txt",
+            test_name,
+        );
+        return;
+    }
+
+    if matches_expectations {
+        assert!(layout_failures.is_empty(), "check-layout assertions failed");
         return;
     }
 
+    if fonts == TestFonts::System {
+        print_diff(&dom, &expected_dom, "DOM differed");
+        print_diff(&layout_tree, &expected_layout_tree, "Layout tree differed");
+        print_diff(&fragment_tree, &expected_fragment_tree, "Fragment tree differed");
+        panic!("Expectation and test mismatch with system fonts!");
+    }
+
     // Override the expectations.
     File::create(&dom_expectations)
         .unwrap()
@@ -89,16 +417,1498 @@ fn compare_with_reference(
     panic!("Expectation and test mismatch!");
 }
 
+fn layout_tree_to_string(builder: &LayoutTreeBuilder) -> String {
+    let mut layout = Cursor::new(Vec::new());
+    builder.layout_tree().print_to(&mut layout, PrintId::No);
+    String::from_utf8(layout.into_inner()).unwrap()
+}
+
+/// Constructs the layout tree of a document, laid out with the bundled test
+/// font.
+fn constructed_builder(html: &str) -> LayoutTreeBuilder {
+    let mut builder =
+        LayoutTreeBuilder::new_with_font_source(&mut Cursor::new(html), None, FontSource::BundledTestFont).unwrap();
+    builder.construct().unwrap();
+    builder
+}
+
+fn first_node(builder: &LayoutTreeBuilder, selector: &str) -> kuchiki::NodeRef {
+    builder.dom().select_first(selector).unwrap().as_node().clone()
+}
+
+fn all_nodes(builder: &LayoutTreeBuilder, selector: &str) -> Vec<kuchiki::NodeRef> {
+    builder.dom().select(selector).unwrap().map(|s| s.as_node().clone()).collect()
+}
+
+fn parse_fragment(html: &str, selector: &str) -> kuchiki::NodeRef {
+    let source = dom::build_dom(&mut Cursor::new(html)).unwrap();
+    let node = source.select_first(selector).unwrap().as_node().clone();
+    node
+}
+
+fn has_principal_box(builder: &LayoutTreeBuilder, node: &kuchiki::NodeRef) -> bool {
+    let id = builder.dom_node_ids().get(node).unwrap();
+    builder.principal_boxes().get(&id).is_some()
+}
+
+type MutationTest = (&'static str, fn(&mut LayoutTreeBuilder));
+
+/// The DOM mutation tests. Each of them mutates `tests/mutations/<name>.html`,
+/// and checks that the resulting layout tree is the same as the one
+/// constructed from scratch for `tests/mutations/<name>-expected.html`.
+const MUTATION_TESTS: &[MutationTest] = &[
+    ("insert-append-remove", |builder| {
+        let body = first_node(builder, "body");
+        let divs = all_nodes(builder, "div");
+        builder.insert_before(&body, parse_fragment("<div>b</div>", "div"), &divs[1]).unwrap();
+        builder.append_child(&body, parse_fragment("<span>e</span>", "span")).unwrap();
+        builder.remove_child(&divs[2]).unwrap();
+    }),
+    ("remove-wrapped-inlines", |builder| {
+        let spans = all_nodes(builder, "span");
+        builder.remove_child(&spans[0]).unwrap();
+        builder.remove_child(&spans[2]).unwrap();
+    }),
+    ("remove-blocks", |builder| {
+        for block in &all_nodes(builder, "div div, span div") {
+            builder.remove_child(block).unwrap();
+        }
+    }),
+    ("insert-legend", |builder| {
+        let fieldset = first_node(builder, "fieldset");
+        let legend = parse_fragment("<legend>a</legend>", "legend");
+        builder.insert_before(&fieldset, legend, &fieldset.first_child().unwrap()).unwrap();
+    }),
+    ("open-details", |builder| {
+        let details = first_node(builder, "details");
+        let div = first_node(builder, "div");
+        assert!(!has_principal_box(builder, &div), "Closed details shouldn't render their contents");
+        let summary = parse_fragment("<summary>a</summary>", "summary");
+        builder.insert_before(&details, summary, &details.first_child().unwrap()).unwrap();
+        builder.set_attribute(&details, "open", "").unwrap();
+        assert!(has_principal_box(builder, &div), "Open details should render their contents");
+    }),
+    ("close-details", |builder| {
+        let details = first_node(builder, "details");
+        let div = first_node(builder, "div");
+        builder.remove_attribute(&details, "open").unwrap();
+        assert!(!has_principal_box(builder, &div), "Closed details shouldn't render their contents");
+    }),
+    ("set-text-data", |builder| {
+        let text = first_node(builder, "span").first_child().unwrap();
+        builder.set_text_data(&text, "  ").unwrap();
+        builder.set_text_data(&text, "c").unwrap();
+    }),
+    ("set-attributes", |builder| {
+        let first = first_node(builder, "div");
+        let span = first_node(builder, "span");
+        builder.set_attribute(&first, "class", "inline").unwrap();
+        builder.set_attribute(&span, "style", "display: block").unwrap();
+    }),
+];
+
+#[test]
+fn dom_mutations_match_static_construction() {
+    let mutations = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("mutations");
+    let read = |file_name: String| fs::read_to_string(mutations.join(file_name)).unwrap();
+
+    for &(name, mutate) in MUTATION_TESTS {
+        let mut builder = constructed_builder(&read(format!("{}.html", name)));
+        mutate(&mut builder);
+        builder.layout_tree().assert_consistent();
+
+        let expected = constructed_builder(&read(format!("{}-expected.html", name)));
+        let actual = layout_tree_to_string(&builder);
+        let expected = layout_tree_to_string(&expected);
+        print_diff(&actual, &expected, &format!("Layout tree differed for {}", name));
+        assert_eq!(actual, expected, "{}", name);
+    }
+}
+
+#[test]
+fn validation_levels_build_the_same_tree() {
+    let html = "<!doctype html><div>a<span>b<div>c</div>d</span></div><fieldset><legend>e</legend>f</fieldset>";
+    let trees = [Validation::None, Validation::Local, Validation::Full]
+        .iter()
+        .map(|validation| {
+            let mut builder = LayoutTreeBuilder::new(&mut Cursor::new(html)).unwrap();
+            builder.set_validation(*validation);
+            builder.construct().unwrap();
+            assert_eq!(builder.layout_tree().validation(), *validation);
+            builder.layout_tree().assert_consistent();
+            layout_tree_to_string(&builder)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(trees[0], trees[1]);
+    assert_eq!(trees[1], trees[2]);
+}
+
+#[test]
+fn invalid_mutations_and_stylesheet_errors_are_reported() {
+    let mut builder = constructed_builder(
+        "<!doctype html><style>@media print { div {} } div { color: } p {}</style><div>a</div><p>b</p>",
+    );
+    let errors = builder.stylesheet_errors();
+    assert!(errors.iter().any(|e| match *e {
+        Error::Unsupported(ref what) => what.starts_with("@media"),
+        _ => false,
+    }));
+    assert!(errors.iter().all(|e| matches!(*e, Error::Unsupported(..) | Error::Parse { .. })));
+
+    let div = builder.dom().select_first("div").unwrap().as_node().clone();
+    let p = builder.dom().select_first("p").unwrap().as_node().clone();
+    let text = div.first_child().unwrap();
+    let orphan = dom::build_dom(&mut Cursor::new("<span></span>")).unwrap();
+    let orphan = orphan.select_first("span").unwrap().as_node().clone();
+    orphan.detach();
+
+    let is_invalid_mutation = |result: Result<(), Error>| matches!(result, Err(Error::InvalidMutation(..)));
+    let before = layout_tree_to_string(&builder);
+    assert!(is_invalid_mutation(builder.insert_before(&div, orphan.clone(), &p)));
+    assert!(is_invalid_mutation(builder.remove_child(&orphan)));
+    assert!(is_invalid_mutation(builder.set_text_data(&div, "c")));
+    assert!(is_invalid_mutation(builder.set_attribute(&text, "class", "c")));
+    assert!(is_invalid_mutation(builder.remove_attribute(&text, "class")));
+    assert!(is_invalid_mutation(builder.construct()));
+    builder.layout_tree().assert_consistent();
+    assert_eq!(before, layout_tree_to_string(&builder));
+}
+
+#[test]
+fn clean_trees_reuse_fragments() {
+    let mut builder = constructed_builder("<!doctype html><div>a</div>");
+    let quirks_mode = builder.dom().as_document().unwrap().quirks_mode();
+    let viewport = euclid::Size2D::new(Au::from_f32_px(800.0), Au::from_f32_px(600.0));
+
+    let first = builder.layout_tree().layout(quirks_mode, viewport);
+    let second = builder.layout_tree().layout(quirks_mode, viewport);
+    assert!(Rc::ptr_eq(&first.root.fragment, &second.root.fragment));
+
+    let div = builder.dom().select_first("div").unwrap().as_node().clone();
+    builder.set_attribute(&div, "style", "color: red").unwrap();
+    let third = builder.layout_tree().layout(quirks_mode, viewport);
+    assert!(!Rc::ptr_eq(&second.root.fragment, &third.root.fragment));
+}
+
+#[test]
+fn layouts_with_compatible_constraints_hit_the_cache() {
+    let builder = constructed_builder("<!doctype html><div>a</div>");
+    let quirks_mode = builder.dom().as_document().unwrap().quirks_mode();
+    let viewport = euclid::Size2D::new(Au::from_f32_px(800.0), Au::from_f32_px(600.0));
+    let shorter_viewport = euclid::Size2D::new(Au::from_f32_px(800.0), Au::from_f32_px(400.0));
+    let narrower_viewport = euclid::Size2D::new(Au::from_f32_px(400.0), Au::from_f32_px(600.0));
+
+    // The block size of the viewport only affects percentages, and there are
+    // none in this document.
+    let first = builder.layout_tree().layout(quirks_mode, viewport);
+    let second = builder.layout_tree().layout(quirks_mode, shorter_viewport);
+    assert!(Rc::ptr_eq(&first.root.fragment, &second.root.fragment));
+
+    let third = builder.layout_tree().layout(quirks_mode, narrower_viewport);
+    assert!(!Rc::ptr_eq(&second.root.fragment, &third.root.fragment));
+}
+
+#[test]
+fn boxes_point_back_to_their_dom_nodes() {
+    let builder = constructed_builder("<!doctype html><span>a<div>b</div>c</span>");
+    let span = builder.dom().select_first("span").unwrap().as_node().clone();
+    let div = builder.dom().select_first("div").unwrap().as_node().clone();
+    let tree = builder.layout_tree();
+    let ids = builder.dom_node_ids();
+    let span_box = builder.principal_boxes()[&ids.get(&span).unwrap()];
+    let div_box = builder.principal_boxes()[&ids.get(&div).unwrap()];
+
+    assert!(tree[div_box].dom_node() == Some(div));
+    assert!(tree[span_box].dom_node() == Some(span.clone()));
+    // The continuation of the span points to the span as well, while the
+    // anonymous block wrapping the div points nowhere.
+    let div_wrapper = tree[div_box].parent.unwrap();
+    let continuation = tree[tree[div_wrapper].next_sibling.unwrap()].first_child().unwrap();
+    assert!(tree[continuation].dom_node() == Some(span));
+    assert!(tree[div_wrapper].dom_node().is_none());
+}
+
+#[test]
+fn containers_keep_track_of_their_children() {
+    let mut builder = constructed_builder(
+        "<!doctype html><div id=a><p>a</p><p>b</p><p style=float:left>c</p><p style=position:absolute>d</p></div>\
+         <div id=b>e<span>f</span></div>",
+    );
+    let ps = builder.dom().select("p").unwrap().map(|p| p.as_node().clone()).collect::<Vec<_>>();
+    let box_of = |builder: &LayoutTreeBuilder, node: &kuchiki::NodeRef| {
+        builder.principal_boxes()[&builder.dom_node_ids().get(node).unwrap()]
+    };
+    let a = builder.dom().select_first("#a").unwrap().as_node().clone();
+    let b = builder.dom().select_first("#b").unwrap().as_node().clone();
+
+    {
+        let tree = builder.layout_tree();
+        let info = tree[box_of(&builder, &a)].children_info();
+        assert_eq!(info.count, 4);
+        assert_eq!(info.in_flow_count, 2);
+        assert!(!info.has_in_flow_inline_children());
+        assert_eq!(info.last_in_flow_child, Some(box_of(&builder, &ps[1])));
+        assert!(!tree[box_of(&builder, &a)].establishes_ifc(tree));
+
+        let info = tree[box_of(&builder, &b)].children_info();
+        assert_eq!(info.count, 2);
+        assert_eq!(info.in_flow_inline_count, 2);
+        assert!(tree[box_of(&builder, &b)].establishes_ifc(tree));
+    }
+
+    builder.remove_child(&ps[1]).unwrap();
+    builder.layout_tree().assert_consistent();
+    let tree = builder.layout_tree();
+    let info = tree[box_of(&builder, &a)].children_info();
+    assert_eq!(info.count, 3);
+    assert_eq!(info.last_in_flow_child, Some(box_of(&builder, &ps[0])));
+    assert_eq!(tree[box_of(&builder, &a)].child_count(), 3);
+}
+
+#[test]
+fn node_flags_follow_insertions_and_style_changes() {
+    let mut builder = constructed_builder("<!doctype html><span>a<div>b</div>c</span><p>d</p>");
+    let span = builder.dom().select_first("span").unwrap().as_node().clone();
+    let div = builder.dom().select_first("div").unwrap().as_node().clone();
+    let p = builder.dom().select_first("p").unwrap().as_node().clone();
+    let box_of = |builder: &LayoutTreeBuilder, node: &kuchiki::NodeRef| {
+        builder.principal_boxes()[&builder.dom_node_ids().get(node).unwrap()]
+    };
+
+    {
+        let tree = builder.layout_tree();
+        let flags = |id| tree[id].flags();
+        let div_wrapper = tree[box_of(&builder, &div)].parent.unwrap();
+        assert!(flags(div_wrapper).contains(LayoutNodeFlags::IS_ANONYMOUS | LayoutNodeFlags::IS_IB_SPLIT));
+        assert!(flags(box_of(&builder, &span)).contains(LayoutNodeFlags::IS_IB_SPLIT));
+        assert!(!flags(box_of(&builder, &span)).contains(LayoutNodeFlags::IS_ANONYMOUS));
+        assert!(!flags(box_of(&builder, &p)).intersects(LayoutNodeFlags::IS_IB_SPLIT | LayoutNodeFlags::ESTABLISHES_BFC));
+        assert!(flags(tree.root()).contains(LayoutNodeFlags::ESTABLISHES_BFC));
+    }
+
+    builder.set_attribute(&p, "style", "overflow: hidden").unwrap();
+    assert!(builder.layout_tree()[box_of(&builder, &p)].establishes_bfc(builder.layout_tree()));
+    builder.set_attribute(&p, "style", "writing-mode: vertical-rl").unwrap();
+    assert!(builder.layout_tree()[box_of(&builder, &p)].establishes_bfc(builder.layout_tree()));
+    builder.remove_attribute(&p, "style").unwrap();
+    assert!(!builder.layout_tree()[box_of(&builder, &p)].establishes_bfc(builder.layout_tree()));
+    builder.layout_tree().assert_consistent();
+}
+
+#[test]
+fn out_of_flow_boxes_register_with_their_containing_block() {
+    let mut builder = constructed_builder(
+        "<!doctype html><div id=rel style=position:relative><p id=block><span id=abs style=position:absolute>a</span>\
+         <span id=float style=float:left>b</span></p></div><span id=fixed style=position:fixed>c</span>",
+    );
+    let node = |builder: &LayoutTreeBuilder, selector: &str| builder.dom().select_first(selector).unwrap().as_node().clone();
+    let box_of = |builder: &LayoutTreeBuilder, selector: &str| {
+        builder.principal_boxes()[&builder.dom_node_ids().get(&node(builder, selector)).unwrap()]
+    };
+    builder.layout_tree().assert_consistent();
+
+    {
+        let tree = builder.layout_tree();
+        assert_eq!(tree[box_of(&builder, "#rel")].oof_descendants(), &[box_of(&builder, "#abs")]);
+        assert_eq!(tree[box_of(&builder, "#block")].oof_descendants(), &[box_of(&builder, "#float")]);
+        assert_eq!(tree.root_node().oof_descendants(), &[box_of(&builder, "#fixed")]);
+        assert!(tree[box_of(&builder, "#rel")].flags().contains(LayoutNodeFlags::HAS_OOF_DESCENDANTS));
+    }
+
+    // Making the containing block static moves the abspos box to the
+    // viewport.
+    let rel = node(&builder, "#rel");
+    builder.set_attribute(&rel, "style", "").unwrap();
+    builder.layout_tree().assert_consistent();
+    let tree = builder.layout_tree();
+    assert!(tree[box_of(&builder, "#rel")].oof_descendants().is_empty());
+    assert!(!tree[box_of(&builder, "#rel")].flags().contains(LayoutNodeFlags::HAS_OOF_DESCENDANTS));
+    let mut viewport_oofs = tree.root_node().oof_descendants().to_vec();
+    viewport_oofs.sort_by_key(|id| format!("{:?}", id));
+    let mut expected = vec![box_of(&builder, "#abs"), box_of(&builder, "#fixed")];
+    expected.sort_by_key(|id| format!("{:?}", id));
+    assert_eq!(viewport_oofs, expected);
+
+    let abs = node(&builder, "#abs");
+    builder.remove_child(&abs).unwrap();
+    builder.layout_tree().assert_consistent();
+    assert_eq!(builder.layout_tree().root_node().oof_descendants(), &[box_of(&builder, "#fixed")]);
+}
+
+#[test]
+fn dom_node_ids_follow_dom_mutations() {
+    let mut builder = constructed_builder("<!doctype html><div>a</div><div>b</div>");
+    let body = builder.dom().select_first("body").unwrap().as_node().clone();
+    let first = body.first_child().unwrap();
+    let first_id = builder.dom_node_ids().get(&first).unwrap();
+    assert!(builder.dom_node_ids().node(first_id) == Some(first.clone()));
+
+    builder.remove_child(&first).unwrap();
+    assert!(builder.dom_node_ids().get(&first).is_none());
+    assert!(builder.dom_node_ids().node(first_id).is_none());
+
+    builder.append_child(&body, first.clone()).unwrap();
+    let new_id = builder.dom_node_ids().get(&first).unwrap();
+    assert_ne!(first_id, new_id);
+    assert!(builder.principal_boxes().contains_key(&new_id));
+    let tree = builder.layout_tree();
+    assert!(builder.principal_boxes().values().all(|id| tree.contains(*id)));
+}
+
+#[test]
+fn allocators_iterate_over_live_items() {
+    let mut allocator = Allocator::default();
+    let ids = (0..3).map(|i| allocator.allocate(i)).collect::<Vec<_>>();
+    allocator.deallocate(ids[1]);
+    let live = allocator.iter().map(|(id, item)| (id, *item)).collect::<Vec<_>>();
+    assert_eq!(live, vec![(ids[0], 0), (ids[2], 2)]);
+
+    for (_, item) in allocator.iter_mut() {
+        *item *= 10;
+    }
+    assert_eq!(allocator[ids[2]], 20);
+
+    let builder = constructed_builder("<!doctype html><div>a<span>b</span></div>");
+    let tree = builder.layout_tree();
+    // The viewport, html, body, div, span and the two text boxes.
+    assert_eq!(tree.iter_nodes().count(), 7);
+    assert!(tree.iter_nodes().all(|(id, _)| tree.contains(id)));
+}
+
+#[test]
+#[should_panic(expected = "Use of node after free")]
+fn stale_allocator_ids_dont_alias_new_items() {
+    let mut allocator = Allocator::default();
+    let first = allocator.allocate("first");
+    allocator.deallocate(first);
+    let second = allocator.allocate("second");
+    assert_eq!(first.index(), second.index());
+    assert!(!allocator.contains(first));
+    assert!(allocator.get(first).is_none());
+    let _ = allocator[first];
+}
+
+#[test]
+fn root_styles_propagate_to_the_viewport() {
+    let mut builder = constructed_builder(
+        "<!doctype html><html style='writing-mode: vertical-rl; direction: rtl'><body style='overflow: hidden'>",
+    );
+    let html = builder.dom().select_first("html").unwrap().as_node().clone();
+    let body = builder.dom().select_first("body").unwrap().as_node().clone();
+    let box_style = |builder: &LayoutTreeBuilder, node: &kuchiki::NodeRef| {
+        let id = builder.principal_boxes()[&builder.dom_node_ids().get(node).unwrap()];
+        builder.layout_tree()[id].style.clone()
+    };
+
+    let viewport = builder.layout_tree().root_node().style.clone();
+    assert!(viewport.writing_mode.is_vertical());
+    assert!(!viewport.writing_mode.is_bidi_ltr());
+    assert_eq!(viewport.overflow_x, Overflow::Hidden);
+    // The body doesn't establish a BFC, since its overflow has been propagated.
+    assert_eq!(box_style(&builder, &body).overflow_x, Overflow::Visible);
+
+    // The overflow of the root element takes precedence over the body's.
+    builder.set_attribute(&html, "style", "overflow: scroll").unwrap();
+    let viewport = builder.layout_tree().root_node().style.clone();
+    assert!(!viewport.writing_mode.is_vertical());
+    assert_eq!(viewport.overflow_x, Overflow::Scroll);
+    assert_eq!(box_style(&builder, &html).overflow_x, Overflow::Visible);
+    assert_eq!(box_style(&builder, &body).overflow_x, Overflow::Hidden);
+}
+
+#[test]
+fn memory_reports_count_shared_data_once() {
+    let builder = constructed_builder("<!doctype html><div>abc</div><div>abc</div>");
+    let tree = builder.layout_tree();
+    let quirks_mode = builder.dom().as_document().unwrap().quirks_mode();
+    let viewport = euclid::Size2D::new(Au::from_f32_px(800.0), Au::from_f32_px(600.0));
+
+    let without_fragments = memory_report(tree, None);
+    assert_eq!(without_fragments.text, 6);
+    assert_eq!(without_fragments.fragments, 0);
+    assert_eq!(without_fragments.shaped_runs, 0);
+
+    let fragment_tree = tree.layout(quirks_mode, viewport);
+    let report = memory_report(tree, Some(&fragment_tree));
+    assert!(report.fragments > 0);
+    assert!(report.shaped_runs > 0);
+    assert!(report.text > without_fragments.text);
+    assert_eq!(report.nodes, without_fragments.nodes);
+
+    // Laying out again without changes reuses the same fragments.
+    let relayout = tree.layout(quirks_mode, viewport);
+    assert_eq!(memory_report(tree, Some(&relayout)), report);
+    assert_eq!(report.total(), report.nodes + report.styles + report.text + report.fragments + report.shaped_runs);
+}
+
+#[test]
+fn form_controls_generate_replaced_boxes() {
+    let builder = constructed_builder(
+        "<!doctype html><input type=hidden><input type=checkbox><textarea cols=10></textarea><textarea></textarea><button>b</button>",
+    );
+    let tree = builder.layout_tree();
+    let box_for = |selector: &str, index: usize| {
+        let node = builder.dom().select(selector).unwrap().nth(index).unwrap().as_node().clone();
+        let id = builder.dom_node_ids().get(&node).unwrap();
+        builder.principal_boxes().get(&id).map(|id| &tree[*id])
+    };
+    let intrinsic_size = |selector: &str, index: usize| match box_for(selector, index).unwrap().kind {
+        LayoutNodeKind::Leaf {
+            kind: LeafKind::Replaced { intrinsic_size, .. },
+        } => intrinsic_size,
+        ref kind => panic!("Expected a replaced box, got {:?}", kind),
+    };
+
+    assert!(box_for("input", 0).is_none());
+    assert_eq!(intrinsic_size("input", 1), euclid::size2(Au::from_px(13), Au::from_px(13)));
+    let narrow = intrinsic_size("textarea", 0);
+    let wide = intrinsic_size("textarea", 1);
+    assert_eq!(narrow.width * 2, wide.width);
+    assert_eq!(narrow.height, wide.height);
+    // Buttons render their contents, so they're regular block containers.
+    assert!(box_for("button", 0).unwrap().is_block_container());
+}
+
+fn replaced_size(builder: &LayoutTreeBuilder, node: &kuchiki::NodeRef) -> euclid::default::Size2D<Au> {
+    let id = builder.principal_boxes()[&builder.dom_node_ids().get(node).unwrap()];
+    match builder.layout_tree()[id].kind {
+        LayoutNodeKind::Leaf {
+            kind: LeafKind::Replaced { intrinsic_size, .. },
+        } => intrinsic_size,
+        ref kind => panic!("Expected a replaced box, got {:?}", kind),
+    }
+}
+
+#[test]
+fn image_sizes_honor_attributes() {
+    let builder = constructed_builder("<!doctype html><img width=40><img width=40 height=30>");
+    let images = builder.dom().select("img").unwrap().map(|i| i.as_node().clone()).collect::<Vec<_>>();
+    assert_eq!(laid_out_size(&builder, &images[0]), euclid::size2(Au::from_px(40), Au::from_px(150)));
+    assert_eq!(laid_out_size(&builder, &images[1]), euclid::size2(Au::from_px(40), Au::from_px(30)));
+}
+
+#[test]
+fn svg_and_canvas_generate_replaced_boxes() {
+    let builder = constructed_builder(
+        "<!doctype html>\
+         <svg width=40 height=20><rect width=10 height=10 /></svg>\
+         <svg width=40 viewBox='0 0 100 50'></svg>\
+         <svg viewBox='0, 0, 100, 100'></svg>\
+         <svg></svg>\
+         <canvas></canvas>\
+         <canvas width=10 height=20></canvas>",
+    );
+    let size_of = |selector: &str| {
+        builder
+            .dom()
+            .select(selector)
+            .unwrap()
+            .map(|e| replaced_size(&builder, e.as_node()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        size_of("svg"),
+        [
+            euclid::size2(Au::from_px(40), Au::from_px(20)),
+            euclid::size2(Au::from_px(40), Au::from_px(20)),
+            euclid::size2(Au::from_px(300), Au::from_px(300)),
+            euclid::size2(Au::from_px(300), Au::from_px(150)),
+        ]
+    );
+    assert_eq!(
+        size_of("canvas"),
+        [
+            euclid::size2(Au::from_px(300), Au::from_px(150)),
+            euclid::size2(Au::from_px(10), Au::from_px(20)),
+        ]
+    );
+    // The contents of the svg don't generate boxes.
+    let rect = builder.dom().select_first("rect").unwrap().as_node().clone();
+    assert!(builder.principal_boxes().get(&builder.dom_node_ids().get(&rect).unwrap()).is_none());
+}
+
+#[test]
+fn presentational_hints_are_overridden_by_author_styles() {
+    let builder = constructed_builder(
+        "<!doctype html><style>.sized { width: 10px; height: auto }</style>\
+         <img width=40 height=20 class=sized><img width=50% height=20><table bgcolor=ff0000 border=3></table>",
+    );
+    let images = builder.dom().select("img").unwrap().map(|i| i.as_node().clone()).collect::<Vec<_>>();
+    assert_eq!(laid_out_size(&builder, &images[0]), euclid::size2(Au::from_px(10), Au::from_px(5)));
+    // Percentages resolve against the 400px wide containing block.
+    assert_eq!(laid_out_size(&builder, &images[1]), euclid::size2(Au::from_px(200), Au::from_px(20)));
+
+    let table = builder.dom().select_first("table").unwrap().as_node().clone();
+    let style = &builder.layout_tree()[builder.principal_boxes()[&builder.dom_node_ids().get(&table).unwrap()]].style;
+    assert_eq!(style.border_top_width.0, Au::from_px(3));
+    assert_eq!(style.resolve_color(&style.background_color), cssparser::RGBA::new(255, 0, 0, 255));
+}
+
+#[cfg(feature = "image")]
+#[test]
+fn image_sizes_come_from_decoded_images() {
+    let resources = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("resources");
+    let html = "<!doctype html><img src=20x10.png><img src=20x10.png width=40><img src=20x10.png height=5 width=7>";
+    let mut builder = LayoutTreeBuilder::new_with_base_directory(&mut Cursor::new(html), Some(&resources)).unwrap();
+    builder.construct().unwrap();
+    let images = builder.dom().select("img").unwrap().map(|i| i.as_node().clone()).collect::<Vec<_>>();
+    assert_eq!(laid_out_size(&builder, &images[0]), euclid::size2(Au::from_px(20), Au::from_px(10)));
+    assert_eq!(laid_out_size(&builder, &images[1]), euclid::size2(Au::from_px(40), Au::from_px(20)));
+    assert_eq!(laid_out_size(&builder, &images[2]), euclid::size2(Au::from_px(7), Au::from_px(5)));
+}
+
+#[test]
+fn logical_rects_intersect_and_contain_points() {
+    let wm = WritingMode::VERTICAL;
+    let rect = |i, b, inline, block| {
+        LogicalRect::new(wm, Au::from_px(i), Au::from_px(b), Au::from_px(inline), Au::from_px(block))
+    };
+    let point = |i, b| LogicalPoint::new(wm, Au::from_px(i), Au::from_px(b));
+
+    let a = rect(0, 0, 100, 50);
+    let b = rect(50, 25, 100, 50);
+    assert_eq!(a.intersection(&b), Some(rect(50, 25, 50, 25)));
+    assert_eq!(a.union(&b), rect(0, 0, 150, 75));
+    assert!(a.intersects(&b));
+    // Rects that only share an edge don't intersect.
+    assert_eq!(a.intersection(&rect(100, 0, 10, 10)), None);
+    assert!(!a.intersects(&rect(0, 50, 10, 10)));
+
+    assert!(a.contains(&point(0, 0)));
+    assert!(a.contains(&point(99, 49)));
+    assert!(!a.contains(&point(100, 0)));
+    assert!(!a.contains(&point(0, 50)));
+
+    assert!(!a.is_empty());
+    assert!(rect(10, 10, 0, 50).is_empty());
+    assert!(rect(10, 10, 50, 0).is_empty());
+}
+
+/// Lays out the principal box of `node` on its own, with a 400x300
+/// containing block, and returns its fragment.
+fn laid_out_fragment(builder: &LayoutTreeBuilder, node: &kuchiki::NodeRef) -> Rc<Fragment> {
+    let tree = builder.layout_tree();
+    let node = &tree[builder.principal_boxes()[&builder.dom_node_ids().get(node).unwrap()]];
+    let wm = node.writing_mode();
+    let containing_block = LogicalSize::new(wm, Au::from_px(400), Au::from_px(300));
+    let context = LayoutContext {
+        quirks_mode: builder.dom().as_document().unwrap().quirks_mode(),
+        layout_tree: tree,
+        initial_containing_block_size: euclid::size2(Au::from_px(800), Au::from_px(600)),
+    };
+    let constraints = ConstraintSpaceBuilder::new(ContainingBlockInfo {
+        size: AvailableSize::definite(wm, containing_block),
+        writing_mode: wm,
+        is_absolute_containing_block: true,
+        is_fixed_containing_block: true,
+    })
+    .build();
+    node.layout(&context, &constraints, None).root_fragment.fragment
+}
+
+#[test]
+fn constraint_spaces_track_containing_blocks() {
+    let builder = constructed_builder(
+        "<!doctype html><div id=rel style=position:relative><p id=static><span id=abs style=position:absolute></span>\
+         <span id=fixed style=position:fixed></span></p></div>",
+    );
+    let builder = &builder;
+    let tree = builder.layout_tree();
+    let node = move |selector: &str| {
+        let node = builder.dom().select_first(selector).unwrap().as_node().clone();
+        &tree[builder.principal_boxes()[&builder.dom_node_ids().get(&node).unwrap()]]
+    };
+    let wm = tree.root_node().writing_mode();
+    let size = |px| AvailableSize::definite(wm, LogicalSize::new(wm, Au::from_px(px), Au::from_px(px)));
+    let icb = ContainingBlockInfo::initial(wm, euclid::size2(Au::from_px(800), Au::from_px(800)));
+    let fragmentainer = Fragmentainer {
+        block_size: Au::from_px(500),
+        block_offset: Au(0),
+    };
+    let viewport = ConstraintSpaceBuilder::new(icb).fragmentainer(Some(fragmentainer)).build();
+    assert_eq!(viewport.available_size, size(800));
+    assert!(viewport.is_new_formatting_context);
+    assert_eq!(viewport.bfc_block_offset, Some(Au(0)));
+
+    let in_rel = ConstraintSpaceBuilder::for_children_of(&viewport, ContainingBlockInfo::new(node("#rel"), size(400))).build();
+    assert!(in_rel.containing_block.is_absolute_containing_block);
+    assert!(!in_rel.containing_block.is_fixed_containing_block);
+    assert_eq!(in_rel.percentage_resolution_size, size(400));
+    assert!(!in_rel.is_new_formatting_context);
+    assert_eq!(in_rel.bfc_block_offset, None);
+    assert_eq!(in_rel.fragmentainer, Some(fragmentainer));
+    assert!(in_rel.exclusion_space.is_empty());
+    let in_static =
+        ConstraintSpaceBuilder::for_children_of(&in_rel, ContainingBlockInfo::new(node("#static"), size(200))).build();
+    assert!(!in_static.containing_block.is_absolute_containing_block);
+
+    assert_eq!(in_static.containing_block_for(node("#static")).size, size(200));
+    assert_eq!(in_static.containing_block_for(node("#abs")).size, size(400));
+    assert_eq!(in_static.containing_block_for(node("#fixed")).size, size(800));
+    assert_eq!(in_static.containing_block_writing_mode(), wm);
+}
+
+#[test]
+fn layout_algorithms_are_chosen_per_box() {
+    let builder = constructed_builder(
+        "<!doctype html><div id=blocks><p id=inlines>Text <span id=span>inline</span></p></div>\
+         <div id=flow-root style=display:flow-root></div><fieldset id=fieldset></fieldset><img id=img>",
+    );
+    let builder = &builder;
+    let tree = builder.layout_tree();
+    let algorithm = |selector: &str| {
+        let node = builder.dom().select_first(selector).unwrap().as_node().clone();
+        tree[builder.principal_boxes()[&builder.dom_node_ids().get(&node).unwrap()]].layout_algorithm(tree)
+    };
+    assert_eq!(tree.root_node().layout_algorithm(tree), Some(LayoutAlgorithmKind::Block));
+    // Block containers that don't establish a formatting context are laid
+    // out by their parent.
+    assert_eq!(algorithm("#blocks"), None);
+    assert_eq!(algorithm("#inlines"), Some(LayoutAlgorithmKind::Inline));
+    assert_eq!(algorithm("#span"), None);
+    assert_eq!(algorithm("#flow-root"), Some(LayoutAlgorithmKind::Block));
+    assert_eq!(algorithm("#fieldset"), Some(LayoutAlgorithmKind::Fieldset));
+    assert_eq!(algorithm("#img"), Some(LayoutAlgorithmKind::Replaced));
+}
+
+/// Like `laid_out_fragment`, but returns the physical size of the border box.
+fn laid_out_size(builder: &LayoutTreeBuilder, node: &kuchiki::NodeRef) -> euclid::default::Size2D<Au> {
+    let fragment = laid_out_fragment(builder, node);
+    fragment.size.to_physical(fragment.style.writing_mode)
+}
+
+#[test]
+fn aspect_ratio_transfers_sizes() {
+    let builder = constructed_builder(
+        "<!doctype html>\
+         <div style='display: flow-root; width: 200px; aspect-ratio: 4 / 1'></div>\
+         <div style='display: flow-root; width: 200px; padding: 5px; aspect-ratio: 2'></div>\
+         <div style='display: flow-root; width: 200px; box-sizing: border-box; padding: 5px; aspect-ratio: 2'></div>\
+         <div style='display: flow-root; width: 200px; height: 10px; aspect-ratio: 2'></div>\
+         <img width=40 height=30 style='width: 80px; height: auto'>\
+         <img width=40 height=30 style='width: auto; height: 60px; aspect-ratio: 1 / 2'>",
+    );
+    let divs = builder.dom().select("div").unwrap().map(|d| d.as_node().clone()).collect::<Vec<_>>();
+    assert_eq!(laid_out_size(&builder, &divs[0]), euclid::size2(Au::from_px(200), Au::from_px(50)));
+    assert_eq!(laid_out_size(&builder, &divs[1]), euclid::size2(Au::from_px(210), Au::from_px(110)));
+    assert_eq!(laid_out_size(&builder, &divs[2]), euclid::size2(Au::from_px(200), Au::from_px(100)));
+    // An explicit block size wins over the ratio.
+    assert_eq!(laid_out_size(&builder, &divs[3]).height, Au::from_px(10));
+
+    let images = builder.dom().select("img").unwrap().map(|i| i.as_node().clone()).collect::<Vec<_>>();
+    // Images that fail to load have no natural aspect ratio, but their
+    // attributes map to one, besides mapping to sizes that `auto` overrides.
+    assert_eq!(laid_out_size(&builder, &images[0]), euclid::size2(Au::from_px(80), Au::from_px(60)));
+    assert_eq!(laid_out_size(&builder, &images[1]), euclid::size2(Au::from_px(30), Au::from_px(60)));
+}
+
+#[cfg(feature = "image")]
+#[test]
+fn natural_aspect_ratio_transfers_sizes() {
+    let resources = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("resources");
+    let html = "<!doctype html><img src=20x10.png style='width: 100px'><img src=20x10.png style='height: 100px'>\
+                <img src=20x10.png style='width: 100px; aspect-ratio: 1'>";
+    let mut builder = LayoutTreeBuilder::new_with_base_directory(&mut Cursor::new(html), Some(&resources)).unwrap();
+    builder.construct().unwrap();
+    let images = builder.dom().select("img").unwrap().map(|i| i.as_node().clone()).collect::<Vec<_>>();
+    assert_eq!(laid_out_size(&builder, &images[0]), euclid::size2(Au::from_px(100), Au::from_px(50)));
+    assert_eq!(laid_out_size(&builder, &images[1]), euclid::size2(Au::from_px(200), Au::from_px(100)));
+    // An explicit ratio without `auto` overrides the natural one.
+    assert_eq!(laid_out_size(&builder, &images[2]), euclid::size2(Au::from_px(100), Au::from_px(100)));
+}
+
+#[test]
+fn linked_stylesheets_apply_in_document_order() {
+    let resources = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("resources");
+    let html = "<!doctype html><link rel=stylesheet href=missing.css><link rel='STYLESHEET' href=stylesheet.css>\
+                <style>.overridden { width: 50px }</style><div class=linked></div><div class='linked overridden'></div>";
+    let mut builder = LayoutTreeBuilder::new_with_base_directory(&mut Cursor::new(html), Some(&resources)).unwrap();
+    builder.construct().unwrap();
+    let divs = builder.dom().select("div").unwrap().map(|d| d.as_node().clone()).collect::<Vec<_>>();
+    assert_eq!(laid_out_size(&builder, &divs[0]), euclid::size2(Au::from_px(100), Au::from_px(20)));
+    assert_eq!(laid_out_size(&builder, &divs[1]), euclid::size2(Au::from_px(50), Au::from_px(20)));
+}
+
+#[test]
+fn stylesheets_are_decoded_honoring_boms_and_charset_rules() {
+    assert_eq!(css::decode_stylesheet(b"@charset \"windows-1252\"; a\x80"), "@charset \"windows-1252\"; a\u{20AC}");
+    assert_eq!(css::decode_stylesheet(b"\xFF\xFEa\x00b\x00"), "ab");
+    assert_eq!(css::decode_stylesheet(b"\xFE\xFF\x00a\x00b"), "ab");
+    // A byte order mark wins over the `@charset` rule.
+    assert_eq!(css::decode_stylesheet(b"\xEF\xBB\xBF@charset \"latin1\"; \xC3\xA9"), "@charset \"latin1\"; \u{E9}");
+    // Sheets in UTF-16 can't declare so with a `@charset` rule.
+    assert_eq!(css::decode_stylesheet(b"@charset \"utf-16\"; \xC3\xA9"), "@charset \"utf-16\"; \u{E9}");
+
+    let resources = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("resources");
+    let html = "<!doctype html><link rel=stylesheet href=bom.css><div class=with-bom></div>";
+    let mut builder = LayoutTreeBuilder::new_with_base_directory(&mut Cursor::new(html), Some(&resources)).unwrap();
+    builder.construct().unwrap();
+    let div = builder.dom().select_first("div").unwrap().as_node().clone();
+    assert_eq!(laid_out_size(&builder, &div), euclid::size2(Au::from_px(30), Au::from_px(30)));
+}
+
+#[test]
+fn bucketed_rules_match_like_unbucketed_ones() {
+    let builder = constructed_builder(
+        "<!doctype html><style>\
+           div { display: flow-root; height: 1px }\
+           .c { width: 60px }\
+           div { width: 70px }\
+           DIV.b.a { width: 20px }\
+           .outer .inner + div { width: 40px }\
+           section > div:not(.skip) { width: 50px }\
+         </style>\
+         <div class='a b'></div>\
+         <div class=outer><p class=inner></p><div id=sibling></div></div>\
+         <section><div id=child></div><div class=skip></div></section>\
+         <div class=c></div>",
+    );
+    let width = |selector: &str| {
+        let node = builder.dom().select_first(selector).unwrap().as_node().clone();
+        laid_out_size(&builder, &node).width
+    };
+    assert_eq!(width(".a"), Au::from_px(20));
+    assert_eq!(width("#sibling"), Au::from_px(40));
+    assert_eq!(width("#child"), Au::from_px(50));
+    assert_eq!(width(".skip"), Au::from_px(70));
+    // Rules from different buckets still cascade in specificity order.
+    assert_eq!(width(".c"), Au::from_px(60));
+}
+
+#[test]
+fn iframes_lay_out_their_document_in_their_content_box() {
+    let resources = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("resources");
+    // The nested document embeds itself, so this also tests that we stop
+    // loading documents at some point.
+    let html = "<!doctype html><iframe src=iframe.html style='width: 200px'></iframe><iframe src=missing.html></iframe>";
+    let mut builder = LayoutTreeBuilder::new_with_font_source(
+        &mut Cursor::new(html),
+        Some(&resources),
+        FontSource::BundledTestFont,
+    )
+    .unwrap();
+    builder.construct().unwrap();
+    let iframes = builder.dom().select("iframe").unwrap().map(|i| i.as_node().clone()).collect::<Vec<_>>();
+
+    let children = |fragment: &Fragment| match fragment.kind {
+        FragmentKind::Container { ref children, .. } => children.to_vec(),
+        _ => panic!("Expected a container fragment"),
+    };
+
+    let loaded = laid_out_fragment(&builder, &iframes[0]);
+    assert_eq!(loaded.size.to_physical(loaded.style.writing_mode), euclid::size2(Au::from_px(204), Au::from_px(154)));
+    let nested = children(&loaded);
+    assert_eq!(nested.len(), 1);
+    assert_eq!((nested[0].offset.i, nested[0].offset.b), (Au::from_px(2), Au::from_px(2)));
+    assert_eq!(nested[0].fragment.size.inline, Au::from_px(200));
+
+    let missing = laid_out_fragment(&builder, &iframes[1]);
+    assert_eq!(missing.size.to_physical(missing.style.writing_mode), euclid::size2(Au::from_px(304), Au::from_px(154)));
+    assert!(children(&missing).is_empty());
+}
+
+#[test]
+fn computed_styles_serialize_as_css() {
+    let builder = constructed_builder(
+        "<!doctype html><div style='display: inline-block; width: calc(10px + 50%); margin-left: auto; \
+         font-family: Foo Bar, \"Baz\", monospace; font-feature-settings: \"liga\" 0; aspect-ratio: auto 16 / 9'>",
+    );
+    let div = builder.dom().select_first("div").unwrap().as_node().clone();
+    let style = &builder.layout_tree()[builder.principal_boxes()[&builder.dom_node_ids().get(&div).unwrap()]].style;
+    let mut css = String::new();
+    style.to_css(&mut css).unwrap();
+    for declaration in &[
+        "display: inline-block;",
+        "width: calc(10px + 50%);",
+        "margin-left: auto;",
+        "margin-right: 0px;",
+        "font-family: Foo Bar, \"Baz\", monospace;",
+        "font-feature-settings: \"liga\" 0;",
+        "font-weight: 400;",
+        "font-stretch: 100%;",
+        "aspect-ratio: auto 16 / 9;",
+        "writing-mode: horizontal-tb;",
+        "color: rgb(0, 0, 0);",
+    ] {
+        assert!(css.lines().any(|line| line == *declaration), "{} not in:\n{}", declaration, css);
+    }
+}
+
+#[test]
+fn computed_style_properties_serialize_by_name() {
+    let builder = constructed_builder(
+        "<!doctype html><div style='writing-mode: vertical-rl; margin-inline-start: 5px; padding-block-end: 2px; \
+         width: 10%; color: blue'></div>",
+    );
+    let div = builder.dom().select_first("div").unwrap().as_node().clone();
+    let style = builder.computed_style(&div).unwrap();
+    assert_eq!(style.serialize_property("width"), "10%");
+    assert_eq!(style.serialize_property("Color"), "rgb(0, 0, 255)");
+    assert_eq!(style.serialize_property("margin-top"), "5px");
+    assert_eq!(style.serialize_property("margin-inline-start"), "5px");
+    assert_eq!(style.serialize_property("padding-left"), "2px");
+    assert_eq!(style.serialize_property("padding-block-end"), "2px");
+    assert_eq!(style.serialize_property("margin"), "");
+    assert_eq!(style.serialize_property("unknown"), "");
+    assert_eq!(builder.computed_style_property(&div, "display").unwrap(), "block");
+    assert_eq!(builder.computed_style_property(&div, "writing-mode").unwrap(), "vertical-rl");
+
+    let css = style.to_css_block();
+    let names = css.lines().map(|line| line.split(':').next().unwrap()).collect::<Vec<_>>();
+    let mut sorted = names.clone();
+    sorted.sort();
+    assert_eq!(names, sorted);
+    for line in css.lines() {
+        let mut parts = line.splitn(2, ": ");
+        let (name, value) = (parts.next().unwrap(), parts.next().unwrap());
+        assert_eq!(format!("{};", style.serialize_property(name)), value);
+    }
+}
+
+#[test]
+fn shorthands_expand_into_their_longhands() {
+    let builder = constructed_builder(
+        "<!doctype html><div style='margin: 1px 2px; padding: 1px 2px 3px; inset: 4px 5px 6px 7px; \
+         border: dashed red; border-left-width: 1px; overflow: hidden; word-wrap: break-word'>",
+    );
+    let div = builder.dom().select_first("div").unwrap().as_node().clone();
+    let style = &builder.layout_tree()[builder.principal_boxes()[&builder.dom_node_ids().get(&div).unwrap()]].style;
+    let mut css = String::new();
+    style.to_css(&mut css).unwrap();
+    for declaration in &[
+        "margin-top: 1px;",
+        "margin-right: 2px;",
+        "margin-bottom: 1px;",
+        "margin-left: 2px;",
+        "padding-right: 2px;",
+        "padding-bottom: 3px;",
+        "padding-left: 2px;",
+        "top: 4px;",
+        "right: 5px;",
+        "bottom: 6px;",
+        "left: 7px;",
+        "border-top-style: dashed;",
+        "border-left-style: dashed;",
+        "border-top-color: rgb(255, 0, 0);",
+        "border-top-width: 3px;",
+        "border-left-width: 1px;",
+        "overflow-x: hidden;",
+        "overflow-y: hidden;",
+        "overflow-wrap: break-word;",
+    ] {
+        assert!(css.lines().any(|line| line == *declaration), "{} not in:\n{}", declaration, css);
+    }
+}
+
+#[test]
+fn only_inherited_properties_are_inherited() {
+    let builder = constructed_builder(
+        "<!doctype html><div style='color: red; white-space: pre; text-indent: 5px; tab-size: 4; \
+         font: italic bold 20px/3 monospace; margin-left: 3px; border: 1px solid; overflow: hidden'>\
+         <span style='display: block'></span></div>",
+    );
+    let css_of = |selector: &str| {
+        let node = builder.dom().select_first(selector).unwrap().as_node().clone();
+        let id = builder.principal_boxes()[&builder.dom_node_ids().get(&node).unwrap()];
+        let mut css = String::new();
+        builder.layout_tree()[id].style.to_css(&mut css).unwrap();
+        css
+    };
+    let parent = css_of("div");
+    let child = css_of("span");
+    for declaration in &[
+        "color: rgb(255, 0, 0);",
+        "white-space: pre;",
+        "text-indent: 5px;",
+        "tab-size: 4;",
+        "font-style: italic;",
+        "font-weight: 700;",
+        "line-height: 3;",
+        "font-family: monospace;",
+    ] {
+        assert!(parent.lines().any(|line| line == *declaration), "{} not in:\n{}", declaration, parent);
+        assert!(child.lines().any(|line| line == *declaration), "{} not inherited:\n{}", declaration, child);
+    }
+    for declaration in &["margin-left: 0px;", "border-top-style: none;", "overflow-x: visible;"] {
+        assert!(child.lines().any(|line| line == *declaration), "{} not reset:\n{}", declaration, child);
+    }
+}
+
+#[test]
+fn property_metadata_describes_every_longhand() {
+    use nglayoutng::css::{PropertyMetadata, PROPERTIES};
+
+    let group_of = |name: &str| PropertyMetadata::find(name).unwrap().logical_group;
+    assert_eq!(group_of("margin-top"), Some("margin"));
+    assert_eq!(group_of("margin-inline-start"), Some("margin"));
+    assert_eq!(group_of("border-left-width"), Some("border-width"));
+    assert_eq!(group_of("border-block-end-color"), Some("border-color"));
+    assert_eq!(group_of("width"), Some("size"));
+    assert_eq!(group_of("max-block-size"), Some("max-size"));
+    assert_eq!(group_of("line-height"), None);
+    assert_eq!(group_of("color"), None);
+
+    assert!(PropertyMetadata::find("MARGIN-TOP").is_some());
+    assert!(PropertyMetadata::find("margin").is_none());
+    assert!(PropertyMetadata::find("writing-mode").unwrap().early);
+    assert!(PropertyMetadata::find("font-family").unwrap().inherited);
+    assert!(PropertyMetadata::find("inline-size").unwrap().logical);
+    assert!(!PropertyMetadata::find("width").unwrap().logical);
+
+    // Every property of the computed style is a longhand we can parse.
+    let style = constructed_builder("<!doctype html>").layout_tree().root_node().style.clone();
+    let mut css = String::new();
+    style.to_css(&mut css).unwrap();
+    for line in css.lines() {
+        let name = line.split(':').next().unwrap();
+        assert!(PROPERTIES.iter().any(|p| p.name == name), "{} is not a longhand", name);
+        assert!(!PropertyMetadata::find(name).unwrap().logical);
+    }
+
+    let mut names = PROPERTIES.iter().map(|p| p.name).collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    assert_eq!(names.len(), PROPERTIES.len());
+}
+
+#[test]
+fn styles_interpolate_between_computed_values() {
+    use nglayoutng::animate::{interpolate, Animate};
+    use nglayoutng::transitions::{interpolate_style, Transition};
+    use std::time::Duration;
+
+    let from = LengthPercentage {
+        fixed: Length(Au::from_px(10)),
+        percentage: None,
+    };
+    let to = LengthPercentage {
+        fixed: Length(Au::from_px(20)),
+        percentage: Some(Percentage(0.5)),
+    };
+    assert_eq!(interpolate(&from, &to, 0.5).to_css_string(), "calc(15px + 25%)");
+    assert_eq!(interpolate(&from, &to, 0.).to_css_string(), "10px");
+    let auto = LengthPercentageOrAuto::Auto;
+    let length = LengthPercentageOrAuto::LengthPercentage(from.clone());
+    assert!(auto.animate(&length, 0.5).is_err());
+    assert_eq!(interpolate(&auto, &length, 0.4), auto);
+    assert_eq!(interpolate(&auto, &length, 0.6), length);
+    assert_eq!(interpolate(&WhiteSpace::Pre, &WhiteSpace::Normal, 0.7), WhiteSpace::Normal);
+
+    let red = cssparser::RGBA::new(255, 0, 0, 255);
+    let transparent = cssparser::RGBA::transparent();
+    // Premultiplied, so fading out doesn't darken the color.
+    assert_eq!(interpolate(&red, &transparent, 0.5), cssparser::RGBA::new(255, 0, 0, 128));
+
+    let builder = constructed_builder(
+        "<!doctype html><div id=a style='width: 100px; margin-left: 10px; color: black; display: block'></div>\
+         <div id=b style='width: 200px; margin-left: auto; color: white; display: inline-block'></div>",
+    );
+    let style_of = |selector: &str| {
+        let node = builder.dom().select_first(selector).unwrap().as_node().clone();
+        builder.layout_tree()[builder.principal_boxes()[&builder.dom_node_ids().get(&node).unwrap()]]
+            .style
+            .clone()
+    };
+    let (a, b) = (style_of("#a"), style_of("#b"));
+    let quarter = interpolate_style(&a, &b, 0.25);
+    let mut css = String::new();
+    quarter.to_css(&mut css).unwrap();
+    for declaration in &["width: 125px;", "margin-left: 10px;", "display: block;", "color: rgb(64, 64, 64);"] {
+        assert!(css.lines().any(|line| line == *declaration), "{} not in:\n{}", declaration, css);
+    }
+    assert_eq!(interpolate_style(&a, &b, 0.), *a);
+    assert_eq!(interpolate_style(&a, &b, 1.), *b);
+
+    let transition = Transition::new(a.clone(), b.clone(), Duration::from_secs(4));
+    assert_eq!(transition.progress_at(Duration::from_secs(1)), 0.25);
+    assert_eq!(transition.style_at(Duration::from_secs(1)), quarter);
+    assert!(transition.is_finished_at(Duration::from_secs(5)));
+    assert_eq!(transition.style_at(Duration::from_secs(5)), *b);
+}
+
+#[test]
+fn documents_lay_out_query_mutate_and_render() {
+    use nglayoutng::document::Document;
+
+    let mut document = Document::from_html(&mut Cursor::new(
+        "<!doctype html><div style='width: 100px; height: 50px; padding: 5px; border: 1px solid'></div>",
+    ))
+    .unwrap();
+    document.set_viewport(euclid::size2(Au::from_px(400), Au::from_px(300)));
+    assert_eq!(document.layout().viewport_size, euclid::size2(Au::from_px(400), Au::from_px(300)));
+
+    let geometry = document.query_selector_geometry("div").unwrap().unwrap();
+    assert_eq!(geometry.border_box.size, euclid::size2(112., 62.));
+    assert_eq!(geometry.client_rect.size, euclid::size2(110., 60.));
+    assert_eq!(geometry.content_box.size, euclid::size2(100., 50.));
+    assert_eq!(geometry.content_box.origin - geometry.border_box.origin, euclid::vec2(6., 6.));
+    assert!(document.query_selector_geometry("span").unwrap().is_none());
+    match document.query_selector_geometry("div[") {
+        Err(Error::InvalidSelector(..)) => {},
+        other => panic!("Expected an invalid selector error, got {:?}", other),
+    }
+
+    let div = document.dom().select_first("div").unwrap().as_node().clone();
+    document.mutate().set_attribute(&div, "style", "width: 200px").unwrap();
+    let geometry = document.query_selector_geometry("div").unwrap().unwrap();
+    assert_eq!(geometry.border_box.size.width, 200.);
+
+    let mut mutation = document.mutate();
+    mutation.remove_child(&div).unwrap();
+    assert!(document.query_selector_geometry("div").unwrap().is_none());
+
+    let rendering = document.render(2.0);
+    assert_eq!((rendering.width(), rendering.height()), (800, 600));
+}
+
+#[test]
+fn fragment_tree_diffs_report_changed_fragments() {
+    use nglayoutng::fragment_tree::diff::{diff, FragmentChange};
+
+    let layout = |html: &str, width: i32| {
+        let result = LayoutTreeBuilder::new(&mut Cursor::new(html)).unwrap().build().unwrap();
+        let quirks_mode = result.dom.as_document().unwrap().quirks_mode();
+        result.layout_tree.layout(quirks_mode, euclid::size2(Au::from_px(width), Au::from_px(600)))
+    };
+    let html = "<!doctype html><body style='margin: 0'><div style='height: 10px'></div><div style='height: 20px'></div>";
+    let tree = layout(html, 800);
+    assert!(diff(&tree, &layout(html, 800)).is_empty());
+
+    let diffs = diff(&tree, &layout(html, 400));
+    assert!(!diffs.is_empty());
+    assert!(diffs.iter().all(|d| matches!(d.change, FragmentChange::Resized { .. })));
+
+    let other = "<!doctype html><body style='margin: 0'><div style='height: 15px'></div>\
+                 <div style='height: 20px'></div><div style='height: 5px'></div>";
+    let diffs = diff(&tree, &layout(other, 800));
+    let count = |f: &dyn Fn(&FragmentChange) -> bool| diffs.iter().filter(|d| f(&d.change)).count();
+    assert_eq!(count(&|c| matches!(*c, FragmentChange::Added(..))), 1);
+    assert_eq!(count(&|c| matches!(*c, FragmentChange::Moved { .. })), 1);
+    assert_eq!(count(&|c| matches!(*c, FragmentChange::Removed(..))), 0);
+}
+
+#[test]
+fn inline_items_are_collected_after_white_space_collapsing() {
+    use nglayoutng::layout_algorithms::inline::InlineItem;
+
+    let result = LayoutTreeBuilder::new(&mut Cursor::new(
+        "<!doctype html><p>  Hello   <b> big </b> <span style='display: inline-block'>x</span>  world </p>",
+    ))
+    .unwrap()
+    .build()
+    .unwrap();
+    let quirks_mode = result.dom.as_document().unwrap().quirks_mode();
+    let viewport_size = euclid::size2(Au::from_px(800), Au::from_px(600));
+    let ifcs = result.layout_tree.inline_items(quirks_mode, viewport_size);
+    assert_eq!(ifcs.len(), 2, "{:?}", ifcs);
+
+    let (_, ref items) = ifcs[0];
+    let text = items.iter().filter_map(|item| item.text()).collect::<String>();
+    assert_eq!(text, "Hello big world");
+    let count = |f: &dyn Fn(&InlineItem) -> bool| items.iter().filter(|item| f(item)).count();
+    assert_eq!(count(&|item| matches!(*item, InlineItem::TagStart(..))), 1);
+    assert_eq!(count(&|item| matches!(*item, InlineItem::TagEnd(..))), 1);
+    assert_eq!(count(&|item| matches!(*item, InlineItem::AtomicInline(..))), 1);
+
+    // The inline-block establishes its own formatting context.
+    let (_, ref items) = ifcs[1];
+    assert_eq!(items.iter().filter_map(|item| item.text()).collect::<String>(), "x");
+}
+
+#[test]
+fn text_fragments_map_to_the_text_before_text_transform() {
+    fn collect_text_runs(fragment: &Fragment, runs: &mut Vec<(String, std::ops::Range<usize>)>) {
+        match fragment.kind {
+            FragmentKind::TextRun { ref content, ref range, .. } => runs.push((content.clone(), range.clone())),
+            FragmentKind::Container { ref children, .. } => {
+                for child in children.iter() {
+                    collect_text_runs(&child.fragment, runs);
+                }
+            },
+        }
+    }
+
+    // U+FB01 (LATIN SMALL LIGATURE FI) is three bytes long, but uppercases to
+    // "FI", which is two.
+    let builder = constructed_builder(
+        "<!doctype html><div style='width: 0px; text-transform: uppercase'>\u{FB01}ne ok</div>",
+    );
+    let div = first_node(&builder, "div");
+    let mut runs = vec![];
+    collect_text_runs(&laid_out_fragment(&builder, &div), &mut runs);
+    assert_eq!(runs, vec![("FINE ".to_owned(), 0..6), ("OK".to_owned(), 6..8)]);
+}
+
+#[test]
+fn layout_traces_record_decisions() {
+    use nglayoutng::trace::{self, TraceEvent};
+
+    let result = LayoutTreeBuilder::new(&mut Cursor::new("<!doctype html><p>Hello<br>world"))
+        .unwrap()
+        .build()
+        .unwrap();
+    let quirks_mode = result.dom.as_document().unwrap().quirks_mode();
+    let viewport_size = euclid::size2(Au::from_px(800), Au::from_px(600));
+    assert!(!trace::is_enabled());
+    let (_, events) = trace::collect(|| result.layout_tree.layout(quirks_mode, viewport_size));
+    assert!(!trace::is_enabled());
+
+    let root = result.layout_tree.root();
+    assert!(matches!(events[0], TraceEvent::Layout { node, resuming: false, .. } if node == root));
+    assert!(events.iter().any(|event| match *event {
+        TraceEvent::LayoutResult { node, inline_size, .. } => node == root && inline_size == Au::from_px(800),
+        _ => false,
+    }));
+    let lines = events.iter().filter(|event| matches!(event, TraceEvent::Line { .. })).count();
+    assert_eq!(lines, 2);
+    assert!(events[0].to_json().starts_with("{\"event\":\"layout\",\"node\":"));
+
+    // Nothing changed, so laying out again reuses the previous result.
+    let (_, events) = trace::collect(|| result.layout_tree.layout(quirks_mode, viewport_size));
+    assert_eq!(events, vec![TraceEvent::CacheHit { node: root }]);
+
+    // And nothing is recorded outside of `collect`.
+    result.layout_tree.layout(quirks_mode, viewport_size);
+    let (_, events) = trace::collect(|| {});
+    assert!(events.is_empty());
+}
+
+#[test]
+fn check_layout_assertions_compare_geometry() {
+    let check = |html: &str| {
+        let result = LayoutTreeBuilder::new(&mut Cursor::new(html)).unwrap().build().unwrap();
+        let quirks_mode = result.dom.as_document().unwrap().quirks_mode();
+        let tree = result.layout_tree.layout(quirks_mode, euclid::size2(Au::from_px(800), Au::from_px(600)));
+        check_layout(&result, Some(&tree))
+    };
+
+    let html = "<!doctype html><body style='margin: 0'>\
+                <div style='position: relative; padding: 5px; width: 100px'>\
+                <div style='height: 10px' data-expected-width=100 data-expected-height=10 data-offset-x=5 data-offset-y=5></div>\
+                <div style='height: 20px' data-offset-y=15></div>\
+                </div>";
+    assert_eq!(check(html), Vec::<String>::new());
+
+    let failures = check(&html.replace("data-offset-y=15", "data-offset-y=16"));
+    assert_eq!(failures.len(), 1, "{:?}", failures);
+    assert!(failures[0].contains("data-offset-y"), "{:?}", failures);
+
+    let failures = check("<!doctype html><div data-expected-width=foo></div>");
+    assert_eq!(failures.len(), 1, "{:?}", failures);
+}
+
+/// A xorshift pseudo-random number generator, so that fuzzing failures can
+/// be reproduced from their seed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Xorshift gets stuck at zero.
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+const FUZZ_TAGS: &[&str] = &["div", "span", "em", "fieldset", "legend", "details", "summary", "br"];
+const FUZZ_TEXTS: &[&str] = &["a", " b ", "  ", "c\nd", ""];
+const FUZZ_STYLES: &[&str] = &[
+    "display: block",
+    "display: inline",
+    "display: none",
+    "display: contents",
+    "display: flow-root",
+    "position: absolute",
+    "position: fixed",
+    "float: left",
+    "writing-mode: vertical-rl",
+];
+
+/// Generates a random HTML fragment, nesting elements up to `depth` levels.
+fn random_html(rng: &mut Rng, depth: usize) -> String {
+    let mut html = String::new();
+    for _ in 0..rng.below(4) {
+        if depth == 0 || rng.below(3) == 0 {
+            html.push_str(rng.pick::<&str>(FUZZ_TEXTS));
+            continue;
+        }
+        let tag = *rng.pick(FUZZ_TAGS);
+        html.push('<');
+        html.push_str(tag);
+        if rng.below(2) == 0 {
+            html.push_str(&format!(" style='{}'", rng.pick(FUZZ_STYLES)));
+        }
+        html.push('>');
+        if tag != "br" {
+            html.push_str(&random_html(rng, depth - 1));
+            html.push_str(&format!("</{}>", tag));
+        }
+    }
+    html
+}
+
+/// Builds a random document, and applies `steps` random mutations to it
+/// through the dynamic APIs of the builder, checking the layout tree after
+/// each of them. The mutations are printed so that failures can be reduced.
+fn fuzz_mutations(seed: u64, steps: usize) {
+    let mut rng = Rng::new(seed);
+    let html = format!("<!doctype html>{}", random_html(&mut rng, 3));
+    println!("seed {}: {:?}", seed, html);
+    let mut builder = LayoutTreeBuilder::new(&mut Cursor::new(&html)).unwrap();
+    builder.set_validation(Validation::Full);
+    builder.construct().unwrap();
+    builder.layout_tree().assert_consistent();
+
+    let body = builder.dom().select_first("body").unwrap().as_node().clone();
+    for _ in 0..steps {
+        let nodes = body.inclusive_descendants().collect::<Vec<_>>();
+        let elements = nodes
+            .iter()
+            .filter(|node| node.as_element().is_some_and(|e| &*e.name.local != "br"))
+            .cloned()
+            .collect::<Vec<_>>();
+        let texts = nodes.iter().filter(|node| node.as_text().is_some()).cloned().collect::<Vec<_>>();
+        match rng.below(5) {
+            0 => {
+                let parent = rng.pick(&elements).clone();
+                let children = parent.children().collect::<Vec<_>>();
+                let reference = if children.is_empty() || rng.below(2) == 0 {
+                    None
+                } else {
+                    Some(rng.pick(&children).clone())
+                };
+                let fragment = random_html(&mut rng, 2);
+                println!("insert {:?} into {}", fragment, dom::node_label(&parent));
+                let source = dom::build_dom(&mut Cursor::new(&fragment)).unwrap();
+                let new_children = source.select_first("body").unwrap().as_node().children().collect::<Vec<_>>();
+                for child in new_children {
+                    match reference {
+                        Some(ref reference) => builder.insert_before(&parent, child, reference).unwrap(),
+                        None => builder.append_child(&parent, child).unwrap(),
+                    }
+                }
+            },
+            1 if nodes.len() > 1 => {
+                let node = rng.pick(&nodes[1..]).clone();
+                println!("remove {}", dom::node_label(&node));
+                builder.remove_child(&node).unwrap();
+            },
+            2 => {
+                let element = rng.pick(&elements).clone();
+                let style = *rng.pick(FUZZ_STYLES);
+                println!("set style of {} to {:?}", dom::node_label(&element), style);
+                builder.set_attribute(&element, "style", style).unwrap();
+            },
+            3 => {
+                let element = rng.pick(&elements).clone();
+                println!("remove style of {}", dom::node_label(&element));
+                builder.remove_attribute(&element, "style").unwrap();
+            },
+            4 if !texts.is_empty() => {
+                let text = rng.pick(&texts).clone();
+                let data = *rng.pick(FUZZ_TEXTS);
+                println!("set data of {} to {:?}", dom::node_label(&text), data);
+                builder.set_text_data(&text, data).unwrap();
+            },
+            _ => continue,
+        }
+        builder.layout_tree().assert_consistent();
+    }
+}
+
+/// Fuzzes incremental box construction, which is where the ib-split and
+/// anonymous box logic is the most fragile.
+///
+/// Set `NGLAYOUTNG_FUZZ_SEEDS` to run with more seeds than the default.
+#[test]
+fn random_dom_mutations_keep_the_layout_tree_consistent() {
+    let seeds = std::env::var("NGLAYOUTNG_FUZZ_SEEDS")
+        .ok()
+        .map_or(32, |seeds| seeds.parse().expect("Invalid NGLAYOUTNG_FUZZ_SEEDS"));
+    for seed in 0..seeds {
+        fuzz_mutations(seed, 20);
+    }
+}
+
+/// A simple implementation of the first phase of white space processing for
+/// `white-space: normal`, on the whole text of an inline formatting context at
+/// once. Spaces at the start and end of the formatting context are removed too.
+///
+/// https://drafts.csswg.org/css-text-3/#white-space-phase-1
+fn reference_collapse_spaces(text: &str) -> String {
+    const ZWSP: char = '\u{200B}';
+    // Good enough for the characters we generate.
+    let is_space_discarding = |c: char| ('\u{3000}'..='\u{9FFF}').contains(&c);
+
+    let chars = text.chars().collect::<Vec<_>>();
+    let is_white_space = |c: char| c == ' ' || c == '\t' || c == '\n';
+    let mut result = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if !is_white_space(chars[i]) {
+            result.push(chars[i]);
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < chars.len() && is_white_space(chars[i]) {
+            i += 1;
+        }
+        if start == 0 || i == chars.len() {
+            continue;
+        }
+        let (before, after) = (chars[start - 1], chars[i]);
+        // Spaces and tabs around segment breaks are removed, and all but the
+        // first of consecutive segment breaks too. The remaining one becomes
+        // a space, or is removed next to a zero width space or between space
+        // discarding characters. Otherwise, it's all spaces and tabs that
+        // collapse to a single space.
+        let has_break = chars[start..i].contains(&'\n');
+        let removed = has_break &&
+            (before == ZWSP || after == ZWSP || (is_space_discarding(before) && is_space_discarding(after)));
+        if !removed {
+            result.push(' ');
+        }
+    }
+    result
+}
+
+/// Generates the text of the inline items of a `<div>`, split in a few text
+/// nodes, and checks that collapsing its white space matches
+/// `reference_collapse_spaces`.
+fn check_white_space_collapsing(rng: &mut Rng) {
+    const CHARS: &[char] = &[' ', ' ', '\t', '\n', 'a', 'b', '\u{200B}', '\u{4E2D}', '\u{6587}'];
+    let segments = (0..1 + rng.below(4))
+        .map(|_| (0..rng.below(6)).map(|_| *rng.pick(CHARS)).collect::<String>())
+        .collect::<Vec<_>>();
+    let html = format!("<!doctype html><div>{}</div>", segments.join("<span></span>"));
+    let result = LayoutTreeBuilder::new(&mut Cursor::new(&html)).unwrap().build().unwrap();
+    let quirks_mode = result.dom.as_document().unwrap().quirks_mode();
+    let viewport_size = euclid::size2(Au::from_px(800), Au::from_px(600));
+    let actual = result
+        .layout_tree
+        .inline_items(quirks_mode, viewport_size)
+        .iter()
+        .flat_map(|(_, items)| items.iter().filter_map(|item| item.text()))
+        .collect::<String>();
+    let expected = reference_collapse_spaces(&segments.concat());
+
+    assert!(!actual.contains("  "), "Double space in {:?} from {:?}", actual, segments);
+    assert!(!actual.contains(['\t', '\n']), "Uncollapsed break in {:?} from {:?}", actual, segments);
+    assert!(!actual.starts_with(' ') && !actual.ends_with(' '), "Untrimmed {:?} from {:?}", actual, segments);
+    assert_eq!(actual, expected, "Collapsing {:?}", segments);
+}
+
+#[test]
+fn white_space_collapsing_matches_the_reference() {
+    for seed in 0..500 {
+        check_white_space_collapsing(&mut Rng::new(seed));
+    }
+}
+
+#[test]
+fn derived_serialization_round_trips() {
+    let keywords = WhiteSpace::all().iter().map(WhiteSpace::to_css_str).collect::<Vec<_>>();
+    assert_eq!(keywords, ["normal", "pre", "nowrap", "pre-wrap", "break-spaces", "pre-line"]);
+    for value in WhiteSpace::all() {
+        assert_eq!(value.to_css_string(), value.to_css_str());
+        assert_eq!(WhiteSpace::from_ident(value.to_css_str()), Ok(*value));
+    }
+    for value in Overflow::all() {
+        assert_eq!(Overflow::from_ident(&value.to_css_string()), Ok(*value));
+    }
+
+    let length = LengthPercentage {
+        fixed: Length(Au::from_px(10)),
+        percentage: Some(Percentage(0.5)),
+    };
+    assert_eq!(LengthPercentageOrAuto::Auto.to_css_string(), "auto");
+    assert_eq!(
+        LengthPercentageOrAuto::LengthPercentage(length.clone()).to_css_string(),
+        length.to_css_string(),
+    );
+    assert_eq!(Size::Keyword(SizeKeyword::MinContent).to_css_string(), "min-content");
+    assert_eq!(Size::LengthPercentage(length.clone()).to_css_string(), "calc(10px + 50%)");
+}
+
 macro_rules! test_doc {
-    ($function:ident, $html_file:expr, $expectations_directory:expr) => {
+    ($function:ident, $html_file:expr, $expectations_directory:expr, $expectation:expr) => {
         #[test]
         fn $function() {
+            let fonts = TestFonts::from_env();
             let mut header = File::open($html_file).unwrap();
-            let builder = LayoutTreeBuilder::new(&mut header).expect("Failed to parse input file?");
+            let base_directory = Path::new($html_file).parent();
+            let builder = LayoutTreeBuilder::new_with_font_source(&mut header, base_directory, fonts.font_source())
+                .expect("Failed to parse input file?");
 
-            compare_with_reference($html_file, $expectations_directory, builder.build());
+            let result = builder.build().unwrap();
+            compare_with_reference($html_file, $expectations_directory, result, $expectation, fonts);
+        }
+    };
+}
+
+/// Builds, lays out and paints a document, returning its display list, and
+/// the fuzz that its comparison with its reference allows.
+fn render(path: &str) -> (DisplayList, Fuzz) {
+    let mut file = File::open(path).unwrap();
+    let base_directory = Path::new(path).parent();
+    let result = LayoutTreeBuilder::new_with_font_source(&mut file, base_directory, FontSource::BundledTestFont)
+        .expect("Failed to parse input file?")
+        .build()
+        .unwrap();
+    let quirks_mode = result.dom.as_document().unwrap().quirks_mode();
+    let viewport = euclid::Size2D::new(Au::from_f32_px(800.0), Au::from_f32_px(600.0));
+    let fragment_tree = result.layout_tree.layout(quirks_mode, viewport);
+    let fuzz = Fuzz::from_document(&result.dom).unwrap();
+    (DisplayList::build(&fragment_tree), fuzz)
+}
+
+/// Renders a test and its reference, and checks that they differ at most by
+/// the fuzz the test allows. On failure, both renderings are written to the
+/// temporary directory.
+fn compare_with_reference_rendering(test_path: &str, reference_path: &str) {
+    let (test_list, fuzz) = render(test_path);
+    let (reference_list, _) = render(reference_path);
+    let test = paint::paint(&test_list, 1.0);
+    let reference = paint::paint(&reference_list, 1.0);
+    let difference = compare_images(&test, &reference).expect("Test and reference have different sizes");
+    if fuzz.allows(&difference) {
+        return;
+    }
+
+    let test_name = Path::new(test_path).file_stem().unwrap().to_str().unwrap();
+    let output = std::env::temp_dir();
+    let test_png = output.join(format!("{}.png", test_name));
+    let reference_png = output.join(format!("{}-ref.png", test_name));
+    paint::paint_to_png(&test_list, 1.0, &test_png).unwrap();
+    paint::paint_to_png(&reference_list, 1.0, &reference_png).unwrap();
+    panic!(
+        "{} pixels differ by up to {}, which is more than {:?} allows, see {:?} and {:?}",
+        difference.total_pixels, difference.max_difference, fuzz, test_png, reference_png,
+    );
+}
+
+macro_rules! reftest {
+    ($function:ident, $html_file:expr, $reference_file:expr) => {
+        #[test]
+        fn $function() {
+            compare_with_reference_rendering($html_file, $reference_file);
         }
     };
 }
 
 include!(concat!(env!("OUT_DIR"), "/tests.rs"));
+include!(concat!(env!("OUT_DIR"), "/reftests.rs"));